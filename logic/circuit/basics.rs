@@ -1,5 +1,6 @@
-use circuit::{gate::Gate, line::Line};
+use circuit::{gate::{Gate, GateKind, Port}, line::Line};
 
+#[derive(Clone)]
 pub struct Source {
     source: Line,
     output: usize
@@ -52,11 +53,20 @@ impl Gate for Source {
         1
     }
 
+    fn kind(&self) -> GateKind {
+        GateKind::Constant(self.source)
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![Port::output("out", 0, 1)]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         lines[self.output] = self.source;
     }
 }
 
+#[derive(Clone)]
 pub struct Sink {
     i0: usize,
     pub(crate) sink: Line
@@ -96,12 +106,21 @@ impl Gate for Sink {
         0
     }
 
+    fn kind(&self) -> GateKind {
+        GateKind::Sink
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![Port::input("in", 0, 1)]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         let tmp = lines[self.i0];
         self.sink = tmp;
     }
 }
 
+#[derive(Clone)]
 pub struct Inverter {
     i0: usize,
     o0: usize
@@ -141,6 +160,14 @@ impl Gate for Inverter {
         1
     }
 
+    fn kind(&self) -> GateKind {
+        GateKind::Not
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![Port::input("in", 0, 1), Port::output("out", 0, 1)]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         lines[self.o0] = !lines[self.i0];
     }
@@ -0,0 +1,174 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use circuit::circuit::Circuit;
+use circuit::testbench::{Report, Testbench};
+
+/// Runs many independent copies of one circuit, such as a regression suite driving the same
+/// design with thousands of stimulus sequences.
+///
+/// Every instance starts from a clone of the prototype circuit, lines, gate state and time
+/// included, so instances never see each other's changes and the prototype is left untouched.
+/// Instances are handed out to the threads one at a time, so uneven runs still keep every thread
+/// busy, and results come back in the order of the stimuli whatever thread ran them.
+pub struct Batch {
+    circuit: Circuit,
+    threads: usize
+}
+
+impl Batch {
+    pub(crate) fn new(circuit: Circuit, threads: usize) -> Self {
+        assert!(threads >= 1, "At least one thread is needed.");
+        Batch {
+            circuit,
+            threads
+        }
+    }
+
+    pub(crate) fn into_circuit(self) -> Circuit {
+        self.circuit
+    }
+
+    /// The prototype every instance is cloned from.
+    pub(crate) fn circuit(&self) -> &Circuit {
+        &self.circuit
+    }
+
+    pub(crate) fn circuit_mut(&mut self) -> &mut Circuit {
+        &mut self.circuit
+    }
+
+    pub(crate) fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Calls `run` once per stimulus with a fresh instance of the circuit, and returns what each
+    /// call returned, in the order of `stimuli`.
+    pub(crate) fn run<S, T, F>(&self, stimuli: &[S], run: F) -> Vec<T>
+        where S: Sync, T: Send, F: Fn(&S, &mut Circuit) -> T + Sync {
+        let threads = self.threads.min(stimuli.len());
+        if threads <= 1 {
+            return stimuli.iter().map(|stimulus| run(stimulus, &mut self.circuit.clone()))
+                .collect();
+        }
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new((0..stimuli.len()).map(|_| None).collect::<Vec<_>>());
+        thread::scope(|scope| {
+            let (next, results, run) = (&next, &results, &run);
+            // Circuits aren't `Sync`, so each thread gets its own prototype to clone from.
+            for prototype in (0..threads).map(|_| self.circuit.clone()) {
+                scope.spawn(move || loop {
+                    let instance = next.fetch_add(1, Ordering::Relaxed);
+                    if instance >= stimuli.len() {
+                        break;
+                    }
+                    let result = run(&stimuli[instance], &mut prototype.clone());
+                    results.lock().unwrap()[instance] = Some(result);
+                });
+            }
+        });
+        results.into_inner().unwrap().into_iter().map(|result| result.unwrap()).collect()
+    }
+
+    /// Runs every testbench against its own instance of the circuit.
+    pub(crate) fn run_testbenches(&self, testbenches: &[Testbench]) -> Vec<Report> {
+        self.run(testbenches, |testbench, circuit| testbench.run(circuit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::clock::Clock;
+    use circuit::gate::tests::{Rng, random_logic};
+    use circuit::line::Line;
+    use circuit::sequential::Counter;
+    use circuit::simplegate::SimpleGate;
+    use circuit::testbench::Expect;
+    use super::*;
+
+    const LINES: [Line; 3] = [Line::Low, Line::High, Line::Disconnected];
+
+    // Random logic with a counter clocked by a clock line and enabled by the first output.
+    fn with_state(inputs: usize) -> Circuit {
+        let mut c = random_logic(inputs as u64, inputs, 60, 2, false);
+        let clock = c.add_clock(Clock::new(2));
+        let counter = c.add_gate(Counter::new(4));
+        let (enable, up) = (c.outputs[0], c.outputs[1]);
+        for (i, &line) in [enable, up, 0, clock].iter().enumerate() {
+            c.set_gate_input(counter, i, line);
+        }
+        for o in 0..5 {
+            let line = c.get_gate_output(counter, o);
+            c.mark_line_as_circuit_output(line);
+        }
+        c
+    }
+
+    // Drives random inputs seeded by `seed`, alternately ticking and evaluating, and returns
+    // every line after each step along with the final time.
+    fn drive(seed: &u64, circuit: &mut Circuit) -> (Vec<Line>, u64) {
+        let mut rng = Rng(*seed);
+        let mut trace = Vec::new();
+        for step in 0..20 {
+            for i in 0..circuit.inputs.len() {
+                circuit.set_circuit_input(i, LINES[rng.below(3) as usize]);
+            }
+            if step % 2 == 0 {
+                circuit.tick();
+            } else {
+                circuit.eval();
+            }
+            trace.extend_from_slice(&circuit.lines);
+        }
+        (trace, circuit.time)
+    }
+
+    #[test]
+    fn batch_matches_serial() {
+        for &inputs in &[0, 1, 64, 65] {
+            let mut prototype = with_state(inputs);
+            // Instances start from the prototype as it is, not as it was built.
+            drive(&1, &mut prototype);
+            let stimuli = (0..23).map(|s| s * 7 + inputs as u64).collect::<Vec<_>>();
+            let expected = stimuli.iter().map(|seed| drive(seed, &mut prototype.clone()))
+                .collect::<Vec<_>>();
+            for &threads in &[1, 2, 4, 64] {
+                let batch = Batch::new(prototype.clone(), threads);
+                assert_eq!(batch.threads(), threads);
+                assert_eq!(batch.run(&stimuli, drive), expected);
+                assert_eq!(batch.circuit().lines, prototype.lines);
+                assert_eq!(batch.circuit().time, prototype.time);
+                assert!(batch.run(&[], drive).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn testbenches_match_serial() {
+        let mut c = Circuit::new();
+        let (a, b) = (c.add_line(Line::Low), c.add_line(Line::Low));
+        let gate = c.add_gate(SimpleGate::and());
+        c.set_gate_input(gate, 0, a);
+        c.set_gate_input(gate, 1, b);
+        let y = c.get_gate_output(gate, 0);
+        let benches = (0..9).map(|i| Testbench::new().input("a", vec![a]).input("b", vec![b])
+            .output("y", vec![y]).vector(&[1, i % 2], &[Expect::value(1)])).collect::<Vec<_>>();
+        let mut batch = Batch::new(c.clone(), 3);
+        let reports = batch.run_testbenches(&benches);
+        for (i, (report, bench)) in reports.iter().zip(&benches).enumerate() {
+            assert_eq!(report.passed(), i % 2 == 1);
+            assert_eq!(report.to_string(), bench.run(&mut c.clone()).to_string());
+        }
+        // Changes to the prototype reach every instance made after them.
+        batch.circuit_mut().set_gate_input(gate, 1, a);
+        assert!(batch.run_testbenches(&benches).iter().all(|report| report.passed()));
+        assert_eq!(batch.into_circuit().get_gate_input(gate, 1), a);
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one thread is needed.")]
+    fn no_threads() {
+        Batch::new(Circuit::new(), 0);
+    }
+}
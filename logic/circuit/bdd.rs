@@ -0,0 +1,642 @@
+use std::collections::{HashMap, HashSet};
+
+use circuit::circuit::Circuit;
+use circuit::netlist::{Netlist, NetlistError, Node, NetInput};
+
+// Variable of the two terminal nodes, below every real variable.
+const TERMINAL: usize = !0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BddNode {
+    var: usize,
+    low: usize,
+    high: usize
+}
+
+/// Product term of a sum-of-products, as `(variable, polarity)` literals.
+pub type Cube = Vec<(usize, bool)>;
+
+type IsopMemo = HashMap<(usize, usize), (Vec<Cube>, usize)>;
+
+/// Reduced ordered binary decision diagrams over a fixed number of variables, ordered by index.
+///
+/// Functions are node indices into the manager, with `Bdd::FALSE` and `Bdd::TRUE` as the two
+/// terminals. Nodes are shared and never freed, so equal functions always have equal indices
+/// and comparing two functions is comparing two numbers.
+#[derive(Clone, Debug)]
+pub struct Bdd {
+    num_vars: usize,
+    nodes: Vec<BddNode>,
+    unique: HashMap<(usize, usize, usize), usize>,
+    ite_cache: HashMap<(usize, usize, usize), usize>,
+    restrict_cache: HashMap<(usize, usize, bool), usize>
+}
+
+impl Bdd {
+    pub const FALSE: usize = 0;
+    pub const TRUE: usize = 1;
+
+    pub fn new(num_vars: usize) -> Self {
+        let terminal = |value| BddNode {
+            var: TERMINAL,
+            low: value,
+            high: value
+        };
+        Bdd {
+            num_vars,
+            nodes: vec![terminal(0), terminal(1)],
+            unique: HashMap::new(),
+            ite_cache: HashMap::new(),
+            restrict_cache: HashMap::new()
+        }
+    }
+
+    pub fn num_vars(&self) -> usize {
+        self.num_vars
+    }
+
+    /// Nodes allocated so far, including both terminals.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn make(&mut self, var: usize, low: usize, high: usize) -> usize {
+        if low == high {
+            return low;
+        }
+        if let Some(&node) = self.unique.get(&(var, low, high)) {
+            return node;
+        }
+        self.nodes.push(BddNode { var, low, high });
+        self.unique.insert((var, low, high), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// The function that's true when `var` is.
+    pub fn var(&mut self, var: usize) -> usize {
+        assert!(var < self.num_vars, "Invalid variable.");
+        self.make(var, Bdd::FALSE, Bdd::TRUE)
+    }
+
+    pub fn constant(&self, value: bool) -> usize {
+        if value { Bdd::TRUE } else { Bdd::FALSE }
+    }
+
+    fn top(&self, f: usize) -> usize {
+        self.nodes[f].var
+    }
+
+    // Cofactors of `f` with respect to `var`, which must not be below `f`'s top variable.
+    fn cofactors(&self, f: usize, var: usize) -> (usize, usize) {
+        let node = self.nodes[f];
+        if node.var == var { (node.low, node.high) } else { (f, f) }
+    }
+
+    /// If `f` then `g` else `h`, the operation every other one is built from.
+    pub fn ite(&mut self, f: usize, g: usize, h: usize) -> usize {
+        if f == Bdd::TRUE || g == h {
+            return g;
+        }
+        if f == Bdd::FALSE {
+            return h;
+        }
+        if g == Bdd::TRUE && h == Bdd::FALSE {
+            return f;
+        }
+        if let Some(&node) = self.ite_cache.get(&(f, g, h)) {
+            return node;
+        }
+        let var = self.top(f).min(self.top(g)).min(self.top(h));
+        let (f0, f1) = self.cofactors(f, var);
+        let (g0, g1) = self.cofactors(g, var);
+        let (h0, h1) = self.cofactors(h, var);
+        let low = self.ite(f0, g0, h0);
+        let high = self.ite(f1, g1, h1);
+        let node = self.make(var, low, high);
+        self.ite_cache.insert((f, g, h), node);
+        node
+    }
+
+    pub fn not(&mut self, f: usize) -> usize {
+        self.ite(f, Bdd::FALSE, Bdd::TRUE)
+    }
+
+    pub fn and(&mut self, f: usize, g: usize) -> usize {
+        self.ite(f, g, Bdd::FALSE)
+    }
+
+    pub fn or(&mut self, f: usize, g: usize) -> usize {
+        self.ite(f, Bdd::TRUE, g)
+    }
+
+    pub fn xor(&mut self, f: usize, g: usize) -> usize {
+        let not_g = self.not(g);
+        self.ite(f, not_g, g)
+    }
+
+    /// `f` with `var` fixed to `value`.
+    pub fn restrict(&mut self, f: usize, var: usize, value: bool) -> usize {
+        let node = self.nodes[f];
+        if node.var == TERMINAL || node.var > var {
+            return f;
+        }
+        if node.var == var {
+            return if value { node.high } else { node.low };
+        }
+        if let Some(&restricted) = self.restrict_cache.get(&(f, var, value)) {
+            return restricted;
+        }
+        let low = self.restrict(node.low, var, value);
+        let high = self.restrict(node.high, var, value);
+        let restricted = self.make(node.var, low, high);
+        self.restrict_cache.insert((f, var, value), restricted);
+        restricted
+    }
+
+    /// The function that holds where `f` does for some value of `var`.
+    pub fn exists(&mut self, f: usize, var: usize) -> usize {
+        let low = self.restrict(f, var, false);
+        let high = self.restrict(f, var, true);
+        self.or(low, high)
+    }
+
+    pub fn eval(&self, f: usize, assignment: &[bool]) -> bool {
+        let mut node = self.nodes[f];
+        while node.var != TERMINAL {
+            node = self.nodes[if assignment[node.var] { node.high } else { node.low }];
+        }
+        node.low == Bdd::TRUE
+    }
+
+    /// Variables `f` depends on, in order.
+    pub fn support(&self, f: usize) -> Vec<usize> {
+        let mut seen = vec![false; self.num_vars];
+        let mut stack = vec![f];
+        let mut visited = HashSet::new();
+        while let Some(f) = stack.pop() {
+            let node = self.nodes[f];
+            if node.var == TERMINAL || !visited.insert(f) {
+                continue;
+            }
+            seen[node.var] = true;
+            stack.push(node.low);
+            stack.push(node.high);
+        }
+        (0..self.num_vars).filter(|&var| seen[var]).collect()
+    }
+
+    /// Number of nodes reachable from `f`, including terminals.
+    pub fn size(&self, f: usize) -> usize {
+        let mut visited = HashSet::new();
+        let mut stack = vec![f];
+        while let Some(f) = stack.pop() {
+            if visited.insert(f) && self.nodes[f].var != TERMINAL {
+                stack.push(self.nodes[f].low);
+                stack.push(self.nodes[f].high);
+            }
+        }
+        visited.len()
+    }
+
+    pub fn is_satisfiable(&self, f: usize) -> bool {
+        f != Bdd::FALSE
+    }
+
+    /// One assignment satisfying `f`, leaving variables that don't matter to it as `None`.
+    pub fn any_sat(&self, f: usize) -> Option<Vec<Option<bool>>> {
+        if f == Bdd::FALSE {
+            return None;
+        }
+        let mut assignment = vec![None; self.num_vars];
+        let mut node = self.nodes[f];
+        while node.var != TERMINAL {
+            let value = node.low == Bdd::FALSE;
+            assignment[node.var] = Some(value);
+            node = self.nodes[if value { node.high } else { node.low }];
+        }
+        Some(assignment)
+    }
+
+    // Level of `f` for counting, with terminals below the last variable.
+    fn level(&self, f: usize) -> usize {
+        self.nodes[f].var.min(self.num_vars)
+    }
+
+    fn count(&self, f: usize, memo: &mut HashMap<usize, u128>) -> u128 {
+        if f <= Bdd::TRUE {
+            return f as u128;
+        }
+        if let Some(&count) = memo.get(&f) {
+            return count;
+        }
+        let node = self.nodes[f];
+        let count = [node.low, node.high].iter().map(|&child| {
+            self.count(child, memo) << (self.level(child) - node.var - 1)
+        }).sum();
+        memo.insert(f, count);
+        count
+    }
+
+    /// Number of assignments to all variables that satisfy `f`.
+    pub fn sat_count(&self, f: usize) -> u128 {
+        assert!(self.num_vars < 128, "Too many variables to count assignments.");
+        self.count(f, &mut HashMap::new()) << self.level(f)
+    }
+
+    // Minato-Morreale: an irredundant cover of primes for any function between `lower` and
+    // `upper`, returned with the function it covers.
+    fn isop(&mut self, lower: usize, upper: usize, memo: &mut IsopMemo) -> (Vec<Cube>, usize) {
+        if lower == Bdd::FALSE {
+            return (Vec::new(), Bdd::FALSE);
+        }
+        if upper == Bdd::TRUE {
+            return (vec![Vec::new()], Bdd::TRUE);
+        }
+        if let Some(result) = memo.get(&(lower, upper)) {
+            return result.clone();
+        }
+        let var = self.top(lower).min(self.top(upper));
+        let (l0, l1) = self.cofactors(lower, var);
+        let (u0, u1) = self.cofactors(upper, var);
+        let not_u1 = self.not(u1);
+        let only_low = self.and(l0, not_u1);
+        let (cover0, f0) = self.isop(only_low, u0, memo);
+        let not_u0 = self.not(u0);
+        let only_high = self.and(l1, not_u0);
+        let (cover1, f1) = self.isop(only_high, u1, memo);
+        let not_f0 = self.not(f0);
+        let not_f1 = self.not(f1);
+        let rest0 = self.and(l0, not_f0);
+        let rest1 = self.and(l1, not_f1);
+        let rest = self.or(rest0, rest1);
+        let both = self.and(u0, u1);
+        let (cover, f) = self.isop(rest, both, memo);
+        let mut cubes = Vec::with_capacity(cover0.len() + cover1.len() + cover.len());
+        for (cover, value) in [(cover0, false), (cover1, true)] {
+            cubes.extend(cover.into_iter().map(|mut cube| {
+                cube.insert(0, (var, value));
+                cube
+            }));
+        }
+        cubes.extend(cover);
+        let x = self.var(var);
+        let split = self.ite(x, f1, f0);
+        let function = self.or(split, f);
+        memo.insert((lower, upper), (cubes.clone(), function));
+        (cubes, function)
+    }
+
+    /// Irredundant sum-of-products cover of `f` made of prime implicants. No cube can be
+    /// dropped and no literal removed from any cube, which for most functions is also minimal.
+    pub fn sop(&mut self, f: usize) -> Vec<Cube> {
+        self.isop(f, f, &mut HashMap::new()).0
+    }
+
+    /// `f` as a sum of products using the operators of `Line`, such as `a*!b + c`, with
+    /// variables written as `names[var]`.
+    pub fn format_sop<S: AsRef<str>>(&mut self, f: usize, names: &[S]) -> String {
+        format_cubes(&self.sop(f), names)
+    }
+}
+
+/// Writes `cubes` using the operators of `Line`, with `0` for no cubes and `1` for an empty one.
+pub fn format_cubes<S: AsRef<str>>(cubes: &[Cube], names: &[S]) -> String {
+    if cubes.is_empty() {
+        return "0".to_string();
+    }
+    cubes.iter().map(|cube| if cube.is_empty() {
+        "1".to_string()
+    } else {
+        cube.iter().map(|&(var, value)| {
+            format!("{}{}", if value { "" } else { "!" }, names[var].as_ref())
+        }).collect::<Vec<_>>().join("*")
+    }).collect::<Vec<_>>().join(" + ")
+}
+
+/// How `circuit_to_bdd` orders the variables, which can make the difference between a small
+/// BDD and an exponentially large one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VariableOrder {
+    /// Netlist input order: circuit inputs, then the outputs of stateful gates.
+    Natural,
+    /// Inputs in the order a depth-first walk from each output in turn first reaches them, which
+    /// keeps inputs that meet early in the logic next to each other.
+    DepthFirst,
+    /// Inputs in decreasing order of weight, where each output has a weight of 1 that every
+    /// node splits evenly among its operands.
+    Weight,
+    /// Netlist input indices from the top variable down. Missing inputs go last.
+    Custom(Vec<usize>)
+}
+
+fn complete(mut order: Vec<usize>, inputs: usize) -> Vec<usize> {
+    let mut placed = vec![false; inputs];
+    order.retain(|&i| i < inputs && !placed[i] && {
+        placed[i] = true;
+        true
+    });
+    order.extend((0..inputs).filter(|&i| !placed[i]));
+    order
+}
+
+fn operands(node: &Node) -> Vec<usize> {
+    match *node {
+        Node::Input(_) | Node::Constant(_) => Vec::new(),
+        Node::Not(a) => vec![a],
+        Node::And(a, b) | Node::Or(a, b) | Node::Xor(a, b) => vec![a, b],
+        Node::Mux { select, low, high } => vec![select, low, high]
+    }
+}
+
+impl VariableOrder {
+    /// Netlist input indices from the top variable down.
+    pub fn order(&self, netlist: &Netlist) -> Vec<usize> {
+        let inputs = netlist.num_inputs();
+        match *self {
+            VariableOrder::Natural => (0..inputs).collect(),
+            VariableOrder::DepthFirst => {
+                let mut order = Vec::new();
+                let mut visited = vec![false; netlist.nodes.len()];
+                for &(output, _) in &netlist.outputs {
+                    let mut stack = vec![output];
+                    while let Some(node) = stack.pop() {
+                        if visited[node] {
+                            continue;
+                        }
+                        visited[node] = true;
+                        if let Node::Input(i) = netlist.nodes[node] {
+                            order.push(i);
+                        }
+                        stack.extend(operands(&netlist.nodes[node]).into_iter().rev());
+                    }
+                }
+                complete(order, inputs)
+            },
+            VariableOrder::Weight => {
+                let mut weights = vec![0.0; netlist.nodes.len()];
+                for &(output, _) in &netlist.outputs {
+                    weights[output] += 1.0;
+                }
+                let mut input_weights = vec![0.0; inputs];
+                for (n, node) in netlist.nodes.iter().enumerate().rev() {
+                    let operands = operands(node);
+                    for &operand in &operands {
+                        weights[operand] += weights[n] / operands.len() as f64;
+                    }
+                    if let Node::Input(i) = *node {
+                        input_weights[i] += weights[n];
+                    }
+                }
+                let mut order = (0..inputs).collect::<Vec<_>>();
+                order.sort_by(|&a, &b| input_weights[b].partial_cmp(&input_weights[a]).unwrap());
+                order
+            },
+            VariableOrder::Custom(ref order) => complete(order.clone(), inputs)
+        }
+    }
+}
+
+impl Bdd {
+    /// Builds every node of `netlist`, with netlist input `i` as variable `vars[i]`, and returns
+    /// their functions.
+    pub fn add_netlist(&mut self, netlist: &Netlist, vars: &[usize]) -> Vec<usize> {
+        assert_eq!(vars.len(), netlist.num_inputs(), "Need one variable per input.");
+        let mut functions: Vec<usize> = Vec::with_capacity(netlist.nodes.len());
+        for node in &netlist.nodes {
+            let function = match *node {
+                Node::Input(i) => self.var(vars[i]),
+                Node::Constant(value) => self.constant(value),
+                Node::Not(a) => self.not(functions[a]),
+                Node::And(a, b) => self.and(functions[a], functions[b]),
+                Node::Or(a, b) => self.or(functions[a], functions[b]),
+                Node::Xor(a, b) => self.xor(functions[a], functions[b]),
+                Node::Mux { select, low, high } => {
+                    self.ite(functions[select], functions[high], functions[low])
+                }
+            };
+            functions.push(function);
+        }
+        functions
+    }
+}
+
+/// BDDs of every output of a circuit's combinational logic, from `circuit_to_bdd`.
+#[derive(Clone, Debug)]
+pub struct CircuitBdd {
+    pub bdd: Bdd,
+    pub netlist: Netlist,
+    /// Netlist input of each variable.
+    pub order: Vec<usize>,
+    /// Function of each netlist output.
+    pub outputs: Vec<usize>
+}
+
+impl CircuitBdd {
+    /// Name of each variable: `in3` for circuit input 3, or `g5.1` for output 1 of stateful gate
+    /// 5.
+    pub fn variable_names(&self) -> Vec<String> {
+        self.order.iter().map(|&i| match self.netlist.inputs[i] {
+            NetInput::Circuit(input) => format!("in{}", input),
+            NetInput::State { gate, output } => format!("g{}.{}", gate, output)
+        }).collect()
+    }
+
+    /// Netlist inputs from the variables of an assignment, as returned by `Bdd::any_sat`, with
+    /// don't-cares low.
+    pub fn inputs(&self, assignment: &[Option<bool>]) -> Vec<bool> {
+        let mut inputs = vec![false; self.order.len()];
+        for (var, &i) in self.order.iter().enumerate() {
+            inputs[i] = assignment[var] == Some(true);
+        }
+        inputs
+    }
+
+    /// Sum of products of output `output`, written with `variable_names`.
+    pub fn format_sop(&mut self, output: usize) -> String {
+        let names = self.variable_names();
+        self.bdd.format_sop(self.outputs[output], &names)
+    }
+}
+
+/// Translates the combinational logic of `circuit` into BDDs, as cut by `Netlist::cut_at_state`:
+/// every marked output and every input of a stateful gate gets a function of the circuit inputs
+/// and the outputs of stateful gates.
+pub fn circuit_to_bdd(circuit: &Circuit, order: &VariableOrder)
+    -> Result<CircuitBdd, NetlistError> {
+    let netlist = Netlist::cut_at_state(circuit)?;
+    let order = order.order(&netlist);
+    let mut vars = vec![0; order.len()];
+    for (var, &i) in order.iter().enumerate() {
+        vars[i] = var;
+    }
+    let mut bdd = Bdd::new(order.len());
+    let functions = bdd.add_netlist(&netlist, &vars);
+    let outputs = netlist.outputs.iter().map(|&(node, _)| functions[node]).collect();
+    Ok(CircuitBdd {
+        bdd,
+        netlist,
+        order,
+        outputs
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::gate::tests::{Rng, random_logic};
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::netlist::NetOutput;
+    use super::*;
+
+    // Random function of `vars` variables, with its truth table.
+    fn random_function(bdd: &mut Bdd, rng: &mut Rng, vars: usize) -> (usize, Vec<bool>) {
+        let netlist = Netlist::from_circuit(&random_logic(rng.next(), vars, 30, 1, false))
+            .unwrap();
+        let f = bdd.add_netlist(&netlist, &(0..vars).collect::<Vec<_>>())[netlist.outputs[0].0];
+        let table = (0..1u32 << vars).map(|row| {
+            let words = (0..vars).map(|v| (row >> v & 1) as u64).collect::<Vec<_>>();
+            netlist.simulate(&words)[0] & 1 == 1
+        }).collect();
+        (f, table)
+    }
+
+    fn assignment(row: usize, vars: usize) -> Vec<bool> {
+        (0..vars).map(|v| row >> v & 1 == 1).collect()
+    }
+
+    #[test]
+    fn functions_match_their_netlists() {
+        let mut rng = Rng(31);
+        for vars in 0..8 {
+            let mut bdd = Bdd::new(vars);
+            for _ in 0..10 {
+                let (f, table) = random_function(&mut bdd, &mut rng, vars);
+                for (row, &value) in table.iter().enumerate() {
+                    assert_eq!(bdd.eval(f, &assignment(row, vars)), value);
+                }
+                assert_eq!(bdd.sat_count(f), table.iter().filter(|&&value| value).count() as u128);
+                assert_eq!(bdd.is_satisfiable(f), table.contains(&true));
+                if let Some(sat) = bdd.any_sat(f) {
+                    let row = (0..vars).filter(|&v| sat[v] == Some(true)).map(|v| 1 << v)
+                        .sum::<usize>();
+                    assert!(table[row]);
+                }
+                let support = bdd.support(f);
+                for v in 0..vars {
+                    let depends = (0..table.len()).any(|row| table[row] != table[row ^ 1 << v]);
+                    assert_eq!(support.contains(&v), depends);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn restrict_and_exists() {
+        let mut rng = Rng(7);
+        let vars = 6;
+        let mut bdd = Bdd::new(vars);
+        for _ in 0..20 {
+            let (f, table) = random_function(&mut bdd, &mut rng, vars);
+            for var in 0..vars {
+                let low = bdd.restrict(f, var, false);
+                let high = bdd.restrict(f, var, true);
+                let some = bdd.exists(f, var);
+                for row in 0..table.len() {
+                    let (row0, row1) = (row & !(1 << var), row | 1 << var);
+                    let input = assignment(row, vars);
+                    assert_eq!(bdd.eval(low, &input), table[row0]);
+                    assert_eq!(bdd.eval(high, &input), table[row1]);
+                    assert_eq!(bdd.eval(some, &input), table[row0] || table[row1]);
+                }
+                // Memoized, so asking again builds nothing.
+                let nodes = bdd.num_nodes();
+                assert_eq!(bdd.restrict(f, var, true), high);
+                assert_eq!(bdd.num_nodes(), nodes);
+            }
+        }
+    }
+
+    #[test]
+    fn covers_are_exact() {
+        let mut rng = Rng(17);
+        let vars = 6;
+        let mut bdd = Bdd::new(vars);
+        for _ in 0..30 {
+            let (f, table) = random_function(&mut bdd, &mut rng, vars);
+            let cubes = bdd.sop(f);
+            for (row, &value) in table.iter().enumerate() {
+                let input = assignment(row, vars);
+                let covered = cubes.iter()
+                    .any(|cube| cube.iter().all(|&(var, polarity)| input[var] == polarity));
+                assert_eq!(covered, value);
+            }
+        }
+        assert_eq!(bdd.format_sop(Bdd::FALSE, &["a"; 6]), "0");
+        assert_eq!(bdd.format_sop(Bdd::TRUE, &["a"; 6]), "1");
+        let (a, b) = (bdd.var(0), bdd.var(1));
+        let not_b = bdd.not(b);
+        let f = bdd.and(a, not_b);
+        assert_eq!(bdd.format_sop(f, &["a", "b", "c", "d", "e", "f"]), "a*!b");
+    }
+
+    #[test]
+    fn equal_functions_share_nodes() {
+        let mut bdd = Bdd::new(3);
+        let (a, b, c) = (bdd.var(0), bdd.var(1), bdd.var(2));
+        let ab = bdd.and(a, b);
+        let left = bdd.or(ab, c);
+        let ac = bdd.or(a, c);
+        let bc = bdd.or(b, c);
+        let right = bdd.and(ac, bc);
+        assert_eq!(left, right);
+        assert_eq!(bdd.size(left), 5);
+        let x = bdd.xor(a, a);
+        assert_eq!(x, Bdd::FALSE);
+    }
+
+    #[test]
+    fn orders_are_permutations() {
+        let netlist = Netlist::from_circuit(&random_logic(9, 12, 80, 4, false)).unwrap();
+        for order in &[VariableOrder::Natural, VariableOrder::DepthFirst, VariableOrder::Weight,
+            VariableOrder::Custom(vec![11, 3, 3, 40])] {
+            let mut order = order.order(&netlist);
+            order.sort();
+            assert_eq!(order, (0..12).collect::<Vec<_>>());
+        }
+        assert_eq!(VariableOrder::Custom(vec![2, 0]).order(&netlist)[..3], [2, 0, 1]);
+    }
+
+    #[test]
+    fn circuits_at_edge_widths() {
+        for &inputs in &[0, 1, 64, 65] {
+            let circuit = random_logic(inputs as u64 + 1, inputs, 40, 2, false);
+            let mut translated = circuit_to_bdd(&circuit, &VariableOrder::DepthFirst).unwrap();
+            assert_eq!(translated.bdd.num_vars(), inputs);
+            let names = translated.variable_names();
+            assert!(names.iter().all(|name| name.starts_with("in")));
+            let mut rng = Rng(3);
+            for _ in 0..50 {
+                let input = (0..inputs).map(|_| rng.below(2) == 1).collect::<Vec<_>>();
+                let words = input.iter().map(|&value| value as u64).collect::<Vec<_>>();
+                let expected = translated.netlist.simulate(&words);
+                let vars = translated.order.iter().map(|&i| input[i]).collect::<Vec<_>>();
+                for (o, &f) in translated.outputs.iter().enumerate() {
+                    assert_eq!(translated.bdd.eval(f, &vars), expected[o] & 1 == 1);
+                }
+            }
+            translated.format_sop(0);
+        }
+    }
+
+    #[test]
+    fn state_becomes_variables() {
+        let mut circuit = Circuit::new();
+        let ff = circuit.add_gate(DFlipFlop::new(Edge::Rising));
+        let q = circuit.get_gate_output(ff, 0);
+        circuit.mark_line_as_circuit_output(q);
+        let translated = circuit_to_bdd(&circuit, &VariableOrder::Natural).unwrap();
+        assert_eq!(translated.variable_names(), vec!["g0.0"]);
+        assert_eq!(translated.netlist.outputs[0].1, NetOutput::Circuit(0));
+        assert_eq!(translated.inputs(&[Some(true)]), vec![true]);
+        assert_eq!(translated.inputs(&[None]), vec![false]);
+    }
+}
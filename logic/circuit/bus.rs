@@ -0,0 +1,235 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::{Index, Range};
+use std::slice;
+
+use circuit::line::Line;
+
+/// Ordered group of lines carrying one multi-bit value, least significant bit first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bus {
+    lines: Vec<usize>
+}
+
+impl Bus {
+    /// A bus over `lines`, least significant bit first.
+    pub fn new(lines: Vec<usize>) -> Self {
+        Bus {
+            lines
+        }
+    }
+
+    /// A bus over `lines` given most significant bit first, as they'd be written in a schematic.
+    pub fn from_msb_first(lines: &[usize]) -> Self {
+        Bus {
+            lines: lines.iter().rev().cloned().collect()
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn lines(&self) -> &[usize] {
+        &self.lines
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, usize> {
+        self.lines.iter()
+    }
+
+    /// Bits `range` of the bus as a bus of their own, so `slice(4..8)` is the upper nibble of a
+    /// byte.
+    pub fn slice(&self, range: Range<usize>) -> Bus {
+        assert!(range.end <= self.width(), "Slice {:?} is outside of a {}-bit bus.", range,
+            self.width());
+        Bus::new(self.lines[range].to_vec())
+    }
+
+    /// A bus with the bits of `self` in the low positions and those of `upper` above them.
+    pub fn concat(&self, upper: &Bus) -> Bus {
+        Bus::new(self.lines.iter().chain(&upper.lines).cloned().collect())
+    }
+
+    /// The same lines in the opposite bit order.
+    pub fn reversed(&self) -> Bus {
+        Bus::new(self.lines.iter().rev().cloned().collect())
+    }
+}
+
+impl Index<usize> for Bus {
+    type Output = usize;
+
+    fn index(&self, bit: usize) -> &usize {
+        &self.lines[bit]
+    }
+}
+
+impl From<Vec<usize>> for Bus {
+    fn from(lines: Vec<usize>) -> Self {
+        Bus::new(lines)
+    }
+}
+
+impl<'a> From<&'a [usize]> for Bus {
+    fn from(lines: &'a [usize]) -> Self {
+        Bus::new(lines.to_vec())
+    }
+}
+
+impl<'a> IntoIterator for &'a Bus {
+    type Item = &'a usize;
+    type IntoIter = slice::Iter<'a, usize>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lines.iter()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusError {
+    /// Bit `bit` of the bus, on line `line`, was disconnected when it was read.
+    Disconnected { bit: usize, line: usize },
+    /// The bus is wider than the 64 bits an integer can hold.
+    TooWide { width: usize }
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BusError::Disconnected { bit, line } => {
+                write!(f, "bit {} (line {}) is disconnected", bit, line)
+            },
+            BusError::TooWide { width } => {
+                write!(f, "a {}-bit bus doesn't fit in 64 bits", width)
+            }
+        }
+    }
+}
+
+impl Error for BusError {}
+
+/// Decodes the value on `pins`, least significant bit first, with disconnected lines counting
+/// as low. This is how gates turn address and select inputs into an index.
+pub(crate) fn decode(lines: &[Line], pins: &[usize]) -> usize {
+    pins.iter().enumerate().map(|(i, &pin)| (lines[pin].is_high() as usize) << i).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::circuit::Circuit;
+    use circuit::line::Line;
+    use circuit::mux::Mux;
+    use super::*;
+
+    #[test]
+    fn slicing_and_ordering() {
+        let bus = Bus::new(vec![10, 11, 12, 13]);
+        assert_eq!(bus.slice(1..3).lines(), &[11, 12]);
+        assert_eq!(bus.slice(2..2), Bus::default());
+        assert_eq!(bus.reversed(), Bus::from_msb_first(bus.lines()));
+        assert_eq!(Bus::from_msb_first(&[1, 2]).lines(), &[2, 1]);
+        assert_eq!(bus.slice(0..2).concat(&bus.slice(2..4)), bus);
+        assert_eq!(bus[3], 13);
+        assert_eq!(Bus::from(&[5, 6][..]).iter().cloned().collect::<Vec<_>>(), vec![5, 6]);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of a 4-bit bus")]
+    fn slice_is_checked() {
+        Bus::new(vec![1, 2, 3, 4]).slice(2..5);
+    }
+
+    #[test]
+    fn round_trips_at_edge_widths() {
+        let mut c = Circuit::new();
+        for &width in &[0, 1, 7, 63, 64] {
+            let bus = c.add_bus(width, Line::Low);
+            for &value in &[0, 1, 0x5555_5555_5555_5555, !0] {
+                let mask = if width == 64 { !0 } else { (1u64 << width) - 1 };
+                c.drive_bus(&bus, value);
+                assert_eq!(c.read_bus(&bus), Ok(value & mask));
+            }
+        }
+    }
+
+    #[test]
+    fn signed_values_at_edge_widths() {
+        let mut c = Circuit::new();
+        let empty = c.add_bus(0, Line::Low);
+        c.drive_bus_signed(&empty, -1);
+        assert_eq!(c.read_bus_signed(&empty), Ok(0));
+        let byte = c.add_bus(8, Line::Low);
+        for &value in &[0, 1, -1, 127, -128] {
+            c.drive_bus_signed(&byte, value);
+            assert_eq!(c.read_bus_signed(&byte), Ok(value));
+        }
+        c.drive_bus_signed(&byte, 128);
+        assert_eq!(c.read_bus_signed(&byte), Ok(-128));
+        let word = c.add_bus(64, Line::Low);
+        for &value in &[i64::MIN, -1, 0, i64::MAX] {
+            c.drive_bus_signed(&word, value);
+            assert_eq!(c.read_bus_signed(&word), Ok(value));
+        }
+        let bit = c.add_bus(1, Line::High);
+        assert_eq!(c.read_bus_signed(&bit), Ok(-1));
+    }
+
+    #[test]
+    fn wide_buses_are_rejected() {
+        let mut c = Circuit::new();
+        let bus = c.add_bus(65, Line::Low);
+        assert_eq!(c.read_bus(&bus), Err(BusError::TooWide { width: 65 }));
+        assert_eq!(c.read_bus_signed(&bus), Err(BusError::TooWide { width: 65 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "wider than 64 bits")]
+    fn wide_buses_cannot_be_driven() {
+        let mut c = Circuit::new();
+        let bus = c.add_bus(65, Line::Low);
+        c.drive_bus(&bus, 1);
+    }
+
+    #[test]
+    fn disconnected_bits_fail_reads() {
+        let mut c = Circuit::new();
+        let bus = c.add_bus(4, Line::Low);
+        c.set_line(bus[2], Line::Disconnected);
+        assert_eq!(c.read_bus(&bus), Err(BusError::Disconnected { bit: 2, line: bus[2] }));
+        assert_eq!(decode(&c.lines, bus.lines()), 0);
+        c.drive_bus(&bus, 0b1111);
+        assert_eq!(decode(&c.lines, bus.lines()), 15);
+    }
+
+    #[test]
+    fn gate_ports_as_buses() {
+        let mut c = Circuit::new();
+        let mux = c.add_gate(Mux::new(2, 4));
+        let (a, b, select) = (c.add_bus(4, Line::Low), c.add_bus(4, Line::Low),
+            c.add_bus(1, Line::Low));
+        c.connect_bus(mux, "in0", &a);
+        c.connect_bus(mux, "in1", &b);
+        c.connect_bus(mux, "select", &select);
+        assert_eq!(c.get_gate_input_bus(mux, "in1"), b);
+        let out = c.get_gate_output_bus(mux, "out");
+        c.drive_bus(&a, 3);
+        c.drive_bus(&b, 12);
+        c.drive_bus(&select, 1);
+        c.eval();
+        assert_eq!(c.read_bus(&out), Ok(12));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bus width doesn't match port")]
+    fn connect_bus_checks_width() {
+        let mut c = Circuit::new();
+        let mux = c.add_gate(Mux::new(2, 4));
+        let bus = c.add_bus(3, Line::Low);
+        c.connect_bus(mux, "in0", &bus);
+    }
+}
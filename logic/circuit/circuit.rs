@@ -1,11 +1,34 @@
-use circuit::gate::Gate;
-use circuit::line::Line;
+use std::mem;
 
+use circuit::gate::{Gate, Port, PortDirection, PortError, StateRegister, check_ports, pin_name};
+use circuit::line::{Line, lines_to_word, word_to_lines};
+use circuit::bus::{Bus, BusError};
+use circuit::clock::Clock;
+use circuit::memory::{Edge, WordMemory};
+
+/// How `Circuit::eval` propagates values between gates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvalMode {
+    /// Gates are evaluated in the order they were added and write straight into the lines, so
+    /// later gates see values computed earlier in the same pass.
+    Immediate,
+    /// Every gate reads the lines as they were at the start of the pass and its outputs only
+    /// become visible once the whole pass is done, making results independent of gate order.
+    /// Combinational logic `n` gates deep takes `n` passes to settle.
+    Synchronous
+}
+
+#[derive(Clone)]
 pub struct Circuit {
     pub(crate) gates: Vec<Box<dyn Gate>>,
     pub(crate) lines: Vec<Line>,
     pub(crate) inputs: Vec<usize>,
-    pub(crate) outputs: Vec<usize>
+    pub(crate) outputs: Vec<usize>,
+    pub(crate) clocks: Vec<(usize, Clock)>,
+    pub(crate) time: u64,
+    pub(crate) passes_per_tick: usize,
+    pub(crate) mode: EvalMode,
+    next_lines: Vec<Line>
 }
 
 impl Circuit {
@@ -14,11 +37,21 @@ impl Circuit {
             gates: Vec::new(),
             lines: vec![Line::Disconnected],
             inputs: Vec::new(),
-            outputs: Vec::new()
+            outputs: Vec::new(),
+            clocks: Vec::new(),
+            time: 0,
+            passes_per_tick: 1,
+            mode: EvalMode::Immediate,
+            next_lines: Vec::new()
         }
     }
 
     pub(crate) fn add_gate<T: Gate + 'static>(&mut self, mut gate: T) -> usize {
+        if cfg!(debug_assertions) {
+            if let Err(e) = check_ports(&gate) {
+                panic!("Gate ports don't match its pins: {}", e);
+            }
+        }
         for i in 0..gate.num_inputs() {
             gate.set_input(i, 0);
         }
@@ -102,14 +135,217 @@ impl Circuit {
         &self.gates[target_gate]
     }
 
+    pub(crate) fn get_gate_ports(&self, target_gate: usize) -> Vec<Port> {
+        self.gates[target_gate].ports()
+    }
+
+    pub(crate) fn find_gate_port(&self, target_gate: usize, name: &str) -> Option<Port> {
+        self.gates[target_gate].ports().into_iter().find(|port| port.name == name)
+    }
+
+    /// Names one input or output pin of a gate, such as `address[3]`, for error messages and
+    /// exported netlists.
+    pub(crate) fn get_pin_name(&self, target_gate: usize, direction: PortDirection, pin: usize)
+        -> String {
+        pin_name(&*self.gates[target_gate], direction, pin)
+    }
+
+    /// Checks that every gate's ports match its pins, returning the first gate that doesn't.
+    pub(crate) fn check_ports(&self) -> Result<(), (usize, PortError)> {
+        for (g, gate) in self.gates.iter().enumerate() {
+            check_ports(&**gate).map_err(|e| (g, e))?;
+        }
+        Ok(())
+    }
+
+    fn find_directed_port(&self, target_gate: usize, name: &str, direction: PortDirection)
+        -> Port {
+        match self.find_gate_port(target_gate, name) {
+            Some(ref port) if port.direction == direction => port.clone(),
+            _ => panic!("Gate {} has no {:?} port named {}.", target_gate, direction, name)
+        }
+    }
+
+    pub(crate) fn add_bus(&mut self, width: usize, line: Line) -> Bus {
+        Bus::new((0..width).map(|_| self.add_line(line)).collect())
+    }
+
+    pub(crate) fn mark_bus_as_circuit_input(&mut self, bus: &Bus) {
+        for &line in bus {
+            self.mark_line_as_circuit_input(line);
+        }
+    }
+
+    pub(crate) fn mark_bus_as_circuit_output(&mut self, bus: &Bus) {
+        for &line in bus {
+            self.mark_line_as_circuit_output(line);
+        }
+    }
+
+    /// Sets the lines of `bus` to the low `bus.width()` bits of `value`.
+    pub(crate) fn drive_bus(&mut self, bus: &Bus, value: u64) {
+        assert!(bus.width() <= 64, "Buses wider than 64 bits can't be driven from an integer.");
+        for (&line, state) in bus.iter().zip(word_to_lines(value, bus.width())) {
+            self.lines[line] = state;
+        }
+    }
+
+    /// Sets the lines of `bus` to `value` in two's complement.
+    pub(crate) fn drive_bus_signed(&mut self, bus: &Bus, value: i64) {
+        self.drive_bus(bus, value as u64);
+    }
+
+    /// Reads `bus` as an unsigned integer. Fails if any of its lines is disconnected, rather than
+    /// reading it as low.
+    pub(crate) fn read_bus(&self, bus: &Bus) -> Result<u64, BusError> {
+        if bus.width() > 64 {
+            return Err(BusError::TooWide { width: bus.width() });
+        }
+        let states = bus.iter().map(|&line| self.lines[line]).collect::<Vec<_>>();
+        match states.iter().position(|state| state.is_disconnected()) {
+            Some(bit) => Err(BusError::Disconnected { bit, line: bus[bit] }),
+            None => Ok(lines_to_word(&states))
+        }
+    }
+
+    /// Reads `bus` as a two's complement integer, sign-extending from its top bit.
+    pub(crate) fn read_bus_signed(&self, bus: &Bus) -> Result<i64, BusError> {
+        let value = self.read_bus(bus)?;
+        let unused = 64 - bus.width() as u32;
+        Ok(if unused == 64 { 0 } else { (value << unused) as i64 >> unused })
+    }
+
+    /// The lines currently connected to input port `port` of a gate.
+    pub(crate) fn get_gate_input_bus(&self, target_gate: usize, port: &str) -> Bus {
+        let port = self.find_directed_port(target_gate, port, PortDirection::Input);
+        Bus::new(port.pins().map(|i| self.gates[target_gate].get_input(i)).collect())
+    }
+
+    /// The lines driven by output port `port` of a gate.
+    pub(crate) fn get_gate_output_bus(&self, target_gate: usize, port: &str) -> Bus {
+        let port = self.find_directed_port(target_gate, port, PortDirection::Output);
+        Bus::new(port.pins().map(|o| self.gates[target_gate].get_output(o)).collect())
+    }
+
+    /// Connects `bus` to input port `port` of a gate, bit for bit. The widths must match.
+    pub(crate) fn connect_bus(&mut self, target_gate: usize, port: &str, bus: &Bus) {
+        let port = self.find_directed_port(target_gate, port, PortDirection::Input);
+        assert_eq!(port.width, bus.width(), "Bus width doesn't match port {}.", port.name);
+        for (i, &line) in port.pins().zip(bus) {
+            self.gates[target_gate].set_input(i, line);
+        }
+    }
+
+    pub(crate) fn get_memory(&self, target_gate: usize) -> Option<&dyn WordMemory> {
+        self.gates[target_gate].memory()
+    }
+
+    pub(crate) fn get_memory_mut(&mut self, target_gate: usize) -> Option<&mut dyn WordMemory> {
+        self.gates[target_gate].memory_mut()
+    }
+
+    pub(crate) fn get_state_registers(&self, target_gate: usize) -> Vec<StateRegister> {
+        self.gates[target_gate].state_registers()
+    }
+
+    fn find_state(&self, target_gate: usize, name: &str, index: usize) -> Option<(usize, usize)> {
+        self.gates[target_gate].state_registers().into_iter().enumerate()
+            .find(|(_, register)| register.name == name && index < register.depth)
+            .map(|(r, register)| (r, register.width))
+    }
+
+    /// Reads state register `name` of a gate directly, without evaluating anything. Returns
+    /// `None` if the gate has no such register or `index` is past its depth.
+    pub(crate) fn peek_state(&self, target_gate: usize, name: &str, index: usize)
+        -> Option<Vec<Line>> {
+        self.find_state(target_gate, name, index)
+            .map(|(register, _)| self.gates[target_gate].peek_state(register, index))
+    }
+
+    /// Overwrites state register `name` of a gate directly, without going through its inputs.
+    /// Returns `false` if the gate has no such register or `index` is past its depth.
+    pub(crate) fn poke_state(&mut self, target_gate: usize, name: &str, index: usize,
+        value: &[Line]) -> bool {
+        match self.find_state(target_gate, name, index) {
+            Some((register, width)) => {
+                assert_eq!(value.len(), width, "Value width doesn't match state register.");
+                self.gates[target_gate].poke_state(register, index, value);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub(crate) fn peek_state_word(&self, target_gate: usize, name: &str, index: usize)
+        -> Option<u64> {
+        self.peek_state(target_gate, name, index).map(|value| lines_to_word(&value))
+    }
+
+    pub(crate) fn poke_state_word(&mut self, target_gate: usize, name: &str, index: usize,
+        value: u64) -> bool {
+        match self.find_state(target_gate, name, index) {
+            Some((_, width)) => {
+                self.poke_state(target_gate, name, index, &word_to_lines(value, width))
+            },
+            None => false
+        }
+    }
+
+    /// Evaluates gate `g` alone. No other gate runs in between, so this is the same in either
+    /// `EvalMode`.
     pub(crate) fn eval_single_gate(&mut self, g: usize) {
         self.gates[g].eval(&mut self.lines);
     }
 
+    pub(crate) fn get_eval_mode(&self) -> EvalMode {
+        self.mode
+    }
+
+    pub(crate) fn set_eval_mode(&mut self, mode: EvalMode) {
+        self.mode = mode;
+    }
+
     pub(crate) fn eval(&mut self) {
+        match self.mode {
+            EvalMode::Immediate => for gate in &mut self.gates {
+                gate.eval(&mut self.lines)
+            },
+            EvalMode::Synchronous => self.eval_synchronous(1)
+        }
+    }
+
+    // Each gate evaluates `evals` times against the current lines and has its outputs swapped
+    // out into the next-state buffer straight away, which puts the previous values back for the
+    // gates after it. Once every gate has run, the next-state buffer becomes the lines.
+    fn eval_synchronous(&mut self, evals: usize) {
+        self.next_lines.clone_from(&self.lines);
         for gate in &mut self.gates {
-            gate.eval(&mut self.lines)
+            for _ in 0..evals {
+                gate.eval(&mut self.lines);
+            }
+            for o in 0..gate.num_outputs() {
+                let line = gate.get_output(o);
+                mem::swap(&mut self.lines[line], &mut self.next_lines[line]);
+            }
+        }
+        mem::swap(&mut self.lines, &mut self.next_lines);
+    }
+
+    /// Evaluates until a pass leaves every line unchanged, for at most `max_passes` passes.
+    /// Returns the number of passes it took, or `None` if the circuit didn't settle, as happens
+    /// with oscillators.
+    pub(crate) fn eval_until_stable(&mut self, max_passes: usize) -> Option<usize> {
+        for pass in 1..=max_passes {
+            let before = self.lines.clone();
+            self.eval();
+            let same = |(a, b): (&Line, &Line)| {
+                a.is_high() == b.is_high() && a.is_disconnected() == b.is_disconnected()
+            };
+            if before.iter().zip(&self.lines).all(same) {
+                return Some(pass);
+            }
         }
+        None
     }
 
     pub(crate) fn eval_n_passes(&mut self, passes: usize) {
@@ -118,14 +354,78 @@ impl Circuit {
         }
     }
 
+    /// Evaluates each gate `evals` times in a row before moving on to the next. In synchronous
+    /// mode the repeated evaluations see the gate's own outputs, but the other gates only see
+    /// them once every gate has run.
     pub(crate) fn eval_n_evals_per_gate(&mut self, evals: usize) {
-        for gate in &mut self.gates {
-            for _ in 0..evals {
-                gate.eval(&mut self.lines);
-            }
+        match self.mode {
+            EvalMode::Immediate => for gate in &mut self.gates {
+                for _ in 0..evals {
+                    gate.eval(&mut self.lines);
+                }
+            },
+            EvalMode::Synchronous => self.eval_synchronous(evals)
         }
     }
 
+    /// Adds a line driven by `clock` and returns it. The line follows the clock whenever
+    /// simulated time advances through `tick`, `advance` or `run_cycles`.
+    pub(crate) fn add_clock(&mut self, clock: Clock) -> usize {
+        let line = self.add_line(clock.level_at(self.time));
+        self.clocks.push((line, clock));
+        line
+    }
+
+    pub(crate) fn get_clock(&self, line: usize) -> Option<Clock> {
+        self.clocks.iter().find(|&&(l, _)| l == line).map(|&(_, clock)| clock)
+    }
+
+    pub(crate) fn get_time(&self) -> u64 {
+        self.time
+    }
+
+    /// How many times every gate is evaluated per tick of simulated time, so that combinational
+    /// logic has time to settle between clock edges. Defaults to 1.
+    pub(crate) fn set_passes_per_tick(&mut self, passes: usize) {
+        assert!(passes >= 1, "At least one pass per tick is needed.");
+        self.passes_per_tick = passes;
+    }
+
+    /// Advances simulated time by one tick, updates every clock line and evaluates the circuit.
+    pub(crate) fn tick(&mut self) {
+        self.time += 1;
+        for &(line, clock) in &self.clocks {
+            self.lines[line] = clock.level_at(self.time);
+        }
+        self.eval_n_passes(self.passes_per_tick);
+    }
+
+    pub(crate) fn advance(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    pub(crate) fn run_until(&mut self, time: u64) {
+        while self.time < time {
+            self.tick();
+        }
+    }
+
+    /// Advances until the clock on line `clock` next has an `edge`, and evaluates at that tick.
+    pub(crate) fn run_to_edge(&mut self, clock: usize, edge: Edge) {
+        let target = self.get_clock(clock).expect("Line isn't driven by a clock.")
+            .next_edge(self.time, edge);
+        self.run_until(target);
+    }
+
+    /// Advances `cycles` full periods of the clock on line `clock`. Other clocks keep running
+    /// at their own rates meanwhile.
+    pub(crate) fn run_cycles(&mut self, clock: usize, cycles: u64) {
+        let period = self.get_clock(clock).expect("Line isn't driven by a clock.").period();
+        self.advance(cycles * period);
+    }
+
     pub(crate) fn remove_gate(&mut self, gate: usize) {
         let outputs = (0..self.gates[gate].num_outputs()).map(|o| self.gates[gate].get_output(o))
             .collect::<Vec<_>>();
@@ -153,8 +453,224 @@ impl Circuit {
                 g.set_output(o, ind - shift);
             }
         }
+        for &mut (ref mut line, _) in &mut self.clocks {
+            *line -= outputs.iter().take_while(|&&output_ind| output_ind < *line).count();
+        }
         for output_ind in outputs.into_iter().rev() {
             self.lines.remove(output_ind);
         }
     }
-}
\ No newline at end of file
+
+    /// Removes every gate `g` for which `keep[g]` is false, along with its output lines, in one
+    /// go. The remaining lines are renumbered in order, and gate pins, marked inputs and
+    /// outputs and clocks follow them. Anything that read a removed line reads line 0 instead.
+    pub(crate) fn retain_gates(&mut self, keep: &[bool]) {
+        let mut removed = vec![false; self.lines.len()];
+        for (gate, _) in self.gates.iter().zip(keep).filter(|&(_, &keep)| !keep) {
+            for o in 0..gate.num_outputs() {
+                removed[gate.get_output(o)] = true;
+            }
+        }
+        let mut renumbered = Vec::with_capacity(self.lines.len());
+        let mut next = 0;
+        for &removed in &removed {
+            renumbered.push(if removed { 0 } else { next });
+            next += !removed as usize;
+        }
+        let mut g = 0;
+        self.gates.retain(|_| {
+            g += 1;
+            keep[g - 1]
+        });
+        for gate in &mut self.gates {
+            for i in 0..gate.num_inputs() {
+                let line = renumbered[gate.get_input(i)];
+                gate.set_input(i, line);
+            }
+            for o in 0..gate.num_outputs() {
+                let line = renumbered[gate.get_output(o)];
+                gate.set_output(o, line);
+            }
+        }
+        for line in self.inputs.iter_mut().chain(&mut self.outputs) {
+            *line = renumbered[*line];
+        }
+        for &mut (ref mut line, _) in &mut self.clocks {
+            *line = renumbered[*line];
+        }
+        let mut l = 0;
+        self.lines.retain(|_| {
+            l += 1;
+            !removed[l - 1]
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::line::Line;
+    use circuit::memory::{DFlipFlop, Edge, Ram, RamKind, RamPort};
+    use circuit::sequential::Register;
+    use circuit::basics::Inverter;
+    use circuit::gate::tests::Rng;
+    use circuit::simplegate::SimpleGate;
+    use super::{Circuit, EvalMode};
+
+    #[test]
+    fn peek_and_poke_state() {
+        let mut c = Circuit::new();
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        let ram = c.add_gate(Ram::new(3, 64, RamKind::MasterSlave, &[RamPort::ReadWrite]));
+        let register = c.add_gate(Register::new(5));
+        assert_eq!(c.peek_state(ff, "state", 0), Some(vec![Line::Low]));
+        assert!(c.poke_state(ff, "state", 0, &[Line::High]));
+        c.eval();
+        assert_eq!(c.get_line_state(c.get_gate_output(ff, 0)), Line::High);
+        assert!(c.poke_state_word(ram, "words", 7, !0));
+        assert_eq!(c.peek_state_word(ram, "words", 7), Some(!0));
+        assert!(c.poke_state_word(register, "state", 0, 0b10110));
+        assert_eq!(c.peek_state_word(register, "state", 0), Some(0b10110));
+    }
+
+    #[test]
+    fn unknown_state_is_none() {
+        let mut c = Circuit::new();
+        let ram = c.add_gate(Ram::new(2, 8, RamKind::Latch, &[RamPort::ReadWrite]));
+        let inverter = c.add_gate(Inverter::new());
+        assert_eq!(c.peek_state(ram, "words", 4), None);
+        assert_eq!(c.peek_state(ram, "masters", 0), None);
+        assert!(!c.poke_state_word(ram, "words", 4, 1));
+        assert!(c.get_state_registers(inverter).is_empty());
+        assert_eq!(c.peek_state(inverter, "state", 0), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Value width")]
+    fn poke_checks_width() {
+        let mut c = Circuit::new();
+        let register = c.add_gate(Register::new(4));
+        c.poke_state(register, "state", 0, &[Line::High; 3]);
+    }
+
+    // A chain of `n` inverters driven by a high line, returning every line along it.
+    fn inverter_chain(c: &mut Circuit, n: usize) -> Vec<usize> {
+        let mut lines = vec![c.add_line(Line::High)];
+        for _ in 0..n {
+            let inverter = c.add_gate(Inverter::new());
+            c.set_gate_input(inverter, 0, *lines.last().unwrap());
+            lines.push(c.get_gate_output(inverter, 0));
+        }
+        lines
+    }
+
+    fn states(c: &Circuit, lines: &[usize]) -> Vec<bool> {
+        lines.iter().map(|&line| c.get_line_state(line).is_high()).collect()
+    }
+
+    #[test]
+    fn synchronous_passes_advance_one_level() {
+        let mut c = Circuit::new();
+        let lines = inverter_chain(&mut c, 3);
+        c.set_eval_mode(EvalMode::Synchronous);
+        // Every inverter reads the line before it as it was, still low past the first.
+        c.eval();
+        assert_eq!(states(&c, &lines), [true, false, true, true]);
+        assert_eq!(c.eval_until_stable(10), Some(2));
+        assert_eq!(states(&c, &lines), [true, false, true, false]);
+        let mut c = Circuit::new();
+        let lines = inverter_chain(&mut c, 3);
+        c.eval();
+        assert_eq!(states(&c, &lines), [true, false, true, false]);
+    }
+
+    #[test]
+    fn evals_per_gate_follow_the_mode() {
+        let mut c = Circuit::new();
+        let lines = inverter_chain(&mut c, 3);
+        c.set_eval_mode(EvalMode::Synchronous);
+        c.eval_n_evals_per_gate(4);
+        assert_eq!(states(&c, &lines), [true, false, true, true]);
+        c.set_eval_mode(EvalMode::Immediate);
+        c.eval_n_evals_per_gate(1);
+        assert_eq!(states(&c, &lines), [true, false, true, false]);
+    }
+
+    #[test]
+    fn repeated_evals_see_the_gates_own_outputs() {
+        for &mode in &[EvalMode::Immediate, EvalMode::Synchronous] {
+            let mut c = Circuit::new();
+            let inverter = c.add_gate(Inverter::new());
+            let output = c.get_gate_output(inverter, 0);
+            c.set_gate_input(inverter, 0, output);
+            c.set_eval_mode(mode);
+            c.eval_n_evals_per_gate(3);
+            assert_eq!(c.get_line_state(output), Line::High);
+            c.eval_single_gate(inverter);
+            assert_eq!(c.get_line_state(output), Line::Low);
+        }
+    }
+
+    // Random network of two-input gates over `inputs` lines, each gate reading any earlier line.
+    // Returns the circuit and its input lines.
+    fn random_network(rng: &mut Rng, inputs: usize, gates: usize, reverse: bool)
+        -> (Circuit, Vec<usize>) {
+        let mut c = Circuit::new();
+        let mut lines = (0..inputs).map(|_| c.add_line(Line::Low)).collect::<Vec<_>>();
+        let specs = (0..gates).map(|g| {
+            let pick = |rng: &mut Rng| rng.below((inputs + g) as u64) as usize;
+            (rng.below(3), pick(rng), pick(rng))
+        }).collect::<Vec<_>>();
+        for _ in 0..gates {
+            lines.push(c.add_line(Line::Low));
+        }
+        let mut order = (0..gates).collect::<Vec<_>>();
+        if reverse {
+            order.reverse();
+        }
+        for g in order {
+            let (kind, a, b) = specs[g];
+            let gate = c.add_gate(match kind {
+                0 => SimpleGate::and(),
+                1 => SimpleGate::or(),
+                _ => SimpleGate::xor()
+            });
+            c.set_gate_input(gate, 0, lines[a]);
+            c.set_gate_input(gate, 1, lines[b]);
+            c.gates[gate].set_output(0, lines[inputs + g]);
+        }
+        let inputs = lines[..inputs].to_vec();
+        (c, inputs)
+    }
+
+    #[test]
+    fn synchronous_results_ignore_gate_order() {
+        let mut rng = Rng(77);
+        for _ in 0..20 {
+            let seed = rng.next();
+            let mut circuits = [false, true].iter().flat_map(|&reverse| {
+                let (c, inputs) = random_network(&mut Rng(seed), 6, 30, reverse);
+                vec![(c.clone(), inputs.clone(), EvalMode::Immediate),
+                    (c, inputs, EvalMode::Synchronous)]
+            }).collect::<Vec<_>>();
+            for _ in 0..10 {
+                let stimulus = rng.next();
+                let mut results = Vec::new();
+                let mut passes = Vec::new();
+                for &mut (ref mut c, ref inputs, mode) in &mut circuits {
+                    c.set_eval_mode(mode);
+                    for (b, &line) in inputs.iter().enumerate() {
+                        c.set_line(line, Line::from(stimulus >> b & 1 == 1));
+                    }
+                    let settled = c.eval_until_stable(100).expect("Network didn't settle.");
+                    let lines = (0..c.lines.len()).collect::<Vec<_>>();
+                    results.push(states(c, &lines));
+                    if mode == EvalMode::Synchronous {
+                        passes.push(settled);
+                    }
+                }
+                assert!(results.iter().all(|result| *result == results[0]));
+                assert_eq!(passes[0], passes[1]);
+            }
+        }
+    }
+}
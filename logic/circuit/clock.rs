@@ -0,0 +1,185 @@
+use circuit::line::Line;
+use circuit::memory::Edge;
+
+/// Periodic clock waveform in ticks of simulated time. Each cycle starts with a rising edge at
+/// `phase` (mod `period`), stays high for `high` ticks and is low for the rest of the period.
+///
+/// Clocks are added to a circuit with `Circuit::add_clock`, which drives a line from them as
+/// simulated time advances. Every clock is its own domain, so clocks with unrelated periods and
+/// phases can drive different parts of the same circuit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Clock {
+    period: u64,
+    high: u64,
+    phase: u64
+}
+
+impl Clock {
+    /// A clock with a 50% duty cycle, rounded down for odd periods, and its first rising edge
+    /// at tick 0.
+    pub fn new(period: u64) -> Self {
+        assert!(period >= 2, "A clock period must be at least 2 ticks.");
+        Clock {
+            period,
+            high: period / 2,
+            phase: 0
+        }
+    }
+
+    /// Keeps the clock high for `high` ticks of each period.
+    pub fn with_high_time(mut self, high: u64) -> Self {
+        assert!((1..self.period).contains(&high), "High time must be between 1 and period - 1.");
+        self.high = high;
+        self
+    }
+
+    /// Keeps the clock high for `percent`% of each period, rounded to the nearest tick but never
+    /// all or none of it.
+    pub fn with_duty_cycle(self, percent: u64) -> Self {
+        let high = (self.period * percent + 50) / 100;
+        let period = self.period;
+        self.with_high_time(high.max(1).min(period - 1))
+    }
+
+    /// Delays every edge by `phase` ticks.
+    pub fn with_phase(mut self, phase: u64) -> Self {
+        self.phase = phase % self.period;
+        self
+    }
+
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    pub fn high_time(&self) -> u64 {
+        self.high
+    }
+
+    pub fn phase(&self) -> u64 {
+        self.phase
+    }
+
+    // Position of `time` within its cycle, counted from the rising edge.
+    fn offset(&self, time: u64) -> u64 {
+        (time % self.period + self.period - self.phase) % self.period
+    }
+
+    pub fn level_at(&self, time: u64) -> Line {
+        Line::from(self.offset(time) < self.high)
+    }
+
+    /// The first tick after `time` at which the clock has an `edge`.
+    pub fn next_edge(&self, time: u64, edge: Edge) -> u64 {
+        let at = match edge {
+            Edge::Rising => 0,
+            Edge::Falling => self.high
+        };
+        let offset = self.offset(time);
+        let wait = if at > offset { at - offset } else { self.period - offset + at };
+        time + wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::circuit::Circuit;
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::line::Line;
+    use super::*;
+
+    fn waveform(clock: &Clock, ticks: u64) -> String {
+        (0..ticks).map(|t| if clock.level_at(t).is_high() { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn waveforms() {
+        assert_eq!(waveform(&Clock::new(4), 8), "11001100");
+        assert_eq!(waveform(&Clock::new(5), 10), "1100011000");
+        assert_eq!(waveform(&Clock::new(4).with_high_time(3), 8), "11101110");
+        assert_eq!(waveform(&Clock::new(4).with_phase(1), 8), "01100110");
+        assert_eq!(waveform(&Clock::new(4).with_phase(5), 8), "01100110");
+        assert_eq!(Clock::new(10).with_duty_cycle(25).high_time(), 3);
+        assert_eq!(Clock::new(10).with_duty_cycle(0).high_time(), 1);
+        assert_eq!(Clock::new(10).with_duty_cycle(100).high_time(), 9);
+    }
+
+    #[test]
+    fn next_edges() {
+        let clock = Clock::new(4).with_phase(1);
+        assert_eq!(clock.next_edge(0, Edge::Rising), 1);
+        assert_eq!(clock.next_edge(1, Edge::Rising), 5);
+        assert_eq!(clock.next_edge(1, Edge::Falling), 3);
+        assert_eq!(clock.next_edge(3, Edge::Falling), 7);
+        for time in 0..20 {
+            let rising = clock.next_edge(time, Edge::Rising);
+            assert!(rising > time && rising <= time + 4);
+            assert!(clock.level_at(rising).is_high() && !clock.level_at(rising - 1).is_high());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 2 ticks")]
+    fn period_is_at_least_two() {
+        Clock::new(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and period - 1")]
+    fn high_time_is_checked() {
+        Clock::new(4).with_high_time(4);
+    }
+
+    #[test]
+    fn circuits_follow_their_clocks() {
+        let mut c = Circuit::new();
+        let fast = c.add_clock(Clock::new(2));
+        let slow = c.add_clock(Clock::new(6).with_phase(3));
+        assert_eq!(c.get_clock(slow), Some(Clock::new(6).with_phase(3)));
+        assert_eq!(c.get_clock(0), None);
+        for time in 1..30 {
+            c.tick();
+            assert_eq!(c.get_time(), time);
+            assert_eq!(c.get_line_state(fast), Clock::new(2).level_at(time));
+            assert_eq!(c.get_line_state(slow), Clock::new(6).with_phase(3).level_at(time));
+        }
+        c.run_to_edge(slow, Edge::Rising);
+        assert_eq!(c.get_time(), 33);
+        c.run_cycles(slow, 2);
+        assert_eq!(c.get_time(), 45);
+        c.run_until(40);
+        assert_eq!(c.get_time(), 45);
+        c.advance(0);
+        assert_eq!(c.get_time(), 45);
+    }
+
+    #[test]
+    fn clocked_gates_see_every_edge() {
+        let mut c = Circuit::new();
+        let clock = c.add_clock(Clock::new(4));
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        c.set_gate_input(ff, 1, clock);
+        let q_bar = c.get_gate_output(ff, 1);
+        // Feeding q-bar back into d halves the clock.
+        c.set_gate_input(ff, 0, q_bar);
+        c.set_passes_per_tick(2);
+        c.tick();
+        let (mut rising_edges, mut toggles) = (0, 0);
+        let mut last = (c.get_line_state(clock), c.get_line_state(c.get_gate_output(ff, 0)));
+        for _ in 0..40 {
+            c.tick();
+            let now = (c.get_line_state(clock), c.get_line_state(c.get_gate_output(ff, 0)));
+            rising_edges += (now.0.is_high() && !last.0.is_high()) as usize;
+            toggles += (now.1 != last.1) as usize;
+            last = now;
+        }
+        assert_eq!((rising_edges, toggles), (10, 10));
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't driven by a clock")]
+    fn unclocked_lines_have_no_edges() {
+        let mut c = Circuit::new();
+        let line = c.add_line(Line::Low);
+        c.run_to_edge(line, Edge::Rising);
+    }
+}
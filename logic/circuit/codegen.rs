@@ -0,0 +1,881 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitXor, Not};
+
+use circuit::circuit::Circuit;
+use circuit::gate::{Gate, GateKind};
+use circuit::netlist::{Netlist, Node};
+
+/// Memories with more words than this can't be turned into arrays in generated code.
+pub const MAX_MEMORY_WORDS: usize = 1 << 24;
+
+/// Language `generate_code` writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    C
+}
+
+/// Two-valued expression in the code generated for a gate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Constant(bool),
+    /// The current value of a line, with anything but high reading as false.
+    Line(usize),
+    /// Bit `i` of the gate's state, as listed in `GateCode::state`.
+    State(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Xor(Box<Expr>, Box<Expr>)
+}
+
+impl Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+}
+
+impl BitAnd for Expr {
+    type Output = Expr;
+
+    fn bitand(self, rhs: Expr) -> Expr {
+        Expr::And(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl BitOr for Expr {
+    type Output = Expr;
+
+    fn bitor(self, rhs: Expr) -> Expr {
+        Expr::Or(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl BitXor for Expr {
+    type Output = Expr;
+
+    fn bitxor(self, rhs: Expr) -> Expr {
+        Expr::Xor(Box::new(self), Box::new(rhs))
+    }
+}
+
+/// One step of the code generated for a gate. Statements run in order, each seeing the lines
+/// and state as the ones before it left them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+    SetLine(usize, Expr),
+    SetState(usize, Expr),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+    /// Stores `value`, least significant bit first, in the word of memory `memory` addressed by
+    /// the bits in `address`.
+    Write { memory: usize, address: Vec<Expr>, value: Vec<Expr> },
+    /// Sets the lines in `outputs` to the word of memory `memory` addressed by the bits in
+    /// `address`, least significant bit first.
+    Read { memory: usize, address: Vec<Expr>, outputs: Vec<usize> },
+    /// Copies every word of memory `from` to memory `to`, which must be the same size.
+    Copy { from: usize, to: usize }
+}
+
+/// How a gate that holds state is written out by `generate_code`: its state as it is now and
+/// the statements that make up one `Gate::eval`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GateCode {
+    pub state: Vec<bool>,
+    /// Current words of each memory, which become arrays of 64-bit words.
+    pub memories: Vec<Vec<u64>>,
+    pub body: Vec<Statement>
+}
+
+/// Whether the number held in `bits`, least significant first, equals `value`.
+pub(crate) fn equals(bits: &[Expr], value: u64) -> Expr {
+    if bits.len() < 64 && value >> bits.len() != 0 {
+        return Expr::Constant(false);
+    }
+    bits.iter().enumerate().fold(Expr::Constant(true), |all, (i, bit)| {
+        all & if value >> i & 1 == 1 { bit.clone() } else { !bit.clone() }
+    })
+}
+
+/// Whether the number held in `bits`, least significant first, is below `value`.
+pub(crate) fn less_than(bits: &[Expr], value: u64) -> Expr {
+    if bits.len() < 64 && value >> bits.len() != 0 {
+        return Expr::Constant(true);
+    }
+    bits.iter().enumerate().fold(Expr::Constant(false), |below, (i, bit)| {
+        if value >> i & 1 == 1 { !bit.clone() | below } else { !bit.clone() & below }
+    })
+}
+
+/// Statements adding one to the number held in state bits `bits`, least significant first, if
+/// `up` is true and subtracting one if not, wrapping around. State bit `carry` is overwritten.
+pub(crate) fn step(bits: &[usize], up: Expr, carry: usize) -> Vec<Statement> {
+    let mut body = vec![Statement::SetState(carry, Expr::Constant(true))];
+    for &bit in bits {
+        body.push(Statement::SetState(bit, Expr::State(bit) ^ Expr::State(carry)));
+        // A bit that just toggled passes the carry on if it wrapped, which leaves it unequal to
+        // `up`.
+        body.push(Statement::SetState(carry, Expr::State(carry) & (Expr::State(bit) ^ up.clone())));
+    }
+    body
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodegenError {
+    /// Gate `gate` has neither `Gate::code` nor a `Gate::lower` that translates into a netlist.
+    Unsupported { gate: usize }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodegenError::Unsupported { gate } => {
+                write!(f, "gate {} can't be expressed in generated code", gate)
+            }
+        }
+    }
+}
+
+impl Error for CodegenError {}
+
+// What generated code does for one gate.
+enum Part {
+    Primitive(usize, Expr),
+    Stateful(GateCode),
+    // A lowered gate's netlist and the lines of the gate's inputs and outputs.
+    Lowered(Netlist, Vec<usize>, Vec<usize>)
+}
+
+fn part(circuit: &Circuit, g: usize) -> Result<Option<Part>, CodegenError> {
+    let gate = &circuit.gates[g];
+    let input = |i: usize| Expr::Line(gate.get_input(i));
+    let binary = || (input(0), input(1));
+    let expr = match gate.kind() {
+        GateKind::Sink => return Ok(None),
+        GateKind::Constant(state) => Expr::Constant(state.is_high()),
+        GateKind::Not => !input(0),
+        GateKind::And => { let (a, b) = binary(); a & b },
+        GateKind::Or => { let (a, b) = binary(); a | b },
+        GateKind::Xor => { let (a, b) = binary(); a ^ b },
+        GateKind::Nand => { let (a, b) = binary(); !(a & b) },
+        GateKind::Nor => { let (a, b) = binary(); !(a | b) },
+        GateKind::Xnor => { let (a, b) = binary(); !(a ^ b) },
+        // An unconnected select leaves the output disconnected, which reads as low.
+        GateKind::Mux => if gate.get_input(2) == 0 {
+            Expr::Constant(false)
+        } else {
+            input(2) & input(1) | !input(2) & input(0)
+        },
+        GateKind::Other => return other_part(&**gate, g).map(Some)
+    };
+    Ok(Some(Part::Primitive(gate.get_output(0), expr)))
+}
+
+fn other_part(gate: &dyn Gate, g: usize) -> Result<Part, CodegenError> {
+    if let Some(code) = gate.code() {
+        if code.memories.iter().any(|memory| memory.len() > MAX_MEMORY_WORDS) {
+            return Err(CodegenError::Unsupported { gate: g });
+        }
+        return Ok(Part::Stateful(code));
+    }
+    let netlist = gate.lower().and_then(|lowered| Netlist::from_circuit(&lowered).ok())
+        .ok_or(CodegenError::Unsupported { gate: g })?;
+    let inputs = (0..gate.num_inputs()).map(|i| gate.get_input(i)).collect();
+    let outputs = (0..gate.num_outputs()).map(|o| gate.get_output(o)).collect();
+    Ok(Part::Lowered(netlist, inputs, outputs))
+}
+
+// Accumulates indented source text.
+struct Writer {
+    language: Language,
+    text: String,
+    indent: usize
+}
+
+impl Writer {
+    fn line(&mut self, line: &str) {
+        if line.starts_with('}') {
+            self.indent -= 1;
+        }
+        for _ in 0..self.indent {
+            self.text.push_str("    ");
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+        if line.ends_with('{') {
+            self.indent += 1;
+        }
+    }
+
+    fn rust(&self) -> bool {
+        self.language == Language::Rust
+    }
+
+    fn state(&self) -> &'static str {
+        if self.rust() { "s." } else { "s->" }
+    }
+
+    fn line_name(&self, line: usize) -> String {
+        format!("{}lines[{}]", self.state(), line)
+    }
+
+    fn expr(&self, expr: &Expr, g: usize) -> String {
+        match *expr {
+            Expr::Constant(value) => value.to_string(),
+            Expr::Line(line) => self.line_name(line),
+            Expr::State(i) => format!("{}g{}[{}]", self.state(), g, i),
+            Expr::Not(ref a) => format!("!{}", self.expr(a, g)),
+            Expr::And(ref a, ref b) => format!("({} & {})", self.expr(a, g), self.expr(b, g)),
+            Expr::Or(ref a, ref b) => format!("({} | {})", self.expr(a, g), self.expr(b, g)),
+            Expr::Xor(ref a, ref b) => format!("({} ^ {})", self.expr(a, g), self.expr(b, g))
+        }
+    }
+
+    // Integer built from `bits`, least significant first.
+    fn word(&self, bits: &[String], ty: &str) -> String {
+        if bits.is_empty() {
+            return "0".to_string();
+        }
+        let bits = bits.iter().enumerate().map(|(i, bit)| {
+            let bit = if self.rust() {
+                format!("({} as {})", bit, ty)
+            } else {
+                format!("(({}){})", ty, bit)
+            };
+            if i == 0 { bit } else { format!("{} << {}", bit, i) }
+        }).collect::<Vec<_>>();
+        format!("({})", bits.join(" | "))
+    }
+
+    fn address(&self, address: &[Expr], g: usize) -> String {
+        let bits = address.iter().map(|bit| self.expr(bit, g)).collect::<Vec<_>>();
+        let ty = if self.rust() { "usize" } else { "size_t" };
+        format!("{} a = {};", if self.rust() { "let" } else { "size_t" }, self.word(&bits, ty))
+    }
+
+    fn statement(&mut self, statement: &Statement, g: usize) {
+        let state = self.state();
+        match *statement {
+            Statement::SetLine(line, ref expr) => {
+                let text = format!("{} = {};", self.line_name(line), self.expr(expr, g));
+                self.line(&text);
+            },
+            Statement::SetState(i, ref expr) => {
+                let text = format!("{}g{}[{}] = {};", state, g, i, self.expr(expr, g));
+                self.line(&text);
+            },
+            Statement::If(ref condition, ref then, ref otherwise) => {
+                let condition = self.expr(condition, g);
+                let text = if self.rust() {
+                    format!("if {} {{", condition)
+                } else {
+                    format!("if ({}) {{", condition)
+                };
+                self.line(&text);
+                for statement in then {
+                    self.statement(statement, g);
+                }
+                if !otherwise.is_empty() {
+                    self.line("} else {");
+                    for statement in otherwise {
+                        self.statement(statement, g);
+                    }
+                }
+                self.line("}");
+            },
+            Statement::Write { memory, ref address, ref value } => {
+                self.line("{");
+                let text = self.address(address, g);
+                self.line(&text);
+                let bits = value.iter().map(|bit| self.expr(bit, g)).collect::<Vec<_>>();
+                let ty = if self.rust() { "u64" } else { "uint64_t" };
+                let text = format!("{}g{}_m{}[a] = {};", state, g, memory, self.word(&bits, ty));
+                self.line(&text);
+                self.line("}");
+            },
+            Statement::Read { memory, ref address, ref outputs } => {
+                self.line("{");
+                let text = self.address(address, g);
+                self.line(&text);
+                let text = format!("{} w = {}g{}_m{}[a];",
+                    if self.rust() { "let" } else { "uint64_t" }, state, g, memory);
+                self.line(&text);
+                for (i, &line) in outputs.iter().enumerate() {
+                    let text = format!("{} = (w >> {} & 1) != 0;", self.line_name(line), i);
+                    self.line(&text);
+                }
+                self.line("}");
+            },
+            Statement::Copy { from, to } => {
+                let text = if self.rust() {
+                    format!("s.g{}_m{}.copy_from_slice(&s.g{}_m{});", g, to, g, from)
+                } else {
+                    format!("memcpy(s->g{}_m{}, s->g{}_m{}, s->g{}_m{}_words * sizeof(uint64_t));",
+                        g, to, g, from, g, from)
+                };
+                self.line(&text);
+            }
+        }
+    }
+
+    // The netlist of a lowered gate as a block of temporaries.
+    fn lowered(&mut self, netlist: &Netlist, inputs: &[usize], outputs: &[usize]) {
+        self.line("{");
+        let node = |n: usize| format!("n{}", n);
+        for (n, &op) in netlist.nodes.iter().enumerate() {
+            let value = match op {
+                Node::Input(i) => self.line_name(inputs[i]),
+                Node::Constant(value) => value.to_string(),
+                Node::Not(a) => format!("!{}", node(a)),
+                Node::And(a, b) => format!("{} & {}", node(a), node(b)),
+                Node::Or(a, b) => format!("{} | {}", node(a), node(b)),
+                Node::Xor(a, b) => format!("{} ^ {}", node(a), node(b)),
+                Node::Mux { select, low, high } => {
+                    format!("({0} & {1}) | (!{0} & {2})", node(select), node(high), node(low))
+                }
+            };
+            let text = format!("{} n{} = {};", if self.rust() { "let" } else { "bool" }, n, value);
+            self.line(&text);
+        }
+        for (&(n, _), &line) in netlist.outputs.iter().zip(outputs) {
+            let text = format!("{} = n{};", self.line_name(line), n);
+            self.line(&text);
+        }
+        self.line("}");
+    }
+}
+
+// Lines, as Rust or C code, setting the clocks from the current time.
+fn clock_lines(circuit: &Circuit, state: &str) -> Vec<String> {
+    circuit.clocks.iter().map(|&(line, clock)| {
+        let (period, high, phase) = (clock.period(), clock.high_time(), clock.phase());
+        format!("{0}lines[{1}] = ({0}time % {2} + {2} - {3}) % {2} < {4};", state, line, period,
+            phase, high)
+    }).collect()
+}
+
+fn write_rust(w: &mut Writer, circuit: &Circuit, parts: &[(usize, Part)]) {
+    let (inputs, outputs) = (circuit.inputs.len(), circuit.outputs.len());
+    w.line(&format!("pub const INPUTS: usize = {};", inputs));
+    w.line(&format!("pub const OUTPUTS: usize = {};", outputs));
+    w.line("");
+    w.line("pub struct State {");
+    w.line(&format!("pub lines: [bool; {}],", circuit.lines.len()));
+    w.line("pub time: u64,");
+    for &(g, ref part) in parts {
+        if let Part::Stateful(ref code) = *part {
+            w.line(&format!("pub g{}: [bool; {}],", g, code.state.len()));
+            for m in 0..code.memories.len() {
+                w.line(&format!("pub g{}_m{}: Vec<u64>,", g, m));
+            }
+        }
+    }
+    w.line("}");
+    w.line("");
+    w.line("impl State {");
+    w.line("#[allow(unused_mut)]");
+    w.line("pub fn new() -> State {");
+    w.line("let mut s = State {");
+    w.line(&format!("lines: [false; {}],", circuit.lines.len()));
+    w.line(&format!("time: {},", circuit.time));
+    for &(g, ref part) in parts {
+        if let Part::Stateful(ref code) = *part {
+            let state = code.state.iter().map(|bit| bit.to_string()).collect::<Vec<_>>();
+            w.line(&format!("g{}: [{}],", g, state.join(", ")));
+            for (m, memory) in code.memories.iter().enumerate() {
+                w.line(&format!("g{}_m{}: vec![0; {}],", g, m, memory.len()));
+            }
+        }
+    }
+    w.line("};");
+    write_initial_values(w, circuit, parts);
+    w.line("s");
+    w.line("}");
+    w.line("}");
+    w.line("");
+    w.line("/// One pass over every gate in order.");
+    w.line("#[allow(unused_parens, unused_variables)]");
+    w.line(&format!("pub fn eval(s: &mut State, inputs: &[bool; {}]) -> [bool; {}] {{", inputs,
+        outputs));
+    write_eval(w, circuit, parts);
+    let outputs = circuit.outputs.iter().map(|&line| w.line_name(line)).collect::<Vec<_>>();
+    w.line(&format!("[{}]", outputs.join(", ")));
+    w.line("}");
+    w.line("");
+    w.line("/// Advances time by one tick, updates the clocks and evaluates.");
+    w.line(&format!("pub fn tick(s: &mut State, inputs: &[bool; {}]) -> [bool; {}] {{",
+        circuit.inputs.len(), circuit.outputs.len()));
+    w.line("s.time += 1;");
+    for line in clock_lines(circuit, "s.") {
+        w.line(&line);
+    }
+    w.line(&format!("let mut outputs = [false; {}];", circuit.outputs.len()));
+    w.line(&format!("for _ in 0..{} {{", circuit.passes_per_tick));
+    w.line("outputs = eval(s, inputs);");
+    w.line("}");
+    w.line("outputs");
+    w.line("}");
+}
+
+fn write_c(w: &mut Writer, circuit: &Circuit, parts: &[(usize, Part)]) {
+    w.line("#include <stdbool.h>");
+    w.line("#include <stddef.h>");
+    w.line("#include <stdint.h>");
+    w.line("#include <stdlib.h>");
+    w.line("#include <string.h>");
+    w.line("");
+    w.line(&format!("#define CIRCUIT_INPUTS {}", circuit.inputs.len()));
+    w.line(&format!("#define CIRCUIT_OUTPUTS {}", circuit.outputs.len()));
+    w.line("");
+    w.line("struct circuit_state {");
+    w.line(&format!("bool lines[{}];", circuit.lines.len()));
+    w.line("uint64_t time;");
+    for &(g, ref part) in parts {
+        if let Part::Stateful(ref code) = *part {
+            w.line(&format!("bool g{}[{}];", g, code.state.len().max(1)));
+            for m in 0..code.memories.len() {
+                w.line(&format!("uint64_t *g{}_m{};", g, m));
+                w.line(&format!("size_t g{}_m{}_words;", g, m));
+            }
+        }
+    }
+    w.line("};");
+    w.line("");
+    w.line("/* Returns false if a memory can't be allocated. Call circuit_free either way. */");
+    w.line("bool circuit_init(struct circuit_state *s) {");
+    w.line("memset(s, 0, sizeof *s);");
+    w.line(&format!("s->time = {};", circuit.time));
+    for &(g, ref part) in parts {
+        if let Part::Stateful(ref code) = *part {
+            for (m, memory) in code.memories.iter().enumerate() {
+                w.line(&format!("s->g{}_m{}_words = {};", g, m, memory.len()));
+                w.line(&format!("s->g{0}_m{1} = calloc({2}, sizeof(uint64_t));", g, m,
+                    memory.len()));
+                w.line(&format!("if (!s->g{}_m{}) {{", g, m));
+                w.line("return false;");
+                w.line("}");
+            }
+        }
+    }
+    write_initial_values(w, circuit, parts);
+    w.line("return true;");
+    w.line("}");
+    w.line("");
+    w.line("void circuit_free(struct circuit_state *s) {");
+    w.line("(void)s;");
+    for &(g, ref part) in parts {
+        if let Part::Stateful(ref code) = *part {
+            for m in 0..code.memories.len() {
+                w.line(&format!("free(s->g{}_m{});", g, m));
+            }
+        }
+    }
+    w.line("}");
+    w.line("");
+    w.line("/* One pass over every gate in order. */");
+    w.line("void circuit_eval(struct circuit_state *s, const bool *inputs, bool *outputs) {");
+    write_eval(w, circuit, parts);
+    for (o, &line) in circuit.outputs.iter().enumerate() {
+        let text = format!("outputs[{}] = {};", o, w.line_name(line));
+        w.line(&text);
+    }
+    w.line("}");
+    w.line("");
+    w.line("/* Advances time by one tick, updates the clocks and evaluates. */");
+    w.line("void circuit_tick(struct circuit_state *s, const bool *inputs, bool *outputs) {");
+    w.line("s->time += 1;");
+    for line in clock_lines(circuit, "s->") {
+        w.line(&line);
+    }
+    w.line(&format!("for (int pass = 0; pass < {}; pass++) {{", circuit.passes_per_tick));
+    w.line("circuit_eval(s, inputs, outputs);");
+    w.line("}");
+    w.line("}");
+}
+
+// Lines, gate state and memory words that don't start out as zero.
+fn write_initial_values(w: &mut Writer, circuit: &Circuit, parts: &[(usize, Part)]) {
+    let state = w.state();
+    let suffix = if w.rust() { "" } else { "ULL" };
+    for (line, value) in circuit.lines.iter().enumerate() {
+        if value.is_high() {
+            w.line(&format!("{}lines[{}] = true;", state, line));
+        }
+    }
+    for &(g, ref part) in parts {
+        if let Part::Stateful(ref code) = *part {
+            if !w.rust() {
+                for (i, _) in code.state.iter().enumerate().filter(|&(_, &bit)| bit) {
+                    w.line(&format!("s->g{}[{}] = true;", g, i));
+                }
+            }
+            for (m, memory) in code.memories.iter().enumerate() {
+                for (address, &word) in memory.iter().enumerate().filter(|&(_, &word)| word != 0) {
+                    w.line(&format!("{}g{}_m{}[{}] = {:#x}{};", state, g, m, address, word,
+                        suffix));
+                }
+            }
+        }
+    }
+}
+
+fn write_eval(w: &mut Writer, circuit: &Circuit, parts: &[(usize, Part)]) {
+    for (i, &line) in circuit.inputs.iter().enumerate() {
+        let text = format!("{} = inputs[{}];", w.line_name(line), i);
+        w.line(&text);
+    }
+    for &(g, ref part) in parts {
+        match *part {
+            Part::Primitive(line, ref expr) => {
+                w.statement(&Statement::SetLine(line, expr.clone()), g);
+            },
+            Part::Stateful(ref code) => for statement in &code.body {
+                w.statement(statement, g);
+            },
+            Part::Lowered(ref netlist, ref inputs, ref outputs) => {
+                w.lowered(netlist, inputs, outputs);
+            }
+        }
+    }
+}
+
+/// Writes `circuit` out as standalone Rust or C source, for simulations too long to run through
+/// `Circuit::eval`.
+///
+/// The Rust version declares a `State` holding every line, the time and the state of each
+/// stateful gate, with `State::new` giving the circuit's current state. `eval` takes the circuit
+/// inputs in order, runs one pass over the gates and returns the circuit outputs, and `tick`
+/// does the same as `Circuit::tick`. The C version has `struct circuit_state` with
+/// `circuit_init`, `circuit_free`, `circuit_eval` and `circuit_tick` to match. Memories become
+/// arrays of 64-bit words.
+///
+/// Gates are evaluated in order, as in `EvalMode::Immediate`, whatever the circuit's mode. Lines
+/// hold two values, so a disconnected line reads as low, although unconnected pins keep their
+/// meaning, such as a flip-flop's enable. Gates of kind `Other` need either `Gate::code` or
+/// `Gate::lower`.
+pub fn generate_code(circuit: &Circuit, language: Language) -> Result<String, CodegenError> {
+    let mut parts = Vec::new();
+    for g in 0..circuit.gates.len() {
+        if let Some(part) = part(circuit, g)? {
+            parts.push((g, part));
+        }
+    }
+    let mut w = Writer {
+        language,
+        text: String::new(),
+        indent: 0
+    };
+    let comment = format!("Generated from a circuit of {} gates and {} lines.",
+        circuit.gates.len(), circuit.lines.len());
+    w.line(&if language == Language::Rust {
+        format!("// {}", comment)
+    } else {
+        format!("/* {} */", comment)
+    });
+    w.line("");
+    match language {
+        Language::Rust => write_rust(&mut w, circuit, &parts),
+        Language::C => write_c(&mut w, circuit, &parts)
+    }
+    Ok(w.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process::{self, Command};
+
+    use circuit::clock::Clock;
+    use circuit::fifo::{Fifo, Stack};
+    use circuit::gate::{PortDirection, PortRole};
+    use circuit::gate::tests::{Rng, random_logic};
+    use circuit::line::Line;
+    use circuit::memory::{DFlipFlop, DualPortRam, Edge, RegisterFile, Rom};
+    use circuit::sequential::{Counter, Lfsr, Register, ShiftRegister};
+    use super::*;
+
+    // Each step is "t" for a tick or "e" for a pass, followed by the circuit inputs as 0s and 1s.
+    fn random_steps(rng: &mut Rng, inputs: usize, steps: usize, tick: bool) -> Vec<String> {
+        (0..steps).map(|_| {
+            let mut step = if tick { "t" } else { "e" }.to_string();
+            step.extend((0..inputs).map(|_| if rng.below(2) == 1 { '1' } else { '0' }));
+            step
+        }).collect()
+    }
+
+    // The circuit outputs after each step, one line of 0s and 1s per step.
+    fn simulate(circuit: &mut Circuit, steps: &[String]) -> String {
+        let mut trace = String::new();
+        for step in steps {
+            for (i, bit) in step[1..].chars().enumerate() {
+                circuit.set_circuit_input(i, Line::from(bit == '1'));
+            }
+            if step.starts_with('t') {
+                circuit.tick();
+            } else {
+                circuit.eval();
+            }
+            trace.extend((0..circuit.outputs.len())
+                .map(|o| if circuit.get_circuit_output(o).is_high() { '1' } else { '0' }));
+            trace.push('\n');
+        }
+        trace
+    }
+
+    const RUST_MAIN: &str = "
+fn main() {
+    let mut s = State::new();
+    for step in STEPS.lines() {
+        let mut inputs = [false; INPUTS];
+        for (input, bit) in inputs.iter_mut().zip(step[1..].chars()) {
+            *input = bit == '1';
+        }
+        let outputs = if step.starts_with('t') {
+            tick(&mut s, &inputs)
+        } else {
+            eval(&mut s, &inputs)
+        };
+        let outputs = outputs.iter().map(|&bit| if bit { '1' } else { '0' });
+        println!(\"{}\", outputs.collect::<String>());
+    }
+}
+";
+
+    const C_MAIN: &str = "
+#include <stdio.h>
+
+int main(void) {
+    struct circuit_state *s = malloc(sizeof *s);
+    bool inputs[CIRCUIT_INPUTS + 1], outputs[CIRCUIT_OUTPUTS + 1];
+    if (!s || !circuit_init(s)) {
+        return 1;
+    }
+    for (const char *step = STEPS; *step; step += CIRCUIT_INPUTS + 2) {
+        for (int i = 0; i < CIRCUIT_INPUTS; i++) {
+            inputs[i] = step[1 + i] == '1';
+        }
+        if (*step == 't') {
+            circuit_tick(s, inputs, outputs);
+        } else {
+            circuit_eval(s, inputs, outputs);
+        }
+        for (int o = 0; o < CIRCUIT_OUTPUTS; o++) {
+            putchar(outputs[o] ? '1' : '0');
+        }
+        putchar('\\n');
+    }
+    circuit_free(s);
+    free(s);
+    return 0;
+}
+";
+
+    // Compiles the code generated for `circuit` with a main function that runs `steps`, and
+    // returns what it prints. A missing compiler fails the test rather than skipping it, as
+    // `$RUSTC` or `rustc`, and `cc`, have to be on the path.
+    fn run_generated(circuit: &Circuit, language: Language, steps: &[String], name: &str)
+        -> String {
+        let dir = env::temp_dir().join(format!("logic-codegen-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let steps = steps.iter().map(|step| format!("{}\n", step)).collect::<String>();
+        let mut source = generate_code(circuit, language).unwrap();
+        let mut compiler = match language {
+            Language::Rust => {
+                source.push_str(&format!("\nconst STEPS: &str = {:?};\n{}", steps, RUST_MAIN));
+                fs::write(dir.join("main.rs"), &source).unwrap();
+                let mut rustc = Command::new(env::var("RUSTC").unwrap_or("rustc".to_string()));
+                rustc.args(["--edition", "2015", "-D", "warnings"]).arg(dir.join("main.rs"));
+                rustc
+            },
+            Language::C => {
+                source.push_str(&format!("\nstatic const char *STEPS = {:?};\n{}", steps, C_MAIN));
+                fs::write(dir.join("main.c"), &source).unwrap();
+                let mut cc = Command::new("cc");
+                cc.arg("-std=c99").arg(dir.join("main.c"));
+                cc
+            }
+        };
+        let compiled = compiler.arg("-o").arg(dir.join("main")).output();
+        let compiled = compiled.unwrap_or_else(|e| {
+            panic!("Couldn't start the {:?} compiler to check generated code: {}", language, e)
+        });
+        assert!(compiled.status.success(), "{:?} code doesn't compile:\n{}", language,
+            String::from_utf8_lossy(&compiled.stderr));
+        let run = Command::new(dir.join("main")).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(run.status.success());
+        String::from_utf8(run.stdout).unwrap()
+    }
+
+    // Checks that the generated Rust and C agree with the circuit over `steps`, starting from
+    // the circuit's current state.
+    fn assert_generated_matches(circuit: &Circuit, steps: &[String], name: &str) {
+        let expected = simulate(&mut circuit.clone(), steps);
+        for &language in &[Language::Rust, Language::C] {
+            let name = format!("{}-{:?}", name, language);
+            let trace = run_generated(circuit, language, steps, &name);
+            for (step, (got, want)) in trace.lines().zip(expected.lines()).enumerate() {
+                assert_eq!(got, want, "{} differs at step {}", name, step);
+            }
+            assert_eq!(trace.lines().count(), steps.len());
+        }
+    }
+
+    // Adds `gate` with its clock pin on `clock` and every other input on a random circuit input
+    // of `inputs`, and makes its outputs circuit outputs.
+    fn add_driven<G: Gate + 'static>(circuit: &mut Circuit, gate: G, inputs: &[usize],
+        clock: usize, rng: &mut Rng) {
+        let g = circuit.add_gate(gate);
+        for port in circuit.get_gate_ports(g) {
+            for pin in port.first..port.first + port.width {
+                match (port.direction, port.role) {
+                    (PortDirection::Input, PortRole::Clock) => {
+                        circuit.set_gate_input(g, pin, clock);
+                    },
+                    (PortDirection::Input, _) => {
+                        let line = inputs[rng.below(inputs.len() as u64) as usize];
+                        circuit.set_gate_input(g, pin, line);
+                    },
+                    (PortDirection::Output, _) => {
+                        let line = circuit.get_gate_output(g, pin);
+                        circuit.mark_line_as_circuit_output(line);
+                    }
+                }
+            }
+        }
+    }
+
+    // Every gate with hand-written code, at small and edge widths, fed from 12 inputs and two
+    // clocks.
+    fn sequential(rng: &mut Rng) -> Circuit {
+        let mut c = Circuit::new();
+        let inputs = (0..12).map(|_| {
+            let line = c.add_line(Line::Low);
+            c.mark_line_as_circuit_input(line);
+            line
+        }).collect::<Vec<_>>();
+        let fast = c.add_clock(Clock::new(2));
+        let slow = c.add_clock(Clock::new(6).with_high_time(1).with_phase(2));
+        add_driven(&mut c, Register::new(0), &inputs, fast, rng);
+        add_driven(&mut c, Register::new(65), &inputs, slow, rng);
+        for &width in &[1, 3, 64] {
+            add_driven(&mut c, Counter::new(width), &inputs, fast, rng);
+        }
+        add_driven(&mut c, ShiftRegister::new(1), &inputs, fast, rng);
+        add_driven(&mut c, ShiftRegister::new(65), &inputs, slow, rng);
+        add_driven(&mut c, Lfsr::new(5, &[4, 2]), &inputs, fast, rng);
+        add_driven(&mut c, Lfsr::new(64, &[63, 62, 60, 59]), &inputs, slow, rng);
+        for &(capacity, width) in &[(1, 1), (3, 2), (4, 64), (5, 3)] {
+            add_driven(&mut c, Fifo::new(capacity, width), &inputs, fast, rng);
+            add_driven(&mut c, Stack::new(capacity, width), &inputs, slow, rng);
+        }
+        add_driven(&mut c, RegisterFile::new(3, 4), &inputs, fast, rng);
+        add_driven(&mut c, RegisterFile::new(4, 64), &inputs, slow, rng);
+        let contents = (0..100).map(|_| rng.next()).collect::<Vec<_>>();
+        add_driven(&mut c, Rom::with_contents(13, 64, 2, &contents), &inputs, fast, rng);
+        add_driven(&mut c, Rom::with_contents(0, 5, 1, &contents[..1]), &inputs, fast, rng);
+        add_driven(&mut c, DualPortRam::new(3, 5), &inputs, slow, rng);
+        add_driven(&mut c, DFlipFlop::new(Edge::Falling), &inputs, fast, rng);
+        c.set_passes_per_tick(2);
+        c
+    }
+
+    #[test]
+    fn stateful_gates_match_the_circuit() {
+        let mut rng = Rng(48);
+        let mut circuit = sequential(&mut rng);
+        // Run for a while first, so that the generated code starts from state that isn't zero.
+        let warm_up = random_steps(&mut rng, 12, 40, true);
+        simulate(&mut circuit, &warm_up);
+        let mut steps = random_steps(&mut rng, 12, 150, true);
+        steps.extend(random_steps(&mut rng, 12, 5, false));
+        assert_generated_matches(&circuit, &steps, "stateful");
+    }
+
+    #[test]
+    fn logic_matches_the_circuit() {
+        let mut rng = Rng(480);
+        for &width in &[0, 1, 64, 65] {
+            let circuit = random_logic(width as u64, width, 60, 3, width % 2 == 1);
+            let steps = random_steps(&mut rng, width, 20, false);
+            assert_generated_matches(&circuit, &steps, &format!("logic{}", width));
+        }
+    }
+
+    #[test]
+    fn large_memories_are_arrays() {
+        let mut circuit = Circuit::new();
+        let rom = circuit.add_gate(Rom::new(13, 8, 1));
+        let rust = generate_code(&circuit, Language::Rust).unwrap();
+        assert!(rust.contains(&format!("pub g{}_m0: Vec<u64>,", rom)));
+        assert!(rust.contains(&format!("g{}_m0: vec![0; 8192],", rom)));
+        let rom = circuit.add_gate(Rom::new(25, 1, 1));
+        assert_eq!(generate_code(&circuit, Language::C),
+            Err(CodegenError::Unsupported { gate: rom }));
+    }
+
+    #[test]
+    fn ticks_run_every_pass() {
+        let mut circuit = random_logic(1, 2, 4, 1, false);
+        circuit.set_passes_per_tick(3);
+        let rust = generate_code(&circuit, Language::Rust).unwrap();
+        let c = generate_code(&circuit, Language::C).unwrap();
+        assert!(rust.contains("for _ in 0..3 {"));
+        assert!(c.contains("for (int pass = 0; pass < 3; pass++) {"));
+    }
+
+    #[test]
+    fn comparisons_on_state() {
+        let bits = (0..3).map(Expr::State).collect::<Vec<_>>();
+        for value in 0..8u64 {
+            for limit in 0..10 {
+                let mut code = GateCode {
+                    state: (0..5).map(|bit| bit < 3 && value >> bit & 1 == 1).collect(),
+                    memories: Vec::new(),
+                    body: vec![
+                        Statement::SetState(3, equals(&bits, limit)),
+                        Statement::SetState(4, less_than(&bits, limit))
+                    ]
+                };
+                run(&mut code);
+                assert_eq!(code.state[3], value == limit);
+                assert_eq!(code.state[4], value < limit);
+            }
+            for &up in &[true, false] {
+                let mut code = GateCode {
+                    state: (0..4).map(|bit| bit < 3 && value >> bit & 1 == 1).collect(),
+                    memories: Vec::new(),
+                    body: step(&[0, 1, 2], Expr::Constant(up), 3)
+                };
+                run(&mut code);
+                let next = if up { value + 1 } else { value + 7 } % 8;
+                assert_eq!(code.state[..3], (0..3).map(|bit| next >> bit & 1 == 1)
+                    .collect::<Vec<_>>()[..]);
+            }
+        }
+    }
+
+    // Runs a body of state assignments with no lines.
+    fn run(code: &mut GateCode) {
+        fn value(expr: &Expr, state: &[bool]) -> bool {
+            match *expr {
+                Expr::Constant(value) => value,
+                Expr::State(i) => state[i],
+                Expr::Not(ref a) => !value(a, state),
+                Expr::And(ref a, ref b) => value(a, state) & value(b, state),
+                Expr::Or(ref a, ref b) => value(a, state) | value(b, state),
+                Expr::Xor(ref a, ref b) => value(a, state) ^ value(b, state),
+                Expr::Line(_) => unreachable!()
+            }
+        }
+        for statement in &code.body {
+            match *statement {
+                Statement::SetState(i, ref expr) => code.state[i] = value(expr, &code.state),
+                _ => unreachable!()
+            }
+        }
+    }
+}
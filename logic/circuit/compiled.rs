@@ -0,0 +1,332 @@
+use std::mem;
+
+use circuit::circuit::{Circuit, EvalMode};
+use circuit::gate::GateKind;
+use circuit::line::{Line, and, or, xor, nand, nor, xnor};
+
+// Every opcode but `Gate` indexes a table of results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Opcode {
+    High,
+    Low,
+    Disconnected,
+    Not,
+    And,
+    Or,
+    Xor,
+    Nand,
+    Nor,
+    Xnor,
+    // Inputs are low, high and select, as for `Mux1_2`.
+    Mux,
+    // Calls `Gate::eval` on the gate whose index is in `output`.
+    Gate
+}
+
+const LINES: [Line; 3] = [Line::High, Line::Low, Line::Disconnected];
+
+// Result of each opcode for every combination of up to three inputs, indexed by
+// `a + 3 * b + 9 * c` where each input counts as its position in `LINES`. Looking values up
+// rather than matching on them keeps the interpreter loop free of data-dependent branches.
+fn tables() -> Vec<[Line; 27]> {
+    let opcodes = [Opcode::High, Opcode::Low, Opcode::Disconnected, Opcode::Not, Opcode::And,
+        Opcode::Or, Opcode::Xor, Opcode::Nand, Opcode::Nor, Opcode::Xnor, Opcode::Mux];
+    opcodes.iter().map(|&opcode| {
+        let mut table = [Line::Disconnected; 27];
+        for (index, entry) in table.iter_mut().enumerate() {
+            let (a, b, c) = (LINES[index % 3], LINES[index / 3 % 3], LINES[index / 9]);
+            *entry = match opcode {
+                Opcode::High => Line::High,
+                Opcode::Low => Line::Low,
+                Opcode::Disconnected | Opcode::Gate => Line::Disconnected,
+                Opcode::Not => !a,
+                Opcode::And => and(a, b),
+                Opcode::Or => or(a, b),
+                Opcode::Xor => xor(a, b),
+                Opcode::Nand => nand(a, b),
+                Opcode::Nor => nor(a, b),
+                Opcode::Xnor => xnor(a, b),
+                Opcode::Mux => match c {
+                    Line::High => b,
+                    Line::Low => a,
+                    Line::Disconnected => Line::Disconnected
+                }
+            };
+        }
+        table
+    }).collect()
+}
+
+// Position of `line` in `LINES`.
+fn index(line: Line) -> usize {
+    match line {
+        Line::High => 0,
+        Line::Low => 1,
+        Line::Disconnected => 2
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Instruction {
+    opcode: Opcode,
+    inputs: [u32; 3],
+    output: u32
+}
+
+/// A circuit translated into a flat list of instructions, one per gate, for faster evaluation.
+///
+/// `Circuit::eval` makes a virtual call per gate per pass. Here the gates that `Gate::kind`
+/// identifies as primitive become instructions holding their line indices, which a single
+/// loop evaluates by table lookup without going through the gate at all. Every other gate is
+/// still called through `Gate::eval`, in its place in the order. Results match `Circuit::eval`
+/// in either `EvalMode`, disconnected lines included.
+///
+/// The circuit's structure is fixed while compiled, but its lines can be driven and read as
+/// usual. `into_circuit` gives it back.
+pub struct CompiledCircuit {
+    circuit: Circuit,
+    program: Vec<Instruction>,
+    tables: Vec<[Line; 27]>,
+    next_lines: Vec<Line>
+}
+
+impl CompiledCircuit {
+    pub(crate) fn new(circuit: Circuit) -> Self {
+        let program = circuit.gates.iter().enumerate().map(|(g, gate)| {
+            let opcode = match gate.kind() {
+                GateKind::Constant(Line::High) => Opcode::High,
+                GateKind::Constant(Line::Low) => Opcode::Low,
+                GateKind::Constant(Line::Disconnected) => Opcode::Disconnected,
+                GateKind::Not => Opcode::Not,
+                GateKind::And => Opcode::And,
+                GateKind::Or => Opcode::Or,
+                GateKind::Xor => Opcode::Xor,
+                GateKind::Nand => Opcode::Nand,
+                GateKind::Nor => Opcode::Nor,
+                GateKind::Xnor => Opcode::Xnor,
+                GateKind::Mux => Opcode::Mux,
+                GateKind::Sink | GateKind::Other => Opcode::Gate
+            };
+            let mut inputs = [0; 3];
+            if opcode != Opcode::Gate {
+                for (i, input) in inputs.iter_mut().enumerate().take(gate.num_inputs()) {
+                    *input = gate.get_input(i) as u32;
+                }
+            }
+            let output = if opcode == Opcode::Gate { g } else { gate.get_output(0) };
+            Instruction { opcode, inputs, output: output as u32 }
+        }).collect();
+        CompiledCircuit {
+            circuit,
+            program,
+            tables: tables(),
+            next_lines: Vec::new()
+        }
+    }
+
+    pub(crate) fn into_circuit(self) -> Circuit {
+        self.circuit
+    }
+
+    pub(crate) fn circuit(&self) -> &Circuit {
+        &self.circuit
+    }
+
+    /// Number of gates still evaluated through `Gate::eval`.
+    pub(crate) fn num_fallbacks(&self) -> usize {
+        self.program.iter().filter(|instruction| instruction.opcode == Opcode::Gate).count()
+    }
+
+    pub(crate) fn set_line(&mut self, line: usize, state: Line) {
+        self.circuit.set_line(line, state);
+    }
+
+    pub(crate) fn get_line_state(&self, line: usize) -> Line {
+        self.circuit.get_line_state(line)
+    }
+
+    pub(crate) fn set_circuit_input(&mut self, input: usize, state: Line) {
+        self.circuit.set_circuit_input(input, state);
+    }
+
+    pub(crate) fn get_circuit_input(&self, input: usize) -> Line {
+        self.circuit.get_circuit_input(input)
+    }
+
+    pub(crate) fn get_circuit_output(&self, output: usize) -> Line {
+        self.circuit.get_circuit_output(output)
+    }
+
+    fn execute(&mut self, instruction: Instruction) {
+        let lines = &mut self.circuit.lines;
+        if instruction.opcode == Opcode::Gate {
+            self.circuit.gates[instruction.output as usize].eval(lines);
+            return;
+        }
+        let [a, b, c] = instruction.inputs;
+        let entry = index(lines[a as usize]) + 3 * index(lines[b as usize])
+            + 9 * index(lines[c as usize]);
+        lines[instruction.output as usize] = self.tables[instruction.opcode as usize][entry];
+    }
+
+    pub(crate) fn eval(&mut self) {
+        match self.circuit.mode {
+            EvalMode::Immediate => for i in 0..self.program.len() {
+                let instruction = self.program[i];
+                self.execute(instruction);
+            },
+            EvalMode::Synchronous => self.eval_synchronous()
+        }
+    }
+
+    // As `Circuit::eval_synchronous`, swapping each instruction's outputs into the next-state
+    // buffer as soon as it has run.
+    fn eval_synchronous(&mut self) {
+        self.next_lines.clone_from(&self.circuit.lines);
+        for i in 0..self.program.len() {
+            let instruction = self.program[i];
+            self.execute(instruction);
+            if instruction.opcode == Opcode::Gate {
+                let gate = &self.circuit.gates[instruction.output as usize];
+                for o in 0..gate.num_outputs() {
+                    let line = gate.get_output(o);
+                    mem::swap(&mut self.circuit.lines[line], &mut self.next_lines[line]);
+                }
+            } else {
+                let line = instruction.output as usize;
+                mem::swap(&mut self.circuit.lines[line], &mut self.next_lines[line]);
+            }
+        }
+        mem::swap(&mut self.circuit.lines, &mut self.next_lines);
+    }
+
+    pub(crate) fn eval_n_passes(&mut self, passes: usize) {
+        for _ in 0..passes {
+            self.eval();
+        }
+    }
+
+    /// As `Circuit::tick`.
+    pub(crate) fn tick(&mut self) {
+        self.circuit.time += 1;
+        for &(line, clock) in &self.circuit.clocks {
+            self.circuit.lines[line] = clock.level_at(self.circuit.time);
+        }
+        let passes = self.circuit.passes_per_tick;
+        self.eval_n_passes(passes);
+    }
+
+    pub(crate) fn advance(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+}
+
+impl From<Circuit> for CompiledCircuit {
+    fn from(circuit: Circuit) -> Self {
+        CompiledCircuit::new(circuit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Sink;
+    use circuit::clock::Clock;
+    use circuit::gate::tests::{Rng, random_logic};
+    use circuit::memory::{DFlipFlop, Edge};
+    use super::*;
+
+    fn random_state(rng: &mut Rng) -> Line {
+        LINES[rng.below(3) as usize]
+    }
+
+    // Random logic whose first output feeds a flip-flop clocked by a clock line, and whose last
+    // output goes into a sink, so that some gates fall back to `Gate::eval`.
+    fn with_state(seed: u64, inputs: usize) -> Circuit {
+        let mut circuit = random_logic(seed, inputs, 40, 2, false);
+        let clock = circuit.add_clock(Clock::new(4));
+        let ff = circuit.add_gate(DFlipFlop::new(Edge::Rising));
+        let (d, last) = (circuit.outputs[0], circuit.outputs[1]);
+        circuit.set_gate_input(ff, 0, d);
+        circuit.set_gate_input(ff, 1, clock);
+        let q = circuit.get_gate_output(ff, 0);
+        circuit.mark_line_as_circuit_output(q);
+        let sink = circuit.add_gate(Sink::new());
+        circuit.set_gate_input(sink, 0, last);
+        circuit
+    }
+
+    #[test]
+    fn compiled_matches_interpreted() {
+        let mut rng = Rng(47);
+        for &width in &[0, 1, 8, 64, 65] {
+            for &mode in &[EvalMode::Immediate, EvalMode::Synchronous] {
+                let mut circuit = with_state(width as u64 + 1, width);
+                circuit.set_eval_mode(mode);
+                let mut compiled = CompiledCircuit::new(circuit.clone());
+                assert_eq!(compiled.num_fallbacks(), 2);
+                for _ in 0..30 {
+                    for i in 0..width {
+                        let state = random_state(&mut rng);
+                        circuit.set_circuit_input(i, state);
+                        compiled.set_circuit_input(i, state);
+                        assert_eq!(compiled.get_circuit_input(i), state);
+                    }
+                    let passes = rng.below(3) as usize;
+                    circuit.eval_n_passes(passes);
+                    compiled.eval_n_passes(passes);
+                    assert_eq!(compiled.circuit().lines, circuit.lines);
+                    circuit.tick();
+                    compiled.tick();
+                    assert_eq!(compiled.circuit().lines, circuit.lines);
+                    for o in 0..3 {
+                        assert_eq!(compiled.get_circuit_output(o), circuit.get_circuit_output(o));
+                    }
+                }
+                circuit.set_passes_per_tick(3);
+                let mut compiled = CompiledCircuit::from(circuit.clone());
+                circuit.advance(9);
+                compiled.advance(9);
+                let compiled = compiled.into_circuit();
+                assert_eq!(compiled.get_time(), circuit.get_time());
+                assert_eq!(compiled.lines, circuit.lines);
+            }
+        }
+    }
+
+    #[test]
+    fn every_primitive_is_compiled() {
+        let circuit = random_logic(3, 4, 200, 1, false);
+        let mut compiled = CompiledCircuit::new(circuit.clone());
+        assert_eq!(compiled.num_fallbacks(), 0);
+        let mut circuit = circuit;
+        for line in 1..circuit.lines.len() {
+            circuit.set_line(line, Line::Disconnected);
+            compiled.set_line(line, Line::Disconnected);
+        }
+        circuit.eval();
+        compiled.eval();
+        assert_eq!(compiled.get_line_state(circuit.outputs[0]),
+            circuit.get_line_state(circuit.outputs[0]));
+        assert_eq!(compiled.circuit().lines, circuit.lines);
+    }
+
+    #[test]
+    fn tables_follow_line_operators() {
+        let tables = tables();
+        for (i, &a) in LINES.iter().enumerate() {
+            for (j, &b) in LINES.iter().enumerate() {
+                let entry = i + 3 * j;
+                assert_eq!(tables[Opcode::Not as usize][entry], !a);
+                assert_eq!(tables[Opcode::Nand as usize][entry], nand(a, b));
+                assert_eq!(tables[Opcode::Mux as usize][entry + 9 * index(Line::High)], b);
+                assert_eq!(tables[Opcode::Mux as usize][entry + 9 * index(Line::Low)], a);
+                assert_eq!(tables[Opcode::Mux as usize][entry + 9 * index(Line::Disconnected)],
+                    Line::Disconnected);
+                assert_eq!(tables[Opcode::High as usize][entry], Line::High);
+            }
+            assert_eq!(LINES[index(a)], a);
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use circuit::{gate::{Gate, Port, PortRole, lower_with}, line::Line};
+use circuit::circuit::Circuit;
+use circuit::bus::decode;
+use circuit::basics::Inverter;
+use circuit::simplegate::{SimpleGate, make_and_tree, make_or_tree};
+
+fn encoded_bits(lines: usize) -> usize {
+    assert!(lines >= 2 && lines.is_power_of_two(), "Number of lines must be a power of two >= 2.");
+    lines.trailing_zeros() as usize
+}
+
+/// Binary decoder from `bits` select lines to `2^bits` outputs. Output `i` is high while the
+/// select lines hold `i` and enable is high.
+///
+/// Spec:
+/// 0..bits: select, least significant bit first
+/// bits: enable
+///
+/// Disconnected inputs count as low. See `make_decoder` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct Decoder {
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl Decoder {
+    pub(crate) fn new(bits: usize) -> Self {
+        assert!(bits >= 1, "A decoder needs at least one select line.");
+        Decoder {
+            inputs: vec![0; bits + 1],
+            outputs: vec![0; 1 << bits]
+        }
+    }
+}
+
+impl Gate for Decoder {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let bits = self.inputs.len() - 1;
+        vec![
+            Port::input("select", 0, bits).with_role(PortRole::Select),
+            Port::input("enable", bits, 1).with_role(PortRole::Enable),
+            Port::output("out", 0, self.outputs.len())
+        ]
+    }
+
+    fn lower(&self) -> Option<Circuit> {
+        let bits = self.inputs.len() - 1;
+        Some(lower_with(self.inputs.len(), |lines, circuit| {
+            make_decoder(&lines[..bits], lines[bits], circuit)
+        }))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let bits = self.inputs.len() - 1;
+        let enable = lines[self.inputs[bits]].is_high();
+        let which = decode(lines, &self.inputs[..bits]);
+        for (i, &o) in self.outputs.iter().enumerate() {
+            lines[o] = Line::from(enable && i == which);
+        }
+    }
+}
+
+/// Priority encoder from `2^bits` request lines to the `bits`-bit index of the highest request
+/// that is high, followed by a valid output that is high when any request is.
+///
+/// Spec:
+/// 0..2^bits: requests
+///
+/// Outputs `0..bits` hold the index, least significant bit first, and output `bits` is valid.
+/// See `make_priority_encoder` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct PriorityEncoder {
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl PriorityEncoder {
+    pub(crate) fn new(bits: usize) -> Self {
+        assert!(bits >= 1, "A priority encoder needs at least one output bit.");
+        PriorityEncoder {
+            inputs: vec![0; 1 << bits],
+            outputs: vec![0; bits + 1]
+        }
+    }
+}
+
+impl Gate for PriorityEncoder {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let bits = self.outputs.len() - 1;
+        vec![
+            Port::input("requests", 0, self.inputs.len()),
+            Port::output("index", 0, bits),
+            Port::output("valid", bits, 1)
+        ]
+    }
+
+    fn lower(&self) -> Option<Circuit> {
+        Some(lower_with(self.inputs.len(), make_priority_encoder))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let bits = self.outputs.len() - 1;
+        let highest = self.inputs.iter().rposition(|&i| lines[i].is_high());
+        let which = highest.unwrap_or(0);
+        for b in 0..bits {
+            lines[self.outputs[b]] = Line::from(which >> b & 1 == 1);
+        }
+        lines[self.outputs[bits]] = Line::from(highest.is_some());
+    }
+}
+
+/// Encoder from `2^bits` one-hot lines to a `bits`-bit index. Each output bit is the OR of the
+/// inputs whose index has that bit set, so several high inputs merge rather than take priority.
+///
+/// Spec:
+/// 0..2^bits: one-hot inputs
+///
+/// Outputs hold the index, least significant bit first. See `make_one_hot_encoder` for the
+/// gate-level equivalent.
+#[derive(Clone)]
+pub struct OneHotEncoder {
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl OneHotEncoder {
+    pub(crate) fn new(bits: usize) -> Self {
+        assert!(bits >= 1, "A one-hot encoder needs at least one output bit.");
+        OneHotEncoder {
+            inputs: vec![0; 1 << bits],
+            outputs: vec![0; bits]
+        }
+    }
+}
+
+impl Gate for OneHotEncoder {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("in", 0, self.inputs.len()),
+            Port::output("index", 0, self.outputs.len())
+        ]
+    }
+
+    fn lower(&self) -> Option<Circuit> {
+        Some(lower_with(self.inputs.len(), make_one_hot_encoder))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        for (b, &o) in self.outputs.iter().enumerate() {
+            let new = self.inputs.iter().enumerate()
+                .any(|(i, &input)| i >> b & 1 == 1 && lines[input].is_high());
+            lines[o] = Line::from(new);
+        }
+    }
+}
+
+/// Builds one AND term per value of `select`, least significant bit first, and returns the
+/// `2^select.len()` lines carrying them. Shared by the decoder and demultiplexer generators.
+pub(crate) fn make_minterms(select: &[usize], circuit: &mut Circuit) -> Vec<usize> {
+    let inverted = select.iter().map(|&s| {
+        let inv = circuit.add_gate(Inverter::new());
+        circuit.set_gate_input(inv, 0, s);
+        circuit.get_gate_output(inv, 0)
+    }).collect::<Vec<_>>();
+    (0..1 << select.len()).map(|i| {
+        let literals = (0..select.len())
+            .map(|b| if i >> b & 1 == 1 { select[b] } else { inverted[b] })
+            .collect::<Vec<_>>();
+        make_and_tree(&literals, circuit)
+    }).collect()
+}
+
+/// Builds the gate-level equivalent of `Decoder` and returns its `2^select.len()` outputs.
+pub fn make_decoder(select: &[usize], enable: usize, circuit: &mut Circuit) -> Vec<usize> {
+    assert!(!select.is_empty(), "A decoder needs at least one select line.");
+    make_minterms(select, circuit).into_iter().map(|minterm| {
+        let gate = circuit.add_gate(SimpleGate::and());
+        circuit.set_gate_input(gate, 0, minterm);
+        circuit.set_gate_input(gate, 1, enable);
+        circuit.get_gate_output(gate, 0)
+    }).collect()
+}
+
+/// Builds the gate-level equivalent of `PriorityEncoder` and returns its index outputs followed
+/// by the valid output.
+pub fn make_priority_encoder(requests: &[usize], circuit: &mut Circuit) -> Vec<usize> {
+    let bits = encoded_bits(requests.len());
+    let last = requests.len() - 1;
+    // granted[i] is high when request i is high and no higher request is.
+    let mut granted = vec![0; requests.len()];
+    granted[last] = requests[last];
+    let mut any_above = requests[last];
+    for i in (0..last).rev() {
+        let inv = circuit.add_gate(Inverter::new());
+        circuit.set_gate_input(inv, 0, any_above);
+        let grant = circuit.add_gate(SimpleGate::and());
+        circuit.set_gate_input(grant, 0, requests[i]);
+        circuit.connect_i_single(grant, 1, inv, 0);
+        granted[i] = circuit.get_gate_output(grant, 0);
+        let any = circuit.add_gate(SimpleGate::or());
+        circuit.set_gate_input(any, 0, requests[i]);
+        circuit.set_gate_input(any, 1, any_above);
+        any_above = circuit.get_gate_output(any, 0);
+    }
+    let mut outputs = (0..bits).map(|b| {
+        let terms = (0..requests.len()).filter(|i| i >> b & 1 == 1).map(|i| granted[i])
+            .collect::<Vec<_>>();
+        make_or_tree(&terms, circuit)
+    }).collect::<Vec<_>>();
+    outputs.push(any_above);
+    outputs
+}
+
+/// Builds the gate-level equivalent of `OneHotEncoder` and returns its index outputs.
+pub fn make_one_hot_encoder(inputs: &[usize], circuit: &mut Circuit) -> Vec<usize> {
+    let bits = encoded_bits(inputs.len());
+    (0..bits).map(|b| {
+        let terms = (0..inputs.len()).filter(|i| i >> b & 1 == 1).map(|i| inputs[i])
+            .collect::<Vec<_>>();
+        make_or_tree(&terms, circuit)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::gate::tests::{assert_lowering_matches, respond, single_gate};
+    use circuit::line::{Line, lines_to_word, word_to_lines};
+    use super::{Decoder, OneHotEncoder, PriorityEncoder};
+
+    #[test]
+    fn lowerings_match() {
+        for bits in 1..=4 {
+            assert_lowering_matches(Decoder::new(bits));
+            assert_lowering_matches(PriorityEncoder::new(bits));
+            assert_lowering_matches(OneHotEncoder::new(bits));
+        }
+        assert_lowering_matches(Decoder::new(7));
+        assert_lowering_matches(PriorityEncoder::new(6));
+    }
+
+    #[test]
+    fn decoder_needs_enable() {
+        let mut circuit = single_gate(Decoder::new(2));
+        for value in 0..4 {
+            let mut inputs = word_to_lines(value, 2);
+            inputs.push(Line::High);
+            let outputs = respond(&mut circuit, &inputs);
+            assert_eq!(lines_to_word(&outputs), 1 << value);
+            inputs[2] = Line::Low;
+            assert_eq!(lines_to_word(&respond(&mut circuit, &inputs)), 0);
+        }
+    }
+
+    #[test]
+    fn priority_encoder_picks_highest_request() {
+        let mut circuit = single_gate(PriorityEncoder::new(3));
+        assert_eq!(lines_to_word(&respond(&mut circuit, &word_to_lines(0b0010_0110, 8))),
+            0b1101);
+        assert_eq!(lines_to_word(&respond(&mut circuit, &word_to_lines(1, 8))), 0b1000);
+        assert_eq!(lines_to_word(&respond(&mut circuit, &word_to_lines(0, 8))), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one")]
+    fn decoder_needs_select() {
+        Decoder::new(0);
+    }
+}
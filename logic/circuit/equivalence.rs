@@ -0,0 +1,369 @@
+use std::error::Error;
+use std::fmt;
+
+use circuit::circuit::Circuit;
+use circuit::bdd::{Bdd, VariableOrder};
+use circuit::line::Line;
+use circuit::netlist::{Netlist, NetlistError, batch_inputs};
+use circuit::sat::{Lit, Solver};
+
+/// How `check_equivalence` proves two circuits equivalent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// Exhaustive simulation up to `EquivalenceOptions::exhaustive_limit` inputs, SAT beyond.
+    Auto,
+    /// Exhaustive simulation regardless of the number of inputs, which must be less than 64.
+    Simulation,
+    Sat,
+    /// Builds both circuits as BDDs over shared variables in depth-first order.
+    Bdd
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EquivalenceOptions {
+    /// Most inputs that `Engine::Auto` simulates exhaustively. Defaults to 20.
+    pub exhaustive_limit: usize,
+    pub engine: Engine
+}
+
+impl Default for EquivalenceOptions {
+    fn default() -> Self {
+        EquivalenceOptions {
+            exhaustive_limit: 20,
+            engine: Engine::Auto
+        }
+    }
+}
+
+/// Inputs on which two circuits disagree, with what each of them outputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counterexample {
+    pub inputs: Vec<Line>,
+    pub outputs_a: Vec<Line>,
+    pub outputs_b: Vec<Line>
+}
+
+impl Counterexample {
+    /// Indices of the outputs that differ.
+    pub fn differing_outputs(&self) -> Vec<usize> {
+        (0..self.outputs_a.len()).filter(|&o| self.outputs_a[o] != self.outputs_b[o]).collect()
+    }
+}
+
+fn format_lines(lines: &[Line]) -> String {
+    lines.iter().rev().map(|line| if line.is_high() { '1' } else { '0' }).collect()
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "inputs {} give {} and {}", format_lines(&self.inputs),
+            format_lines(&self.outputs_a), format_lines(&self.outputs_b))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Equivalence {
+    Equivalent,
+    Different(Counterexample)
+}
+
+impl Equivalence {
+    pub fn is_equivalent(&self) -> bool {
+        *self == Equivalence::Equivalent
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EquivalenceError {
+    InputCount { a: usize, b: usize },
+    OutputCount { a: usize, b: usize },
+    TooManyInputs { inputs: usize },
+    /// One of the circuits isn't purely combinational.
+    Netlist(NetlistError)
+}
+
+impl fmt::Display for EquivalenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EquivalenceError::InputCount { a, b } => {
+                write!(f, "circuits have {} and {} inputs", a, b)
+            },
+            EquivalenceError::OutputCount { a, b } => {
+                write!(f, "circuits have {} and {} outputs", a, b)
+            },
+            EquivalenceError::TooManyInputs { inputs } => {
+                write!(f, "{} inputs are too many to simulate exhaustively", inputs)
+            },
+            EquivalenceError::Netlist(ref error) => error.fmt(f)
+        }
+    }
+}
+
+impl Error for EquivalenceError {}
+
+impl From<NetlistError> for EquivalenceError {
+    fn from(error: NetlistError) -> Self {
+        EquivalenceError::Netlist(error)
+    }
+}
+
+fn counterexample(a: &Netlist, b: &Netlist, inputs: Vec<Line>) -> Counterexample {
+    Counterexample {
+        outputs_a: a.eval(&inputs),
+        outputs_b: b.eval(&inputs),
+        inputs
+    }
+}
+
+// Lowest bit at which any pair of output words differs, within `valid`.
+fn first_difference(a: &[u64], b: &[u64], valid: u64) -> Option<u32> {
+    let diff = a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) & valid;
+    if diff == 0 { None } else { Some(diff.trailing_zeros()) }
+}
+
+fn simulate(a: &Netlist, b: &Netlist) -> Equivalence {
+    let inputs = a.num_inputs();
+    let valid = if inputs < 6 { (1u64 << (1 << inputs)) - 1 } else { !0 };
+    let batches = if inputs <= 6 { 1 } else { 1u64 << (inputs - 6) };
+    for base in (0..batches).map(|batch| batch * 64) {
+        let words = batch_inputs(inputs, base);
+        if let Some(bit) = first_difference(&a.simulate(&words), &b.simulate(&words), valid) {
+            let row = base + bit as u64;
+            let inputs = (0..inputs).map(|i| Line::from(row >> i & 1 == 1)).collect();
+            return Equivalence::Different(counterexample(a, b, inputs));
+        }
+    }
+    Equivalence::Equivalent
+}
+
+fn solve(a: &Netlist, b: &Netlist) -> Equivalence {
+    let mut solver = Solver::new();
+    let inputs = (0..a.num_inputs()).map(|_| Lit::positive(solver.new_var()))
+        .collect::<Vec<_>>();
+    let nodes_a = solver.add_netlist(a, &inputs);
+    let nodes_b = solver.add_netlist(b, &inputs);
+    let differences = a.outputs.iter().zip(&b.outputs)
+        .map(|(&(x, _), &(y, _))| solver.add_xor(nodes_a[x], nodes_b[y]))
+        .collect::<Vec<_>>();
+    solver.add_clause(&differences);
+    match solver.solve() {
+        Some(model) => {
+            let inputs = inputs.iter().map(|lit| Line::from(model[lit.var()])).collect();
+            Equivalence::Different(counterexample(a, b, inputs))
+        },
+        None => Equivalence::Equivalent
+    }
+}
+
+fn compare_bdds(a: &Netlist, b: &Netlist) -> Equivalence {
+    let order = VariableOrder::DepthFirst.order(a);
+    let mut vars = vec![0; order.len()];
+    for (var, &i) in order.iter().enumerate() {
+        vars[i] = var;
+    }
+    let mut bdd = Bdd::new(order.len());
+    let functions_a = bdd.add_netlist(a, &vars);
+    let functions_b = bdd.add_netlist(b, &vars);
+    let mut differences = Bdd::FALSE;
+    for (&(x, _), &(y, _)) in a.outputs.iter().zip(&b.outputs) {
+        let difference = bdd.xor(functions_a[x], functions_b[y]);
+        differences = bdd.or(differences, difference);
+    }
+    match bdd.any_sat(differences) {
+        Some(assignment) => {
+            let inputs = vars.iter().map(|&var| Line::from(assignment[var] == Some(true)))
+                .collect();
+            Equivalence::Different(counterexample(a, b, inputs))
+        },
+        None => Equivalence::Equivalent
+    }
+}
+
+/// Checks whether two combinational circuits compute the same function, matching their marked
+/// inputs and outputs by index.
+///
+/// Small circuits are simulated on every input combination, 64 at a time. Larger ones are
+/// turned into a miter, which asks the built-in SAT solver for inputs on which any pair of
+/// outputs differ; there are none exactly when the circuits are equivalent. `Engine::Bdd` compares
+/// canonical BDDs instead, which is fast for structured logic such as adders. Both circuits are
+/// compared as two-valued logic, so a disconnected line counts as low.
+pub fn check_equivalence(a: &Circuit, b: &Circuit, options: &EquivalenceOptions)
+    -> Result<Equivalence, EquivalenceError> {
+    if a.inputs.len() != b.inputs.len() {
+        return Err(EquivalenceError::InputCount { a: a.inputs.len(), b: b.inputs.len() });
+    }
+    if a.outputs.len() != b.outputs.len() {
+        return Err(EquivalenceError::OutputCount { a: a.outputs.len(), b: b.outputs.len() });
+    }
+    let (a, b) = (Netlist::from_circuit(a)?, Netlist::from_circuit(b)?);
+    let inputs = a.num_inputs();
+    match options.engine {
+        Engine::Auto if inputs <= options.exhaustive_limit && inputs < 64 => Ok(simulate(&a, &b)),
+        Engine::Simulation if inputs < 64 => Ok(simulate(&a, &b)),
+        Engine::Simulation => Err(EquivalenceError::TooManyInputs { inputs }),
+        Engine::Bdd => Ok(compare_bdds(&a, &b)),
+        _ => Ok(solve(&a, &b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Inverter;
+    use circuit::gate::{Gate, lower_with};
+    use circuit::gate::tests::{Rng, random_logic, respond, same, single_gate};
+    use circuit::line;
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::mux::Mux;
+    use circuit::simplegate::SimpleGate;
+    use super::*;
+
+    fn engines(inputs: usize) -> Vec<EquivalenceOptions> {
+        let mut engines = vec![Engine::Auto, Engine::Sat, Engine::Bdd];
+        if inputs < 64 {
+            engines.push(Engine::Simulation);
+        }
+        engines.into_iter().map(|engine| EquivalenceOptions { engine, ..Default::default() })
+            .collect()
+    }
+
+    // `circuit` with output 0 inverted.
+    fn with_output_inverted(mut circuit: Circuit) -> Circuit {
+        let inverter = circuit.add_gate(Inverter::new());
+        let output = circuit.outputs[0];
+        circuit.set_gate_input(inverter, 0, output);
+        circuit.outputs[0] = circuit.get_gate_output(inverter, 0);
+        circuit
+    }
+
+    #[test]
+    fn rewritten_logic_is_equivalent() {
+        for seed in 1..15 {
+            for &inputs in &[0, 1, 7, 14, 64, 65] {
+                let a = random_logic(seed, inputs, 60, 3, false);
+                let b = random_logic(seed, inputs, 60, 3, true);
+                for options in engines(inputs) {
+                    assert_eq!(check_equivalence(&a, &b, &options), Ok(Equivalence::Equivalent),
+                        "{:?} with {} inputs, seed {}.", options.engine, inputs, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn differences_come_with_real_counterexamples() {
+        for seed in 1..15 {
+            for &inputs in &[0, 3, 12, 64, 65] {
+                let a = random_logic(seed, inputs, 60, 3, false);
+                let b = with_output_inverted(random_logic(seed, inputs, 60, 3, true));
+                for options in engines(inputs) {
+                    let counterexample = match check_equivalence(&a, &b, &options) {
+                        Ok(Equivalence::Different(counterexample)) => counterexample,
+                        other => panic!("{:?} found {:?}.", options.engine, other)
+                    };
+                    assert_eq!(counterexample.differing_outputs(), vec![0]);
+                    let (mut a, mut b) = (a.clone(), b.clone());
+                    assert_eq!(respond(&mut a, &counterexample.inputs), counterexample.outputs_a);
+                    assert_eq!(respond(&mut b, &counterexample.inputs), counterexample.outputs_b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lowered_gates_match_their_behavior() {
+        let mut behavioral = single_gate(Mux::new(8, 16));
+        let mut lowered = Mux::new(8, 16).lower().unwrap();
+        let mut rng = Rng(11);
+        for _ in 0..20 {
+            let data = (0..128).map(|_| rng.line()).collect::<Vec<_>>();
+            for select in 0..8 {
+                let mut inputs = data.clone();
+                inputs.extend((0..3).map(|i| Line::from(select >> i & 1 == 1)));
+                let outputs = respond(&mut behavioral, &inputs);
+                assert!(outputs.iter().zip(&data[select * 16..]).all(|(&a, &b)| same(a, b)),
+                    "Mux didn't pick word {}.", select);
+                assert_eq!(respond(&mut lowered, &inputs), outputs, "Select {}.", select);
+            }
+        }
+    }
+
+    /// An AND gate whose lowering is an XOR, as if `lower` had a bug.
+    #[derive(Clone)]
+    struct MisloweredAnd {
+        inputs: [usize; 2],
+        output: usize
+    }
+
+    impl Gate for MisloweredAnd {
+        fn get_input(&self, i: usize) -> usize {
+            self.inputs[i]
+        }
+
+        fn set_input(&mut self, i: usize, new_i: usize) {
+            self.inputs[i] = new_i;
+        }
+
+        fn num_inputs(&self) -> usize {
+            2
+        }
+
+        fn get_output(&self, _o: usize) -> usize {
+            self.output
+        }
+
+        fn set_output(&mut self, _o: usize, new_o: usize) {
+            self.output = new_o;
+        }
+
+        fn num_outputs(&self) -> usize {
+            1
+        }
+
+        fn eval(&mut self, lines: &mut Vec<Line>) {
+            lines[self.output] = line::and(lines[self.inputs[0]], lines[self.inputs[1]]);
+        }
+
+        fn lower(&self) -> Option<Circuit> {
+            Some(lower_with(2, |lines, circuit| {
+                let xor = circuit.add_gate(SimpleGate::xor());
+                circuit.set_gate_input(xor, 0, lines[0]);
+                circuit.set_gate_input(xor, 1, lines[1]);
+                vec![circuit.get_gate_output(xor, 0)]
+            }))
+        }
+    }
+
+    #[test]
+    fn lowerings_that_differ_from_eval_are_caught() {
+        let mislowered = single_gate(MisloweredAnd { inputs: [0; 2], output: 0 });
+        let reference = single_gate(SimpleGate::and());
+        for options in engines(2) {
+            let counterexample = match check_equivalence(&mislowered, &reference, &options) {
+                Ok(Equivalence::Different(counterexample)) => counterexample,
+                other => panic!("{:?} found {:?}.", options.engine, other)
+            };
+            // `eval` agrees with the reference, so only the lowering gave `outputs_a`.
+            let (mut mislowered, mut reference) = (mislowered.clone(), reference.clone());
+            assert_eq!(respond(&mut mislowered, &counterexample.inputs),
+                counterexample.outputs_b);
+            assert_eq!(respond(&mut reference, &counterexample.inputs), counterexample.outputs_b);
+            assert_ne!(counterexample.outputs_a, counterexample.outputs_b);
+        }
+    }
+
+    #[test]
+    fn mismatched_circuits_are_errors() {
+        let a = random_logic(1, 3, 10, 2, false);
+        assert_eq!(check_equivalence(&a, &random_logic(1, 4, 10, 2, false), &Default::default()),
+            Err(EquivalenceError::InputCount { a: 3, b: 4 }));
+        assert_eq!(check_equivalence(&a, &random_logic(1, 3, 10, 1, false), &Default::default()),
+            Err(EquivalenceError::OutputCount { a: 2, b: 1 }));
+        let wide = random_logic(1, 64, 10, 1, false);
+        let options = EquivalenceOptions { engine: Engine::Simulation, ..Default::default() };
+        assert_eq!(check_equivalence(&wide, &wide, &options),
+            Err(EquivalenceError::TooManyInputs { inputs: 64 }));
+        let state = single_gate(DFlipFlop::new(Edge::Rising));
+        assert_eq!(check_equivalence(&state, &state, &Default::default()),
+            Err(EquivalenceError::Netlist(NetlistError::Opaque { gate: 0 })));
+    }
+}
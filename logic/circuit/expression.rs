@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use circuit::basics::{Inverter, Source};
+use circuit::circuit::Circuit;
+use circuit::line::Line;
+use circuit::simplegate::SimpleGate;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    /// Something other than `expected` was found, described by `found`.
+    Expected { expected: &'static str, found: String },
+    /// The same output was assigned twice.
+    Redefined(String),
+    /// An output was assigned after being used as an input.
+    AssignedInput(String),
+    /// An output was used in its own assignment.
+    SelfReference(String)
+}
+
+/// Error from `parse_circuit`, at a 1-based line and column of the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ParseErrorKind
+}
+
+impl ParseError {
+    /// The offending line of `source` with a caret under the column, for error messages.
+    pub fn caret(&self, source: &str) -> String {
+        let text = source.lines().nth(self.line - 1).unwrap_or("");
+        format!("{}\n{}^", text, " ".repeat(self.column - 1))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match self.kind {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::Expected { expected, ref found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            },
+            ParseErrorKind::Redefined(ref name) => write!(f, "{} is already assigned", name),
+            ParseErrorKind::AssignedInput(ref name) => {
+                write!(f, "{} is assigned after being used as an input", name)
+            },
+            ParseErrorKind::SelfReference(ref name) => {
+                write!(f, "{} is used in its own assignment", name)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Constant(bool),
+    And,
+    Or,
+    Xor,
+    Not,
+    Open,
+    Close,
+    Assign,
+    // A newline outside of parentheses, or `;`.
+    End,
+    Eof
+}
+
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+    // How the token is described in errors.
+    text: String
+}
+
+// Tokens with the line and column they start at. `#` starts a comment running to the end of
+// the line.
+fn tokenize(source: &str) -> Result<Vec<Spanned>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+    let mut end = (1, 1);
+    for (l, text) in source.lines().enumerate() {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut c = 0;
+        while c < chars.len() {
+            let column = c + 1;
+            let token = match chars[c] {
+                '#' => break,
+                ' ' | '\t' | '\r' => {
+                    c += 1;
+                    continue;
+                },
+                '*' | '&' => Token::And,
+                '+' | '|' => Token::Or,
+                '^' => Token::Xor,
+                '!' => Token::Not,
+                '=' => Token::Assign,
+                ';' => Token::End,
+                '(' => {
+                    depth += 1;
+                    Token::Open
+                },
+                ')' => {
+                    depth = depth.saturating_sub(1);
+                    Token::Close
+                },
+                '0' | '1' if !chars.get(c + 1).is_some_and(|c| c.is_alphanumeric()) => {
+                    Token::Constant(chars[c] == '1')
+                },
+                ch if ch.is_alphabetic() || ch == '_' => {
+                    let start = c;
+                    while c < chars.len() && (chars[c].is_alphanumeric() || chars[c] == '_') {
+                        c += 1;
+                    }
+                    let name = chars[start..c].iter().collect::<String>();
+                    tokens.push(Spanned {
+                        text: format!("`{}`", name),
+                        token: Token::Name(name),
+                        line: l + 1,
+                        column
+                    });
+                    continue;
+                },
+                ch => {
+                    return Err(ParseError {
+                        line: l + 1,
+                        column,
+                        kind: ParseErrorKind::UnexpectedChar(ch)
+                    });
+                }
+            };
+            tokens.push(Spanned {
+                token,
+                line: l + 1,
+                column,
+                text: format!("`{}`", chars[c])
+            });
+            c += 1;
+        }
+        end = (l + 1, chars.len() + 1);
+        if depth == 0 {
+            tokens.push(Spanned {
+                token: Token::End,
+                line: end.0,
+                column: end.1,
+                text: "end of line".to_string()
+            });
+        }
+    }
+    tokens.push(Spanned {
+        token: Token::Eof,
+        line: end.0,
+        column: end.1,
+        text: "end of input".to_string()
+    });
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Op {
+    And,
+    Or,
+    Xor,
+    Not
+}
+
+/// A circuit parsed from Boolean expressions, with the names of its inputs and outputs.
+pub struct ParsedCircuit {
+    pub circuit: Circuit,
+    /// Name of each circuit input, in order of first use.
+    pub inputs: Vec<String>,
+    /// Name of each circuit output, in order of assignment.
+    pub outputs: Vec<String>
+}
+
+impl ParsedCircuit {
+    /// Line of the input called `name`.
+    pub fn input(&self, name: &str) -> Option<usize> {
+        self.inputs.iter().position(|input| input == name).map(|i| self.circuit.inputs[i])
+    }
+
+    /// Line of the output called `name`.
+    pub fn output(&self, name: &str) -> Option<usize> {
+        self.outputs.iter().position(|output| output == name).map(|o| self.circuit.outputs[o])
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    next: usize,
+    circuit: Circuit,
+    inputs: Vec<String>,
+    // Line carrying each name, input or output.
+    names: HashMap<String, usize>,
+    outputs: Vec<String>,
+    // Line already computing each operation, so repeated subexpressions share gates.
+    gates: HashMap<(Op, usize, usize), usize>,
+    constants: [Option<usize>; 2]
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].token
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        self.error_at(self.next, kind)
+    }
+
+    fn error_at(&self, token: usize, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.tokens[token].line,
+            column: self.tokens[token].column,
+            kind
+        }
+    }
+
+    fn expected(&self, expected: &'static str) -> ParseError {
+        self.error(ParseErrorKind::Expected {
+            expected,
+            found: self.tokens[self.next].text.clone()
+        })
+    }
+
+    fn gate(&mut self, op: Op, a: usize, b: usize) -> usize {
+        let key = if op != Op::Not && b < a { (op, b, a) } else { (op, a, b) };
+        if let Some(&line) = self.gates.get(&key) {
+            return line;
+        }
+        let gate = match op {
+            Op::And => self.circuit.add_gate(SimpleGate::and()),
+            Op::Or => self.circuit.add_gate(SimpleGate::or()),
+            Op::Xor => self.circuit.add_gate(SimpleGate::xor()),
+            Op::Not => self.circuit.add_gate(Inverter::new())
+        };
+        self.circuit.set_gate_input(gate, 0, a);
+        if op != Op::Not {
+            self.circuit.set_gate_input(gate, 1, b);
+        }
+        let line = self.circuit.get_gate_output(gate, 0);
+        self.gates.insert(key, line);
+        line
+    }
+
+    fn constant(&mut self, value: bool) -> usize {
+        if let Some(line) = self.constants[value as usize] {
+            return line;
+        }
+        let gate = if value {
+            self.circuit.add_gate(Source::new_high())
+        } else {
+            self.circuit.add_gate(Source::new_low())
+        };
+        let line = self.circuit.get_gate_output(gate, 0);
+        self.constants[value as usize] = Some(line);
+        line
+    }
+
+    fn primary(&mut self) -> Result<usize, ParseError> {
+        let token = self.peek().clone();
+        self.next += 1;
+        match token {
+            Token::Constant(value) => Ok(self.constant(value)),
+            Token::Name(name) => {
+                if let Some(&line) = self.names.get(&name) {
+                    return Ok(line);
+                }
+                let line = self.circuit.add_line(Line::Low);
+                self.circuit.mark_line_as_circuit_input(line);
+                self.names.insert(name.clone(), line);
+                self.inputs.push(name);
+                Ok(line)
+            },
+            Token::Open => {
+                let line = self.expression()?;
+                if *self.peek() != Token::Close {
+                    return Err(self.expected("`)`"));
+                }
+                self.next += 1;
+                Ok(line)
+            },
+            _ => {
+                self.next -= 1;
+                Err(self.expected("an expression"))
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<usize, ParseError> {
+        if *self.peek() == Token::Not {
+            self.next += 1;
+            let operand = self.unary()?;
+            Ok(self.gate(Op::Not, operand, 0))
+        } else {
+            self.primary()
+        }
+    }
+
+    // Left-associative chain of `op` over operands parsed by `operand`.
+    fn chain(&mut self, token: Token, op: Op, operand: fn(&mut Parser) -> Result<usize, ParseError>)
+        -> Result<usize, ParseError> {
+        let mut line = operand(self)?;
+        while *self.peek() == token {
+            self.next += 1;
+            let right = operand(self)?;
+            line = self.gate(op, line, right);
+        }
+        Ok(line)
+    }
+
+    fn and(&mut self) -> Result<usize, ParseError> {
+        self.chain(Token::And, Op::And, Parser::unary)
+    }
+
+    fn xor(&mut self) -> Result<usize, ParseError> {
+        self.chain(Token::Xor, Op::Xor, Parser::and)
+    }
+
+    fn expression(&mut self) -> Result<usize, ParseError> {
+        self.chain(Token::Or, Op::Or, Parser::xor)
+    }
+
+    fn statement(&mut self) -> Result<(), ParseError> {
+        let name = match *self.peek() {
+            Token::Name(ref name) => name.clone(),
+            _ => return Err(self.expected("an output name"))
+        };
+        if self.outputs.contains(&name) {
+            return Err(self.error(ParseErrorKind::Redefined(name)));
+        }
+        if self.inputs.contains(&name) {
+            return Err(self.error(ParseErrorKind::AssignedInput(name)));
+        }
+        let at = self.next;
+        self.next += 1;
+        if *self.peek() != Token::Assign {
+            return Err(self.expected("`=`"));
+        }
+        self.next += 1;
+        let line = self.expression()?;
+        if *self.peek() != Token::End && *self.peek() != Token::Eof {
+            return Err(self.expected("an operator or end of statement"));
+        }
+        // A name only becomes an input when first read, so `a = a & b` shows up here.
+        if self.inputs.contains(&name) {
+            return Err(self.error_at(at, ParseErrorKind::SelfReference(name)));
+        }
+        self.circuit.mark_line_as_circuit_output(line);
+        self.names.insert(name.clone(), line);
+        self.outputs.push(name);
+        Ok(())
+    }
+}
+
+/// Builds a circuit of `SimpleGate`s and `Inverter`s from assignments such as
+/// `out = (a & b) | !(c ^ d)`, one per line or separated by `;`.
+///
+/// Operators follow those of `Line`: `*` or `&` for AND, `+` or `|` for OR, `^` for XOR and a
+/// prefix `!` for NOT, binding tightest to loosest as NOT, AND, XOR, OR. `0` and `1` are
+/// constants and `#` starts a comment. Every assigned name becomes a circuit output, and later
+/// assignments can use it. Any other name becomes a circuit input. Repeated subexpressions
+/// share the same gates.
+pub fn parse_circuit(source: &str) -> Result<ParsedCircuit, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        next: 0,
+        circuit: Circuit::new(),
+        inputs: Vec::new(),
+        names: HashMap::new(),
+        outputs: Vec::new(),
+        gates: HashMap::new(),
+        constants: [None, None]
+    };
+    while *parser.peek() != Token::Eof {
+        if *parser.peek() == Token::End {
+            parser.next += 1;
+        } else {
+            parser.statement()?;
+        }
+    }
+    Ok(ParsedCircuit {
+        circuit: parser.circuit,
+        inputs: parser.inputs,
+        outputs: parser.outputs
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::gate::tests::{Rng, respond};
+    use circuit::line::Line;
+    use super::*;
+
+    enum Expr {
+        Var(usize),
+        Constant(bool),
+        Not(Box<Expr>),
+        Binary(Op, Box<Expr>, Box<Expr>)
+    }
+
+    impl Expr {
+        fn random(rng: &mut Rng, vars: usize, depth: usize) -> Expr {
+            match if depth == 0 { rng.below(8) / 7 } else { rng.below(6) } {
+                0 if vars > 0 => Expr::Var(rng.below(vars as u64) as usize),
+                0 | 1 => Expr::Constant(rng.below(2) == 1),
+                2 => Expr::Not(Box::new(Expr::random(rng, vars, depth - 1))),
+                op => {
+                    let op = [Op::And, Op::Or, Op::Xor][op as usize - 3];
+                    Expr::Binary(op, Box::new(Expr::random(rng, vars, depth - 1)),
+                        Box::new(Expr::random(rng, vars, depth - 1)))
+                }
+            }
+        }
+
+        fn eval(&self, vars: &[bool]) -> bool {
+            match *self {
+                Expr::Var(v) => vars[v],
+                Expr::Constant(value) => value,
+                Expr::Not(ref a) => !a.eval(vars),
+                Expr::Binary(op, ref a, ref b) => match op {
+                    Op::And => a.eval(vars) && b.eval(vars),
+                    Op::Or => a.eval(vars) || b.eval(vars),
+                    _ => a.eval(vars) != b.eval(vars)
+                }
+            }
+        }
+
+        // Binding strength, loosest first.
+        fn level(&self) -> usize {
+            match *self {
+                Expr::Binary(Op::Or, _, _) => 0,
+                Expr::Binary(Op::Xor, _, _) => 1,
+                Expr::Binary(_, _, _) => 2,
+                _ => 3
+            }
+        }
+
+        // Source text with only the parentheses precedence needs, and random operator spellings.
+        fn write(&self, rng: &mut Rng) -> String {
+            match *self {
+                Expr::Var(v) => format!("v{}", v),
+                Expr::Constant(value) => (value as u8).to_string(),
+                Expr::Not(ref a) => format!("!{}", a.operand(3, rng)),
+                Expr::Binary(op, ref a, ref b) => {
+                    let level = self.level();
+                    let symbol = match op {
+                        Op::And => ["&", "*"][rng.below(2) as usize],
+                        Op::Or => ["|", "+"][rng.below(2) as usize],
+                        _ => "^"
+                    };
+                    format!("{} {} {}", a.operand(level, rng), symbol, b.operand(level + 1, rng))
+                }
+            }
+        }
+
+        fn operand(&self, level: usize, rng: &mut Rng) -> String {
+            if self.level() < level {
+                format!("({})", self.write(rng))
+            } else {
+                self.write(rng)
+            }
+        }
+    }
+
+    // Values of the parsed circuit's outputs with input `v{i}` set to `vars[i]`.
+    fn outputs(parsed: &mut ParsedCircuit, vars: &[bool]) -> Vec<bool> {
+        let inputs = parsed.inputs.iter()
+            .map(|name| Line::from(vars[name[1..].parse::<usize>().unwrap()]))
+            .collect::<Vec<_>>();
+        respond(&mut parsed.circuit, &inputs).iter().map(|line| line.is_high()).collect()
+    }
+
+    fn error(source: &str) -> ParseError {
+        parse_circuit(source).err().expect("Source parsed.")
+    }
+
+    #[test]
+    fn random_expressions_match() {
+        let mut rng = Rng(42);
+        for vars in 0..6 {
+            for _ in 0..40 {
+                let exprs = (0..3).map(|_| Expr::random(&mut rng, vars, 5)).collect::<Vec<_>>();
+                let source = exprs.iter().enumerate()
+                    .map(|(o, expr)| format!("out{} = {}", o, expr.write(&mut rng)))
+                    .collect::<Vec<_>>().join(if rng.below(2) == 0 { "\n" } else { "; " });
+                let mut parsed = parse_circuit(&source).unwrap();
+                assert_eq!(parsed.outputs, vec!["out0", "out1", "out2"]);
+                for v in 0..1usize << vars {
+                    let values = (0..vars).map(|i| v >> i & 1 == 1).collect::<Vec<_>>();
+                    let expected = exprs.iter().map(|expr| expr.eval(&values)).collect::<Vec<_>>();
+                    assert_eq!(outputs(&mut parsed, &values), expected, "{}", source);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn precedence_and_sharing() {
+        let mut parsed = parse_circuit("x = a | b ^ c & !d  # comment\ny = (c & !d) ^ x").unwrap();
+        assert_eq!(parsed.inputs, vec!["a", "b", "c", "d"]);
+        assert_eq!(parsed.outputs, vec!["x", "y"]);
+        // !d, c & !d, b ^ .., a | .. and y's XOR.
+        assert_eq!(parsed.circuit.gates.len(), 5);
+        assert_eq!(parsed.input("c"), Some(parsed.circuit.inputs[2]));
+        assert_eq!(parsed.output("y"), Some(parsed.circuit.outputs[1]));
+        assert_eq!(parsed.input("x"), None);
+        let inputs = [Line::Low, Line::Low, Line::High, Line::Low];
+        assert_eq!(respond(&mut parsed.circuit, &inputs), vec![Line::High, Line::Low]);
+    }
+
+    #[test]
+    fn parentheses_continue_lines() {
+        let parsed = parse_circuit("out = (a &\n  b) | 0\n\n;other = 1").unwrap();
+        assert_eq!(parsed.inputs, vec!["a", "b"]);
+        assert_eq!(parsed.outputs, vec!["out", "other"]);
+    }
+
+    #[test]
+    fn wide_expressions() {
+        for &width in &[0, 1, 64, 65] {
+            let names = (0..width).map(|i| format!("v{}", i)).collect::<Vec<_>>();
+            let source = if width == 0 { "p = 0".to_string() } else {
+                format!("p = {}", names.join(" ^ "))
+            };
+            let mut parsed = parse_circuit(&source).unwrap();
+            assert_eq!(parsed.inputs, names);
+            let mut rng = Rng(width as u64 + 1);
+            for _ in 0..20 {
+                let values = (0..width).map(|_| rng.below(2) == 1).collect::<Vec<_>>();
+                let parity = values.iter().filter(|&&value| value).count() % 2 == 1;
+                assert_eq!(outputs(&mut parsed, &values), vec![parity]);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let source = "a = b\nb = c";
+        let e = error(source);
+        assert_eq!((e.line, e.column), (2, 1));
+        assert_eq!(e.kind, ParseErrorKind::AssignedInput("b".to_string()));
+        assert_eq!(e.caret(source), "b = c\n^");
+        assert_eq!(error("a = b; a = c").kind, ParseErrorKind::Redefined("a".to_string()));
+        let e = error("x = 1\n  a = !(a & b)");
+        assert_eq!((e.line, e.column), (2, 3));
+        assert_eq!(e.kind, ParseErrorKind::SelfReference("a".to_string()));
+        assert_eq!(e.to_string(), "line 2, column 3: a is used in its own assignment");
+        assert_eq!(error("a = b $ c").kind, ParseErrorKind::UnexpectedChar('$'));
+        assert_eq!(error("a = (b & c").to_string(),
+            "line 1, column 11: expected `)`, found end of input");
+        assert_eq!(error("a = b c").to_string(),
+            "line 1, column 7: expected an operator or end of statement, found `c`");
+        assert_eq!(error("= b").to_string(),
+            "line 1, column 1: expected an output name, found `=`");
+        assert_eq!(error("a b").to_string(), "line 1, column 3: expected `=`, found `b`");
+        assert_eq!(error("a = & b").to_string(),
+            "line 1, column 5: expected an expression, found `&`");
+    }
+}
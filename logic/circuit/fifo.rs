@@ -0,0 +1,637 @@
+use circuit::codegen::{Expr, GateCode, Statement, MAX_MEMORY_WORDS, equals, less_than, step};
+use circuit::gate::{Gate, Port, PortRole, StateRegister};
+use circuit::line::{Line, lines_to_word, word_to_lines};
+use circuit::memory::{Edge, address_bits};
+
+/// Pin layout and clocked control shared by `Fifo` and `Stack`.
+#[derive(Clone)]
+struct QueuePins {
+    width: usize,
+    capacity: usize,
+    count_width: usize,
+    last_clock: Line,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+/// Operations requested on a rising clock edge.
+struct Request {
+    clear: bool,
+    push: Option<u64>,
+    pop: bool
+}
+
+impl QueuePins {
+    fn new(capacity: usize, width: usize) -> Self {
+        assert!(capacity >= 1, "A queue needs room for at least one word.");
+        assert!((1..=64).contains(&width), "Word width must be between 1 and 64 bits.");
+        let count_width = address_bits(capacity + 1);
+        QueuePins {
+            width,
+            capacity,
+            count_width,
+            last_clock: Line::Low,
+            inputs: vec![0; width + 4],
+            outputs: vec![0; width + 2 + count_width]
+        }
+    }
+
+    fn data_in(&self, bit: usize) -> usize {
+        assert!(bit < self.width, "Invalid data bit.");
+        bit
+    }
+
+    fn push(&self) -> usize {
+        self.width
+    }
+
+    fn pop(&self) -> usize {
+        self.width + 1
+    }
+
+    fn clear(&self) -> usize {
+        self.width + 2
+    }
+
+    fn clock(&self) -> usize {
+        self.width + 3
+    }
+
+    fn data_out(&self, bit: usize) -> usize {
+        assert!(bit < self.width, "Invalid data bit.");
+        bit
+    }
+
+    fn full(&self) -> usize {
+        self.width
+    }
+
+    fn empty(&self) -> usize {
+        self.width + 1
+    }
+
+    fn count(&self, bit: usize) -> usize {
+        assert!(bit < self.count_width, "Invalid count bit.");
+        self.width + 2 + bit
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("data_in", self.data_in(0), self.width),
+            Port::input("push", self.push(), 1).with_role(PortRole::Control),
+            Port::input("pop", self.pop(), 1).with_role(PortRole::Control),
+            Port::input("clear", self.clear(), 1).with_role(PortRole::Control),
+            Port::input("clock", self.clock(), 1).with_role(PortRole::Clock),
+            Port::output("data_out", self.data_out(0), self.width),
+            Port::output("full", self.full(), 1),
+            Port::output("empty", self.empty(), 1),
+            Port::output("count", self.count(0), self.count_width)
+        ]
+    }
+
+    /// Samples the control pins and returns what to do if the clock just rose. Pushes are dropped
+    /// while full and pops while empty, unless both happen on the same edge.
+    fn clocked(&mut self, lines: &[Line], count: usize) -> Option<Request> {
+        let clock = lines[self.inputs[self.clock()]];
+        let rising = Edge::Rising.triggered(self.last_clock, clock);
+        self.last_clock = clock;
+        if !rising {
+            return None;
+        }
+        let high = |pin: usize| lines[self.inputs[pin]].is_high();
+        let pop = high(self.pop()) && count > 0;
+        let push = high(self.push()) && (count < self.capacity || pop);
+        let data = self.inputs[..self.width].iter().map(|&i| lines[i]).collect::<Vec<_>>();
+        Some(Request {
+            clear: high(self.clear()),
+            push: if push { Some(lines_to_word(&data)) } else { None },
+            pop
+        })
+    }
+
+    fn drive(&self, lines: &mut [Line], head: Option<u64>, count: usize) {
+        for (bit, new) in word_to_lines(head.unwrap_or(0), self.width).into_iter().enumerate() {
+            lines[self.outputs[self.data_out(bit)]] = new;
+        }
+        lines[self.outputs[self.full()]] = Line::from(count == self.capacity);
+        lines[self.outputs[self.empty()]] = Line::from(count == 0);
+        let count = word_to_lines(count as u64, self.count_width);
+        for (bit, new) in count.into_iter().enumerate() {
+            lines[self.outputs[self.count(bit)]] = new;
+        }
+    }
+}
+
+// Layout of the state in the code generated for a queue: the last clock, whether to pop and push
+// on this edge, a carry bit for counting and the count, followed by the queue's own bits.
+const POP: usize = 1;
+const PUSH: usize = 2;
+const CARRY: usize = 3;
+const COUNT: usize = 4;
+
+fn state_word(bits: &[usize]) -> Vec<Expr> {
+    bits.iter().map(|&bit| Expr::State(bit)).collect()
+}
+
+fn clear_state(bits: &[usize]) -> Vec<Statement> {
+    bits.iter().map(|&bit| Statement::SetState(bit, Expr::Constant(false))).collect()
+}
+
+impl QueuePins {
+    fn count_bits(&self) -> Vec<usize> {
+        (COUNT..COUNT + self.count_width).collect()
+    }
+
+    fn data(&self) -> Vec<Expr> {
+        (0..self.width).map(|bit| Expr::Line(self.inputs[self.data_in(bit)])).collect()
+    }
+
+    fn data_out_lines(&self) -> Vec<usize> {
+        (0..self.width).map(|bit| self.outputs[self.data_out(bit)]).collect()
+    }
+
+    // Generated code for a queue holding `count` words in `memory`, with `extra` state bits
+    // after the count that clear along with it. `update` runs on an edge that doesn't clear,
+    // with the pop and push bits set, and `read` sets data out.
+    fn code(&self, count: usize, extra: &[bool], memory: Vec<u64>, update: Vec<Statement>,
+        read: Statement) -> GateCode {
+        let line = |pin: usize| Expr::Line(self.inputs[pin]);
+        let mut state = vec![self.last_clock.is_high(), false, false, false];
+        state.extend((0..self.count_width).map(|bit| count >> bit & 1 == 1));
+        state.extend_from_slice(extra);
+        let count = state_word(&self.count_bits());
+        let empty = equals(&count, 0);
+        let cleared = (COUNT..state.len()).collect::<Vec<_>>();
+        let on_edge = vec![
+            Statement::SetState(POP, line(self.pop()) & !empty.clone()),
+            Statement::SetState(PUSH, line(self.push())
+                & (less_than(&count, self.capacity as u64) | Expr::State(POP))),
+            Statement::If(line(self.clear()), clear_state(&cleared), update)
+        ];
+        let clock = line(self.clock());
+        let mut body = vec![
+            Statement::If(!Expr::State(0) & clock.clone(), on_edge, Vec::new()),
+            Statement::SetState(0, clock),
+            read,
+            Statement::If(empty.clone(), self.data_out_lines().into_iter()
+                .map(|o| Statement::SetLine(o, Expr::Constant(false))).collect(), Vec::new()),
+            Statement::SetLine(self.outputs[self.full()], equals(&count, self.capacity as u64)),
+            Statement::SetLine(self.outputs[self.empty()], empty)
+        ];
+        body.extend(count.into_iter().enumerate()
+            .map(|(bit, value)| Statement::SetLine(self.outputs[self.count(bit)], value)));
+        GateCode {
+            state,
+            memories: vec![memory],
+            body
+        }
+    }
+}
+
+macro_rules! queue_gate {
+    ($name:ident) => {
+        impl $name {
+            pub fn width(&self) -> usize {
+                self.pins.width
+            }
+
+            pub fn capacity(&self) -> usize {
+                self.pins.capacity
+            }
+
+            pub fn count_width(&self) -> usize {
+                self.pins.count_width
+            }
+
+            pub fn data_in(&self, bit: usize) -> usize {
+                self.pins.data_in(bit)
+            }
+
+            pub fn push(&self) -> usize {
+                self.pins.push()
+            }
+
+            pub fn pop(&self) -> usize {
+                self.pins.pop()
+            }
+
+            pub fn clear(&self) -> usize {
+                self.pins.clear()
+            }
+
+            pub fn clock(&self) -> usize {
+                self.pins.clock()
+            }
+
+            pub fn data_out(&self, bit: usize) -> usize {
+                self.pins.data_out(bit)
+            }
+
+            pub fn full(&self) -> usize {
+                self.pins.full()
+            }
+
+            pub fn empty(&self) -> usize {
+                self.pins.empty()
+            }
+
+            pub fn count(&self, bit: usize) -> usize {
+                self.pins.count(bit)
+            }
+        }
+    }
+}
+
+/// Synchronous first-in, first-out queue of `capacity` words of `width` bits. On the rising clock
+/// edge clear empties the queue, and otherwise push appends data in and pop drops the oldest
+/// word. Pushing while full and popping while empty are ignored, except that a full queue can
+/// push and pop on the same edge.
+///
+/// Spec:
+/// `data_in(0..width)`, `push()`, `pop()`, `clear()`, `clock()`
+///
+/// Outputs are `data_out(0..width)`, holding the oldest word or 0 while empty, then `full()`,
+/// `empty()` and `count(0..count_width)`.
+#[derive(Clone)]
+pub struct Fifo {
+    pins: QueuePins,
+    storage: Vec<u64>,
+    head: usize,
+    count: usize
+}
+
+impl Fifo {
+    pub(crate) fn new(capacity: usize, width: usize) -> Self {
+        Fifo {
+            pins: QueuePins::new(capacity, width),
+            storage: vec![0; capacity],
+            head: 0,
+            count: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+queue_gate!(Fifo);
+
+impl Gate for Fifo {
+    fn get_input(&self, i: usize) -> usize {
+        self.pins.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.pins.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.pins.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.pins.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.pins.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.pins.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        self.pins.ports()
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        if let Some(request) = self.pins.clocked(lines, self.count) {
+            let capacity = self.storage.len();
+            if request.clear {
+                self.head = 0;
+                self.count = 0;
+            } else {
+                if request.pop {
+                    self.head = (self.head + 1) % capacity;
+                    self.count -= 1;
+                }
+                if let Some(value) = request.push {
+                    self.storage[(self.head + self.count) % capacity] = value;
+                    self.count += 1;
+                }
+            }
+        }
+        let head = if self.count > 0 { Some(self.storage[self.head]) } else { None };
+        self.pins.drive(lines, head, self.count);
+    }
+
+    // The head and then the tail follow the count in the state, where pushes are written.
+    fn code(&self) -> Option<GateCode> {
+        let capacity = self.storage.len();
+        if capacity > MAX_MEMORY_WORDS {
+            return None;
+        }
+        let pointer_width = address_bits(capacity);
+        let head = (0..pointer_width).map(|bit| COUNT + self.pins.count_width + bit)
+            .collect::<Vec<_>>();
+        let tail = head.iter().map(|&bit| bit + pointer_width).collect::<Vec<_>>();
+        let advance = |pointer: &[usize]| Statement::If(
+            equals(&state_word(pointer), capacity as u64 - 1), clear_state(pointer),
+            step(pointer, Expr::Constant(true), CARRY));
+        let count = self.pins.count_bits();
+        let mut pop = vec![advance(&head)];
+        pop.extend(step(&count, Expr::Constant(false), CARRY));
+        let mut push = vec![
+            Statement::Write { memory: 0, address: state_word(&tail), value: self.pins.data() },
+            advance(&tail)
+        ];
+        push.extend(step(&count, Expr::Constant(true), CARRY));
+        let tail_at = (self.head + self.count) % capacity;
+        let pointers = (0..2 * pointer_width).map(|bit| {
+            let pointer = if bit < pointer_width { self.head } else { tail_at };
+            pointer >> (bit % pointer_width) & 1 == 1
+        }).collect::<Vec<_>>();
+        let read = Statement::Read {
+            memory: 0,
+            address: state_word(&head),
+            outputs: self.pins.data_out_lines()
+        };
+        Some(self.pins.code(self.count, &pointers, self.storage.clone(), vec![
+            Statement::If(Expr::State(POP), pop, Vec::new()),
+            Statement::If(Expr::State(PUSH), push, Vec::new())
+        ], read))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![
+            StateRegister::new("storage", self.pins.width, self.storage.len()),
+            StateRegister::new("head", address_bits(self.storage.len()), 1),
+            StateRegister::new("count", self.pins.count_width, 1)
+        ]
+    }
+
+    fn peek_state(&self, register: usize, index: usize) -> Vec<Line> {
+        match register {
+            0 => word_to_lines(self.storage[index], self.pins.width),
+            1 => word_to_lines(self.head as u64, address_bits(self.storage.len())),
+            _ => word_to_lines(self.count as u64, self.pins.count_width)
+        }
+    }
+
+    fn poke_state(&mut self, register: usize, index: usize, value: &[Line]) {
+        let value = lines_to_word(value);
+        match register {
+            0 => self.storage[index] = value,
+            1 => self.head = value as usize % self.storage.len(),
+            _ => self.count = (value as usize).min(self.storage.len())
+        }
+    }
+}
+
+/// Synchronous last-in, first-out stack of `capacity` words of `width` bits, with the same pins
+/// as `Fifo`. Data out holds the word on top of the stack, and pushing and popping on the same
+/// edge replaces it.
+///
+/// Spec:
+/// `data_in(0..width)`, `push()`, `pop()`, `clear()`, `clock()`
+///
+/// Outputs are `data_out(0..width)`, holding the top word or 0 while empty, then `full()`,
+/// `empty()` and `count(0..count_width)`.
+#[derive(Clone)]
+pub struct Stack {
+    pins: QueuePins,
+    storage: Vec<u64>,
+    count: usize
+}
+
+impl Stack {
+    pub(crate) fn new(capacity: usize, width: usize) -> Self {
+        Stack {
+            pins: QueuePins::new(capacity, width),
+            storage: vec![0; capacity],
+            count: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+queue_gate!(Stack);
+
+impl Gate for Stack {
+    fn get_input(&self, i: usize) -> usize {
+        self.pins.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.pins.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.pins.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.pins.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.pins.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.pins.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        self.pins.ports()
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        if let Some(request) = self.pins.clocked(lines, self.count) {
+            if request.clear {
+                self.count = 0;
+            } else {
+                if request.pop {
+                    self.count -= 1;
+                }
+                if let Some(value) = request.push {
+                    self.storage[self.count] = value;
+                    self.count += 1;
+                }
+            }
+        }
+        let top = if self.count > 0 { Some(self.storage[self.count - 1]) } else { None };
+        self.pins.drive(lines, top, self.count);
+    }
+
+    // The memory holds a zero word ahead of the stack, so that the count addresses the top word
+    // and reads as 0 while empty.
+    fn code(&self) -> Option<GateCode> {
+        if self.storage.len() >= MAX_MEMORY_WORDS {
+            return None;
+        }
+        let count = self.pins.count_bits();
+        let pop = step(&count, Expr::Constant(false), CARRY);
+        let mut push = step(&count, Expr::Constant(true), CARRY);
+        push.push(Statement::Write { memory: 0, address: state_word(&count),
+            value: self.pins.data() });
+        let mut memory = vec![0];
+        memory.extend_from_slice(&self.storage);
+        let read = Statement::Read {
+            memory: 0,
+            address: state_word(&count),
+            outputs: self.pins.data_out_lines()
+        };
+        Some(self.pins.code(self.count, &[], memory, vec![
+            Statement::If(Expr::State(POP), pop, Vec::new()),
+            Statement::If(Expr::State(PUSH), push, Vec::new())
+        ], read))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![
+            StateRegister::new("storage", self.pins.width, self.storage.len()),
+            StateRegister::new("count", self.pins.count_width, 1)
+        ]
+    }
+
+    fn peek_state(&self, register: usize, index: usize) -> Vec<Line> {
+        match register {
+            0 => word_to_lines(self.storage[index], self.pins.width),
+            _ => word_to_lines(self.count as u64, self.pins.count_width)
+        }
+    }
+
+    fn poke_state(&mut self, register: usize, index: usize, value: &[Line]) {
+        let value = lines_to_word(value);
+        match register {
+            0 => self.storage[index] = value,
+            _ => self.count = (value as usize).min(self.storage.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use circuit::circuit::Circuit;
+    use circuit::gate::tests::Rng;
+    use circuit::line::Line;
+    use super::*;
+
+    // Wires a queue's inputs to lines of their own and returns them with the gate.
+    fn wired<G: Gate + 'static>(gate: G) -> (Circuit, usize, Vec<usize>) {
+        let mut c = Circuit::new();
+        let g = c.add_gate(gate);
+        let inputs = (0..c.gates[g].num_inputs()).map(|i| {
+            let line = c.add_line(Line::Low);
+            c.set_gate_input(g, i, line);
+            line
+        }).collect();
+        (c, g, inputs)
+    }
+
+    fn output(c: &Circuit, g: usize, first: usize, width: usize) -> u64 {
+        (0..width).map(|b| (c.get_line_state(c.get_gate_output(g, first + b)).is_high() as u64)
+            << b).sum()
+    }
+
+    // Clocks random pushes, pops and clears into a queue, `lifo` picking the end pops come
+    // from, and checks every output against a `VecDeque` after each edge.
+    fn matches_model<G: Gate + 'static>(gate: G, capacity: usize, width: usize, lifo: bool) {
+        let count_width = address_bits(capacity + 1);
+        let (mut c, g, inputs) = wired(gate);
+        let mask = if width == 64 { !0 } else { (1 << width) - 1 };
+        let mut model = VecDeque::new();
+        let mut rng = Rng(capacity as u64 * 7 + width as u64);
+        for _ in 0..500 {
+            let value = rng.next() & mask;
+            let (push, pop, clear) = (rng.below(2) == 1, rng.below(2) == 1, rng.below(20) == 0);
+            for (b, &line) in inputs[..width].iter().enumerate() {
+                c.set_line(line, Line::from(value >> b & 1 == 1));
+            }
+            c.set_line(inputs[width], Line::from(push));
+            c.set_line(inputs[width + 1], Line::from(pop));
+            c.set_line(inputs[width + 2], Line::from(clear));
+            c.set_line(inputs[width + 3], Line::High);
+            c.eval();
+            c.set_line(inputs[width + 3], Line::Low);
+            c.eval();
+            if clear {
+                model.clear();
+            } else {
+                let pop = pop && !model.is_empty();
+                let push = push && (model.len() < capacity || pop);
+                if pop {
+                    if lifo { model.pop_back(); } else { model.pop_front(); }
+                }
+                if push {
+                    model.push_back(value);
+                }
+            }
+            let head = if lifo { model.back() } else { model.front() };
+            assert_eq!(output(&c, g, 0, width), head.cloned().unwrap_or(0));
+            assert_eq!(output(&c, g, width, 1) == 1, model.len() == capacity);
+            assert_eq!(output(&c, g, width + 1, 1) == 1, model.is_empty());
+            assert_eq!(output(&c, g, width + 2, count_width), model.len() as u64);
+        }
+    }
+
+    #[test]
+    fn fifo_matches_model() {
+        for &(capacity, width) in &[(1, 1), (3, 8), (4, 64)] {
+            matches_model(Fifo::new(capacity, width), capacity, width, false);
+        }
+    }
+
+    #[test]
+    fn stack_matches_model() {
+        for &(capacity, width) in &[(1, 1), (3, 8), (4, 64)] {
+            matches_model(Stack::new(capacity, width), capacity, width, true);
+        }
+    }
+
+    #[test]
+    fn full_queue_pushes_and_pops_on_one_edge() {
+        let (mut c, g, inputs) = wired(Fifo::new(1, 4));
+        for &(value, pop) in &[(5, false), (9, true)] {
+            for (b, &line) in inputs[..4].iter().enumerate() {
+                c.set_line(line, Line::from(value >> b & 1 == 1));
+            }
+            c.set_line(inputs[4], Line::High);
+            c.set_line(inputs[5], Line::from(pop));
+            c.set_line(inputs[7], Line::High);
+            c.eval();
+            c.set_line(inputs[7], Line::Low);
+            c.eval();
+            assert_eq!(output(&c, g, 0, 4), value);
+            assert_eq!(output(&c, g, 4, 1), 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and 64")]
+    fn word_width_is_limited() {
+        Fifo::new(2, 65);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one word")]
+    fn capacity_is_at_least_one() {
+        Stack::new(0, 8);
+    }
+}
@@ -1,6 +1,173 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
 use circuit::line::Line;
+use circuit::memory::WordMemory;
+use circuit::circuit::Circuit;
+use circuit::codegen::GateCode;
+
+/// A named piece of internal state that a gate exposes for debugging and test setup: `depth`
+/// entries of `width` bits each. A flip-flop has one entry of one bit, a RAM one entry per word.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateRegister {
+    pub name: &'static str,
+    pub width: usize,
+    pub depth: usize
+}
+
+impl StateRegister {
+    pub fn new(name: &'static str, width: usize, depth: usize) -> Self {
+        StateRegister {
+            name,
+            width,
+            depth
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortDirection {
+    Input,
+    Output
+}
+
+/// What a port does, so that tools can tell clocks and enables apart from ordinary data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortRole {
+    Data,
+    Address,
+    Select,
+    Clock,
+    Enable,
+    /// Any other control signal, such as load, write, clear or push.
+    Control
+}
+
+/// A named group of `width` consecutive pins starting at pin `first`. Single-bit ports have a
+/// width of 1, and buses hold their least significant bit at `first`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Port {
+    pub name: String,
+    pub direction: PortDirection,
+    pub first: usize,
+    pub width: usize,
+    pub role: PortRole
+}
+
+impl Port {
+    pub fn input(name: &str, first: usize, width: usize) -> Self {
+        Port {
+            name: name.to_string(),
+            direction: PortDirection::Input,
+            first,
+            width,
+            role: PortRole::Data
+        }
+    }
+
+    pub fn output(name: &str, first: usize, width: usize) -> Self {
+        Port {
+            name: name.to_string(),
+            direction: PortDirection::Output,
+            first,
+            width,
+            role: PortRole::Data
+        }
+    }
+
+    pub fn with_role(mut self, role: PortRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn pins(&self) -> Range<usize> {
+        self.first..self.first + self.width
+    }
+
+    /// Name of one pin of the port: the port name for single-bit ports and `name[bit]` for
+    /// buses.
+    pub fn pin_name(&self, pin: usize) -> String {
+        assert!(self.pins().contains(&pin), "Pin isn't part of this port.");
+        if self.width == 1 {
+            self.name.clone()
+        } else {
+            format!("{}[{}]", self.name, pin - self.first)
+        }
+    }
+}
+
+/// A mismatch between the ports a gate describes and the pins it actually has.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortError {
+    /// A pin that no port covers.
+    Uncovered { direction: PortDirection, pin: usize },
+    /// A pin that more than one port covers.
+    Overlap { direction: PortDirection, pin: usize },
+    /// A port that reaches past the gate's last pin.
+    OutOfRange { name: String, direction: PortDirection, pins: usize }
+}
+
+impl fmt::Display for PortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PortError::Uncovered { direction, pin } => {
+                write!(f, "{:?} pin {} isn't part of any port", direction, pin)
+            },
+            PortError::Overlap { direction, pin } => {
+                write!(f, "{:?} pin {} is part of more than one port", direction, pin)
+            },
+            PortError::OutOfRange { ref name, direction, pins } => {
+                write!(f, "port {} reaches past the gate's {} {:?} pins", name, pins, direction)
+            }
+        }
+    }
+}
+
+impl Error for PortError {}
+
+/// The primitive function a gate computes, for tools that analyse circuits structurally rather
+/// than by evaluating them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GateKind {
+    /// Drives its single output with a fixed value.
+    Constant(Line),
+    /// Reads its single input and has no outputs.
+    Sink,
+    Not,
+    And,
+    Or,
+    Xor,
+    Nand,
+    Nor,
+    Xnor,
+    /// `Mux1_2`: inputs 0 and 1 selected by input 2.
+    Mux,
+    /// Anything else, including every gate that holds state. See `Gate::lower`.
+    Other
+}
+
+/// Copies a gate behind a `Box<dyn Gate>`, so that circuits can be cloned. Every gate that is
+/// `Clone` gets it.
+pub trait GateClone {
+    fn clone_gate(&self) -> Box<dyn Gate>;
+}
 
-pub trait Gate {
+impl<T: Gate + Clone + 'static> GateClone for T {
+    fn clone_gate(&self) -> Box<dyn Gate> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Gate> {
+    fn clone(&self) -> Self {
+        self.clone_gate()
+    }
+}
+
+/// Gates are `Send` so that `ParallelCircuit` can evaluate them on other threads, and `Clone` so
+/// that `Batch` can run copies of a circuit.
+pub trait Gate: Send + GateClone {
     fn get_input(&self, i: usize) -> usize;
     fn set_input(&mut self, i: usize, new_i: usize);
     fn num_inputs(&self) -> usize;
@@ -8,4 +175,427 @@ pub trait Gate {
     fn set_output(&mut self, o: usize, new_o: usize);
     fn num_outputs(&self) -> usize;
     fn eval(&mut self, lines: &mut Vec<Line>);
-}
\ No newline at end of file
+
+    /// Contents of gates that hold addressable memory, such as `Ram` and `Rom`.
+    fn memory(&self) -> Option<&dyn WordMemory> {
+        None
+    }
+
+    fn memory_mut(&mut self) -> Option<&mut dyn WordMemory> {
+        None
+    }
+
+    /// Internal state registers, indexed in the order given here by `peek_state` and
+    /// `poke_state`. Stateless gates have none.
+    fn state_registers(&self) -> Vec<StateRegister> {
+        Vec::new()
+    }
+
+    /// Reads entry `index` of state register `register`. Callers must stay within what
+    /// `state_registers` describes.
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        panic!("Gate has no state registers.");
+    }
+
+    /// Overwrites entry `index` of state register `register` with `value`, which must be as wide
+    /// as the register. The new state reaches the gate's outputs the next time it's evaluated.
+    fn poke_state(&mut self, _register: usize, _index: usize, _value: &[Line]) {
+        panic!("Gate has no state registers.");
+    }
+
+    fn kind(&self) -> GateKind {
+        GateKind::Other
+    }
+
+    /// Current state and behaviour of a gate that holds state, for `generate_code`.
+    fn code(&self) -> Option<GateCode> {
+        None
+    }
+
+    /// Gate-level equivalent of a combinational gate of kind `Other`, as a circuit whose marked
+    /// inputs and outputs line up with this gate's input and output pins. Gates without one,
+    /// such as those holding state, return `None`.
+    fn lower(&self) -> Option<Circuit> {
+        None
+    }
+
+    /// Named groups of input and output pins. Together they must cover every pin exactly once,
+    /// which `check_ports` verifies. Gates that don't describe their pins get one `in` bus and
+    /// one `out` bus.
+    fn ports(&self) -> Vec<Port> {
+        let mut ports = Vec::new();
+        if self.num_inputs() > 0 {
+            ports.push(Port::input("in", 0, self.num_inputs()));
+        }
+        if self.num_outputs() > 0 {
+            ports.push(Port::output("out", 0, self.num_outputs()));
+        }
+        ports
+    }
+}
+
+/// Checks that the ports of `gate` cover each of its input and output pins exactly once.
+pub fn check_ports(gate: &dyn Gate) -> Result<(), PortError> {
+    let ports = gate.ports();
+    for &(direction, pins) in &[(PortDirection::Input, gate.num_inputs()),
+        (PortDirection::Output, gate.num_outputs())] {
+        let mut covered = vec![false; pins];
+        for port in ports.iter().filter(|port| port.direction == direction) {
+            if port.first + port.width > pins {
+                return Err(PortError::OutOfRange { name: port.name.clone(), direction, pins });
+            }
+            for pin in port.pins() {
+                if covered[pin] {
+                    return Err(PortError::Overlap { direction, pin });
+                }
+                covered[pin] = true;
+            }
+        }
+        if let Some(pin) = covered.iter().position(|&covered| !covered) {
+            return Err(PortError::Uncovered { direction, pin });
+        }
+    }
+    Ok(())
+}
+
+/// Finds the port that pin `pin` in `direction` belongs to and names the pin, falling back to
+/// its index for pins outside of every port.
+pub fn pin_name(gate: &dyn Gate, direction: PortDirection, pin: usize) -> String {
+    gate.ports().into_iter()
+        .find(|port| port.direction == direction && port.pins().contains(&pin))
+        .map_or_else(|| format!("#{}", pin), |port| port.pin_name(pin))
+}
+
+/// Builds the circuit for `Gate::lower`: `inputs` marked input lines are handed to `build`, and
+/// the lines it returns are marked as outputs.
+pub(crate) fn lower_with<F>(inputs: usize, build: F) -> Circuit
+    where F: FnOnce(&[usize], &mut Circuit) -> Vec<usize> {
+    let mut circuit = Circuit::new();
+    let lines = (0..inputs).map(|_| {
+        let line = circuit.add_line(Line::Low);
+        circuit.mark_line_as_circuit_input(line);
+        line
+    }).collect::<Vec<_>>();
+    for output in build(&lines, &mut circuit) {
+        circuit.mark_line_as_circuit_output(output);
+    }
+    circuit
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use circuit::basics::Inverter;
+    use circuit::circuit::Circuit;
+    use circuit::line::Line;
+    use circuit::encoder::Decoder;
+    use circuit::fifo::Fifo;
+    use circuit::memory::{DFlipFlop, Edge, RamKind, RamPort, Ram, RegisterFile, Rom};
+    use circuit::mux::{Mux, Mux1_2};
+    use circuit::sequential::{Counter, Register};
+    use circuit::simplegate::SimpleGate;
+    use super::*;
+
+    /// Xorshift generator, so that tests can draw reproducible random stimuli without any
+    /// dependencies.
+    pub(crate) struct Rng(pub(crate) u64);
+
+    impl Rng {
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        pub(crate) fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        pub(crate) fn line(&mut self) -> Line {
+            Line::from(self.next() & 1 == 1)
+        }
+    }
+
+    /// A circuit holding only `gate`, with a marked input line for each of its input pins and
+    /// its outputs marked.
+    pub(crate) fn single_gate<G: Gate + 'static>(gate: G) -> Circuit {
+        let mut circuit = Circuit::new();
+        let g = circuit.add_gate(gate);
+        for i in 0..circuit.gates[g].num_inputs() {
+            let line = circuit.add_line(Line::Low);
+            circuit.mark_line_as_circuit_input(line);
+            circuit.set_gate_input(g, i, line);
+        }
+        for o in 0..circuit.gates[g].num_outputs() {
+            let line = circuit.get_gate_output(g, o);
+            circuit.mark_line_as_circuit_output(line);
+        }
+        circuit
+    }
+
+    /// Drives the marked inputs of `circuit` with `inputs`, lets it settle and reads its marked
+    /// outputs.
+    pub(crate) fn respond(circuit: &mut Circuit, inputs: &[Line]) -> Vec<Line> {
+        assert_eq!(inputs.len(), circuit.inputs.len(), "Need one value per circuit input.");
+        for (i, &state) in inputs.iter().enumerate() {
+            circuit.set_circuit_input(i, state);
+        }
+        assert!(circuit.eval_until_stable(1000).is_some(), "Circuit didn't settle.");
+        (0..circuit.outputs.len()).map(|o| circuit.get_circuit_output(o)).collect()
+    }
+
+    /// Whether two lines are the same state, telling disconnected apart from low.
+    pub(crate) fn same(a: Line, b: Line) -> bool {
+        a.is_high() == b.is_high() && a.is_disconnected() == b.is_disconnected()
+    }
+
+    /// Checks that `Gate::lower` of `gate` computes the same outputs as its `eval` for every
+    /// combination of high and low inputs, or for 500 random ones if there are more than 10
+    /// inputs.
+    pub(crate) fn assert_lowering_matches<G: Gate + Clone + 'static>(gate: G) {
+        let mut lowered = gate.lower().expect("Gate has no lowering.");
+        let mut behavioral = single_gate(gate);
+        let inputs = behavioral.inputs.len();
+        assert_eq!(lowered.inputs.len(), inputs, "Lowering has the wrong number of inputs.");
+        assert_eq!(lowered.outputs.len(), behavioral.outputs.len(),
+            "Lowering has the wrong number of outputs.");
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let vectors = if inputs <= 10 { 1 << inputs } else { 500 };
+        for v in 0..vectors {
+            let stimulus = (0..inputs)
+                .map(|i| if inputs <= 10 { Line::from(v >> i & 1 == 1) } else { rng.line() })
+                .collect::<Vec<_>>();
+            let expected = respond(&mut behavioral, &stimulus);
+            let actual = respond(&mut lowered, &stimulus);
+            assert!(expected.iter().zip(&actual).all(|(&a, &b)| same(a, b)),
+                "Lowering differs for inputs {:?}: expected {:?}, got {:?}.", stimulus, expected,
+                actual);
+        }
+    }
+
+    /// Random combinational circuit over `inputs` marked inputs: `gates` gates, each an AND,
+    /// OR, XOR, inverter or 2:1 mux reading any earlier line, with the last `outputs` lines
+    /// marked as outputs. The same `seed` always gives the same function, and `rewritten` builds
+    /// every gate from others by De Morgan's laws instead, so the two versions are equivalent but
+    /// share no structure.
+    pub(crate) fn random_logic(seed: u64, inputs: usize, gates: usize, outputs: usize,
+        rewritten: bool) -> Circuit {
+        let mut rng = Rng(seed);
+        let mut c = Circuit::new();
+        let mut lines = (0..inputs).map(|_| {
+            let line = c.add_line(Line::Low);
+            c.mark_line_as_circuit_input(line);
+            line
+        }).collect::<Vec<_>>();
+        if lines.is_empty() {
+            lines.push(c.add_line(Line::Low));
+        }
+        for _ in 0..gates {
+            let kind = rng.below(5);
+            let mut pick = || lines[rng.below(lines.len() as u64) as usize];
+            let (a, b, select) = (pick(), pick(), pick());
+            let out = match (kind, rewritten) {
+                (0, false) => two(&mut c, SimpleGate::and(), a, b),
+                (0, true) => {
+                    let (a, b) = (not(&mut c, a), not(&mut c, b));
+                    two(&mut c, SimpleGate::nor(), a, b)
+                },
+                (1, false) => two(&mut c, SimpleGate::or(), a, b),
+                (1, true) => {
+                    let (a, b) = (not(&mut c, a), not(&mut c, b));
+                    two(&mut c, SimpleGate::nand(), a, b)
+                },
+                (2, false) => two(&mut c, SimpleGate::xor(), a, b),
+                (2, true) => {
+                    let a = not(&mut c, a);
+                    two(&mut c, SimpleGate::xnor(), a, b)
+                },
+                (3, false) => not(&mut c, a),
+                (3, true) => two(&mut c, SimpleGate::nand(), a, a),
+                (_, false) => {
+                    let mux = c.add_gate(Mux1_2::new());
+                    for (i, &line) in [a, b, select].iter().enumerate() {
+                        c.set_gate_input(mux, i, line);
+                    }
+                    c.get_gate_output(mux, 0)
+                },
+                (_, true) => {
+                    let high = two(&mut c, SimpleGate::and(), select, b);
+                    let low_select = not(&mut c, select);
+                    let low = two(&mut c, SimpleGate::and(), low_select, a);
+                    two(&mut c, SimpleGate::or(), high, low)
+                }
+            };
+            lines.push(out);
+        }
+        for &line in &lines[lines.len() - outputs..] {
+            c.mark_line_as_circuit_output(line);
+        }
+        c
+    }
+
+    fn two(c: &mut Circuit, gate: SimpleGate, a: usize, b: usize) -> usize {
+        let g = c.add_gate(gate);
+        c.set_gate_input(g, 0, a);
+        c.set_gate_input(g, 1, b);
+        c.get_gate_output(g, 0)
+    }
+
+    fn not(c: &mut Circuit, a: usize) -> usize {
+        let g = c.add_gate(Inverter::new());
+        c.set_gate_input(g, 0, a);
+        c.get_gate_output(g, 0)
+    }
+
+    /// Gate with the given pin counts and ports, for checking port validation.
+    #[derive(Clone)]
+    struct Described {
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+        ports: Vec<Port>
+    }
+
+    impl Described {
+        fn new(inputs: usize, outputs: usize, ports: Vec<Port>) -> Self {
+            Described {
+                inputs: vec![0; inputs],
+                outputs: vec![0; outputs],
+                ports
+            }
+        }
+    }
+
+    // The pin bookkeeping of the test gates, which only differ in their ports.
+    macro_rules! pins {
+        () => {
+            fn get_input(&self, i: usize) -> usize {
+                self.inputs[i]
+            }
+
+            fn set_input(&mut self, i: usize, new_i: usize) {
+                self.inputs[i] = new_i;
+            }
+
+            fn num_inputs(&self) -> usize {
+                self.inputs.len()
+            }
+
+            fn get_output(&self, o: usize) -> usize {
+                self.outputs[o]
+            }
+
+            fn set_output(&mut self, o: usize, new_o: usize) {
+                self.outputs[o] = new_o;
+            }
+
+            fn num_outputs(&self) -> usize {
+                self.outputs.len()
+            }
+
+            fn eval(&mut self, _lines: &mut Vec<Line>) {}
+        }
+    }
+
+    impl Gate for Described {
+        pins!();
+
+        fn ports(&self) -> Vec<Port> {
+            self.ports.clone()
+        }
+    }
+
+    /// Gate that keeps the default ports.
+    #[derive(Clone)]
+    struct Undescribed {
+        inputs: Vec<usize>,
+        outputs: Vec<usize>
+    }
+
+    impl Gate for Undescribed {
+        pins!();
+    }
+
+    #[test]
+    fn built_in_gates_describe_every_pin() {
+        let gates: Vec<Box<dyn Gate>> = vec![
+            Box::new(SimpleGate::and()),
+            Box::new(Mux1_2::new()),
+            Box::new(Mux::new(4, 8)),
+            Box::new(Decoder::new(3)),
+            Box::new(DFlipFlop::new(Edge::Rising)),
+            Box::new(Ram::new(3, 8, RamKind::MasterSlave,
+                &[RamPort::ReadWrite, RamPort::Read, RamPort::Write])),
+            Box::new(Rom::new(0, 4, 2)),
+            Box::new(RegisterFile::new(5, 64)),
+            Box::new(Register::new(0)),
+            Box::new(Counter::new(64)),
+            Box::new(Fifo::new(3, 8))
+        ];
+        for gate in &gates {
+            assert_eq!(check_ports(&**gate), Ok(()));
+        }
+    }
+
+    #[test]
+    fn default_ports_are_in_and_out() {
+        let gate = Undescribed { inputs: vec![0; 3], outputs: vec![0; 2] };
+        assert_eq!(gate.ports(), vec![Port::input("in", 0, 3), Port::output("out", 0, 2)]);
+        assert_eq!(check_ports(&gate), Ok(()));
+        let gate = Undescribed { inputs: Vec::new(), outputs: vec![0] };
+        assert_eq!(gate.ports(), vec![Port::output("out", 0, 1)]);
+    }
+
+    #[test]
+    fn mismatched_ports_are_reported() {
+        let uncovered = Described::new(4, 1, vec![Port::input("a", 0, 2), Port::input("b", 3, 1),
+            Port::output("q", 0, 1)]);
+        assert_eq!(check_ports(&uncovered), Err(PortError::Uncovered {
+            direction: PortDirection::Input, pin: 2 }));
+        let overlap = Described::new(2, 2, vec![Port::input("a", 0, 2), Port::output("q", 0, 2),
+            Port::output("r", 1, 1)]);
+        assert_eq!(check_ports(&overlap), Err(PortError::Overlap {
+            direction: PortDirection::Output, pin: 1 }));
+        let past_end = Described::new(2, 0, vec![Port::input("a", 1, 2)]);
+        assert_eq!(check_ports(&past_end), Err(PortError::OutOfRange { name: "a".to_string(),
+            direction: PortDirection::Input, pins: 2 }));
+        assert_eq!(check_ports(&Described::new(0, 0, Vec::new())), Ok(()));
+    }
+
+    #[test]
+    fn pins_are_named_by_port() {
+        let gate = Described::new(3, 1, vec![Port::input("a", 0, 1), Port::input("b", 1, 2),
+            Port::output("q", 0, 1)]);
+        assert_eq!(pin_name(&gate, PortDirection::Input, 0), "a");
+        assert_eq!(pin_name(&gate, PortDirection::Input, 2), "b[1]");
+        assert_eq!(pin_name(&gate, PortDirection::Output, 0), "q");
+        assert_eq!(pin_name(&gate, PortDirection::Output, 5), "#5");
+        let ram = Ram::new(2, 4, RamKind::Latch, &[RamPort::ReadWrite]);
+        let address = Gate::ports(&ram).into_iter().find(|port| port.name.contains("address"))
+            .unwrap();
+        assert_eq!(address.role, PortRole::Address);
+        assert_eq!(address.pins(), 0..2);
+    }
+
+    #[test]
+    fn circuit_reports_the_gate_with_bad_ports() {
+        let mut c = Circuit::new();
+        c.add_gate(SimpleGate::and());
+        // Added directly, since `add_gate` rejects it in debug builds.
+        c.gates.push(Box::new(Described::new(1, 0, Vec::new())));
+        assert_eq!(c.check_ports(), Err((1, PortError::Uncovered {
+            direction: PortDirection::Input, pin: 0 })));
+        assert_eq!(c.get_pin_name(0, PortDirection::Input, 1), "b");
+    }
+
+    #[test]
+    #[should_panic(expected = "Gate ports don't match its pins")]
+    fn add_gate_checks_ports() {
+        Circuit::new().add_gate(Described::new(1, 1, vec![Port::output("q", 0, 1)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't part of this port")]
+    fn pin_outside_port_has_no_name() {
+        Port::input("a", 2, 2).pin_name(1);
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+
+use circuit::memory::WordMemory;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// A record that couldn't be parsed, with its 1-based line number.
+    Syntax { line: usize, message: &'static str },
+    Checksum { line: usize, expected: u8, found: u8 },
+    UnsupportedRecord { line: usize, record: u8 },
+    /// A byte landed outside of the memory it was being loaded into.
+    OutOfRange { address: usize, words: usize }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref e) => write!(f, "{}", e),
+            ImageError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ImageError::Checksum { line, expected, found } => {
+                write!(f, "line {}: checksum is {:02X}, expected {:02X}", line, found, expected)
+            },
+            ImageError::UnsupportedRecord { line, record } => {
+                write!(f, "line {}: unsupported record type {}", line, record)
+            },
+            ImageError::OutOfRange { address, words } => {
+                write!(f, "word {:#X} is outside of a memory of {} words", address, words)
+            }
+        }
+    }
+}
+
+impl Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+/// File formats `MemoryImage` reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Raw bytes, loaded at address 0.
+    Binary,
+    IntelHex,
+    /// Motorola S-records.
+    SRecord
+}
+
+impl ImageFormat {
+    /// Guesses the format from a file extension: `.hex`, `.ihx` and `.ihex` are Intel HEX,
+    /// `.srec`, `.s19`, `.s28`, `.s37` and `.mot` are S-records and anything else is binary.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path.as_ref().extension().and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("hex") | Some("ihx") | Some("ihex") => ImageFormat::IntelHex,
+            Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => {
+                ImageFormat::SRecord
+            },
+            _ => ImageFormat::Binary
+        }
+    }
+}
+
+/// Sparse, byte-addressed memory contents, as read from or written to a file.
+///
+/// When loaded into a memory gate, each word takes `ceil(data_width / 8)` consecutive bytes,
+/// least significant byte first, so byte address `a` lands in word `a / bytes_per_word`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryImage {
+    bytes: BTreeMap<usize, u8>
+}
+
+fn bytes_per_word(data_width: usize) -> usize {
+    data_width.div_ceil(8)
+}
+
+fn parse_hex_bytes(text: &str, line: usize) -> Result<Vec<u8>, ImageError> {
+    if !text.len().is_multiple_of(2) {
+        return Err(ImageError::Syntax { line, message: "odd number of hex digits" });
+    }
+    (0..text.len()).step_by(2).map(|i| {
+        text.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or(ImageError::Syntax { line, message: "invalid hex digit" })
+    }).collect()
+}
+
+impl MemoryImage {
+    pub fn new() -> Self {
+        MemoryImage {
+            bytes: BTreeMap::new()
+        }
+    }
+
+    pub fn get(&self, address: usize) -> Option<u8> {
+        self.bytes.get(&address).cloned()
+    }
+
+    pub fn set(&mut self, address: usize, byte: u8) {
+        self.bytes.insert(address, byte);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Raw bytes placed from `base` up.
+    pub fn from_binary(data: &[u8], base: usize) -> Self {
+        MemoryImage {
+            bytes: data.iter().enumerate().map(|(i, &b)| (base + i, b)).collect()
+        }
+    }
+
+    /// Reads an image from a file in the format its extension names, as with
+    /// `ImageFormat::from_extension`. Use `from_file_as` for files named differently.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let format = ImageFormat::from_extension(&path);
+        MemoryImage::from_file_as(path, format)
+    }
+
+    /// Reads an image from a file in the given format. Text formats must be valid UTF-8.
+    pub fn from_file_as<P: AsRef<Path>>(path: P, format: ImageFormat)
+        -> Result<Self, ImageError> {
+        let data = fs::read(path)?;
+        let text = || str::from_utf8(&data)
+            .map_err(|e| ImageError::Io(io::Error::new(io::ErrorKind::InvalidData, e)));
+        match format {
+            ImageFormat::Binary => Ok(MemoryImage::from_binary(&data, 0)),
+            ImageFormat::IntelHex => MemoryImage::from_intel_hex(text()?),
+            ImageFormat::SRecord => MemoryImage::from_srecord(text()?)
+        }
+    }
+
+    /// Parses Intel HEX, including extended segment and linear address records. Start address
+    /// records are accepted and ignored.
+    pub fn from_intel_hex(text: &str) -> Result<Self, ImageError> {
+        let mut image = MemoryImage::new();
+        let mut base = 0;
+        for (i, record) in text.lines().enumerate() {
+            let line = i + 1;
+            let record = record.trim();
+            if record.is_empty() {
+                continue;
+            }
+            if !record.starts_with(':') {
+                return Err(ImageError::Syntax { line, message: "record doesn't start with ':'" });
+            }
+            let bytes = parse_hex_bytes(&record[1..], line)?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(ImageError::Syntax { line, message: "wrong record length" });
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+            if expected != checksum[0] {
+                return Err(ImageError::Checksum { line, expected, found: checksum[0] });
+            }
+            let offset = (body[1] as usize) << 8 | body[2] as usize;
+            let data = &body[4..];
+            match body[3] {
+                0x00 => for (j, &b) in data.iter().enumerate() {
+                    image.set(base + offset + j, b);
+                },
+                0x01 => break,
+                0x02 | 0x04 if data.len() != 2 => {
+                    return Err(ImageError::Syntax { line, message: "wrong address record length" });
+                },
+                0x02 => base = ((data[0] as usize) << 8 | data[1] as usize) << 4,
+                0x04 => base = ((data[0] as usize) << 8 | data[1] as usize) << 16,
+                0x03 | 0x05 => {},
+                record => return Err(ImageError::UnsupportedRecord { line, record })
+            }
+        }
+        Ok(image)
+    }
+
+    /// Parses Motorola S-records. Header, count and start address records are accepted and
+    /// ignored.
+    pub fn from_srecord(text: &str) -> Result<Self, ImageError> {
+        let mut image = MemoryImage::new();
+        for (i, record) in text.lines().enumerate() {
+            let line = i + 1;
+            let record = record.trim();
+            if record.is_empty() {
+                continue;
+            }
+            if !record.starts_with('S') {
+                return Err(ImageError::Syntax { line, message: "record doesn't start with 'S'" });
+            }
+            if record.len() < 4 {
+                return Err(ImageError::Syntax { line, message: "record too short" });
+            }
+            let kind = record.as_bytes()[1];
+            let digits = record.get(2..)
+                .ok_or(ImageError::Syntax { line, message: "invalid record type" })?;
+            let bytes = parse_hex_bytes(digits, line)?;
+            if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+                return Err(ImageError::Syntax { line, message: "wrong record length" });
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = !body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if expected != checksum[0] {
+                return Err(ImageError::Checksum { line, expected, found: checksum[0] });
+            }
+            let address_bytes = match kind {
+                b'1' => 2,
+                b'2' => 3,
+                b'3' => 4,
+                b'0' | b'5' | b'6' | b'7' | b'8' | b'9' => continue,
+                _ => {
+                    let record = kind.wrapping_sub(b'0');
+                    return Err(ImageError::UnsupportedRecord { line, record });
+                }
+            };
+            if body.len() < 1 + address_bytes {
+                return Err(ImageError::Syntax { line, message: "record too short for address" });
+            }
+            let address = body[1..1 + address_bytes].iter()
+                .fold(0, |address, &b| address << 8 | b as usize);
+            for (j, &b) in body[1 + address_bytes..].iter().enumerate() {
+                image.set(address + j, b);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Copies every word of `memory` into a new image.
+    pub fn from_memory(memory: &dyn WordMemory) -> Self {
+        let per_word = bytes_per_word(memory.data_width());
+        let mut image = MemoryImage::new();
+        for word in 0..memory.words() {
+            let value = memory.get_word(word);
+            for b in 0..per_word {
+                image.set(word * per_word + b, (value >> (8 * b)) as u8);
+            }
+        }
+        image
+    }
+
+    /// Writes the image into `memory`. Bits above the memory's data width are dropped, and
+    /// bytes beyond the end of the memory are an error, in which case nothing is written.
+    pub fn load_into(&self, memory: &mut dyn WordMemory) -> Result<(), ImageError> {
+        let per_word = bytes_per_word(memory.data_width());
+        if let Some((&last, _)) = self.bytes.iter().next_back() {
+            if last / per_word >= memory.words() {
+                return Err(ImageError::OutOfRange {
+                    address: last / per_word,
+                    words: memory.words()
+                });
+            }
+        }
+        let mask = !0 >> (64 - memory.data_width());
+        let mut word = None;
+        let mut value = 0;
+        for (&address, &byte) in &self.bytes {
+            let this_word = address / per_word;
+            if word != Some(this_word) {
+                if let Some(previous) = word {
+                    memory.set_word(previous, value & mask);
+                }
+                word = Some(this_word);
+                value = memory.get_word(this_word);
+            }
+            let shift = 8 * (address % per_word);
+            value = value & !(0xFF << shift) | (byte as u64) << shift;
+        }
+        if let Some(word) = word {
+            memory.set_word(word, value & mask);
+        }
+        Ok(())
+    }
+
+    /// The bytes from address 0 to the highest address set, with gaps filled with zeroes.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let len = self.bytes.keys().next_back().map_or(0, |&last| last + 1);
+        let mut data = vec![0; len];
+        for (&address, &byte) in &self.bytes {
+            data[address] = byte;
+        }
+        data
+    }
+}
+
+/// Formats the contents of `memory` as a hexdump, one row of words per line prefixed with the
+/// address of its first word. Byte-wide memories also get an ASCII column. Runs of rows equal to
+/// the one before them are collapsed into a single `*`.
+pub fn hexdump(memory: &dyn WordMemory) -> String {
+    let width = memory.data_width();
+    let digits = width.div_ceil(4);
+    let per_row = match bytes_per_word(width) {
+        1 => 16,
+        2 => 8,
+        3 | 4 => 4,
+        _ => 2
+    };
+    let address_digits = format!("{:X}", memory.words().saturating_sub(1)).len().max(4);
+    let mut out = String::new();
+    let mut previous = None;
+    let mut collapsed = false;
+    for row_start in (0..memory.words()).step_by(per_row) {
+        let row = (row_start..(row_start + per_row).min(memory.words()))
+            .map(|w| memory.get_word(w)).collect::<Vec<_>>();
+        if previous.as_ref() == Some(&row) {
+            if !collapsed {
+                out.push_str("*\n");
+                collapsed = true;
+            }
+            continue;
+        }
+        collapsed = false;
+        out.push_str(&format!("{:0w$X}:", row_start, w = address_digits));
+        for word in &row {
+            out.push_str(&format!(" {:0w$X}", word, w = digits));
+        }
+        if width <= 8 {
+            for _ in row.len()..per_row {
+                out.push_str(&" ".repeat(digits + 1));
+            }
+            let ascii = row.iter().map(|&w| match w as u8 {
+                c @ 0x20..=0x7E => c as char,
+                _ => '.'
+            }).collect::<String>();
+            out.push_str(&format!("  |{}|", ascii));
+        }
+        out.push('\n');
+        previous = Some(row);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use circuit::memory::{Ram, RamKind, RamPort, WordMemory};
+    use super::*;
+
+    const HEX: &str = ":10010000214601360121470136007EFE09D2190140\n\
+        :100110002146017E17C20001FF5F16002148011928\n:00000001FF\n";
+    const SREC: &str = "S00F000068656C6C6F202020202000003C\n\
+        S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\n\
+        S5030001FB\nS9030000FC\n";
+
+    fn ram(address_width: usize, data_width: usize) -> Ram {
+        Ram::new(address_width, data_width, RamKind::Latch, &[RamPort::ReadWrite])
+    }
+
+    #[test]
+    fn parses_intel_hex() {
+        let image = MemoryImage::from_intel_hex(HEX).unwrap();
+        assert_eq!(image.len(), 32);
+        assert_eq!(image.get(0x100), Some(0x21));
+        assert_eq!(image.get(0x11F), Some(0x19));
+        assert_eq!(image.get(0xFF), None);
+    }
+
+    #[test]
+    fn intel_hex_extended_linear_address() {
+        let image = MemoryImage::from_intel_hex(":020000040001F9\n:01001000559A\n").unwrap();
+        assert_eq!(image.get(0x1_0010), Some(0x55));
+    }
+
+    #[test]
+    fn reports_bad_checksum() {
+        match MemoryImage::from_intel_hex(":10010000214601360121470136007EFE09D2190141") {
+            Err(ImageError::Checksum { line: 1, expected: 0x40, found: 0x41 }) => {},
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parses_srecords() {
+        let image = MemoryImage::from_srecord(SREC).unwrap();
+        assert_eq!(&image.to_binary()[..4], &[0x7C, 0x08, 0x02, 0xA6]);
+    }
+
+    #[test]
+    fn short_srecord_is_a_length_error() {
+        match MemoryImage::from_srecord("S1") {
+            Err(ImageError::Syntax { line: 1, message }) => assert_eq!(message, "record too short"),
+            other => panic!("Unexpected result {:?}", other)
+        }
+        match MemoryImage::from_srecord("X1030000FC") {
+            Err(ImageError::Syntax { message, .. }) => assert!(message.contains("start")),
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn format_comes_from_extension() {
+        assert_eq!(ImageFormat::from_extension("rom.HEX"), ImageFormat::IntelHex);
+        assert_eq!(ImageFormat::from_extension("boot.s19"), ImageFormat::SRecord);
+        assert_eq!(ImageFormat::from_extension("data.bin"), ImageFormat::Binary);
+        assert_eq!(ImageFormat::from_extension("noextension"), ImageFormat::Binary);
+    }
+
+    #[test]
+    fn binary_files_starting_like_text_stay_binary() {
+        let dir = env::temp_dir();
+        let binary = dir.join(format!("logic_image_test_{}.bin", ::std::process::id()));
+        fs::write(&binary, b":S\x00\xFF").unwrap();
+        let image = MemoryImage::from_file(&binary).unwrap();
+        assert_eq!(image.to_binary(), b":S\x00\xFF".to_vec());
+        let hex = binary.with_extension("hex");
+        fs::write(&hex, HEX).unwrap();
+        assert_eq!(MemoryImage::from_file(&hex).unwrap().get(0x100), Some(0x21));
+        assert!(MemoryImage::from_file_as(&binary, ImageFormat::IntelHex).is_err());
+        fs::remove_file(binary).unwrap();
+        fs::remove_file(hex).unwrap();
+    }
+
+    #[test]
+    fn words_round_trip_through_images() {
+        for &width in &[1, 8, 12, 64] {
+            let mut source = ram(4, width);
+            for word in 0..16 {
+                source.set_word(word, 0x0123_4567_89AB_CDEFu64.rotate_left(word as u32 * 5));
+            }
+            let image = MemoryImage::from_memory(&source);
+            let mut target = ram(4, width);
+            image.load_into(&mut target).unwrap();
+            assert!((0..16).all(|word| target.get_word(word) == source.get_word(word)));
+        }
+    }
+
+    #[test]
+    fn loading_past_the_end_writes_nothing() {
+        let image = MemoryImage::from_binary(&[1, 2, 3], 15);
+        let mut target = ram(4, 8);
+        match image.load_into(&mut target) {
+            Err(ImageError::OutOfRange { address: 17, words: 16 }) => {},
+            other => panic!("Unexpected result {:?}", other)
+        }
+        assert_eq!(target.get_word(15), 0);
+    }
+
+    #[test]
+    fn hexdump_collapses_repeated_rows() {
+        let mut memory = ram(6, 8);
+        memory.set_word(0, b'A' as u64);
+        let dump = hexdump(&memory);
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0000: 41 00"));
+        assert!(lines[0].ends_with("|A...............|"));
+        assert_eq!(lines[2], "*");
+    }
+}
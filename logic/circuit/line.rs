@@ -75,6 +75,20 @@ pub fn xnor(l0: Line, l1: Line) -> Line {
     }
 }
 
+/// Packs `lines` into an integer, first line in the least significant bit. Anything but high
+/// counts as 0. Panics if there are more than 64 lines.
+pub fn lines_to_word(lines: &[Line]) -> u64 {
+    assert!(lines.len() <= 64, "{} lines don't fit in a 64-bit word.", lines.len());
+    lines.iter().enumerate().map(|(i, l)| (l.is_high() as u64) << i).sum()
+}
+
+/// Unpacks the low `width` bits of `value` into lines, least significant bit first. Panics if
+/// `width` is over 64.
+pub fn word_to_lines(value: u64, width: usize) -> Vec<Line> {
+    assert!(width <= 64, "A 64-bit word doesn't have {} bits.", width);
+    (0..width).map(|i| Line::from(value >> i & 1 == 1)).collect()
+}
+
 impl Line {
     pub fn is_high(&self) -> bool {
         match self {
@@ -185,4 +199,32 @@ impl BitXor for Line {
     }
 }
 
-lmao_im_lazy!{Mul, mul, and | Add, add, or | BitXor, bitxor, xor}
\ No newline at end of file
+lmao_im_lazy!{Mul, mul, and | Add, add, or | BitXor, bitxor, xor}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_up_to_64_bits() {
+        let value = 0x8000_0000_0000_0001;
+        let lines = word_to_lines(value, 64);
+        assert_eq!((lines[0], lines[1], lines[63]), (Line::High, Line::Low, Line::High));
+        assert_eq!(lines_to_word(&lines), value);
+        assert_eq!(lines_to_word(&[Line::High, Line::Disconnected, Line::High]), 5);
+        assert_eq!(word_to_lines(value, 0), Vec::new());
+        assert_eq!(lines_to_word(&[]), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "65 lines don't fit in a 64-bit word.")]
+    fn packing_65_lines() {
+        lines_to_word(&[Line::High; 65]);
+    }
+
+    #[test]
+    #[should_panic(expected = "A 64-bit word doesn't have 65 bits.")]
+    fn unpacking_65_bits() {
+        word_to_lines(1, 65);
+    }
+}
@@ -0,0 +1,174 @@
+use circuit::basics::Source;
+use circuit::circuit::Circuit;
+use circuit::gate::{Gate, Port, PortRole, lower_with};
+use circuit::line::Line;
+use circuit::mux::Mux1_2;
+
+/// Most inputs a `Lut` can have.
+pub const MAX_LUT_INPUTS: usize = 6;
+
+/// Look-up table computing any function of up to six inputs.
+///
+/// Spec:
+/// 0..inputs: address, least significant bit first
+/// 0: bit `address` of the table
+///
+/// Disconnected inputs read as low.
+#[derive(Clone)]
+pub struct Lut {
+    table: u64,
+    inputs: Vec<usize>,
+    output: usize
+}
+
+impl Lut {
+    pub(crate) fn new(inputs: usize, table: u64) -> Self {
+        assert!(inputs <= MAX_LUT_INPUTS, "Too many inputs for a LUT.");
+        let mask = if inputs == MAX_LUT_INPUTS { !0 } else { (1 << (1 << inputs)) - 1 };
+        Lut {
+            table: table & mask,
+            inputs: vec![0; inputs],
+            output: 0
+        }
+    }
+
+    pub fn table(&self) -> u64 {
+        self.table
+    }
+}
+
+impl Gate for Lut {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        match o {
+            0 => self.output,
+            _ => panic!("Invalid output.")
+        }
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        match o {
+            0 => self.output = new_o,
+            _ => panic!("Attempting to set invalid output.")
+        }
+    }
+
+    fn num_outputs(&self) -> usize {
+        1
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let mut ports = Vec::new();
+        if !self.inputs.is_empty() {
+            ports.push(Port::input("address", 0, self.inputs.len()).with_role(PortRole::Address));
+        }
+        ports.push(Port::output("out", 0, 1));
+        ports
+    }
+
+    // A tree of `Mux1_2`s selecting between constants, with muxes of two equal lines left out.
+    fn lower(&self) -> Option<Circuit> {
+        Some(lower_with(self.inputs.len(), |lines, circuit| {
+            let low = circuit.add_gate(Source::new_low());
+            let high = circuit.add_gate(Source::new_high());
+            let constants = [circuit.get_gate_output(low, 0), circuit.get_gate_output(high, 0)];
+            let mut level = (0..1 << lines.len())
+                .map(|m| constants[(self.table >> m & 1) as usize])
+                .collect::<Vec<_>>();
+            for &select in lines {
+                level = level.chunks(2).map(|pair| if pair[0] == pair[1] {
+                    pair[0]
+                } else {
+                    let mux = circuit.add_gate(Mux1_2::new());
+                    circuit.set_gate_input(mux, 0, pair[0]);
+                    circuit.set_gate_input(mux, 1, pair[1]);
+                    circuit.set_gate_input(mux, 2, select);
+                    circuit.get_gate_output(mux, 0)
+                }).collect();
+            }
+            level
+        }))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let address = self.inputs.iter().enumerate()
+            .fold(0, |address, (i, &input)| address | (lines[input].is_high() as u64) << i);
+        lines[self.output] = Line::from(self.table >> address & 1 == 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::gate::{Gate, Port, PortRole, check_ports};
+    use circuit::gate::tests::{Rng, assert_lowering_matches, respond, single_gate};
+    use circuit::line::Line;
+    use super::{Lut, MAX_LUT_INPUTS};
+
+    #[test]
+    fn luts_read_their_table() {
+        let mut rng = Rng(6);
+        for inputs in 0..=MAX_LUT_INPUTS {
+            let table = rng.next();
+            let mut circuit = single_gate(Lut::new(inputs, table));
+            for address in 0..1u64 << inputs {
+                let lines = (0..inputs).map(|i| Line::from(address >> i & 1 == 1))
+                    .collect::<Vec<_>>();
+                let expected = Line::from(table >> address & 1 == 1);
+                assert_eq!(respond(&mut circuit, &lines), vec![expected]);
+            }
+        }
+    }
+
+    #[test]
+    fn tables_are_masked() {
+        assert_eq!(Lut::new(0, !0).table(), 1);
+        assert_eq!(Lut::new(2, 0xFF).table(), 0xF);
+        assert_eq!(Lut::new(5, !0).table(), 0xFFFF_FFFF);
+        assert_eq!(Lut::new(6, !0).table(), !0);
+    }
+
+    #[test]
+    fn disconnected_inputs_read_low() {
+        let mut circuit = single_gate(Lut::new(2, 0b0010));
+        assert_eq!(respond(&mut circuit, &[Line::High, Line::Disconnected]), vec![Line::High]);
+        assert_eq!(respond(&mut circuit, &[Line::Disconnected, Line::High]), vec![Line::Low]);
+    }
+
+    #[test]
+    fn lowerings_match() {
+        let mut rng = Rng(60);
+        for inputs in 0..=MAX_LUT_INPUTS {
+            for &table in &[0, !0, rng.next(), rng.next()] {
+                assert_lowering_matches(Lut::new(inputs, table));
+            }
+        }
+    }
+
+    #[test]
+    fn ports_name_the_address() {
+        let lut = Lut::new(4, 0x8000);
+        assert_eq!(lut.ports(), vec![Port::input("address", 0, 4).with_role(PortRole::Address),
+            Port::output("out", 0, 1)]);
+        assert_eq!(check_ports(&lut), Ok(()));
+        let constant = Lut::new(0, 1);
+        assert_eq!(constant.ports(), vec![Port::output("out", 0, 1)]);
+        assert_eq!(check_ports(&constant), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many inputs")]
+    fn seven_inputs_panic() {
+        Lut::new(7, 0);
+    }
+}
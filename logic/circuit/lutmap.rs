@@ -0,0 +1,466 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use circuit::basics::Source;
+use circuit::circuit::Circuit;
+use circuit::gate::GateKind;
+use circuit::lut::{Lut, MAX_LUT_INPUTS};
+use circuit::netlist::{Netlist, NetlistError, NetInput, NetOutput, Node, batch_inputs};
+
+/// What `map_luts` minimizes first, using the other to break ties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LutGoal {
+    /// Fewest LUTs on the longest path.
+    Depth,
+    /// Fewest LUTs overall.
+    Area
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LutOptions {
+    /// Inputs per LUT, from 3 to `MAX_LUT_INPUTS`. Defaults to 4.
+    pub k: usize,
+    pub goal: LutGoal,
+    /// Cuts kept per node during enumeration, trading run time for quality. Defaults to 8.
+    pub cut_limit: usize
+}
+
+impl Default for LutOptions {
+    fn default() -> Self {
+        LutOptions {
+            k: 4,
+            goal: LutGoal::Depth,
+            cut_limit: 8
+        }
+    }
+}
+
+/// Resources used by a circuit after `map_luts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LutReport {
+    pub gates_before: usize,
+    pub luts: usize,
+    /// Number of LUTs using each number of inputs, starting from none.
+    pub sizes: Vec<usize>,
+    /// Most LUTs on any path between inputs, stateful gates and outputs.
+    pub depth: usize,
+    /// Gates kept as they were: those holding state, and sinks.
+    pub kept: usize
+}
+
+impl fmt::Display for LutReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} gates -> {} LUTs and {} other gates", self.gates_before, self.luts,
+            self.kept)?;
+        for (inputs, &count) in self.sizes.iter().enumerate().filter(|&(_, &count)| count > 0) {
+            writeln!(f, "  {}-input LUTs: {}", inputs, count)?;
+        }
+        write!(f, "depth {}", self.depth)
+    }
+}
+
+// Netlist node operands.
+fn fanins(node: Node) -> Vec<usize> {
+    match node {
+        Node::Input(_) | Node::Constant(_) => Vec::new(),
+        Node::Not(a) => vec![a],
+        Node::And(a, b) | Node::Or(a, b) | Node::Xor(a, b) => vec![a, b],
+        Node::Mux { select, low, high } => vec![select, low, high]
+    }
+}
+
+// Sorted union of two cuts, unless it has more than `k` leaves.
+fn merge(a: &[usize], b: &[usize], k: usize) -> Option<Vec<usize>> {
+    let mut cut = Vec::with_capacity(k);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let leaf = if j == b.len() || i < a.len() && a[i] < b[j] {
+            i += 1;
+            a[i - 1]
+        } else {
+            if i < a.len() && a[i] == b[j] {
+                i += 1;
+            }
+            j += 1;
+            b[j - 1]
+        };
+        if cut.len() == k {
+            return None;
+        }
+        cut.push(leaf);
+    }
+    Some(cut)
+}
+
+struct Mapper<'a> {
+    netlist: &'a Netlist,
+    options: &'a LutOptions,
+    fanout: Vec<usize>,
+    // Non-trivial cuts of each node, best first.
+    cuts: Vec<Vec<Vec<usize>>>,
+    depth: Vec<usize>,
+    // Estimated LUTs needed for each node, shared among its readers.
+    flow: Vec<f64>
+}
+
+impl<'a> Mapper<'a> {
+    fn cost(&self, cut: &[usize]) -> (usize, f64) {
+        let depth = 1 + cut.iter().map(|&leaf| self.depth[leaf]).max().unwrap_or(0);
+        let flow = 1.0 + cut.iter().map(|&leaf| self.flow[leaf] / self.fanout[leaf] as f64)
+            .sum::<f64>();
+        (depth, flow)
+    }
+
+    fn compare(&self, a: (usize, f64), b: (usize, f64)) -> Ordering {
+        let (a, b) = match self.options.goal {
+            LutGoal::Depth => ((a.0 as f64, a.1), (b.0 as f64, b.1)),
+            LutGoal::Area => ((a.1, a.0 as f64), (b.1, b.0 as f64))
+        };
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    }
+
+    // Cuts of `n` that fanins can build on: its best cuts, and `n` itself as a single leaf.
+    // Constants need no leaves at all.
+    fn extensions(&self, n: usize) -> Vec<Vec<usize>> {
+        match self.netlist.nodes[n] {
+            Node::Constant(_) => vec![Vec::new()],
+            Node::Input(_) => vec![vec![n]],
+            _ => self.cuts[n].iter().cloned().chain(Some(vec![n])).collect()
+        }
+    }
+
+    fn enumerate(&mut self, n: usize) {
+        let k = self.options.k;
+        let mut cuts = vec![Vec::new()];
+        for fanin in fanins(self.netlist.nodes[n]) {
+            let extensions = self.extensions(fanin);
+            cuts = cuts.iter().flat_map(|cut| {
+                extensions.iter().filter_map(move |extension| merge(cut, extension, k))
+            }).collect();
+        }
+        cuts.sort();
+        cuts.dedup();
+        // Drop cuts containing a smaller one, as those are never better.
+        let dominated = |cut: &Vec<usize>, cuts: &[Vec<usize>]| cuts.iter().any(|other| {
+            other.len() < cut.len() && other.iter().all(|leaf| cut.binary_search(leaf).is_ok())
+        });
+        let mut cuts = cuts.iter().filter(|cut| !dominated(cut, &cuts)).cloned()
+            .map(|cut| (self.cost(&cut), cut))
+            .collect::<Vec<_>>();
+        cuts.sort_by(|a, b| self.compare(a.0, b.0));
+        cuts.truncate(self.options.cut_limit.max(1));
+        if let Some(&((depth, flow), _)) = cuts.first() {
+            self.depth[n] = depth;
+            self.flow[n] = flow;
+        }
+        self.cuts[n] = cuts.into_iter().map(|(_, cut)| cut).collect();
+    }
+
+    // Truth table of node `n` over the leaves of `cut`, the first leaf being the least
+    // significant address bit.
+    fn table(&self, n: usize, cut: &[usize]) -> u64 {
+        let mut values = cut.iter().cloned().zip(batch_inputs(cut.len(), 0))
+            .collect::<HashMap<_, _>>();
+        self.value(n, &mut values)
+    }
+
+    fn value(&self, n: usize, values: &mut HashMap<usize, u64>) -> u64 {
+        if let Some(&value) = values.get(&n) {
+            return value;
+        }
+        let value = match self.netlist.nodes[n] {
+            Node::Input(_) => unreachable!("Cut doesn't cover node."),
+            Node::Constant(value) => if value { !0 } else { 0 },
+            Node::Not(a) => !self.value(a, values),
+            Node::And(a, b) => self.value(a, values) & self.value(b, values),
+            Node::Or(a, b) => self.value(a, values) | self.value(b, values),
+            Node::Xor(a, b) => self.value(a, values) ^ self.value(b, values),
+            Node::Mux { select, low, high } => {
+                let select = self.value(select, values);
+                select & self.value(high, values) | !select & self.value(low, values)
+            }
+        };
+        values.insert(n, value);
+        value
+    }
+}
+
+fn is_opaque(circuit: &Circuit, g: usize) -> bool {
+    circuit.gates[g].kind() == GateKind::Other && circuit.gates[g].lower().is_none()
+}
+
+/// Replaces the combinational logic of `circuit` with `Lut`s of up to `options.k` inputs, the
+/// way an FPGA would implement it, and reports the resources used.
+///
+/// Gates that hold state, such as flip-flops, and sinks are kept as they are, and the LUTs
+/// take their place between them, the marked inputs and outputs, and any undriven lines such
+/// as clocks. Every node's cuts, the sets of at most `k` signals it can be computed from, are
+/// enumerated, keeping the best few by the goal in `options`. The logic is then covered from
+/// the outputs back with one LUT per chosen cut, favouring cheaper cuts wherever that doesn't
+/// add depth. The marked inputs and outputs keep their order, but lines are renumbered. The
+/// LUTs compute two-valued logic, so a disconnected value may become low.
+pub fn map_luts(circuit: &mut Circuit, options: &LutOptions) -> Result<LutReport, NetlistError> {
+    assert!(options.k >= 3 && options.k <= MAX_LUT_INPUTS, "Invalid LUT size.");
+    let gates_before = circuit.gates.len();
+
+    // Treat undriven lines as inputs and sinks as outputs while building the netlist.
+    let mut driven = vec![false; circuit.lines.len()];
+    driven[0] = true;
+    for gate in &circuit.gates {
+        for o in 0..gate.num_outputs() {
+            driven[gate.get_output(o)] = true;
+        }
+    }
+    for &line in &circuit.inputs {
+        driven[line] = true;
+    }
+    let (inputs, outputs) = (circuit.inputs.len(), circuit.outputs.len());
+    let undriven = (0..circuit.lines.len()).filter(|&line| !driven[line]).collect::<Vec<_>>();
+    let sinks = (0..circuit.gates.len()).filter(|&g| circuit.gates[g].kind() == GateKind::Sink)
+        .collect::<Vec<_>>();
+    circuit.inputs.extend(&undriven);
+    for &g in &sinks {
+        let line = circuit.gates[g].get_input(0);
+        circuit.outputs.push(line);
+    }
+    let netlist = Netlist::cut_at_state(circuit);
+    let extra_inputs = circuit.inputs.split_off(inputs);
+    circuit.outputs.truncate(outputs);
+    let netlist = netlist?;
+
+    let mut fanout = vec![0; netlist.nodes.len()];
+    let mut used = vec![false; netlist.nodes.len()];
+    for &(node, _) in &netlist.outputs {
+        fanout[node] += 1;
+        used[node] = true;
+    }
+    for n in (0..netlist.nodes.len()).rev() {
+        if used[n] {
+            for fanin in fanins(netlist.nodes[n]) {
+                fanout[fanin] += 1;
+                used[fanin] = true;
+            }
+        }
+    }
+    let mut mapper = Mapper {
+        netlist: &netlist,
+        options,
+        fanout,
+        cuts: vec![Vec::new(); netlist.nodes.len()],
+        depth: vec![0; netlist.nodes.len()],
+        flow: vec![0.0; netlist.nodes.len()]
+    };
+    for n in 0..netlist.nodes.len() {
+        match netlist.nodes[n] {
+            Node::Input(_) | Node::Constant(_) => {},
+            _ => mapper.enumerate(n)
+        }
+    }
+
+    // Choose the cuts to build, from the outputs back. Whatever read line 0 keeps doing so, as
+    // gates may treat a disconnected input differently from a low one.
+    let destinations = netlist.outputs.iter().cloned().filter(|&(_, output)| match output {
+        NetOutput::Circuit(o) if o < outputs => circuit.outputs[o] != 0,
+        NetOutput::Circuit(o) => circuit.gates[sinks[o - outputs]].get_input(0) != 0,
+        NetOutput::State { gate, input } => circuit.gates[gate].get_input(input) != 0
+    }).collect::<Vec<_>>();
+    // When aiming for depth, each node then takes the cut with the least area flow that still
+    // meets the depth the outputs need, which recovers much of the area spent on depth.
+    let mut needed = vec![false; netlist.nodes.len()];
+    let mut required = vec![usize::MAX; netlist.nodes.len()];
+    if options.goal == LutGoal::Depth {
+        let depth = destinations.iter().map(|&(node, _)| mapper.depth[node]).max().unwrap_or(0);
+        for &(node, _) in &destinations {
+            required[node] = depth;
+        }
+    }
+    for &(node, _) in &destinations {
+        needed[node] = true;
+    }
+    let mut chosen = vec![0; netlist.nodes.len()];
+    for n in (0..netlist.nodes.len()).rev() {
+        if !needed[n] {
+            continue;
+        }
+        let best = mapper.cuts[n].iter().enumerate()
+            .map(|(c, cut)| (c, mapper.cost(cut)))
+            .filter(|&(_, (depth, _))| depth <= required[n])
+            .min_by(|a, b| (a.1).1.partial_cmp(&(b.1).1).unwrap_or(Ordering::Equal));
+        if let Some((c, _)) = best {
+            chosen[n] = c;
+            for &leaf in &mapper.cuts[n][c] {
+                needed[leaf] = true;
+                required[leaf] = required[leaf].min(required[n] - 1);
+            }
+        }
+    }
+
+    let mut lines = vec![0; netlist.nodes.len()];
+    let mut levels = vec![0; netlist.nodes.len()];
+    let mut keep = (0..circuit.gates.len())
+        .map(|g| circuit.gates[g].kind() == GateKind::Sink || is_opaque(circuit, g))
+        .collect::<Vec<_>>();
+    let kept = keep.iter().filter(|&&keep| keep).count();
+    let mut sizes = vec![0; options.k + 1];
+    for n in (0..netlist.nodes.len()).filter(|&n| needed[n]) {
+        lines[n] = match netlist.nodes[n] {
+            Node::Input(i) => match netlist.inputs[i] {
+                NetInput::Circuit(i) if i < inputs => circuit.inputs[i],
+                NetInput::Circuit(i) => extra_inputs[i - inputs],
+                NetInput::State { gate, output } => circuit.gates[gate].get_output(output)
+            },
+            Node::Constant(value) => {
+                let source = if value { Source::new_high() } else { Source::new_low() };
+                let gate = circuit.add_gate(source);
+                circuit.get_gate_output(gate, 0)
+            },
+            _ => {
+                let cut = &mapper.cuts[n][chosen[n]];
+                let gate = circuit.add_gate(Lut::new(cut.len(), mapper.table(n, cut)));
+                for (i, &leaf) in cut.iter().enumerate() {
+                    circuit.set_gate_input(gate, i, lines[leaf]);
+                }
+                levels[n] = 1 + cut.iter().map(|&leaf| levels[leaf]).max().unwrap_or(0);
+                sizes[cut.len()] += 1;
+                circuit.get_gate_output(gate, 0)
+            }
+        };
+    }
+    keep.resize(circuit.gates.len(), true);
+
+    for &(node, output) in &destinations {
+        match output {
+            NetOutput::Circuit(o) if o < outputs => circuit.outputs[o] = lines[node],
+            NetOutput::Circuit(o) => circuit.gates[sinks[o - outputs]].set_input(0, lines[node]),
+            NetOutput::State { gate, input } => circuit.gates[gate].set_input(input, lines[node])
+        }
+    }
+    let depth = destinations.iter().map(|&(node, _)| levels[node]).max().unwrap_or(0);
+    circuit.retain_gates(&keep);
+    Ok(LutReport {
+        gates_before,
+        luts: sizes.iter().sum(),
+        sizes,
+        depth,
+        kept
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::{Inverter, Sink};
+    use circuit::equivalence::{Engine, EquivalenceOptions, check_equivalence};
+    use circuit::gate::tests::{Rng, random_logic, respond};
+    use circuit::line::Line;
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::simplegate::{SimpleGate, make_and_tree};
+    use super::*;
+
+    fn assert_equivalent(a: &Circuit, b: &Circuit) {
+        let options = EquivalenceOptions { engine: Engine::Sat, ..Default::default() };
+        assert!(check_equivalence(a, b, &options).unwrap().is_equivalent());
+    }
+
+    #[test]
+    fn random_logic_maps_to_luts() {
+        for &width in &[0, 1, 8, 64, 65] {
+            for seed in 1..3 {
+                let original = random_logic(seed, width, 60, 4, seed == 2);
+                for k in 3..=MAX_LUT_INPUTS {
+                    let mut depths = Vec::new();
+                    for &goal in &[LutGoal::Depth, LutGoal::Area] {
+                        let mut mapped = original.clone();
+                        let options = LutOptions { k, goal, ..Default::default() };
+                        let report = map_luts(&mut mapped, &options).unwrap();
+                        assert_equivalent(&original, &mapped);
+                        assert_eq!(report.gates_before, original.gates.len());
+                        assert_eq!(report.kept, 0);
+                        assert_eq!(report.sizes.len(), k + 1);
+                        assert_eq!(report.luts, mapped.gates.len() - mapped.gates.iter()
+                            .filter(|gate| gate.kind() != GateKind::Other).count());
+                        depths.push((report.depth, report.luts));
+                    }
+                    assert!(depths[0].0 <= depths[1].0, "{:?}", depths);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_functions() {
+        // A 4-input AND tree is one 4-LUT, or two 3-LUTs.
+        let mut c = Circuit::new();
+        let inputs = (0..4).map(|_| {
+            let line = c.add_line(Line::Low);
+            c.mark_line_as_circuit_input(line);
+            line
+        }).collect::<Vec<_>>();
+        let and = make_and_tree(&inputs, &mut c);
+        c.mark_line_as_circuit_output(and);
+        let mut four = c.clone();
+        let report = map_luts(&mut four, &Default::default()).unwrap();
+        assert_eq!((report.luts, report.depth, report.sizes[4]), (1, 1, 1));
+        assert_eq!(four.gates.len(), 1);
+        let mut three = c.clone();
+        let report = map_luts(&mut three, &LutOptions { k: 3, ..Default::default() }).unwrap();
+        assert_eq!((report.luts, report.depth), (2, 2));
+        assert_eq!(report.to_string(),
+            "3 gates -> 2 LUTs and 0 other gates\n  2-input LUTs: 1\n  3-input LUTs: 1\ndepth 2");
+        assert_equivalent(&c, &three);
+    }
+
+    #[test]
+    fn state_and_sinks_are_kept() {
+        // A toggle flip-flop enabled by `t`, with its clock an undriven line.
+        let mut c = Circuit::new();
+        let t = c.add_line(Line::Low);
+        c.mark_line_as_circuit_input(t);
+        let clock = c.add_line(Line::Low);
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        let q = c.get_gate_output(ff, 0);
+        let xor = c.add_gate(SimpleGate::xor());
+        c.set_gate_input(xor, 0, q);
+        c.set_gate_input(xor, 1, t);
+        let d = c.get_gate_output(xor, 0);
+        let inverter = c.add_gate(Inverter::new());
+        c.set_gate_input(inverter, 0, d);
+        let sink = c.add_gate(Sink::new());
+        c.set_gate_input(sink, 0, c.get_gate_output(inverter, 0));
+        c.set_gate_input(ff, 0, d);
+        c.set_gate_input(ff, 1, clock);
+        c.mark_line_as_circuit_output(q);
+        let mut mapped = c.clone();
+        let report = map_luts(&mut mapped, &Default::default()).unwrap();
+        assert_eq!((report.kept, report.luts, report.depth), (2, 2, 1));
+        assert_eq!(mapped.gates.len(), 4);
+        let clock = mapped.gates.iter().find(|gate| gate.num_outputs() == 2).unwrap()
+            .get_input(1);
+        let mut rng = Rng(12);
+        for _ in 0..40 {
+            let inputs = [rng.line()];
+            let level = rng.line();
+            c.lines[c.gates[ff].get_input(1)] = level;
+            mapped.lines[clock] = level;
+            assert_eq!(respond(&mut c, &inputs), respond(&mut mapped, &inputs));
+        }
+    }
+
+    #[test]
+    fn loops_are_errors() {
+        let mut c = Circuit::new();
+        let gate = c.add_gate(Inverter::new());
+        let out = c.get_gate_output(gate, 0);
+        c.set_gate_input(gate, 0, out);
+        c.mark_line_as_circuit_output(out);
+        assert_eq!(map_luts(&mut c, &Default::default()), Err(NetlistError::Loop { line: out }));
+        assert_eq!(c.outputs, vec![out]);
+        assert!(c.inputs.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LUT size")]
+    fn two_input_luts_panic() {
+        map_luts(&mut Circuit::new(), &LutOptions { k: 2, ..Default::default() }).unwrap();
+    }
+}
@@ -1,7 +1,17 @@
-use circuit::line::{Line, xor};
+use std::ops::{Deref, DerefMut};
 
-use circuit::gate::Gate;
+use circuit::line::{Line, xor, lines_to_word, word_to_lines};
 
+use circuit::gate::{Gate, Port, PortRole, StateRegister, lower_with};
+use circuit::circuit::Circuit;
+use circuit::codegen::{Expr, GateCode, Statement, MAX_MEMORY_WORDS, less_than};
+use circuit::basics::{Inverter, Source};
+use circuit::simplegate::SimpleGate;
+use circuit::mux::{Mux1_2, make_mux};
+use circuit::storage::Storage;
+use circuit::bus::decode;
+
+#[derive(Clone)]
 pub struct NORLatch {
     i0: usize,
     i1: usize,
@@ -63,18 +73,58 @@ impl Gate for NORLatch {
         1
     }
 
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("reset", 0, 1),
+            Port::input("set", 1, 1),
+            Port::input("clock", 2, 1).with_role(PortRole::Clock),
+            Port::output("q", 0, 1)
+        ]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         if lines[self.clock].is_high() {
             if lines[self.i0].is_high() {
-                lines[self.output] = Line::Low;
+                self.state = Line::Low;
             }
             if lines[self.i1].is_high() {
-                lines[self.output] = Line::High;
+                self.state = Line::High;
             }
         }
+        lines[self.output] = self.state;
+    }
+
+    fn code(&self) -> Option<GateCode> {
+        let reset = Statement::If(Expr::Line(self.i0), vec![
+            Statement::SetState(0, Expr::Constant(false))
+        ], Vec::new());
+        let set = Statement::If(Expr::Line(self.i1), vec![
+            Statement::SetState(0, Expr::Constant(true))
+        ], Vec::new());
+        Some(GateCode {
+            state: vec![self.state.is_high()],
+            memories: Vec::new(),
+            body: vec![
+                Statement::If(Expr::Line(self.clock), vec![reset, set], Vec::new()),
+                Statement::SetLine(self.output, Expr::State(0))
+            ]
+        })
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", 1, 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        vec![self.state]
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        self.state = value[0];
     }
 }
 
+#[derive(Clone)]
 pub struct MasterSlaveFlipFlop {
     i0: usize,
     i1: usize,
@@ -136,6 +186,15 @@ impl Gate for MasterSlaveFlipFlop {
         1
     }
 
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("d", 0, 1),
+            Port::input("d_bar", 1, 1),
+            Port::input("clock", 2, 1).with_role(PortRole::Clock),
+            Port::output("q", 0, 1)
+        ]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         if lines[self.clock].is_high() && xor(lines[self.i0], lines[self.i1]).is_high() {
             let new_master = lines[self.i0];
@@ -145,31 +204,146 @@ impl Gate for MasterSlaveFlipFlop {
             lines[self.output] = new_slave;
         }
     }
+
+    fn code(&self) -> Option<GateCode> {
+        let (i0, i1) = (Expr::Line(self.i0), Expr::Line(self.i1));
+        let capture = Statement::If(i0.clone() ^ i1, vec![Statement::SetState(0, i0)],
+            Vec::new());
+        Some(GateCode {
+            state: vec![self.master.is_high()],
+            memories: Vec::new(),
+            body: vec![
+                Statement::If(Expr::Line(self.clock), vec![capture], vec![
+                    Statement::SetLine(self.output, Expr::State(0))
+                ])
+            ]
+        })
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("master", 1, 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        vec![self.master]
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        self.master = value[0];
+    }
+}
+
+/// Which clock transition an edge-triggered flip-flop samples on.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Edge {
+    Rising,
+    Falling
+}
+
+impl Edge {
+    pub(crate) fn triggered(self, last: Line, now: Line) -> bool {
+        match self {
+            Edge::Rising => !last.is_high() && now.is_high(),
+            Edge::Falling => last.is_high() && !now.is_high()
+        }
+    }
 }
 
+// Shared by the edge-triggered flip-flops: clear wins over preset, and both act immediately
+// regardless of the clock.
+fn async_override(lines: &[Line], preset: usize, clear: usize) -> Option<Line> {
+    if lines[clear].is_high() {
+        Some(Line::Low)
+    } else if lines[preset].is_high() {
+        Some(Line::High)
+    } else {
+        None
+    }
+}
+
+// Whether an edge-triggered flip-flop takes its next state on an edge. An enable pin left
+// unconnected leaves the flip-flop enabled, while a connected one only enables it while high, so
+// a disconnected enable line disables it just as it does in the gate-level references.
+fn enabled(lines: &[Line], enable: usize) -> bool {
+    enable == 0 || lines[enable].is_high()
+}
+
+// Generated code shared by the edge-triggered flip-flops, whose state is the last clock followed
+// by the stored value. `control` holds the clock, enable, preset and clear lines, and `next` is
+// the value stored on an enabled edge.
+fn flip_flop_code(edge: Edge, last_clock: Line, state: Line, control: &[usize],
+    outputs: [usize; 2], next: Expr) -> GateCode {
+    let clock = Expr::Line(control[0]);
+    let triggered = match edge {
+        Edge::Rising => !Expr::State(0) & clock.clone(),
+        Edge::Falling => Expr::State(0) & !clock.clone()
+    };
+    let enabled = if control[1] == 0 { Expr::Constant(true) } else { Expr::Line(control[1]) };
+    let store = Statement::If(triggered & enabled, vec![Statement::SetState(1, next)], Vec::new());
+    let preset = Statement::If(Expr::Line(control[2]), vec![
+        Statement::SetState(1, Expr::Constant(true))
+    ], vec![store]);
+    GateCode {
+        state: vec![last_clock.is_high(), state.is_high()],
+        memories: Vec::new(),
+        body: vec![
+            Statement::If(Expr::Line(control[3]), vec![
+                Statement::SetState(1, Expr::Constant(false))
+            ], vec![preset]),
+            Statement::SetState(0, clock),
+            Statement::SetLine(outputs[0], Expr::State(1)),
+            Statement::SetLine(outputs[1], !Expr::State(1))
+        ]
+    }
+}
+
+// Ports of an edge-triggered flip-flop with the given data inputs ahead of its clock, enable,
+// preset and clear.
+fn flip_flop_ports(data: &[&str]) -> Vec<Port> {
+    let n = data.len();
+    let mut ports = data.iter().enumerate().map(|(i, name)| Port::input(name, i, 1))
+        .collect::<Vec<_>>();
+    ports.push(Port::input("clock", n, 1).with_role(PortRole::Clock));
+    ports.push(Port::input("enable", n + 1, 1).with_role(PortRole::Enable));
+    ports.push(Port::input("preset", n + 2, 1).with_role(PortRole::Control));
+    ports.push(Port::input("clear", n + 3, 1).with_role(PortRole::Control));
+    ports.push(Port::output("q", 0, 1));
+    ports.push(Port::output("q_bar", 1, 1));
+    ports
+}
+
+/// Edge-triggered D flip-flop.
+///
 /// Spec:
-/// 0..8: address
-/// 8..16: write value
-/// 16: write
-/// 17: read
-/// 18: clock
-pub struct NORLatchRAM8 {
-    inputs: [usize; 19],
-    storage: [bool; 256 * 8],
-    outputs: [usize; 8]
+/// 0: d
+/// 1: clock
+/// 2: enable
+/// 3: preset
+/// 4: clear
+///
+/// Outputs are q and q-bar. Leave enable, preset and clear unconnected when they aren't needed.
+#[derive(Clone)]
+pub struct DFlipFlop {
+    inputs: [usize; 5],
+    edge: Edge,
+    last_clock: Line,
+    state: Line,
+    outputs: [usize; 2]
 }
 
-impl NORLatchRAM8 {
-    fn new() -> Self {
-        NORLatchRAM8 {
-            inputs: [0; 19],
-            storage: [false; 256 * 8],
-            outputs: [0; 8]
+impl DFlipFlop {
+    pub(crate) fn new(edge: Edge) -> Self {
+        DFlipFlop {
+            inputs: [0; 5],
+            edge,
+            last_clock: Line::Low,
+            state: Line::Low,
+            outputs: [0; 2]
         }
     }
 }
 
-impl Gate for NORLatchRAM8 {
+impl Gate for DFlipFlop {
     fn get_input(&self, i: usize) -> usize {
         self.inputs[i]
     }
@@ -179,7 +353,7 @@ impl Gate for NORLatchRAM8 {
     }
 
     fn num_inputs(&self) -> usize {
-        19
+        5
     }
 
     fn get_output(&self, o: usize) -> usize {
@@ -191,23 +365,544 @@ impl Gate for NORLatchRAM8 {
     }
 
     fn num_outputs(&self) -> usize {
-        8
+        2
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        flip_flop_ports(&["d"])
     }
 
     fn eval(&mut self, lines: &mut Vec<Line>) {
-        if lines[self.inputs[18]].is_high() {
-            let addr = (0..8).map(|i| (lines[self.inputs[i]].is_high() as usize) << i)
-                .sum::<usize>();
-            if lines[self.inputs[18]].is_high() {
-                if lines[self.inputs[16]].is_high() {
-                    for i in 0..8 {
-                        let new = lines[self.inputs[8 + i]].into();
-                        self.storage[8 * addr + i] = new;
+        let clock = lines[self.inputs[1]];
+        let edge = self.edge.triggered(self.last_clock, clock);
+        self.last_clock = clock;
+        if let Some(forced) = async_override(lines, self.inputs[3], self.inputs[4]) {
+            self.state = forced;
+        } else if edge && enabled(lines, self.inputs[2]) {
+            self.state = Line::from(lines[self.inputs[0]].is_high());
+        }
+        lines[self.outputs[0]] = self.state;
+        lines[self.outputs[1]] = !self.state;
+    }
+
+    fn code(&self) -> Option<GateCode> {
+        Some(flip_flop_code(self.edge, self.last_clock, self.state, &self.inputs[1..],
+            self.outputs, Expr::Line(self.inputs[0])))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", 1, 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        vec![self.state]
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        self.state = value[0];
+    }
+}
+
+/// Edge-triggered JK flip-flop. On an edge, j sets, k resets and both together toggle.
+///
+/// Spec:
+/// 0: j
+/// 1: k
+/// 2: clock
+/// 3: enable
+/// 4: preset
+/// 5: clear
+///
+/// Outputs are q and q-bar. Leave enable, preset and clear unconnected when they aren't needed.
+#[derive(Clone)]
+pub struct JKFlipFlop {
+    inputs: [usize; 6],
+    edge: Edge,
+    last_clock: Line,
+    state: Line,
+    outputs: [usize; 2]
+}
+
+impl JKFlipFlop {
+    pub(crate) fn new(edge: Edge) -> Self {
+        JKFlipFlop {
+            inputs: [0; 6],
+            edge,
+            last_clock: Line::Low,
+            state: Line::Low,
+            outputs: [0; 2]
+        }
+    }
+}
+
+impl Gate for JKFlipFlop {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        6
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        2
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        flip_flop_ports(&["j", "k"])
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let clock = lines[self.inputs[2]];
+        let edge = self.edge.triggered(self.last_clock, clock);
+        self.last_clock = clock;
+        if let Some(forced) = async_override(lines, self.inputs[4], self.inputs[5]) {
+            self.state = forced;
+        } else if edge && enabled(lines, self.inputs[3]) {
+            self.state = match (lines[self.inputs[0]].is_high(), lines[self.inputs[1]].is_high()) {
+                (false, false) => self.state,
+                (false, true) => Line::Low,
+                (true, false) => Line::High,
+                (true, true) => !self.state
+            };
+        }
+        lines[self.outputs[0]] = self.state;
+        lines[self.outputs[1]] = !self.state;
+    }
+
+    fn code(&self) -> Option<GateCode> {
+        let (j, k) = (Expr::Line(self.inputs[0]), Expr::Line(self.inputs[1]));
+        let next = j & !Expr::State(1) | !k & Expr::State(1);
+        Some(flip_flop_code(self.edge, self.last_clock, self.state, &self.inputs[2..],
+            self.outputs, next))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", 1, 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        vec![self.state]
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        self.state = value[0];
+    }
+}
+
+/// Edge-triggered T flip-flop. On an edge, the state toggles while t is high.
+///
+/// Spec:
+/// 0: t
+/// 1: clock
+/// 2: enable
+/// 3: preset
+/// 4: clear
+///
+/// Outputs are q and q-bar. Leave enable, preset and clear unconnected when they aren't needed.
+#[derive(Clone)]
+pub struct TFlipFlop {
+    inputs: [usize; 5],
+    edge: Edge,
+    last_clock: Line,
+    state: Line,
+    outputs: [usize; 2]
+}
+
+impl TFlipFlop {
+    pub(crate) fn new(edge: Edge) -> Self {
+        TFlipFlop {
+            inputs: [0; 5],
+            edge,
+            last_clock: Line::Low,
+            state: Line::Low,
+            outputs: [0; 2]
+        }
+    }
+}
+
+impl Gate for TFlipFlop {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        5
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        2
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        flip_flop_ports(&["t"])
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let clock = lines[self.inputs[1]];
+        let edge = self.edge.triggered(self.last_clock, clock);
+        self.last_clock = clock;
+        if let Some(forced) = async_override(lines, self.inputs[3], self.inputs[4]) {
+            self.state = forced;
+        } else if edge && enabled(lines, self.inputs[2]) && lines[self.inputs[0]].is_high() {
+            self.state = !self.state;
+        }
+        lines[self.outputs[0]] = self.state;
+        lines[self.outputs[1]] = !self.state;
+    }
+
+    fn code(&self) -> Option<GateCode> {
+        let next = Expr::State(1) ^ Expr::Line(self.inputs[0]);
+        Some(flip_flop_code(self.edge, self.last_clock, self.state, &self.inputs[1..],
+            self.outputs, next))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", 1, 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        vec![self.state]
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        self.state = value[0];
+    }
+}
+
+// (sel * if_high) + (!sel * if_low), so a disconnected select picks `if_low`.
+pub(crate) fn make_select(sel: usize, if_high: usize, if_low: usize, circuit: &mut Circuit)
+    -> usize {
+    let inv = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(inv, 0, sel);
+    let high = circuit.add_gate(SimpleGate::and());
+    circuit.set_gate_input(high, 0, sel);
+    circuit.set_gate_input(high, 1, if_high);
+    let low = circuit.add_gate(SimpleGate::and());
+    circuit.connect_i_single(low, 0, inv, 0);
+    circuit.set_gate_input(low, 1, if_low);
+    let out = circuit.add_gate(SimpleGate::or());
+    circuit.connect_i_single(out, 0, high, 0);
+    circuit.connect_i_single(out, 1, low, 0);
+    circuit.get_gate_output(out, 0)
+}
+
+// Transparent latch made from a `Mux1_2` feeding back into itself, with the async overrides
+// applied after the mux. Returns the mux so the caller can connect its data input, and the
+// latch output.
+fn make_gated_latch(enable: usize, preset: Option<usize>, clear: Option<usize>,
+    circuit: &mut Circuit) -> (usize, usize) {
+    let mux = circuit.add_gate(Mux1_2::new());
+    circuit.set_gate_input(mux, 2, enable);
+    let mut out = circuit.get_gate_output(mux, 0);
+    if let Some(preset) = preset {
+        let set = circuit.add_gate(SimpleGate::or());
+        circuit.set_gate_input(set, 0, out);
+        circuit.set_gate_input(set, 1, preset);
+        out = circuit.get_gate_output(set, 0);
+    }
+    if let Some(clear) = clear {
+        let inv = circuit.add_gate(Inverter::new());
+        circuit.set_gate_input(inv, 0, clear);
+        let reset = circuit.add_gate(SimpleGate::and());
+        circuit.set_gate_input(reset, 0, out);
+        circuit.connect_i_single(reset, 1, inv, 0);
+        out = circuit.get_gate_output(reset, 0);
+    }
+    circuit.set_gate_input(mux, 0, out);
+    (mux, out)
+}
+
+// Master/slave pair of gated latches. Returns the master's mux, whose data input is left for the
+// caller to connect once the next-state logic (which may depend on q) exists, and q. Building
+// the next-state logic after the latches keeps the master closed before it sees the edge.
+fn make_edge_core(clock: usize, edge: Edge, preset: Option<usize>, clear: Option<usize>,
+    circuit: &mut Circuit) -> (usize, usize) {
+    let inv = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(inv, 0, clock);
+    let inverted = circuit.get_gate_output(inv, 0);
+    let (master_enable, slave_enable) = match edge {
+        Edge::Rising => (inverted, clock),
+        Edge::Falling => (clock, inverted)
+    };
+    let (master, master_out) = make_gated_latch(master_enable, preset, clear, circuit);
+    let (slave, q) = make_gated_latch(slave_enable, preset, clear, circuit);
+    circuit.set_gate_input(slave, 1, master_out);
+    (master, q)
+}
+
+fn finish_flip_flop(master: usize, next: usize, q: usize, enable: Option<usize>,
+    circuit: &mut Circuit) -> (usize, usize) {
+    let next = match enable {
+        Some(enable) => make_select(enable, next, q, circuit),
+        None => next
+    };
+    circuit.set_gate_input(master, 1, next);
+    let inv = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(inv, 0, q);
+    (q, circuit.get_gate_output(inv, 0))
+}
+
+/// Gate-level reference for `DFlipFlop`. Returns q and q-bar.
+pub fn make_d_flip_flop(d: usize, clock: usize, edge: Edge, enable: Option<usize>,
+    preset: Option<usize>, clear: Option<usize>, circuit: &mut Circuit) -> (usize, usize) {
+    let (master, q) = make_edge_core(clock, edge, preset, clear, circuit);
+    finish_flip_flop(master, d, q, enable, circuit)
+}
+
+/// Gate-level reference for `JKFlipFlop`. Returns q and q-bar.
+#[allow(clippy::too_many_arguments)]
+pub fn make_jk_flip_flop(j: usize, k: usize, clock: usize, edge: Edge, enable: Option<usize>,
+    preset: Option<usize>, clear: Option<usize>, circuit: &mut Circuit) -> (usize, usize) {
+    let (master, q) = make_edge_core(clock, edge, preset, clear, circuit);
+    // next = (j * !q) + (!k * q)
+    let not_q = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(not_q, 0, q);
+    let set = circuit.add_gate(SimpleGate::and());
+    circuit.set_gate_input(set, 0, j);
+    circuit.connect_i_single(set, 1, not_q, 0);
+    let not_k = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(not_k, 0, k);
+    let hold = circuit.add_gate(SimpleGate::and());
+    circuit.connect_i_single(hold, 0, not_k, 0);
+    circuit.set_gate_input(hold, 1, q);
+    let either = circuit.add_gate(SimpleGate::or());
+    circuit.connect_i_single(either, 0, set, 0);
+    circuit.connect_i_single(either, 1, hold, 0);
+    let next = circuit.get_gate_output(either, 0);
+    finish_flip_flop(master, next, q, enable, circuit)
+}
+
+/// Gate-level reference for `TFlipFlop`. Returns q and q-bar.
+pub fn make_t_flip_flop(t: usize, clock: usize, edge: Edge, enable: Option<usize>,
+    preset: Option<usize>, clear: Option<usize>, circuit: &mut Circuit) -> (usize, usize) {
+    let (master, q) = make_edge_core(clock, edge, preset, clear, circuit);
+    let toggle = circuit.add_gate(SimpleGate::xor());
+    circuit.set_gate_input(toggle, 0, t);
+    circuit.set_gate_input(toggle, 1, q);
+    let next = circuit.get_gate_output(toggle, 0);
+    finish_flip_flop(master, next, q, enable, circuit)
+}
+
+/// Word-addressable contents of a memory gate, for loading and inspecting it without going
+/// through its input lines.
+pub trait WordMemory {
+    fn data_width(&self) -> usize;
+    fn words(&self) -> usize;
+    fn get_word(&self, address: usize) -> u64;
+    fn set_word(&mut self, address: usize, value: u64);
+}
+
+/// How a `Ram` stores what is written to it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RamKind {
+    /// Writes and reads only happen while the clock is high, and reads see writes immediately.
+    Latch,
+    /// Writes go to the masters while the clock is high and are copied to the slaves once it
+    /// goes low. Reads happen regardless of the clock and always come from the slaves.
+    MasterSlave
+}
+
+/// One port of a `Ram`. Every port has its own address lines.
+///
+/// Spec, relative to the start of the port:
+/// `ReadWrite`: 0..a: address, a..a + d: write value, a + d: write, a + d + 1: read
+/// `Read`: 0..a: address, a: read
+/// `Write`: 0..a: address, a..a + d: write value, a + d: write
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RamPort {
+    ReadWrite,
+    Read,
+    Write
+}
+
+// Offsets of one port's pins within the gate's inputs and outputs.
+#[derive(Copy, Clone, Debug)]
+struct PortPins {
+    address: usize,
+    write_value: Option<usize>,
+    write: Option<usize>,
+    read: Option<usize>,
+    output: Option<usize>
+}
+
+// Memories at or below this many bits are allocated up front, anything bigger pages on write.
+const DENSE_LIMIT: usize = 1 << 16;
+
+/// Widest address a `Ram` or `Rom` takes. Large memories are sparse, and their page tables grow
+/// with the pages written, so a new 32-bit, 64-bit-wide memory takes 256 KiB, or twice that for
+/// a master/slave `Ram` with its second copy of the words.
+pub const MAX_ADDRESS_WIDTH: usize = 32;
+
+fn new_storage(bits: usize) -> Storage {
+    if bits <= DENSE_LIMIT {
+        Storage::dense(bits)
+    } else {
+        Storage::sparse(bits)
+    }
+}
+
+/// RAM of `2^address_width` words of `data_width` bits with any number of ports.
+///
+/// Spec:
+/// The pins of each port in order, as described by `RamPort`, then the clock.
+///
+/// Each port that can read has `data_width` outputs, in port order. Writes from every port
+/// happen before any reads, and later ports win when two write the same word.
+#[derive(Clone)]
+pub struct Ram {
+    address_width: usize,
+    data_width: usize,
+    kind: RamKind,
+    ports: Vec<PortPins>,
+    inputs: Vec<usize>,
+    storage: Storage,
+    slaves: Option<Storage>,
+    slaves_current: bool,
+    outputs: Vec<usize>
+}
+
+impl Ram {
+    pub(crate) fn new(address_width: usize, data_width: usize, kind: RamKind, ports: &[RamPort])
+        -> Self {
+        assert!(address_width <= MAX_ADDRESS_WIDTH, "Address width must be at most {} bits.",
+            MAX_ADDRESS_WIDTH);
+        assert!((1..=64).contains(&data_width), "Data width must be between 1 and 64 bits.");
+        assert!(!ports.is_empty(), "A RAM needs at least one port.");
+        let mut pins = Vec::with_capacity(ports.len());
+        let (mut input, mut output) = (0, 0);
+        for port in ports {
+            let mut port_pins = PortPins {
+                address: input,
+                write_value: None,
+                write: None,
+                read: None,
+                output: None
+            };
+            input += address_width;
+            if *port != RamPort::Read {
+                port_pins.write_value = Some(input);
+                port_pins.write = Some(input + data_width);
+                input += data_width + 1;
+            }
+            if *port != RamPort::Write {
+                port_pins.read = Some(input);
+                port_pins.output = Some(output);
+                input += 1;
+                output += data_width;
+            }
+            pins.push(port_pins);
+        }
+        let bits = data_width << address_width;
+        Ram {
+            address_width,
+            data_width,
+            kind,
+            ports: pins,
+            inputs: vec![0; input + 1],
+            storage: new_storage(bits),
+            slaves: match kind {
+                RamKind::Latch => None,
+                RamKind::MasterSlave => Some(new_storage(bits))
+            },
+            slaves_current: true,
+            outputs: vec![0; output]
+        }
+    }
+
+    pub fn address_width(&self) -> usize {
+        self.address_width
+    }
+
+    pub fn kind(&self) -> RamKind {
+        self.kind
+    }
+
+    fn clock(&self) -> usize {
+        self.inputs[self.inputs.len() - 1]
+    }
+
+    fn address_pins(&self, port: &PortPins) -> &[usize] {
+        &self.inputs[port.address..port.address + self.address_width]
+    }
+
+    fn write_ports(&mut self, lines: &[Line]) {
+        for p in 0..self.ports.len() {
+            let port = self.ports[p];
+            if let (Some(value), Some(write)) = (port.write_value, port.write) {
+                if lines[self.inputs[write]].is_high() {
+                    let address = decode(lines, self.address_pins(&port));
+                    for i in 0..self.data_width {
+                        let new = lines[self.inputs[value + i]].into();
+                        self.storage.set(address * self.data_width + i, new);
                     }
+                    self.slaves_current = false;
                 }
-                if lines[self.inputs[17]].is_high() {
-                    for i in 0..8 {
-                        lines[self.outputs[i]] = Line::from(self.storage[addr * 8 + i]);
+            }
+        }
+    }
+
+    // Each port's writes and reads, guarded by its write and read lines.
+    fn port_code(&self) -> (Vec<Statement>, Vec<Statement>) {
+        let (mut writes, mut reads) = (Vec::new(), Vec::new());
+        for port in &self.ports {
+            let address = self.address_pins(port).iter().map(|&line| Expr::Line(line))
+                .collect::<Vec<_>>();
+            if let (Some(value), Some(write)) = (port.write_value, port.write) {
+                let value = (0..self.data_width).map(|i| Expr::Line(self.inputs[value + i]))
+                    .collect();
+                let mut store = vec![Statement::Write { memory: 0, address: address.clone(),
+                    value }];
+                if self.slaves.is_some() {
+                    store.push(Statement::SetState(0, Expr::Constant(false)));
+                }
+                writes.push(Statement::If(Expr::Line(self.inputs[write]), store, Vec::new()));
+            }
+            if let (Some(read), Some(output)) = (port.read, port.output) {
+                let load = Statement::Read {
+                    memory: if self.slaves.is_some() { 1 } else { 0 },
+                    address,
+                    outputs: self.outputs[output..output + self.data_width].to_vec()
+                };
+                reads.push(Statement::If(Expr::Line(self.inputs[read]), vec![load], Vec::new()));
+            }
+        }
+        (writes, reads)
+    }
+
+    fn read_ports(&self, lines: &mut [Line]) {
+        let source = self.slaves.as_ref().unwrap_or(&self.storage);
+        for port in &self.ports {
+            if let (Some(read), Some(output)) = (port.read, port.output) {
+                if lines[self.inputs[read]].is_high() {
+                    let address = decode(lines, self.address_pins(port));
+                    for i in 0..self.data_width {
+                        let new = source.get(address * self.data_width + i).into();
+                        lines[self.outputs[output + i]] = new;
                     }
                 }
             }
@@ -215,29 +910,32 @@ impl Gate for NORLatchRAM8 {
     }
 }
 
-/// Spec:
-/// 0..8: address
-/// 8..16: write value
-/// 16: write
-/// 17: read
-/// 18: clock
-pub struct MSFFRAM8 {
-    inputs: [usize; 19],
-    storage: [bool; 256 * 8 * 2 + 1],
-    outputs: [usize; 8]
-}
-
-impl MSFFRAM8 {
-    pub(crate) fn new() -> Self {
-        MSFFRAM8 {
-            inputs: [0; 19],
-            storage: [false; 256 * 8 * 2 + 1],
-            outputs: [0; 8]
+impl WordMemory for Ram {
+    fn data_width(&self) -> usize {
+        self.data_width
+    }
+
+    fn words(&self) -> usize {
+        1 << self.address_width
+    }
+
+    /// Reads a word as the read ports would see it.
+    fn get_word(&self, address: usize) -> u64 {
+        self.slaves.as_ref().unwrap_or(&self.storage).get_word(address, self.data_width)
+    }
+
+    /// Writes a word directly, bypassing the clock. For master/slave RAM both halves are
+    /// written so the new value is visible straight away.
+    fn set_word(&mut self, address: usize, value: u64) {
+        assert!(address < self.words(), "Address {} is out of range.", address);
+        self.storage.set_word(address, self.data_width, value);
+        if let Some(ref mut slaves) = self.slaves {
+            slaves.set_word(address, self.data_width, value);
         }
     }
 }
 
-impl Gate for MSFFRAM8 {
+impl Gate for Ram {
     fn get_input(&self, i: usize) -> usize {
         self.inputs[i]
     }
@@ -247,7 +945,7 @@ impl Gate for MSFFRAM8 {
     }
 
     fn num_inputs(&self) -> usize {
-        18
+        self.inputs.len()
     }
 
     fn get_output(&self, o: usize) -> usize {
@@ -259,64 +957,172 @@ impl Gate for MSFFRAM8 {
     }
 
     fn num_outputs(&self) -> usize {
-        8
+        self.outputs.len()
     }
 
-    fn eval(&mut self, lines: &mut Vec<Line>) {
-        if lines[self.inputs[18]].is_high() {
-            if lines[self.inputs[16]].is_high() {
-                let last_ind = self.storage.len() - 1;
-                self.storage[last_ind] = false;
-                let addr = (0..8).map(|i| (lines[self.inputs[i]].is_high() as usize) << i)
-                    .sum::<usize>();
-                for i in 0..8 {
-                    let new = lines[self.inputs[8 + i]].into();
-                    self.storage[8 * addr + i] = new;
-                }
+    fn ports(&self) -> Vec<Port> {
+        let suffix = |p: usize| if self.ports.len() == 1 { String::new() } else { p.to_string() };
+        let mut ports = Vec::new();
+        for (p, pins) in self.ports.iter().enumerate() {
+            let name = |base: &str| format!("{}{}", base, suffix(p));
+            ports.push(Port::input(&name("address"), pins.address, self.address_width)
+                .with_role(PortRole::Address));
+            if let (Some(value), Some(write)) = (pins.write_value, pins.write) {
+                ports.push(Port::input(&name("write_value"), value, self.data_width));
+                ports.push(Port::input(&name("write"), write, 1).with_role(PortRole::Control));
             }
-        } else if lines[self.inputs[18]].is_low() && !self.storage[self.storage.len() - 1] {
-            let (masters, slaves) = self.storage.split_at_mut(256 * 8);
-            {
-                let range = 0..slaves.len() - 1;
-                let slaves = &mut slaves[range];
-                slaves.copy_from_slice(&*masters);
+            if let (Some(read), Some(output)) = (pins.read, pins.output) {
+                ports.push(Port::input(&name("read"), read, 1).with_role(PortRole::Control));
+                ports.push(Port::output(&name("read_value"), output, self.data_width));
             }
-            let last = slaves.len() - 1;
-            slaves[last] = true;
         }
-        if lines[self.inputs[17]].is_high() {
-            let addr = (0..8).map(|i| (lines[self.inputs[i]].is_high() as usize) << i)
-                .sum::<usize>();
-            for i in 0..8 {
-                lines[self.outputs[i]] = self.storage[256 * 8 + 8 * addr + i].into();
+        ports.push(Port::input("clock", self.inputs.len() - 1, 1).with_role(PortRole::Clock));
+        ports
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let clock = lines[self.clock()];
+        match self.kind {
+            RamKind::Latch => if clock.is_high() {
+                self.write_ports(lines);
+                self.read_ports(lines);
+            },
+            RamKind::MasterSlave => {
+                if clock.is_high() {
+                    self.write_ports(lines);
+                } else if clock.is_low() && !self.slaves_current {
+                    if let Some(ref mut slaves) = self.slaves {
+                        slaves.copy_from(&self.storage);
+                    }
+                    self.slaves_current = true;
+                }
+                self.read_ports(lines);
             }
         }
     }
+
+    fn memory(&self) -> Option<&dyn WordMemory> {
+        Some(self)
+    }
+
+    fn memory_mut(&mut self) -> Option<&mut dyn WordMemory> {
+        Some(self)
+    }
+
+    // The masters are memory 0 and the slaves memory 1, with whether the slaves are current as
+    // the only state bit.
+    fn code(&self) -> Option<GateCode> {
+        if self.words() > MAX_MEMORY_WORDS {
+            return None;
+        }
+        let words = |storage: &Storage| (0..self.words())
+            .map(|address| storage.get_word(address, self.data_width)).collect();
+        let clock = Expr::Line(self.clock());
+        let (mut writes, reads) = self.port_code();
+        Some(match self.slaves {
+            None => {
+                writes.extend(reads);
+                GateCode {
+                    state: Vec::new(),
+                    memories: vec![words(&self.storage)],
+                    body: vec![Statement::If(clock, writes, Vec::new())]
+                }
+            },
+            Some(ref slaves) => {
+                let copy = Statement::If(!Expr::State(0), vec![
+                    Statement::Copy { from: 0, to: 1 },
+                    Statement::SetState(0, Expr::Constant(true))
+                ], Vec::new());
+                let mut body = vec![Statement::If(clock, writes, vec![copy])];
+                body.extend(reads);
+                GateCode {
+                    state: vec![self.slaves_current],
+                    memories: vec![words(&self.storage), words(slaves)],
+                    body
+                }
+            }
+        })
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("words", self.data_width, self.words())]
+    }
+
+    fn peek_state(&self, _register: usize, index: usize) -> Vec<Line> {
+        word_to_lines(self.get_word(index), self.data_width)
+    }
+
+    fn poke_state(&mut self, _register: usize, index: usize, value: &[Line]) {
+        self.set_word(index, lines_to_word(value));
+    }
 }
 
+/// Read-only memory of `2^address_width` words of `data_width` bits. Reads are combinational.
+///
 /// Spec:
-/// 0..16: address
-/// 16..32: write value
-/// 32: write
-/// 33: read
-/// 34: clock
-pub struct NORLatchRAM16 {
-    inputs: [usize; 35],
-    storage: [bool; 65536 * 16],
-    outputs: [usize; 16]
+/// The address of each read port in turn, least significant bit first.
+///
+/// Each read port has `data_width` outputs, in port order. Contents are set up front with
+/// `set_word` or `with_contents`.
+#[derive(Clone)]
+pub struct Rom {
+    address_width: usize,
+    data_width: usize,
+    storage: Storage,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
 }
 
-impl NORLatchRAM16 {
-    fn new() -> Self {
-        NORLatchRAM16 {
-            inputs: [0; 35],
-            storage: [false; 65536 * 16],
-            outputs: [0; 16]
+impl Rom {
+    pub(crate) fn new(address_width: usize, data_width: usize, read_ports: usize) -> Self {
+        assert!(address_width <= MAX_ADDRESS_WIDTH, "Address width must be at most {} bits.",
+            MAX_ADDRESS_WIDTH);
+        assert!((1..=64).contains(&data_width), "Data width must be between 1 and 64 bits.");
+        assert!(read_ports >= 1, "A ROM needs at least one read port.");
+        Rom {
+            address_width,
+            data_width,
+            storage: new_storage(data_width << address_width),
+            inputs: vec![0; address_width * read_ports],
+            outputs: vec![0; data_width * read_ports]
+        }
+    }
+
+    /// Builds a ROM holding `contents` from address 0 up, with the rest of it low.
+    pub(crate) fn with_contents(address_width: usize, data_width: usize, read_ports: usize,
+        contents: &[u64]) -> Self {
+        let mut rom = Rom::new(address_width, data_width, read_ports);
+        for (address, &word) in contents.iter().enumerate() {
+            rom.set_word(address, word);
         }
+        rom
+    }
+
+    pub fn address_width(&self) -> usize {
+        self.address_width
+    }
+}
+
+impl WordMemory for Rom {
+    fn data_width(&self) -> usize {
+        self.data_width
+    }
+
+    fn words(&self) -> usize {
+        1 << self.address_width
+    }
+
+    fn get_word(&self, address: usize) -> u64 {
+        self.storage.get_word(address, self.data_width)
+    }
+
+    fn set_word(&mut self, address: usize, value: u64) {
+        assert!(address < self.words(), "Address {} is out of range.", address);
+        self.storage.set_word(address, self.data_width, value);
     }
 }
 
-impl Gate for NORLatchRAM16 {
+impl Gate for Rom {
     fn get_input(&self, i: usize) -> usize {
         self.inputs[i]
     }
@@ -326,7 +1132,7 @@ impl Gate for NORLatchRAM16 {
     }
 
     fn num_inputs(&self) -> usize {
-        35
+        self.inputs.len()
     }
 
     fn get_output(&self, o: usize) -> usize {
@@ -338,53 +1144,377 @@ impl Gate for NORLatchRAM16 {
     }
 
     fn num_outputs(&self) -> usize {
-        16
+        self.outputs.len()
     }
 
-    fn eval(&mut self, lines: &mut Vec<Line>) {
-        if lines[self.inputs[34]].is_high() {
-            let addr = (0..16).map(|i| (lines[self.inputs[i]].is_high() as usize) << i)
-                .sum::<usize>();
-            if lines[self.inputs[34]].is_high() {
-                if lines[self.inputs[32]].is_high() {
-                    for i in 0..16 {
-                        let new = lines[self.inputs[16 + i]].into();
-                        self.storage[16 * addr + i] = new;
-                    }
-                }
-                if lines[self.inputs[33]].is_high() {
-                    for i in 0..16 {
-                        lines[self.outputs[i]] = Line::from(self.storage[addr * 16 + i]);
-                    }
+    fn ports(&self) -> Vec<Port> {
+        let read_ports = self.outputs.len() / self.data_width;
+        let suffix = |p: usize| if read_ports == 1 { String::new() } else { p.to_string() };
+        (0..read_ports).flat_map(|p| vec![
+            Port::input(&format!("address{}", suffix(p)), p * self.address_width,
+                self.address_width).with_role(PortRole::Address),
+            Port::output(&format!("data{}", suffix(p)), p * self.data_width, self.data_width)
+        ]).collect()
+    }
+
+    // Every bit becomes a constant line feeding one multiplexer per read port, so only small
+    // ROMs are expanded. A ROM with no address bits is just its one word.
+    fn lower(&self) -> Option<Circuit> {
+        if self.address_width > 12 {
+            return None;
+        }
+        Some(lower_with(self.inputs.len(), |lines, circuit| {
+            let low = circuit.add_gate(Source::new_low());
+            let low = circuit.get_gate_output(low, 0);
+            let high = circuit.add_gate(Source::new_high());
+            let high = circuit.get_gate_output(high, 0);
+            let data = (0..self.words() * self.data_width)
+                .map(|bit| if self.storage.get(bit) { high } else { low })
+                .collect::<Vec<_>>();
+            let mut outputs = Vec::with_capacity(self.outputs.len());
+            for p in 0..self.outputs.len() / self.data_width {
+                if self.address_width == 0 {
+                    outputs.extend_from_slice(&data);
+                } else {
+                    let address = &lines[p * self.address_width..(p + 1) * self.address_width];
+                    outputs.extend(make_mux(&data, address, self.data_width, circuit));
                 }
             }
+            outputs
+        }))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        for (p, outputs) in self.outputs.chunks(self.data_width).enumerate() {
+            let address_pins = &self.inputs[p * self.address_width..(p + 1) * self.address_width];
+            let address = decode(lines, address_pins);
+            for (i, &o) in outputs.iter().enumerate() {
+                lines[o] = self.storage.get(address * self.data_width + i).into();
+            }
+        }
+    }
+
+    // Every port reads from one array of the words, for ROMs too large to lower.
+    fn code(&self) -> Option<GateCode> {
+        if self.words() > MAX_MEMORY_WORDS {
+            return None;
+        }
+        let body = self.outputs.chunks(self.data_width).enumerate().map(|(p, outputs)| {
+            Statement::Read {
+                memory: 0,
+                address: self.inputs[p * self.address_width..(p + 1) * self.address_width]
+                    .iter().map(|&line| Expr::Line(line)).collect(),
+                outputs: outputs.to_vec()
+            }
+        }).collect();
+        Some(GateCode {
+            state: Vec::new(),
+            memories: vec![(0..self.words()).map(|address| self.get_word(address)).collect()],
+            body
+        })
+    }
+
+    fn memory(&self) -> Option<&dyn WordMemory> {
+        Some(self)
+    }
+
+    fn memory_mut(&mut self) -> Option<&mut dyn WordMemory> {
+        Some(self)
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("words", self.data_width, self.words())]
+    }
+
+    fn peek_state(&self, _register: usize, index: usize) -> Vec<Line> {
+        word_to_lines(self.get_word(index), self.data_width)
+    }
+
+    fn poke_state(&mut self, _register: usize, index: usize, value: &[Line]) {
+        self.set_word(index, lines_to_word(value));
+    }
+}
+
+// Implements `Deref` to `Ram` and `Gate` for a wrapper around `Ram` by forwarding to it.
+macro_rules! forward_to_ram {
+    ($name:ident) => {
+        forward_to_ram!($name, |gate: &$name| gate.0.ports());
+    };
+    ($name:ident, $ports:expr) => {
+        impl Deref for $name {
+            type Target = Ram;
+
+            fn deref(&self) -> &Ram {
+                &self.0
+            }
+        }
+
+        impl DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut Ram {
+                &mut self.0
+            }
+        }
+
+        impl Gate for $name {
+            fn get_input(&self, i: usize) -> usize {
+                self.0.get_input(i)
+            }
+
+            fn set_input(&mut self, i: usize, new_i: usize) {
+                self.0.set_input(i, new_i);
+            }
+
+            fn num_inputs(&self) -> usize {
+                self.0.num_inputs()
+            }
+
+            fn get_output(&self, o: usize) -> usize {
+                self.0.get_output(o)
+            }
+
+            fn set_output(&mut self, o: usize, new_o: usize) {
+                self.0.set_output(o, new_o);
+            }
+
+            fn num_outputs(&self) -> usize {
+                self.0.num_outputs()
+            }
+
+            fn ports(&self) -> Vec<Port> {
+                ($ports)(self)
+            }
+
+            fn eval(&mut self, lines: &mut Vec<Line>) {
+                self.0.eval(lines);
+            }
+
+            fn memory(&self) -> Option<&dyn WordMemory> {
+                self.0.memory()
+            }
+
+            fn memory_mut(&mut self) -> Option<&mut dyn WordMemory> {
+                self.0.memory_mut()
+            }
+
+            fn code(&self) -> Option<GateCode> {
+                self.0.code()
+            }
+
+            fn state_registers(&self) -> Vec<StateRegister> {
+                self.0.state_registers()
+            }
+
+            fn peek_state(&self, register: usize, index: usize) -> Vec<Line> {
+                self.0.peek_state(register, index)
+            }
+
+            fn poke_state(&mut self, register: usize, index: usize, value: &[Line]) {
+                self.0.poke_state(register, index, value);
+            }
         }
     }
 }
 
+// Declares a fixed-size, single-port wrapper around `Ram`.
+macro_rules! ram_wrapper {
+    ($($(#[$attr:meta])* $name:ident: $address_width:expr, $data_width:expr, $kind:expr);*) => {$(
+        $(#[$attr])*
+        #[derive(Clone)]
+        pub struct $name(Ram);
+
+        impl $name {
+            pub(crate) fn new() -> Self {
+                $name(Ram::new($address_width, $data_width, $kind, &[RamPort::ReadWrite]))
+            }
+        }
+
+        forward_to_ram!($name);
+    )*}
+}
+
+ram_wrapper!{
+    /// Single-port 256 x 8 latch RAM.
+    ///
+    /// Spec:
+    /// 0..8: address
+    /// 8..16: write value
+    /// 16: write
+    /// 17: read
+    /// 18: clock
+    NORLatchRAM8: 8, 8, RamKind::Latch;
+    /// Single-port 256 x 8 master/slave RAM.
+    ///
+    /// Spec:
+    /// 0..8: address
+    /// 8..16: write value
+    /// 16: write
+    /// 17: read
+    /// 18: clock
+    MSFFRAM8: 8, 8, RamKind::MasterSlave;
+    /// Single-port 65536 x 16 latch RAM.
+    ///
+    /// Spec:
+    /// 0..16: address
+    /// 16..32: write value
+    /// 32: write
+    /// 33: read
+    /// 34: clock
+    NORLatchRAM16: 16, 16, RamKind::Latch;
+    /// Single-port 65536 x 16 master/slave RAM.
+    ///
+    /// Spec:
+    /// 0..16: address
+    /// 16..32: write value
+    /// 32: write
+    /// 33: read
+    /// 34: clock
+    MSFFRAM16: 16, 16, RamKind::MasterSlave
+}
+
+/// Master/slave RAM with independent write and read ports, so a word can be read at one address
+/// while another is written.
+///
 /// Spec:
-/// 0..16: address
-/// 16..32: write value
-/// 32: write
-/// 33: read
-/// 34: clock
-pub struct MSFFRAM16 {
-    inputs: [usize; 35],
-    storage: [bool; 65536 * 16 * 2 + 1],
-    outputs: [usize; 16]
-}
-
-impl MSFFRAM16 {
-    pub(crate) fn new() -> Self {
-        MSFFRAM16 {
-            inputs: [0; 35],
-            storage: [false; 65536 * 16 * 2 + 1],
-            outputs: [0; 16]
+/// `write_address(0..address_width)`, `write_value(0..data_width)`, `write()`,
+/// `read_address(0..address_width)`, `read()`, `clock()`
+///
+/// Outputs are `read_value(0..data_width)`.
+#[derive(Clone)]
+pub struct DualPortRam(Ram);
+
+impl DualPortRam {
+    pub(crate) fn new(address_width: usize, data_width: usize) -> Self {
+        DualPortRam(Ram::new(address_width, data_width, RamKind::MasterSlave,
+            &[RamPort::Write, RamPort::Read]))
+    }
+
+    pub fn write_address(&self, bit: usize) -> usize {
+        assert!(bit < self.0.address_width, "Invalid address bit.");
+        bit
+    }
+
+    pub fn write_value(&self, bit: usize) -> usize {
+        assert!(bit < self.0.data_width, "Invalid data bit.");
+        self.0.address_width + bit
+    }
+
+    pub fn write(&self) -> usize {
+        self.0.address_width + self.0.data_width
+    }
+
+    pub fn read_address(&self, bit: usize) -> usize {
+        assert!(bit < self.0.address_width, "Invalid address bit.");
+        self.write() + 1 + bit
+    }
+
+    pub fn read(&self) -> usize {
+        self.write() + 1 + self.0.address_width
+    }
+
+    pub fn clock(&self) -> usize {
+        self.read() + 1
+    }
+
+    pub fn read_value(&self, bit: usize) -> usize {
+        assert!(bit < self.0.data_width, "Invalid data bit.");
+        bit
+    }
+
+    fn named_ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("write_address", 0, self.0.address_width)
+                .with_role(PortRole::Address),
+            Port::input("write_value", self.write_value(0), self.0.data_width),
+            Port::input("write", self.write(), 1).with_role(PortRole::Control),
+            Port::input("read_address", self.write() + 1, self.0.address_width)
+                .with_role(PortRole::Address),
+            Port::input("read", self.read(), 1).with_role(PortRole::Control),
+            Port::input("clock", self.clock(), 1).with_role(PortRole::Clock),
+            Port::output("read_value", self.read_value(0), self.0.data_width)
+        ]
+    }
+}
+
+forward_to_ram!(DualPortRam, DualPortRam::named_ports);
+
+pub(crate) fn address_bits(entries: usize) -> usize {
+    (usize::MAX.count_ones() - (entries - 1).leading_zeros()).max(1) as usize
+}
+
+/// Bank of `registers` registers of `width` bits with one write port and two read ports. Writes
+/// happen on the rising clock edge while write is high, and reads are combinational. Addresses
+/// past the last register read as 0 and ignore writes.
+///
+/// Spec:
+/// `write_address(0..address_width)`, `write_value(0..width)`, `write()`,
+/// `read_address(0, 0..address_width)`, `read_address(1, 0..address_width)`, `clock()`
+///
+/// Outputs are `read_value(0, 0..width)` followed by `read_value(1, 0..width)`.
+#[derive(Clone)]
+pub struct RegisterFile {
+    address_width: usize,
+    width: usize,
+    registers: Vec<u64>,
+    last_clock: Line,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl RegisterFile {
+    pub(crate) fn new(registers: usize, width: usize) -> Self {
+        assert!(registers >= 1, "A register file needs at least one register.");
+        assert!((1..=64).contains(&width), "Register width must be between 1 and 64 bits.");
+        let address_width = address_bits(registers);
+        RegisterFile {
+            address_width,
+            width,
+            registers: vec![0; registers],
+            last_clock: Line::Low,
+            inputs: vec![0; 3 * address_width + width + 2],
+            outputs: vec![0; 2 * width]
         }
     }
+
+    pub fn address_width(&self) -> usize {
+        self.address_width
+    }
+
+    pub fn write_address(&self, bit: usize) -> usize {
+        assert!(bit < self.address_width, "Invalid address bit.");
+        bit
+    }
+
+    pub fn write_value(&self, bit: usize) -> usize {
+        assert!(bit < self.width, "Invalid data bit.");
+        self.address_width + bit
+    }
+
+    pub fn write(&self) -> usize {
+        self.address_width + self.width
+    }
+
+    pub fn read_address(&self, port: usize, bit: usize) -> usize {
+        assert!(port < 2, "Register files have two read ports.");
+        assert!(bit < self.address_width, "Invalid address bit.");
+        self.write() + 1 + port * self.address_width + bit
+    }
+
+    pub fn clock(&self) -> usize {
+        self.write() + 1 + 2 * self.address_width
+    }
+
+    pub fn read_value(&self, port: usize, bit: usize) -> usize {
+        assert!(port < 2, "Register files have two read ports.");
+        assert!(bit < self.width, "Invalid data bit.");
+        port * self.width + bit
+    }
+
+    fn address(&self, lines: &[Line], first_pin: usize) -> usize {
+        decode(lines, &self.inputs[first_pin..first_pin + self.address_width])
+    }
 }
 
-impl Gate for MSFFRAM16 {
+impl Gate for RegisterFile {
     fn get_input(&self, i: usize) -> usize {
         self.inputs[i]
     }
@@ -394,7 +1524,7 @@ impl Gate for MSFFRAM16 {
     }
 
     fn num_inputs(&self) -> usize {
-        35
+        self.inputs.len()
     }
 
     fn get_output(&self, o: usize) -> usize {
@@ -406,33 +1536,405 @@ impl Gate for MSFFRAM16 {
     }
 
     fn num_outputs(&self) -> usize {
-        16
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("write_address", self.write_address(0), self.address_width)
+                .with_role(PortRole::Address),
+            Port::input("write_value", self.write_value(0), self.width),
+            Port::input("write", self.write(), 1).with_role(PortRole::Control),
+            Port::input("read_address0", self.read_address(0, 0), self.address_width)
+                .with_role(PortRole::Address),
+            Port::input("read_address1", self.read_address(1, 0), self.address_width)
+                .with_role(PortRole::Address),
+            Port::input("clock", self.clock(), 1).with_role(PortRole::Clock),
+            Port::output("read_value0", self.read_value(0, 0), self.width),
+            Port::output("read_value1", self.read_value(1, 0), self.width)
+        ]
     }
 
     fn eval(&mut self, lines: &mut Vec<Line>) {
-        if lines[self.inputs[34]].is_high() {
-            if lines[self.inputs[32]].is_high() {
-                let last_ind = self.storage.len() - 1;
-                self.storage[last_ind] = false;
-                let addr = (0..16).map(|i| (lines[self.inputs[i]].is_high() as usize) << i)
-                    .sum::<usize>();
-                for i in 0..16 {
-                    let new = lines[self.inputs[16 + i]].into();
-                    self.storage[16 * addr + i] = new;
-                }
+        let clock = lines[self.inputs[self.clock()]];
+        let write = lines[self.inputs[self.write()]].is_high();
+        if Edge::Rising.triggered(self.last_clock, clock) && write {
+            let address = self.address(lines, self.write_address(0));
+            let pins = &self.inputs[self.write_value(0)..self.write_value(0) + self.width];
+            let value = lines_to_word(&pins.iter().map(|&pin| lines[pin]).collect::<Vec<_>>());
+            if let Some(register) = self.registers.get_mut(address) {
+                *register = value;
+            }
+        }
+        self.last_clock = clock;
+        for port in 0..2 {
+            let address = self.address(lines, self.read_address(port, 0));
+            let value = self.registers.get(address).cloned().unwrap_or(0);
+            for (bit, new) in word_to_lines(value, self.width).into_iter().enumerate() {
+                lines[self.outputs[self.read_value(port, bit)]] = new;
+            }
+        }
+    }
+
+    // The state is the last clock, and the memory is padded out to every address with words
+    // that are never written, so that addresses past the last register read as 0.
+    fn code(&self) -> Option<GateCode> {
+        let words = 1 << self.address_width;
+        if words > MAX_MEMORY_WORDS {
+            return None;
+        }
+        let address = |first_pin: usize| (first_pin..first_pin + self.address_width)
+            .map(|pin| Expr::Line(self.inputs[pin])).collect::<Vec<_>>();
+        let line = |pin: usize| Expr::Line(self.inputs[pin]);
+        let clock = line(self.clock());
+        let write = Statement::Write {
+            memory: 0,
+            address: address(self.write_address(0)),
+            value: (0..self.width).map(|bit| line(self.write_value(bit))).collect()
+        };
+        let in_range = less_than(&address(self.write_address(0)), self.registers.len() as u64);
+        let mut body = vec![
+            Statement::If(!Expr::State(0) & clock.clone() & line(self.write()) & in_range,
+                vec![write], Vec::new()),
+            Statement::SetState(0, clock)
+        ];
+        body.extend((0..2).map(|port| Statement::Read {
+            memory: 0,
+            address: address(self.read_address(port, 0)),
+            outputs: (0..self.width).map(|bit| self.outputs[self.read_value(port, bit)]).collect()
+        }));
+        let mut memory = self.registers.clone();
+        memory.resize(words, 0);
+        Some(GateCode {
+            state: vec![self.last_clock.is_high()],
+            memories: vec![memory],
+            body
+        })
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("registers", self.width, self.registers.len())]
+    }
+
+    fn peek_state(&self, _register: usize, index: usize) -> Vec<Line> {
+        word_to_lines(self.registers[index], self.width)
+    }
+
+    fn poke_state(&mut self, _register: usize, index: usize, value: &[Line]) {
+        self.registers[index] = lines_to_word(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::circuit::Circuit;
+    use circuit::gate::tests::{Rng, assert_lowering_matches};
+    use circuit::line::Line;
+    use super::*;
+
+    // Drives a behavioral flip-flop of each kind and its gate-level reference with the same
+    // random data, enable, preset, clear and clock sequence and checks that q and q-bar agree.
+    fn flip_flops_match(edge: Edge, connect_enable: bool) {
+        let mut c = Circuit::new();
+        // d or j, k, clock, enable, preset, clear
+        let l = (0..6).map(|_| c.add_line(Line::Low)).collect::<Vec<_>>();
+        let enable = if connect_enable { l[3] } else { 0 };
+        let d = c.add_gate(DFlipFlop::new(edge));
+        let jk = c.add_gate(JKFlipFlop::new(edge));
+        let t = c.add_gate(TFlipFlop::new(edge));
+        for &(gate, pins) in &[(d, &[l[0], l[2], enable, l[4], l[5]][..]),
+            (jk, &[l[0], l[1], l[2], enable, l[4], l[5]][..]),
+            (t, &[l[0], l[2], enable, l[4], l[5]][..])] {
+            for (i, &line) in pins.iter().enumerate() {
+                c.set_gate_input(gate, i, line);
+            }
+        }
+        let enable = if connect_enable { Some(l[3]) } else { None };
+        let references = [
+            make_d_flip_flop(l[0], l[2], edge, enable, Some(l[4]), Some(l[5]), &mut c),
+            make_jk_flip_flop(l[0], l[1], l[2], edge, enable, Some(l[4]), Some(l[5]), &mut c),
+            make_t_flip_flop(l[0], l[2], edge, enable, Some(l[4]), Some(l[5]), &mut c)
+        ];
+        let mut rng = Rng(12345);
+        c.eval_n_passes(10);
+        for step in 0..4000 {
+            let r = rng.next();
+            if step % 2 == 0 {
+                c.set_line(l[0], Line::from(r & 1 == 1));
+                c.set_line(l[1], Line::from(r & 2 == 2));
+                c.set_line(l[3], match r >> 2 & 3 {
+                    0 => Line::Low,
+                    1 => Line::Disconnected,
+                    _ => Line::High
+                });
+                c.set_line(l[4], Line::from(r >> 4 & 15 == 1));
+                c.set_line(l[5], Line::from(r >> 8 & 15 == 1));
+            } else {
+                let clock = c.get_line_state(l[2]);
+                c.set_line(l[2], !clock);
+            }
+            c.eval_n_passes(10);
+            for (&gate, &(q, q_bar)) in [d, jk, t].iter().zip(&references) {
+                assert_eq!(c.get_line_state(c.get_gate_output(gate, 0)), c.get_line_state(q),
+                    "q of gate {} differs at step {}.", gate, step);
+                assert_eq!(c.get_line_state(c.get_gate_output(gate, 1)),
+                    c.get_line_state(q_bar), "q-bar of gate {} differs at step {}.", gate, step);
             }
-        } else if lines[self.inputs[34]].is_low() && !self.storage[self.storage.len() - 1] {
-            let (masters, slaves) = self.storage.split_at_mut(65536 * 16);
-            slaves.copy_from_slice(&*masters);
-            let last = slaves.len() - 1;
-            slaves[last] = true;
         }
-        if lines[self.inputs[33]].is_high() {
-            let addr = (0..16).map(|i| (lines[self.inputs[i]].is_high() as usize) << i)
-                .sum::<usize>();
-            for i in 0..16 {
-                lines[self.outputs[i]] = self.storage[65536 * 16 + 16 * addr + i].into();
+    }
+
+    #[test]
+    fn flip_flops_match_gate_level() {
+        for &edge in &[Edge::Rising, Edge::Falling] {
+            flip_flops_match(edge, true);
+            flip_flops_match(edge, false);
+        }
+    }
+
+    #[test]
+    fn disconnected_enable_disables() {
+        let mut c = Circuit::new();
+        let d = c.add_line(Line::High);
+        let clock = c.add_line(Line::Low);
+        let enable = c.add_line(Line::Disconnected);
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        c.set_gate_input(ff, 0, d);
+        c.set_gate_input(ff, 1, clock);
+        c.set_gate_input(ff, 2, enable);
+        c.eval();
+        c.set_line(clock, Line::High);
+        c.eval();
+        assert!(!c.get_line_state(c.get_gate_output(ff, 0)).is_high());
+        c.set_gate_input(ff, 2, 0);
+        c.set_line(clock, Line::Low);
+        c.eval();
+        c.set_line(clock, Line::High);
+        c.eval();
+        assert!(c.get_line_state(c.get_gate_output(ff, 0)).is_high());
+    }
+
+    #[test]
+    fn clear_wins_over_preset() {
+        let mut c = Circuit::new();
+        let preset = c.add_line(Line::High);
+        let clear = c.add_line(Line::High);
+        let ff = c.add_gate(JKFlipFlop::new(Edge::Rising));
+        c.set_gate_input(ff, 4, preset);
+        c.set_gate_input(ff, 5, clear);
+        c.eval();
+        assert_eq!(c.get_line_state(c.get_gate_output(ff, 0)), Line::Low);
+        c.set_line(clear, Line::Low);
+        c.eval();
+        assert_eq!(c.get_line_state(c.get_gate_output(ff, 0)), Line::High);
+    }
+
+    // Drives every input of `gate`, in a circuit of its own, from a line of its own.
+    fn wired<G: Gate + 'static>(gate: G) -> (Circuit, usize, Vec<usize>) {
+        let mut c = Circuit::new();
+        let g = c.add_gate(gate);
+        let inputs = (0..c.gates[g].num_inputs()).map(|i| {
+            let line = c.add_line(Line::Low);
+            c.set_gate_input(g, i, line);
+            line
+        }).collect();
+        (c, g, inputs)
+    }
+
+    fn drive(c: &mut Circuit, lines: &[usize], value: u64) {
+        for (b, &line) in lines.iter().enumerate() {
+            c.set_line(line, Line::from(value >> b & 1 == 1));
+        }
+    }
+
+    fn read(c: &Circuit, g: usize, outputs: ::std::ops::Range<usize>) -> u64 {
+        outputs.enumerate()
+            .map(|(b, o)| (c.get_line_state(c.get_gate_output(g, o)).is_high() as u64) << b).sum()
+    }
+
+    #[test]
+    fn rom_without_address_bits_drives_its_word() {
+        let (mut c, g, _) = wired(Rom::with_contents(0, 4, 2, &[0b1011]));
+        c.eval();
+        assert_eq!(read(&c, g, 0..4), 0b1011);
+        assert_eq!(read(&c, g, 4..8), 0b1011);
+    }
+
+    #[test]
+    fn rom_ports_read_independently() {
+        let contents = (0..16).map(|word| word * 3 + 1).collect::<Vec<u64>>();
+        let (mut c, g, inputs) = wired(Rom::with_contents(4, 8, 2, &contents));
+        for address in 0..16 {
+            drive(&mut c, &inputs[..4], address);
+            drive(&mut c, &inputs[4..], 15 - address);
+            c.eval();
+            assert_eq!(read(&c, g, 0..8), contents[address as usize]);
+            assert_eq!(read(&c, g, 8..16), contents[15 - address as usize]);
+        }
+    }
+
+    #[test]
+    fn rom_lowering_matches() {
+        let mut rng = Rng(99);
+        for &(address_width, data_width, ports) in &[(0, 4, 2), (1, 1, 1), (3, 4, 2), (2, 64, 1)] {
+            let contents = (0..1 << address_width).map(|_| rng.next()).collect::<Vec<_>>();
+            assert_lowering_matches(Rom::with_contents(address_width, data_width, ports,
+                &contents));
+        }
+    }
+
+    #[test]
+    fn latch_ram_only_acts_while_clock_is_high() {
+        let (mut c, g, inputs) = wired(Ram::new(2, 8, RamKind::Latch, &[RamPort::ReadWrite]));
+        drive(&mut c, &inputs[..2], 2);
+        drive(&mut c, &inputs[2..10], 0xA5);
+        drive(&mut c, &inputs[10..12], 0b11);
+        c.eval();
+        assert_eq!(c.get_memory(g).unwrap().get_word(2), 0);
+        c.set_line(inputs[12], Line::High);
+        c.eval();
+        assert_eq!(c.get_memory(g).unwrap().get_word(2), 0xA5);
+        assert_eq!(read(&c, g, 0..8), 0xA5);
+    }
+
+    #[test]
+    fn master_slave_ram_publishes_on_falling_clock() {
+        let (mut c, g, inputs) = wired(Ram::new(2, 8, RamKind::MasterSlave,
+            &[RamPort::Write, RamPort::Read]));
+        // Write port: address 0..2, value 2..10, write 10. Read port: address 11..13, read 13.
+        drive(&mut c, &inputs[..2], 1);
+        drive(&mut c, &inputs[2..10], 0x3C);
+        c.set_line(inputs[10], Line::High);
+        drive(&mut c, &inputs[11..13], 1);
+        c.set_line(inputs[13], Line::High);
+        c.set_line(inputs[14], Line::High);
+        c.eval();
+        assert_eq!(read(&c, g, 0..8), 0);
+        c.set_line(inputs[14], Line::Low);
+        c.eval();
+        assert_eq!(read(&c, g, 0..8), 0x3C);
+    }
+
+    #[test]
+    fn later_ports_win_write_conflicts() {
+        let (mut c, g, inputs) = wired(Ram::new(1, 4, RamKind::Latch,
+            &[RamPort::Write, RamPort::Write]));
+        for port in 0..2 {
+            let base = port * 6;
+            drive(&mut c, &inputs[base + 1..base + 5], 1 + port as u64);
+            c.set_line(inputs[base + 5], Line::High);
+        }
+        c.set_line(inputs[12], Line::High);
+        c.eval();
+        assert_eq!(c.get_memory(g).unwrap().get_word(0), 2);
+    }
+
+    #[test]
+    fn aliases_are_configured_rams() {
+        let ram: &Ram = &NORLatchRAM16::new();
+        assert_eq!((ram.address_width(), ram.data_width(), ram.kind()), (16, 16, RamKind::Latch));
+        let ram: &Ram = &MSFFRAM8::new();
+        assert_eq!((ram.address_width(), ram.data_width(), ram.kind()),
+            (8, 8, RamKind::MasterSlave));
+        assert_eq!(ram.num_inputs(), 19);
+    }
+
+    #[test]
+    fn full_width_words() {
+        let mut ram = Ram::new(1, 64, RamKind::MasterSlave, &[RamPort::ReadWrite]);
+        ram.set_word(1, !0);
+        assert_eq!(ram.get_word(1), !0);
+        assert_eq!(ram.peek_state(0, 1).len(), 64);
+    }
+
+    #[test]
+    fn dual_port_ram_reads_while_writing() {
+        let ram = DualPortRam::new(3, 8);
+        let (write_address, write, read_address, read_enable, clock) = (ram.write_address(0),
+            ram.write(), ram.read_address(0), ram.read(), ram.clock());
+        let (mut c, g, inputs) = wired(ram);
+        c.set_line(inputs[read_enable], Line::High);
+        for address in 0..8 {
+            drive(&mut c, &inputs[write_address..write_address + 3], address);
+            drive(&mut c, &inputs[write_address + 3..write], 0x11 * address + 1);
+            c.set_line(inputs[write], Line::High);
+            drive(&mut c, &inputs[read_address..read_address + 3], address.saturating_sub(1));
+            c.set_line(inputs[clock], Line::High);
+            c.eval();
+            c.set_line(inputs[clock], Line::Low);
+            c.eval();
+            assert_eq!(read(&c, g, 0..8), if address == 0 { 1 } else { 0x11 * address - 0x10 });
+        }
+        c.set_line(inputs[write], Line::Low);
+        drive(&mut c, &inputs[read_address..read_address + 3], 7);
+        c.set_line(inputs[clock], Line::High);
+        c.eval();
+        c.set_line(inputs[clock], Line::Low);
+        c.eval();
+        assert_eq!(read(&c, g, 0..8), 0x78);
+    }
+
+    #[test]
+    fn register_file_matches_model() {
+        for &(registers, width) in &[(1, 1), (5, 8), (4, 64)] {
+            let file = RegisterFile::new(registers, width);
+            let aw = file.address_width();
+            let (write_address, write_value, write, clock) = (file.write_address(0),
+                file.write_value(0), file.write(), file.clock());
+            let read_addresses = [file.read_address(0, 0), file.read_address(1, 0)];
+            let (mut c, g, inputs) = wired(file);
+            let mut model = vec![0u64; registers];
+            let mask = if width == 64 { !0 } else { (1 << width) - 1 };
+            let mut rng = Rng(registers as u64 * 31 + width as u64);
+            for _ in 0..200 {
+                let address = rng.below(1 << aw) as usize;
+                let value = rng.next() & mask;
+                let enabled = rng.below(2) == 1;
+                drive(&mut c, &inputs[write_address..write_address + aw], address as u64);
+                drive(&mut c, &inputs[write_value..write_value + width], value);
+                c.set_line(inputs[write], Line::from(enabled));
+                c.set_line(inputs[clock], Line::High);
+                c.eval();
+                c.set_line(inputs[clock], Line::Low);
+                if enabled && address < registers {
+                    model[address] = value;
+                }
+                let reads = [rng.below(1 << aw) as usize, rng.below(1 << aw) as usize];
+                for (&pin, &address) in read_addresses.iter().zip(&reads) {
+                    drive(&mut c, &inputs[pin..pin + aw], address as u64);
+                }
+                c.eval();
+                for (port, &address) in reads.iter().enumerate() {
+                    assert_eq!(read(&c, g, port * width..(port + 1) * width),
+                        model.get(address).cloned().unwrap_or(0));
+                }
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn address_bits_cover_entries() {
+        assert_eq!(address_bits(1), 1);
+        assert_eq!(address_bits(2), 1);
+        assert_eq!(address_bits(3), 2);
+        assert_eq!(address_bits(64), 6);
+        assert_eq!(address_bits(65), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and 64")]
+    fn register_file_width_is_limited() {
+        RegisterFile::new(4, 65);
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and 64")]
+    fn data_is_at_most_64_bits() {
+        Rom::new(2, 65, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 32 bits")]
+    fn address_width_is_limited() {
+        Ram::new(MAX_ADDRESS_WIDTH + 1, 1, RamKind::Latch, &[RamPort::ReadWrite]);
+    }
+}
@@ -0,0 +1,718 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+
+use circuit::basics::{Inverter, Source};
+use circuit::circuit::Circuit;
+use circuit::line::Line;
+use circuit::netlist::{Netlist, NetlistError, batch_inputs};
+use circuit::simplegate::{make_and_tree, make_or_tree};
+use circuit::truth_table::TruthTable;
+
+/// Most inputs a `BooleanFunction` can have.
+pub const MAX_INPUTS: usize = 16;
+
+/// Most outputs a `BooleanFunction` can have, one per bit of a term's output mask.
+pub const MAX_OUTPUTS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MinimizeError {
+    TooManyInputs { inputs: usize, limit: usize },
+    TooManyOutputs { outputs: usize, limit: usize },
+    /// The circuit isn't purely combinational.
+    Netlist(NetlistError)
+}
+
+impl fmt::Display for MinimizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MinimizeError::TooManyInputs { inputs, limit } => {
+                write!(f, "{} inputs is more than the limit of {}", inputs, limit)
+            },
+            MinimizeError::TooManyOutputs { outputs, limit } => {
+                write!(f, "{} outputs is more than the limit of {}", outputs, limit)
+            },
+            MinimizeError::Netlist(ref error) => error.fmt(f)
+        }
+    }
+}
+
+impl Error for MinimizeError {}
+
+impl From<NetlistError> for MinimizeError {
+    fn from(error: NetlistError) -> Self {
+        MinimizeError::Netlist(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Form {
+    SumOfProducts,
+    ProductOfSums
+}
+
+/// Set of input combinations with the inputs in `care` fixed to their bits in `value` and the
+/// rest free, which is a product of literals. Input `i` is bit `i`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Implicant {
+    pub value: u64,
+    pub care: u64
+}
+
+impl Implicant {
+    pub fn literals(&self) -> usize {
+        self.care.count_ones() as usize
+    }
+
+    pub fn contains(&self, minterm: u64) -> bool {
+        minterm & self.care == self.value
+    }
+
+    // Every combination of `inputs` inputs in the implicant.
+    fn minterms(&self, inputs: usize) -> Vec<u64> {
+        let free = !self.care & ((1u64 << inputs) - 1);
+        let mut minterms = Vec::with_capacity(1 << free.count_ones());
+        let mut subset = 0u64;
+        loop {
+            minterms.push(self.value | subset);
+            if subset == free {
+                return minterms;
+            }
+            subset = subset.wrapping_sub(free) & free;
+        }
+    }
+}
+
+/// Boolean function of up to `MAX_INPUTS` inputs and `MAX_OUTPUTS` outputs, each of which is 0,
+/// 1 or don't-care for every input combination. Input `i` is bit `i` of a combination, as in
+/// `TruthTable`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BooleanFunction {
+    inputs: usize,
+    // Value of each output for each combination, `None` for don't-care.
+    values: Vec<Vec<Option<bool>>>
+}
+
+fn value_char(value: Option<bool>) -> char {
+    match value {
+        Some(true) => '1',
+        Some(false) => '0',
+        None => 'x'
+    }
+}
+
+fn gray(code: u64) -> u64 {
+    code ^ code >> 1
+}
+
+fn check_outputs(outputs: usize) -> Result<(), MinimizeError> {
+    if outputs > MAX_OUTPUTS {
+        return Err(MinimizeError::TooManyOutputs { outputs, limit: MAX_OUTPUTS });
+    }
+    Ok(())
+}
+
+fn input_names(bits: ::std::ops::Range<usize>) -> String {
+    bits.rev().map(|i| format!("in{}", i)).collect::<Vec<_>>().join(" ")
+}
+
+impl BooleanFunction {
+    /// A function whose outputs are all 0.
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        assert!(inputs <= MAX_INPUTS, "Too many inputs.");
+        assert!(outputs <= MAX_OUTPUTS, "Too many outputs.");
+        BooleanFunction {
+            inputs,
+            values: vec![vec![Some(false); 1 << inputs]; outputs]
+        }
+    }
+
+    /// A single-output function that's 1 on `ones`, don't-care on `dont_cares` and 0 elsewhere.
+    pub fn from_minterms(inputs: usize, ones: &[u64], dont_cares: &[u64]) -> Self {
+        let mut function = BooleanFunction::new(inputs, 1);
+        for &minterm in ones {
+            function.set(0, minterm, Some(true));
+        }
+        for &minterm in dont_cares {
+            function.set(0, minterm, None);
+        }
+        function
+    }
+
+    /// The function in `table`, with disconnected outputs and any rows missing from a sampled
+    /// table as don't-cares.
+    pub fn from_truth_table(table: &TruthTable) -> Result<Self, MinimizeError> {
+        if table.inputs() > MAX_INPUTS {
+            return Err(MinimizeError::TooManyInputs { inputs: table.inputs(), limit: MAX_INPUTS });
+        }
+        check_outputs(table.outputs())?;
+        let mut function = BooleanFunction::new(table.inputs(), table.outputs());
+        for values in &mut function.values {
+            for value in values.iter_mut() {
+                *value = None;
+            }
+        }
+        for &(row, ref outputs) in table.rows() {
+            for (o, line) in outputs.iter().enumerate() {
+                let value = if line.is_disconnected() { None } else { Some(line.is_high()) };
+                function.set(o, row, value);
+            }
+        }
+        Ok(function)
+    }
+
+    /// The function a combinational circuit computes from its marked inputs to its marked
+    /// outputs, found by simulating every input combination.
+    pub fn from_circuit(circuit: &Circuit) -> Result<Self, MinimizeError> {
+        let netlist = Netlist::from_circuit(circuit)?;
+        let inputs = netlist.num_inputs();
+        if inputs > MAX_INPUTS {
+            return Err(MinimizeError::TooManyInputs { inputs, limit: MAX_INPUTS });
+        }
+        check_outputs(netlist.num_outputs())?;
+        let mut function = BooleanFunction::new(inputs, netlist.num_outputs());
+        for base in (0..1u64 << inputs).step_by(64) {
+            let outputs = netlist.simulate(&batch_inputs(inputs, base));
+            for minterm in base..(base + 64).min(1 << inputs) {
+                for (o, word) in outputs.iter().enumerate() {
+                    function.set(o, minterm, Some(word >> (minterm - base) & 1 == 1));
+                }
+            }
+        }
+        Ok(function)
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Value of `output` for `minterm`, `None` if it's a don't-care.
+    pub fn get(&self, output: usize, minterm: u64) -> Option<bool> {
+        self.values[output][minterm as usize]
+    }
+
+    pub fn set(&mut self, output: usize, minterm: u64, value: Option<bool>) {
+        self.values[output][minterm as usize] = value;
+    }
+
+    // The same function with 0 and 1 swapped in every output.
+    fn complement(&self) -> BooleanFunction {
+        BooleanFunction {
+            inputs: self.inputs,
+            values: self.values.iter()
+                .map(|values| values.iter().map(|value| value.map(|value| !value)).collect())
+                .collect()
+        }
+    }
+
+    // Multiple-output prime implicants, with the outputs each one is an implicant of. A cube is
+    // an implicant of an output if that output is 1 or don't-care everywhere in it.
+    fn primes(&self) -> Vec<(Implicant, u64)> {
+        let full = (1u64 << self.inputs) - 1;
+        let mut level = BTreeMap::new();
+        for minterm in 0..1u64 << self.inputs {
+            let outputs = (0..self.outputs()).filter(|&o| self.get(o, minterm) != Some(false))
+                .fold(0u64, |mask, o| mask | 1 << o);
+            if outputs != 0 {
+                level.insert(Implicant { value: minterm, care: full }, outputs);
+            }
+        }
+        let mut primes = Vec::new();
+        while !level.is_empty() {
+            let mut next = BTreeMap::new();
+            let mut absorbed = BTreeSet::new();
+            for (&implicant, &outputs) in &level {
+                for bit in (0..self.inputs).map(|i| 1u64 << i)
+                    .filter(|&bit| implicant.care & bit != 0 && implicant.value & bit == 0) {
+                    let partner = Implicant { value: implicant.value | bit, care: implicant.care };
+                    let partner_outputs = match level.get(&partner) {
+                        Some(&outputs) => outputs,
+                        None => continue
+                    };
+                    let shared = outputs & partner_outputs;
+                    if shared == 0 {
+                        continue;
+                    }
+                    let merged = Implicant { value: implicant.value, care: implicant.care & !bit };
+                    *next.entry(merged).or_insert(0) |= shared;
+                    if shared == outputs {
+                        absorbed.insert(implicant);
+                    }
+                    if shared == partner_outputs {
+                        absorbed.insert(partner);
+                    }
+                }
+            }
+            primes.extend(level.into_iter().filter(|&(implicant, _)| {
+                !absorbed.contains(&implicant)
+            }));
+            level = next;
+        }
+        primes
+    }
+
+    // Sum-of-products cover of the 1s of every output.
+    fn cover(&self) -> Vec<Term> {
+        let primes = self.primes();
+        let mut targets = BTreeMap::new();
+        for o in 0..self.outputs() {
+            for minterm in 0..1u64 << self.inputs {
+                if self.get(o, minterm) == Some(true) {
+                    let index = targets.len();
+                    targets.insert((o, minterm), index);
+                }
+            }
+        }
+        let covers = primes.iter().map(|&(implicant, outputs)| {
+            implicant.minterms(self.inputs).into_iter().flat_map(|minterm| {
+                (0..self.outputs()).filter(move |&o| outputs >> o & 1 == 1)
+                    .map(move |o| (o, minterm))
+            }).filter_map(|target| targets.get(&target).cloned()).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        let mut coverers = vec![Vec::new(); targets.len()];
+        for (p, cover) in covers.iter().enumerate() {
+            for &target in cover {
+                coverers[target].push(p);
+            }
+        }
+        let mut selected = vec![false; primes.len()];
+        let mut covered = vec![false; targets.len()];
+        let select = |p: usize, selected: &mut Vec<bool>, covered: &mut Vec<bool>| {
+            selected[p] = true;
+            for &target in &covers[p] {
+                covered[target] = true;
+            }
+        };
+        // Essential primes, the only ones covering some 1.
+        for target in 0..targets.len() {
+            if coverers[target].len() == 1 && !selected[coverers[target][0]] {
+                select(coverers[target][0], &mut selected, &mut covered);
+            }
+        }
+        // Then greedily the prime covering the most new 1s per literal.
+        while let Some(target) = covered.iter().position(|&covered| !covered) {
+            let gain = |p: usize| covers[p].iter().filter(|&&t| !covered[t]).count();
+            let best = coverers[target].iter().cloned().max_by(|&a, &b| {
+                let (cost_a, cost_b) = (primes[a].0.literals() + 1, primes[b].0.literals() + 1);
+                (gain(a) * cost_b).cmp(&(gain(b) * cost_a)).then(cost_b.cmp(&cost_a))
+            }).unwrap();
+            select(best, &mut selected, &mut covered);
+        }
+        // Finally each output drops the terms it doesn't need, largest first.
+        let mut terms = (0..primes.len()).filter(|&p| selected[p]).map(|p| Term {
+            implicant: primes[p].0,
+            outputs: primes[p].1
+        }).collect::<Vec<_>>();
+        terms.sort_by_key(|term| (term.implicant.literals(), term.implicant));
+        for o in 0..self.outputs() {
+            for t in (0..terms.len()).rev() {
+                if terms[t].outputs >> o & 1 == 0 {
+                    continue;
+                }
+                let elsewhere = |minterm: u64| terms.iter().enumerate().any(|(u, term)| {
+                    u != t && term.outputs >> o & 1 == 1 && term.implicant.contains(minterm)
+                });
+                let needed = terms[t].implicant.minterms(self.inputs).into_iter()
+                    .any(|minterm| self.get(o, minterm) == Some(true) && !elsewhere(minterm));
+                if !needed {
+                    terms[t].outputs &= !(1 << o);
+                }
+            }
+        }
+        terms.retain(|term| term.outputs != 0);
+        terms
+    }
+
+    /// A two-level cover of every output, sharing terms between outputs where that saves some.
+    ///
+    /// Prime implicants are generated Quine-McCluskey style, and the cover takes the essential
+    /// ones, then greedily the one covering the most remaining 1s per literal, then drops any
+    /// term an output doesn't need. The result is irredundant, and minimal for most functions of
+    /// a few inputs, but not guaranteed to be. Products of sums are covers of the 0s.
+    pub fn minimize(&self, form: Form) -> Cover {
+        let terms = match form {
+            Form::SumOfProducts => self.cover(),
+            Form::ProductOfSums => self.complement().cover()
+        };
+        Cover {
+            inputs: self.inputs,
+            outputs: self.outputs(),
+            form,
+            terms
+        }
+    }
+
+    /// Karnaugh map of `output` as text, with `x` for don't-cares. Rows and columns are in Gray
+    /// code order, and functions of 5 or 6 inputs get one map per value of the inputs past 4.
+    pub fn karnaugh_map(&self, output: usize) -> String {
+        assert!(self.inputs <= 6, "Karnaugh maps need at most 6 inputs.");
+        let cols = self.inputs.min(4).div_ceil(2);
+        let rows = self.inputs.min(4) - cols;
+        if self.inputs == 0 {
+            return format!("{}\n", value_char(self.get(output, 0)));
+        }
+        let corner = if rows == 0 {
+            input_names(0..cols)
+        } else {
+            format!("{} \\ {}", input_names(cols..cols + rows), input_names(0..cols))
+        };
+        let width = cols.max(1);
+        let code = |value: u64, bits: usize| if bits == 0 {
+            String::new()
+        } else {
+            format!("{:0w$b}", gray(value), w = bits)
+        };
+        let mut out = String::new();
+        for extra in 0..1u64 << (self.inputs.max(4) - 4) {
+            if self.inputs > 4 {
+                out.push_str(&format!("{} = {}:\n", input_names(4..self.inputs),
+                    code(extra, self.inputs - 4)));
+            }
+            out.push_str(&format!("{} |", corner));
+            for col in 0..1u64 << cols {
+                out.push_str(&format!(" {:>w$}", code(col, cols), w = width));
+            }
+            out.push_str(&format!("\n{}+{}\n", "-".repeat(corner.len() + 1),
+                "-".repeat((width + 1) << cols)));
+            for row in 0..1u64 << rows {
+                out.push_str(&format!("{:>w$} |", code(row, rows), w = corner.len()));
+                for col in 0..1u64 << cols {
+                    let minterm = gray(col) | gray(row) << cols | gray(extra) << 4;
+                    out.push_str(&format!(" {:>w$}", value_char(self.get(output, minterm)),
+                        w = width));
+                }
+                out.push('\n');
+            }
+            if extra + 1 < 1 << (self.inputs.max(4) - 4) {
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// One product term of a sum of products, or one sum term of a product of sums, and the
+/// outputs sharing it as a bit mask.
+///
+/// In a product of sums the implicant is a cube of 0s, so the sum term is its complement: input
+/// `i` appears inverted when its bit in `value` is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Term {
+    pub implicant: Implicant,
+    pub outputs: u64
+}
+
+/// Two-level implementation of a `BooleanFunction`, from `BooleanFunction::minimize`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cover {
+    inputs: usize,
+    outputs: usize,
+    form: Form,
+    terms: Vec<Term>
+}
+
+impl Cover {
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    pub fn form(&self) -> Form {
+        self.form
+    }
+
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    /// Terms used by `output`.
+    pub fn terms_of(&self, output: usize) -> Vec<Implicant> {
+        self.terms.iter().filter(|term| term.outputs >> output & 1 == 1)
+            .map(|term| term.implicant).collect()
+    }
+
+    /// Literals over all terms, counting shared terms once.
+    pub fn num_literals(&self) -> usize {
+        self.terms.iter().map(|term| term.implicant.literals()).sum()
+    }
+
+    pub fn eval(&self, output: usize, minterm: u64) -> bool {
+        let hit = self.terms_of(output).iter().any(|implicant| implicant.contains(minterm));
+        match self.form {
+            Form::SumOfProducts => hit,
+            Form::ProductOfSums => !hit
+        }
+    }
+
+    // Literals of a term as `(input, positive)`.
+    fn literals(&self, implicant: Implicant) -> Vec<(usize, bool)> {
+        (0..self.inputs).filter(|&i| implicant.care >> i & 1 == 1).map(|i| {
+            let set = implicant.value >> i & 1 == 1;
+            (i, set == (self.form == Form::SumOfProducts))
+        }).collect()
+    }
+
+    fn format_term<S: AsRef<str>>(&self, implicant: Implicant, inputs: &[S]) -> String {
+        let literals = self.literals(implicant).into_iter().map(|(i, positive)| {
+            format!("{}{}", if positive { "" } else { "!" }, inputs[i].as_ref())
+        }).collect::<Vec<_>>();
+        match (self.form, literals.len()) {
+            (Form::SumOfProducts, 0) => "1".to_string(),
+            (Form::ProductOfSums, 0) => "0".to_string(),
+            (Form::SumOfProducts, _) => literals.join("*"),
+            (Form::ProductOfSums, 1) => literals[0].clone(),
+            (Form::ProductOfSums, _) => format!("({})", literals.join(" + "))
+        }
+    }
+
+    /// One `output = expression` line per output, with `Line` operators, which
+    /// `parse_circuit` reads back.
+    pub fn format<S: AsRef<str>, T: AsRef<str>>(&self, inputs: &[S], outputs: &[T]) -> String {
+        let mut out = String::new();
+        for (o, output) in outputs[..self.outputs].iter().enumerate() {
+            let terms = self.terms_of(o).into_iter()
+                .map(|implicant| self.format_term(implicant, inputs)).collect::<Vec<_>>();
+            let expression = match (self.form, terms.is_empty()) {
+                (Form::SumOfProducts, true) => "0".to_string(),
+                (Form::ProductOfSums, true) => "1".to_string(),
+                (Form::SumOfProducts, false) => terms.join(" + "),
+                (Form::ProductOfSums, false) => terms.join("*")
+            };
+            out.push_str(&format!("{} = {}\n", output.as_ref(), expression));
+        }
+        out
+    }
+
+    /// Builds the cover as a circuit of `SimpleGate`s, `Inverter`s and constant `Source`s, with
+    /// the function's inputs and outputs marked in order. Inverted inputs and shared terms are
+    /// built once.
+    pub fn to_circuit(&self) -> Circuit {
+        let mut circuit = Circuit::new();
+        let inputs = (0..self.inputs).map(|_| {
+            let line = circuit.add_line(Line::Low);
+            circuit.mark_line_as_circuit_input(line);
+            line
+        }).collect::<Vec<_>>();
+        let mut inverted = vec![None; self.inputs];
+        let mut constants = [None, None];
+        let mut constant = |value: bool, circuit: &mut Circuit| {
+            *constants[value as usize].get_or_insert_with(|| {
+                let source = if value { Source::new_high() } else { Source::new_low() };
+                let gate = circuit.add_gate(source);
+                circuit.get_gate_output(gate, 0)
+            })
+        };
+        let sop = self.form == Form::SumOfProducts;
+        let terms = self.terms.iter().map(|term| {
+            let literals = self.literals(term.implicant).into_iter().map(|(i, positive)| {
+                if positive {
+                    return inputs[i];
+                }
+                *inverted[i].get_or_insert_with(|| {
+                    let inverter = circuit.add_gate(Inverter::new());
+                    circuit.set_gate_input(inverter, 0, inputs[i]);
+                    circuit.get_gate_output(inverter, 0)
+                })
+            }).collect::<Vec<_>>();
+            match (literals.is_empty(), sop) {
+                (true, _) => constant(sop, &mut circuit),
+                (false, true) => make_and_tree(&literals, &mut circuit),
+                (false, false) => make_or_tree(&literals, &mut circuit)
+            }
+        }).collect::<Vec<_>>();
+        for o in 0..self.outputs {
+            let lines = (0..self.terms.len()).filter(|&t| self.terms[t].outputs >> o & 1 == 1)
+                .map(|t| terms[t]).collect::<Vec<_>>();
+            let line = match (lines.is_empty(), sop) {
+                (true, _) => constant(!sop, &mut circuit),
+                (false, true) => make_or_tree(&lines, &mut circuit),
+                (false, false) => make_and_tree(&lines, &mut circuit)
+            };
+            circuit.mark_line_as_circuit_output(line);
+        }
+        circuit
+    }
+}
+
+/// Writes the cover with inputs named `in0`, `in1`... and outputs `out0`, `out1`...
+impl fmt::Display for Cover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inputs = (0..self.inputs).map(|i| format!("in{}", i)).collect::<Vec<_>>();
+        let outputs = (0..self.outputs).map(|o| format!("out{}", o)).collect::<Vec<_>>();
+        write!(f, "{}", self.format(&inputs, &outputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::expression::parse_circuit;
+    use circuit::gate::tests::{Rng, random_logic, respond};
+    use circuit::simplegate::SimpleGate;
+    use circuit::truth_table::{TruthTableOptions, truth_table};
+    use super::*;
+
+    fn random_function(rng: &mut Rng, inputs: usize, outputs: usize) -> BooleanFunction {
+        let mut function = BooleanFunction::new(inputs, outputs);
+        for o in 0..outputs {
+            for minterm in 0..1u64 << inputs {
+                let value = [Some(false), Some(true), None][rng.below(3) as usize];
+                function.set(o, minterm, value);
+            }
+        }
+        function
+    }
+
+    fn stimulus(minterm: u64, inputs: usize) -> Vec<Line> {
+        (0..inputs).map(|i| Line::from(minterm >> i & 1 == 1)).collect()
+    }
+
+    // Checks that `circuit`, with inputs in order, computes `function` wherever it cares.
+    fn assert_implements(function: &BooleanFunction, circuit: &mut Circuit) {
+        for minterm in 0..1u64 << function.inputs() {
+            let outputs = respond(circuit, &stimulus(minterm, function.inputs()));
+            for (o, line) in outputs.iter().enumerate() {
+                if let Some(value) = function.get(o, minterm) {
+                    assert_eq!(line.is_high(), value, "output {} at {:#b}", o, minterm);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covers_implement_random_functions() {
+        let mut rng = Rng(5);
+        for inputs in 0..=6 {
+            for outputs in 1..=3 {
+                let function = random_function(&mut rng, inputs, outputs);
+                for &form in &[Form::SumOfProducts, Form::ProductOfSums] {
+                    let cover = function.minimize(form);
+                    assert_eq!((cover.inputs(), cover.outputs(), cover.form()),
+                        (inputs, outputs, form));
+                    for o in 0..outputs {
+                        for minterm in 0..1u64 << inputs {
+                            if let Some(value) = function.get(o, minterm) {
+                                assert_eq!(cover.eval(o, minterm), value);
+                            }
+                        }
+                    }
+                    assert_implements(&function, &mut cover.to_circuit());
+                    // Written out and parsed back, the inputs come in order of first use.
+                    let mut parsed = parse_circuit(&cover.to_string()).unwrap();
+                    for minterm in 0..1u64 << inputs {
+                        let values = stimulus(minterm, inputs);
+                        let applied = parsed.inputs.iter()
+                            .map(|name| values[name[2..].parse::<usize>().unwrap()])
+                            .collect::<Vec<_>>();
+                        let outputs = respond(&mut parsed.circuit, &applied);
+                        for (o, line) in outputs.iter().enumerate() {
+                            assert_eq!(line.is_high(), cover.eval(o, minterm));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covers_are_irredundant() {
+        let mut rng = Rng(11);
+        for _ in 0..30 {
+            let function = random_function(&mut rng, 5, 2);
+            let cover = function.minimize(Form::SumOfProducts);
+            for o in 0..2 {
+                let terms = cover.terms_of(o);
+                for (t, term) in terms.iter().enumerate() {
+                    let needed = (0..32).any(|minterm| {
+                        function.get(o, minterm) == Some(true) && term.contains(minterm)
+                            && !terms.iter().enumerate()
+                                .any(|(u, other)| u != t && other.contains(minterm))
+                    });
+                    assert!(needed, "term {:?} of output {} isn't needed", term, o);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn textbook_example() {
+        let function = BooleanFunction::from_minterms(4, &[4, 8, 10, 11, 12, 15], &[9, 14]);
+        let cover = function.minimize(Form::SumOfProducts);
+        assert_eq!(cover.terms().len(), 3);
+        assert_eq!(cover.num_literals(), 7);
+        let parity = BooleanFunction::from_circuit(&parity(4)).unwrap();
+        assert_eq!(parity.minimize(Form::SumOfProducts).terms().len(), 8);
+        assert_eq!(parity.minimize(Form::ProductOfSums).num_literals(), 32);
+    }
+
+    fn parity(inputs: usize) -> Circuit {
+        let mut circuit = Circuit::new();
+        let mut acc = circuit.add_line(Line::Low);
+        circuit.mark_line_as_circuit_input(acc);
+        for _ in 1..inputs {
+            let line = circuit.add_line(Line::Low);
+            circuit.mark_line_as_circuit_input(line);
+            let gate = circuit.add_gate(SimpleGate::xor());
+            circuit.set_gate_input(gate, 0, acc);
+            circuit.set_gate_input(gate, 1, line);
+            acc = circuit.get_gate_output(gate, 0);
+        }
+        circuit.mark_line_as_circuit_output(acc);
+        circuit
+    }
+
+    #[test]
+    fn circuits_and_truth_tables_agree() {
+        for seed in 1..6 {
+            let mut circuit = random_logic(seed, 5, 25, 3, false);
+            let from_circuit = BooleanFunction::from_circuit(&circuit).unwrap();
+            let table = truth_table(&mut circuit, &TruthTableOptions::default()).unwrap();
+            assert_eq!(BooleanFunction::from_truth_table(&table).unwrap(), from_circuit);
+            assert_implements(&from_circuit, &mut circuit);
+            assert_implements(&from_circuit,
+                &mut from_circuit.minimize(Form::ProductOfSums).to_circuit());
+        }
+    }
+
+    #[test]
+    fn edge_sizes() {
+        let constant = BooleanFunction::from_circuit(&random_logic(3, 0, 4, 1, false)).unwrap();
+        assert_eq!(constant.inputs(), 0);
+        assert_implements(&constant, &mut constant.minimize(Form::SumOfProducts).to_circuit());
+        assert_implements(&constant, &mut constant.minimize(Form::ProductOfSums).to_circuit());
+        assert_eq!(BooleanFunction::from_circuit(&parity(MAX_INPUTS)).unwrap().inputs(), 16);
+        assert_eq!(BooleanFunction::from_circuit(&parity(17)),
+            Err(MinimizeError::TooManyInputs { inputs: 17, limit: 16 }));
+        let mut wide = random_logic(8, 3, 70, 64, false);
+        let function = BooleanFunction::from_circuit(&wide).unwrap();
+        assert_eq!(function.outputs(), 64);
+        let cover = function.minimize(Form::SumOfProducts);
+        assert_implements(&function, &mut cover.to_circuit());
+        assert_implements(&function, &mut wide);
+        let mut wider = random_logic(8, 3, 70, 65, false);
+        let error = MinimizeError::TooManyOutputs { outputs: 65, limit: 64 };
+        assert_eq!(BooleanFunction::from_circuit(&wider), Err(error.clone()));
+        let table = truth_table(&mut wider, &TruthTableOptions::default()).unwrap();
+        assert_eq!(BooleanFunction::from_truth_table(&table), Err(error.clone()));
+        assert_eq!(error.to_string(), "65 outputs is more than the limit of 64");
+    }
+
+    #[test]
+    fn karnaugh_maps() {
+        let function = BooleanFunction::from_minterms(2, &[3], &[1]);
+        assert_eq!(function.karnaugh_map(0),
+            "in1 \\ in0 | 0 1\n----------+----\n        0 | 0 x\n        1 | 0 1\n");
+        assert_eq!(BooleanFunction::from_minterms(0, &[0], &[]).karnaugh_map(0), "1\n");
+        let map = BooleanFunction::from_minterms(5, &[31], &[]).karnaugh_map(0);
+        assert!(map.starts_with("in4 = 0:\n"));
+        assert!(map.contains("\n\nin4 = 1:\n"));
+        // A title, header, rule and four rows for each map, and a blank line between them.
+        assert_eq!(map.lines().count(), 15);
+    }
+}
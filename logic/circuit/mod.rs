@@ -1,8 +1,31 @@
 pub mod line;
+pub mod bus;
 pub mod gate;
 pub mod basics;
 pub mod simplegate;
+pub mod lut;
 pub mod mux;
+pub mod encoder;
+pub mod storage;
 pub mod memory;
+pub mod image;
+pub mod sequential;
+pub mod clock;
+pub mod testbench;
+pub mod truth_table;
+pub mod fifo;
+pub mod netlist;
+pub mod sat;
+pub mod bdd;
+pub mod equivalence;
+pub mod expression;
+pub mod minimize;
+pub mod optimize;
+pub mod techmap;
+pub mod lutmap;
+pub mod compiled;
+pub mod codegen;
+pub mod parallel;
+pub mod batch;
 pub mod circuit;
 //pub mod integrated_circuit;
\ No newline at end of file
@@ -1,5 +1,10 @@
-use circuit::{gate::Gate, line::Line};
+use circuit::{gate::{Gate, GateKind, Port, PortRole, lower_with}, line::{Line, and}};
+use circuit::circuit::Circuit;
+use circuit::bus::decode;
+use circuit::simplegate::SimpleGate;
+use circuit::encoder::make_minterms;
 
+#[derive(Clone)]
 pub struct Mux1_2 {
     i0: usize,
     i1: usize,
@@ -59,6 +64,19 @@ impl Gate for Mux1_2 {
         1
     }
 
+    fn kind(&self) -> GateKind {
+        GateKind::Mux
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("in0", 0, 1),
+            Port::input("in1", 1, 1),
+            Port::input("select", 2, 1).with_role(PortRole::Select),
+            Port::output("out", 0, 1)
+        ]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         let tmp = match lines[self.sel] {
             Line::High => lines[self.i1],
@@ -69,6 +87,7 @@ impl Gate for Mux1_2 {
     }
 }
 
+#[derive(Clone)]
 pub struct Dmux1_2 {
     i0: usize,
     sel: usize,
@@ -128,6 +147,15 @@ impl Gate for Dmux1_2 {
         2
     }
 
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("in", 0, 1),
+            Port::input("select", 1, 1).with_role(PortRole::Select),
+            Port::output("out0", 0, 1),
+            Port::output("out1", 1, 1)
+        ]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         let tmp = lines[self.i0];
         match lines[self.sel] {
@@ -139,4 +167,286 @@ impl Gate for Dmux1_2 {
             }
         };
     }
-}
\ No newline at end of file
+}
+
+fn select_bits(ways: usize) -> usize {
+    assert!(ways >= 2 && ways.is_power_of_two(), "Number of ways must be a power of two >= 2.");
+    ways.trailing_zeros() as usize
+}
+
+/// `ways`:1 multiplexer of `width`-bit words.
+///
+/// Spec:
+/// 0..ways * width: data, word `i` bit `b` at `i * width + b`
+/// ways * width..: select, least significant bit first
+///
+/// If any select bit is disconnected every output is disconnected, matching a tree of `Mux1_2`s.
+/// See `make_mux` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct Mux {
+    ways: usize,
+    width: usize,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl Mux {
+    pub(crate) fn new(ways: usize, width: usize) -> Self {
+        let sel_bits = select_bits(ways);
+        Mux {
+            ways,
+            width,
+            inputs: vec![0; ways * width + sel_bits],
+            outputs: vec![0; width]
+        }
+    }
+}
+
+impl Gate for Mux {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let data = self.ways * self.width;
+        let mut ports = (0..self.ways)
+            .map(|i| Port::input(&format!("in{}", i), i * self.width, self.width))
+            .collect::<Vec<_>>();
+        ports.push(Port::input("select", data, self.inputs.len() - data)
+            .with_role(PortRole::Select));
+        ports.push(Port::output("out", 0, self.width));
+        ports
+    }
+
+    fn lower(&self) -> Option<Circuit> {
+        let data = self.ways * self.width;
+        Some(lower_with(self.inputs.len(), |lines, circuit| {
+            make_mux(&lines[..data], &lines[data..], self.width, circuit)
+        }))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let sel = &self.inputs[self.ways * self.width..];
+        if sel.iter().any(|&s| lines[s].is_disconnected()) {
+            for &o in &self.outputs {
+                lines[o] = Line::Disconnected;
+            }
+            return;
+        }
+        let which = decode(lines, sel);
+        for b in 0..self.width {
+            let new = lines[self.inputs[which * self.width + b]];
+            lines[self.outputs[b]] = new;
+        }
+    }
+}
+
+/// 1:`ways` demultiplexer of `width`-bit words. The selected word follows the data input and
+/// every other word is held low.
+///
+/// Spec:
+/// 0..width: data
+/// width..: select, least significant bit first
+///
+/// Output word `i` bit `b` is at `i * width + b`. Disconnected select bits count as low. See
+/// `make_dmux` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct Dmux {
+    ways: usize,
+    width: usize,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl Dmux {
+    pub(crate) fn new(ways: usize, width: usize) -> Self {
+        let sel_bits = select_bits(ways);
+        Dmux {
+            ways,
+            width,
+            inputs: vec![0; width + sel_bits],
+            outputs: vec![0; ways * width]
+        }
+    }
+}
+
+impl Gate for Dmux {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let mut ports = vec![
+            Port::input("in", 0, self.width),
+            Port::input("select", self.width, self.inputs.len() - self.width)
+                .with_role(PortRole::Select)
+        ];
+        ports.extend((0..self.ways)
+            .map(|i| Port::output(&format!("out{}", i), i * self.width, self.width)));
+        ports
+    }
+
+    fn lower(&self) -> Option<Circuit> {
+        Some(lower_with(self.inputs.len(), |lines, circuit| {
+            make_dmux(&lines[..self.width], &lines[self.width..], circuit)
+        }))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let which = decode(lines, &self.inputs[self.width..]);
+        for w in 0..self.ways {
+            let selected = Line::from(w == which);
+            for b in 0..self.width {
+                let new = and(lines[self.inputs[b]], selected);
+                lines[self.outputs[w * self.width + b]] = new;
+            }
+        }
+    }
+}
+
+/// Builds a `2^select.len()`:1 multiplexer of `width`-bit words as a tree of `Mux1_2`s. `data`
+/// follows the layout of `Mux`, and the returned lines are the `width` output bits.
+pub fn make_mux(data: &[usize], select: &[usize], width: usize, circuit: &mut Circuit)
+    -> Vec<usize> {
+    let ways = 1 << select.len();
+    select_bits(ways);
+    assert_eq!(data.len(), ways * width, "Data lines don't match the number of ways and width.");
+    let mut outputs = Vec::with_capacity(width);
+    for b in 0..width {
+        let mut layer = (0..ways).map(|w| data[w * width + b]).collect::<Vec<_>>();
+        for &sel in select {
+            layer = layer.chunks(2).map(|pair| {
+                let mux = circuit.add_gate(Mux1_2::new());
+                circuit.set_gate_input(mux, 0, pair[0]);
+                circuit.set_gate_input(mux, 1, pair[1]);
+                circuit.set_gate_input(mux, 2, sel);
+                circuit.get_gate_output(mux, 0)
+            }).collect();
+        }
+        outputs.push(layer[0]);
+    }
+    outputs
+}
+
+/// Builds a 1:`2^select.len()` demultiplexer of `data.len()`-bit words from a decoder and AND
+/// gates. The returned lines follow the output layout of `Dmux`.
+pub fn make_dmux(data: &[usize], select: &[usize], circuit: &mut Circuit) -> Vec<usize> {
+    select_bits(1 << select.len());
+    let minterms = make_minterms(select, circuit);
+    let mut outputs = Vec::with_capacity(minterms.len() * data.len());
+    for minterm in minterms {
+        for &d in data {
+            let gate = circuit.add_gate(SimpleGate::and());
+            circuit.set_gate_input(gate, 0, d);
+            circuit.set_gate_input(gate, 1, minterm);
+            outputs.push(circuit.get_gate_output(gate, 0));
+        }
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::circuit::Circuit;
+    use circuit::gate::Gate;
+    use circuit::gate::tests::{assert_lowering_matches, respond, same, single_gate};
+    use circuit::line::Line;
+    use super::{Dmux, Mux, make_mux};
+
+    #[test]
+    fn mux_lowering_matches() {
+        for &(ways, width) in &[(2, 1), (4, 2), (8, 1), (2, 3), (2, 0), (2, 64), (4, 65)] {
+            assert_lowering_matches(Mux::new(ways, width));
+        }
+    }
+
+    #[test]
+    fn dmux_lowering_matches() {
+        for &(ways, width) in &[(2, 1), (4, 1), (4, 3), (8, 1), (2, 0), (2, 64), (2, 65)] {
+            assert_lowering_matches(Dmux::new(ways, width));
+        }
+    }
+
+    #[test]
+    fn mux_selects_word() {
+        let mut circuit = single_gate(Mux::new(4, 2));
+        for sel in 0..4 {
+            let mut inputs = (0..8).map(|i| Line::from(i / 2 == sel && i % 2 == 0))
+                .collect::<Vec<_>>();
+            inputs.extend((0..2).map(|b| Line::from(sel >> b & 1 == 1)));
+            assert_eq!(respond(&mut circuit, &inputs), vec![Line::High, Line::Low]);
+        }
+    }
+
+    #[test]
+    fn disconnected_select_disconnects_both_forms() {
+        let inputs = [Line::High, Line::High, Line::High, Line::High, Line::Low,
+            Line::Disconnected];
+        let mut behavioral = single_gate(Mux::new(4, 1));
+        let mut lowered = Mux::new(4, 1).lower().unwrap();
+        for outputs in &[respond(&mut behavioral, &inputs), respond(&mut lowered, &inputs)] {
+            assert!(same(outputs[0], Line::Disconnected));
+        }
+    }
+
+    #[test]
+    fn dmux_holds_unselected_words_low() {
+        let mut circuit = single_gate(Dmux::new(4, 1));
+        let outputs = respond(&mut circuit, &[Line::High, Line::High, Line::Disconnected]);
+        assert_eq!(outputs, vec![Line::Low, Line::High, Line::Low, Line::Low]);
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn mux_needs_power_of_two_ways() {
+        Mux::new(3, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Data lines")]
+    fn make_mux_checks_data_width() {
+        let mut circuit = Circuit::new();
+        let lines = (0..5).map(|_| circuit.add_line(Line::Low)).collect::<Vec<_>>();
+        make_mux(&lines[..4], &lines[4..], 1, &mut circuit);
+    }
+}
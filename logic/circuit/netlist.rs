@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use circuit::circuit::Circuit;
+use circuit::gate::GateKind;
+use circuit::line::Line;
+
+/// One node of a `Netlist`. Operands are indices of earlier nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Node {
+    /// Input `i` of the netlist.
+    Input(usize),
+    Constant(bool),
+    Not(usize),
+    And(usize, usize),
+    Or(usize, usize),
+    Xor(usize, usize),
+    /// `high` if `select` is set, `low` otherwise.
+    Mux { select: usize, low: usize, high: usize }
+}
+
+/// Where the value of a netlist input comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetInput {
+    /// The circuit input with this index.
+    Circuit(usize),
+    /// Output `output` of gate `gate`, which holds state and was cut out of the netlist.
+    State { gate: usize, output: usize }
+}
+
+/// Where the value of a netlist output goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetOutput {
+    /// The circuit output with this index.
+    Circuit(usize),
+    /// Input `input` of gate `gate`, which holds state and was cut out of the netlist.
+    State { gate: usize, input: usize }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetlistError {
+    /// Gate `gate` can't be expressed with primitive gates, because it holds state or has no
+    /// `Gate::lower`.
+    Opaque { gate: usize },
+    /// Line `line` depends on itself without a stateful gate in between.
+    Loop { line: usize }
+}
+
+impl fmt::Display for NetlistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetlistError::Opaque { gate } => {
+                write!(f, "gate {} isn't combinational or can't be lowered", gate)
+            },
+            NetlistError::Loop { line } => {
+                write!(f, "line {} is part of a combinational loop", line)
+            }
+        }
+    }
+}
+
+impl Error for NetlistError {}
+
+// Input words for the low six inputs, which vary within each 64-combination batch.
+const PATTERNS: [u64; 6] = [
+    0xAAAA_AAAA_AAAA_AAAA,
+    0xCCCC_CCCC_CCCC_CCCC,
+    0xF0F0_F0F0_F0F0_F0F0,
+    0xFF00_FF00_FF00_FF00,
+    0xFFFF_0000_FFFF_0000,
+    0xFFFF_FFFF_0000_0000
+];
+
+/// Input words for `Netlist::simulate` that evaluate the 64 input combinations starting at
+/// `base`, a multiple of 64, so bit `k` of the outputs is the result for combination `base + k`.
+/// With fewer than six inputs, the combinations repeat across the word.
+pub(crate) fn batch_inputs(inputs: usize, base: u64) -> Vec<u64> {
+    (0..inputs).map(|i| if i < 6 {
+        PATTERNS[i]
+    } else if base >> i & 1 == 1 {
+        !0
+    } else {
+        0
+    }).collect()
+}
+
+/// Two-valued, levelized view of the combinational logic in a `Circuit`, built from primitive
+/// nodes with behavioral gates expanded through `Gate::lower`. Nodes are in topological order,
+/// so every operand comes before the node using it.
+///
+/// Disconnected values are treated as low throughout. Lines that no gate drives and that aren't
+/// circuit inputs become constants holding their current state, and line 0 is always low.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Netlist {
+    pub nodes: Vec<Node>,
+    pub inputs: Vec<NetInput>,
+    /// Node and destination of each output.
+    pub outputs: Vec<(usize, NetOutput)>
+}
+
+// Structural state for translating one circuit, or one lowered gate inside another.
+struct Builder<'a> {
+    circuit: &'a Circuit,
+    // Which gate and output pin drives each line.
+    drivers: HashMap<usize, (usize, usize)>,
+    // Node carrying each line, once known. `None` marks a line being translated.
+    lines: HashMap<usize, Option<usize>>,
+    // Lowerings of gates whose inputs are still being translated.
+    lowerings: HashMap<usize, Circuit>,
+    // Nodes of the outputs of each gate that's been lowered.
+    lowered: HashMap<usize, Vec<usize>>,
+    cut: bool
+}
+
+impl<'a> Builder<'a> {
+    fn new(circuit: &'a Circuit, inputs: &[usize], cut: bool) -> Self {
+        let mut drivers = HashMap::new();
+        for (g, gate) in circuit.gates.iter().enumerate() {
+            for o in 0..gate.num_outputs() {
+                drivers.insert(gate.get_output(o), (g, o));
+            }
+        }
+        let lines = circuit.inputs.iter().zip(inputs).map(|(&line, &node)| (line, Some(node)))
+            .collect();
+        Builder {
+            circuit,
+            drivers,
+            lines,
+            lowerings: HashMap::new(),
+            lowered: HashMap::new(),
+            cut
+        }
+    }
+
+    fn is_opaque(&self, gate: usize) -> bool {
+        self.circuit.gates[gate].kind() == GateKind::Other
+            && !self.lowered.contains_key(&gate)
+            && self.circuit.gates[gate].lower().is_none()
+    }
+
+    // Returns the node carrying `line`, translating everything it depends on first. The lines
+    // still to do are kept on a stack of their own rather than the call stack, so that long
+    // chains of gates can't overflow it.
+    fn line(&mut self, line: usize, netlist: &mut Netlist) -> Result<usize, NetlistError> {
+        let mut stack = vec![line];
+        while let Some(&top) = stack.last() {
+            match self.lines.get(&top) {
+                Some(&Some(_)) => {
+                    stack.pop();
+                },
+                // Back on top of the stack, so everything it depends on is done.
+                Some(&None) => {
+                    let node = self.translate(top, netlist)?;
+                    self.lines.insert(top, Some(node));
+                    stack.pop();
+                },
+                None => {
+                    self.lines.insert(top, None);
+                    for dependency in self.dependencies(top)?.into_iter().rev() {
+                        match self.lines.get(&dependency) {
+                            Some(&Some(_)) => {},
+                            Some(&None) => return Err(NetlistError::Loop { line: dependency }),
+                            None => stack.push(dependency)
+                        }
+                    }
+                }
+            }
+        }
+        Ok(self.lines[&line].expect("Line was just translated."))
+    }
+
+    // Lines that need nodes before `line` can have one.
+    fn dependencies(&mut self, line: usize) -> Result<Vec<usize>, NetlistError> {
+        let g = match self.drivers.get(&line) {
+            Some(&(g, _)) => g,
+            None => return Ok(Vec::new())
+        };
+        let circuit = self.circuit;
+        let gate = &circuit.gates[g];
+        let inputs = match gate.kind() {
+            GateKind::Constant(_) | GateKind::Sink => 0,
+            GateKind::Not => 1,
+            GateKind::Mux => 3,
+            GateKind::Other if self.lowered.contains_key(&g) => 0,
+            GateKind::Other if self.lowerings.contains_key(&g) => gate.num_inputs(),
+            GateKind::Other => match gate.lower() {
+                Some(lowered) => {
+                    self.lowerings.insert(g, lowered);
+                    gate.num_inputs()
+                },
+                None if self.cut => 0,
+                None => return Err(NetlistError::Opaque { gate: g })
+            },
+            _ => 2
+        };
+        Ok((0..inputs).map(|i| gate.get_input(i)).collect())
+    }
+
+    fn translate(&mut self, line: usize, netlist: &mut Netlist) -> Result<usize, NetlistError> {
+        match self.drivers.get(&line).cloned() {
+            Some((gate, output)) => self.gate_output(gate, output, netlist),
+            None => {
+                let state = line != 0 && self.circuit.lines[line].is_high();
+                Ok(netlist.push(Node::Constant(state)))
+            }
+        }
+    }
+
+    fn input(&self, gate: usize, i: usize) -> usize {
+        let line = self.circuit.gates[gate].get_input(i);
+        self.lines[&line].expect("Inputs are translated before the gate.")
+    }
+
+    fn gate_output(&mut self, g: usize, output: usize, netlist: &mut Netlist)
+        -> Result<usize, NetlistError> {
+        let node = match self.circuit.gates[g].kind() {
+            GateKind::Constant(state) => Node::Constant(state.is_high()),
+            GateKind::Not => Node::Not(self.input(g, 0)),
+            GateKind::And => Node::And(self.input(g, 0), self.input(g, 1)),
+            GateKind::Or => Node::Or(self.input(g, 0), self.input(g, 1)),
+            GateKind::Xor => Node::Xor(self.input(g, 0), self.input(g, 1)),
+            GateKind::Nand => {
+                let and = Node::And(self.input(g, 0), self.input(g, 1));
+                Node::Not(netlist.push(and))
+            },
+            GateKind::Nor => {
+                let or = Node::Or(self.input(g, 0), self.input(g, 1));
+                Node::Not(netlist.push(or))
+            },
+            GateKind::Xnor => {
+                let xor = Node::Xor(self.input(g, 0), self.input(g, 1));
+                Node::Not(netlist.push(xor))
+            },
+            GateKind::Mux => Node::Mux {
+                low: self.input(g, 0),
+                high: self.input(g, 1),
+                select: self.input(g, 2)
+            },
+            GateKind::Sink => unreachable!("Sinks have no outputs."),
+            GateKind::Other => return self.other_output(g, output, netlist)
+        };
+        Ok(netlist.push(node))
+    }
+
+    fn other_output(&mut self, g: usize, output: usize, netlist: &mut Netlist)
+        -> Result<usize, NetlistError> {
+        if let Some(nodes) = self.lowered.get(&g) {
+            return Ok(nodes[output]);
+        }
+        let lowered = match self.lowerings.remove(&g) {
+            Some(lowered) => lowered,
+            None => {
+                netlist.inputs.push(NetInput::State { gate: g, output });
+                return Ok(netlist.push(Node::Input(netlist.inputs.len() - 1)));
+            }
+        };
+        let inputs = (0..self.circuit.gates[g].num_inputs()).map(|i| self.input(g, i))
+            .collect::<Vec<_>>();
+        let mut inner = Builder::new(&lowered, &inputs, false);
+        let nodes = lowered.outputs.iter().map(|&line| inner.line(line, netlist))
+            .collect::<Result<Vec<_>, _>>()?;
+        let node = nodes[output];
+        self.lowered.insert(g, nodes);
+        Ok(node)
+    }
+}
+
+impl Netlist {
+    fn empty() -> Self {
+        Netlist {
+            nodes: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn build(circuit: &Circuit, cut: bool) -> Result<Netlist, NetlistError> {
+        let mut netlist = Netlist::empty();
+        let inputs = (0..circuit.inputs.len()).map(|i| {
+            netlist.inputs.push(NetInput::Circuit(i));
+            netlist.push(Node::Input(i))
+        }).collect::<Vec<_>>();
+        let mut builder = Builder::new(circuit, &inputs, cut);
+        for (o, &line) in circuit.outputs.iter().enumerate() {
+            let node = builder.line(line, &mut netlist)?;
+            netlist.outputs.push((node, NetOutput::Circuit(o)));
+        }
+        if cut {
+            for g in 0..circuit.gates.len() {
+                if builder.is_opaque(g) {
+                    for i in 0..circuit.gates[g].num_inputs() {
+                        let node = builder.line(circuit.gates[g].get_input(i), &mut netlist)?;
+                        netlist.outputs.push((node, NetOutput::State { gate: g, input: i }));
+                    }
+                }
+            }
+        }
+        Ok(netlist)
+    }
+
+    /// Translates a purely combinational circuit, from its marked inputs to its marked outputs.
+    pub fn from_circuit(circuit: &Circuit) -> Result<Netlist, NetlistError> {
+        Netlist::build(circuit, false)
+    }
+
+    /// Translates the combinational logic of any circuit. Gates that can't be lowered are cut
+    /// out: their outputs become extra `NetInput::State` inputs after the circuit's own, and their
+    /// inputs become extra `NetOutput::State` outputs after the circuit's own.
+    pub fn cut_at_state(circuit: &Circuit) -> Result<Netlist, NetlistError> {
+        Netlist::build(circuit, true)
+    }
+
+    pub fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Evaluates 64 input vectors at once, one per bit of each input word, and returns the
+    /// value of every node.
+    pub fn simulate_nodes(&self, inputs: &[u64]) -> Vec<u64> {
+        assert_eq!(inputs.len(), self.inputs.len(), "Need one word per input.");
+        let mut values: Vec<u64> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let value = match *node {
+                Node::Input(i) => inputs[i],
+                Node::Constant(state) => if state { !0 } else { 0 },
+                Node::Not(a) => !values[a],
+                Node::And(a, b) => values[a] & values[b],
+                Node::Or(a, b) => values[a] | values[b],
+                Node::Xor(a, b) => values[a] ^ values[b],
+                Node::Mux { select, low, high } => {
+                    values[select] & values[high] | !values[select] & values[low]
+                }
+            };
+            values.push(value);
+        }
+        values
+    }
+
+    /// Evaluates 64 input vectors at once and returns one word per output.
+    pub fn simulate(&self, inputs: &[u64]) -> Vec<u64> {
+        let values = self.simulate_nodes(inputs);
+        self.outputs.iter().map(|&(node, _)| values[node]).collect()
+    }
+
+    /// Evaluates a single input vector.
+    pub fn eval(&self, inputs: &[Line]) -> Vec<Line> {
+        let words = inputs.iter().map(|line| if line.is_high() { 1 } else { 0 })
+            .collect::<Vec<_>>();
+        self.simulate(&words).into_iter().map(|word| Line::from(word & 1 == 1)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Inverter;
+    use circuit::gate::tests::{Rng, random_logic, respond, single_gate};
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::mux::Mux;
+    use circuit::simplegate::SimpleGate;
+    use super::*;
+
+    // Checks `netlist` against evaluating `circuit` on random inputs, or all of them for up to
+    // ten inputs.
+    fn assert_matches(circuit: &mut Circuit, netlist: &Netlist) {
+        let inputs = circuit.inputs.len();
+        let mut rng = Rng(5);
+        for v in 0..if inputs <= 10 { 1 << inputs } else { 200 } {
+            let stimulus = (0..inputs)
+                .map(|i| if inputs <= 10 { Line::from(v >> i & 1 == 1) } else { rng.line() })
+                .collect::<Vec<_>>();
+            let expected = respond(circuit, &stimulus);
+            let actual = netlist.eval(&stimulus);
+            assert!(expected.iter().zip(&actual).all(|(a, b)| a.is_high() == b.is_high()),
+                "Netlist differs for inputs {:?}.", stimulus);
+        }
+    }
+
+    #[test]
+    fn random_logic_matches() {
+        for seed in 1..30 {
+            for &(inputs, rewritten) in &[(0, false), (4, false), (9, true), (64, false),
+                (65, true)] {
+                let mut circuit = random_logic(seed, inputs, 40, 3, rewritten);
+                let netlist = Netlist::from_circuit(&circuit).unwrap();
+                assert_eq!((netlist.num_inputs(), netlist.num_outputs()), (inputs, 3));
+                assert_matches(&mut circuit, &netlist);
+            }
+        }
+    }
+
+    #[test]
+    fn behavioral_gates_are_lowered() {
+        let mut circuit = single_gate(Mux::new(4, 3));
+        let netlist = Netlist::from_circuit(&circuit).unwrap();
+        assert!(netlist.nodes.iter().all(|node| match *node {
+            Node::Input(_) | Node::Constant(_) | Node::Not(_) | Node::And(..) | Node::Or(..)
+                | Node::Xor(..) | Node::Mux { .. } => true
+        }));
+        assert_matches(&mut circuit, &netlist);
+    }
+
+    #[test]
+    fn nodes_are_in_topological_order() {
+        let netlist = Netlist::from_circuit(&random_logic(3, 8, 200, 5, true)).unwrap();
+        for (n, node) in netlist.nodes.iter().enumerate() {
+            let operands = match *node {
+                Node::Input(_) | Node::Constant(_) => vec![],
+                Node::Not(a) => vec![a],
+                Node::And(a, b) | Node::Or(a, b) | Node::Xor(a, b) => vec![a, b],
+                Node::Mux { select, low, high } => vec![select, low, high]
+            };
+            assert!(operands.iter().all(|&operand| operand < n));
+        }
+    }
+
+    #[test]
+    fn long_chains_build() {
+        let mut circuit = Circuit::new();
+        let input = circuit.add_line(Line::Low);
+        circuit.mark_line_as_circuit_input(input);
+        let mut line = input;
+        for _ in 0..3000 {
+            let inverter = circuit.add_gate(Inverter::new());
+            circuit.set_gate_input(inverter, 0, line);
+            line = circuit.get_gate_output(inverter, 0);
+        }
+        circuit.mark_line_as_circuit_output(line);
+        let netlist = Netlist::from_circuit(&circuit).unwrap();
+        assert_eq!(netlist.nodes.len(), 3001);
+        assert_eq!(netlist.simulate(&[0b10]), vec![0b10]);
+    }
+
+    #[test]
+    fn undriven_lines_are_constants() {
+        let mut circuit = Circuit::new();
+        let high = circuit.add_line(Line::High);
+        let gate = circuit.add_gate(SimpleGate::and());
+        circuit.set_gate_input(gate, 0, high);
+        let out = circuit.get_gate_output(gate, 0);
+        circuit.mark_line_as_circuit_output(out);
+        circuit.mark_line_as_circuit_output(high);
+        let netlist = Netlist::from_circuit(&circuit).unwrap();
+        assert_eq!(netlist.simulate(&[]), vec![0, !0]);
+    }
+
+    #[test]
+    fn loops_and_state_are_errors() {
+        let mut circuit = Circuit::new();
+        let gate = circuit.add_gate(SimpleGate::and());
+        let out = circuit.get_gate_output(gate, 0);
+        circuit.set_gate_input(gate, 1, out);
+        circuit.mark_line_as_circuit_output(out);
+        assert_eq!(Netlist::from_circuit(&circuit), Err(NetlistError::Loop { line: out }));
+        let circuit = single_gate(DFlipFlop::new(Edge::Rising));
+        assert_eq!(Netlist::from_circuit(&circuit), Err(NetlistError::Opaque { gate: 0 }));
+    }
+
+    #[test]
+    fn state_is_cut_out() {
+        // A flip-flop toggling through an inverter.
+        let mut circuit = Circuit::new();
+        let clock = circuit.add_line(Line::Low);
+        circuit.mark_line_as_circuit_input(clock);
+        let ff = circuit.add_gate(DFlipFlop::new(Edge::Rising));
+        let inverter = circuit.add_gate(Inverter::new());
+        let q = circuit.get_gate_output(ff, 0);
+        circuit.set_gate_input(inverter, 0, q);
+        circuit.set_gate_input(ff, 0, circuit.get_gate_output(inverter, 0));
+        circuit.set_gate_input(ff, 1, clock);
+        circuit.mark_line_as_circuit_output(q);
+        let netlist = Netlist::cut_at_state(&circuit).unwrap();
+        assert_eq!(netlist.inputs, vec![NetInput::Circuit(0),
+            NetInput::State { gate: ff, output: 0 }]);
+        let destinations = netlist.outputs.iter().map(|&(_, output)| output).collect::<Vec<_>>();
+        assert_eq!(destinations.len(), 1 + circuit.gates[ff].num_inputs());
+        assert_eq!(destinations[..3], [NetOutput::Circuit(0),
+            NetOutput::State { gate: ff, input: 0 }, NetOutput::State { gate: ff, input: 1 }]);
+        // D is the inverted state, and the clock passes straight through.
+        assert_eq!(netlist.simulate(&[0b1100, 0b1010])[..3], [0b1010, !0b1010, 0b1100]);
+    }
+
+    #[test]
+    fn batches_count_up() {
+        let words = batch_inputs(8, 128);
+        for k in 0..64 {
+            let row = (0..8).map(|i| (words[i] >> k & 1) << i).sum::<u64>();
+            assert_eq!(row, 128 + k);
+        }
+    }
+}
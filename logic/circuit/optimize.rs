@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use circuit::basics::{Inverter, Source};
+use circuit::circuit::Circuit;
+use circuit::gate::{Gate, GateKind};
+use circuit::line::Line;
+
+/// Which passes `optimize` runs. All of them by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// Folds gates with inputs driven by `Source`s.
+    pub constant_propagation: bool,
+    /// Removes gates whose outputs reach no circuit output and no gate without outputs, such as
+    /// a `Sink`. This includes stateful gates nothing reads from.
+    pub dead_logic: bool,
+    /// Merges gates of the same kind reading the same lines.
+    pub structural_hashing: bool,
+    /// Replaces an inverter of an inverter with the original line.
+    pub double_inverters: bool,
+    /// Simplifies gates with the same line on both inputs, such as `x & x` to `x`.
+    pub tied_inputs: bool
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            constant_propagation: true,
+            dead_logic: true,
+            structural_hashing: true,
+            double_inverters: true,
+            tied_inputs: true
+        }
+    }
+}
+
+/// Gates each pass of `optimize` removed, net of any gates it added in their place.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    pub gates_before: usize,
+    pub gates_after: usize,
+    pub constant_propagation: isize,
+    pub dead_logic: isize,
+    pub structural_hashing: isize,
+    pub double_inverters: isize,
+    pub tied_inputs: isize
+}
+
+impl OptimizeReport {
+    pub fn removed(&self) -> isize {
+        self.gates_before as isize - self.gates_after as isize
+    }
+}
+
+impl fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} gates -> {} gates", self.gates_before, self.gates_after)?;
+        writeln!(f, "  constant propagation: {}", self.constant_propagation)?;
+        writeln!(f, "  dead logic:           {}", self.dead_logic)?;
+        writeln!(f, "  structural hashing:   {}", self.structural_hashing)?;
+        writeln!(f, "  double inverters:     {}", self.double_inverters)?;
+        write!(f, "  tied inputs:          {}", self.tied_inputs)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pass {
+    ConstantPropagation,
+    StructuralHashing,
+    DoubleInverters,
+    TiedInputs
+}
+
+// What a gate's output can be replaced with.
+enum Rewrite {
+    Line(usize),
+    Constant(bool),
+    Not(usize)
+}
+
+fn is_simple(kind: GateKind) -> bool {
+    !matches!(kind, GateKind::Other | GateKind::Sink | GateKind::Constant(_))
+}
+
+// Gate kind with its Line value, for hashing.
+fn kind_key(kind: GateKind) -> (u8, u8) {
+    match kind {
+        GateKind::Constant(line) => (0, match line {
+            Line::High => 1,
+            Line::Low => 0,
+            Line::Disconnected => 2
+        }),
+        GateKind::Sink => (1, 0),
+        GateKind::Not => (2, 0),
+        GateKind::And => (3, 0),
+        GateKind::Or => (4, 0),
+        GateKind::Xor => (5, 0),
+        GateKind::Nand => (6, 0),
+        GateKind::Nor => (7, 0),
+        GateKind::Xnor => (8, 0),
+        GateKind::Mux => (9, 0),
+        GateKind::Other => (10, 0)
+    }
+}
+
+struct Optimizer<'a> {
+    circuit: &'a mut Circuit,
+    // Line that each line has been replaced with, or itself.
+    alias: Vec<usize>,
+    // Gate and output driving each line.
+    drivers: Vec<Option<usize>>,
+    // Pass that made each gate redundant, if one did.
+    bypassed: Vec<Option<Pass>>,
+    // Gates each pass added.
+    added: Vec<Pass>,
+    constants: [Option<usize>; 2]
+}
+
+impl<'a> Optimizer<'a> {
+    fn new(circuit: &'a mut Circuit) -> Self {
+        let mut optimizer = Optimizer {
+            alias: (0..circuit.lines.len()).collect(),
+            drivers: vec![None; circuit.lines.len()],
+            bypassed: vec![None; circuit.gates.len()],
+            added: Vec::new(),
+            constants: [None, None],
+            circuit
+        };
+        for g in 0..optimizer.circuit.gates.len() {
+            optimizer.track(g);
+        }
+        optimizer
+    }
+
+    fn track(&mut self, g: usize) {
+        let gate = &self.circuit.gates[g];
+        for o in 0..gate.num_outputs() {
+            self.drivers[gate.get_output(o)] = Some(g);
+        }
+        if let GateKind::Constant(line) = gate.kind() {
+            if !line.is_disconnected() && self.constants[line.is_high() as usize].is_none() {
+                self.constants[line.is_high() as usize] = Some(gate.get_output(0));
+            }
+        }
+    }
+
+    fn add<T: Gate + 'static>(&mut self, gate: T, pass: Pass) -> usize {
+        let g = self.circuit.add_gate(gate);
+        let lines = self.circuit.lines.len();
+        self.alias.extend(self.alias.len()..lines);
+        self.drivers.resize(lines, None);
+        self.bypassed.push(None);
+        self.added.push(pass);
+        self.track(g);
+        g
+    }
+
+    fn resolve(&self, mut line: usize) -> usize {
+        while self.alias[line] != line {
+            line = self.alias[line];
+        }
+        line
+    }
+
+    fn input(&self, g: usize, i: usize) -> usize {
+        self.resolve(self.circuit.gates[g].get_input(i))
+    }
+
+    // Live gate driving `line`, if any.
+    fn driver(&self, line: usize) -> Option<usize> {
+        self.drivers[line].filter(|&g| self.bypassed[g].is_none())
+    }
+
+    fn constant_value(&self, line: usize) -> Option<bool> {
+        match self.driver(line).map(|g| self.circuit.gates[g].kind()) {
+            Some(GateKind::Constant(Line::High)) => Some(true),
+            Some(GateKind::Constant(Line::Low)) => Some(false),
+            _ => None
+        }
+    }
+
+    fn constant(&mut self, value: bool, pass: Pass) -> usize {
+        if let Some(line) = self.constants[value as usize] {
+            if self.driver(line).is_some() {
+                return line;
+            }
+        }
+        let source = if value { Source::new_high() } else { Source::new_low() };
+        let g = self.add(source, pass);
+        let line = self.circuit.gates[g].get_output(0);
+        self.constants[value as usize] = Some(line);
+        line
+    }
+
+    fn inverted(&mut self, line: usize, pass: Pass) -> usize {
+        if let Some(g) = self.driver(line) {
+            if self.circuit.gates[g].kind() == GateKind::Not {
+                return self.input(g, 0);
+            }
+        }
+        let g = self.add(Inverter::new(), pass);
+        self.circuit.gates[g].set_input(0, line);
+        self.circuit.gates[g].get_output(0)
+    }
+
+    fn rewrite(&mut self, g: usize, rewrite: Rewrite, pass: Pass) -> bool {
+        let output = self.circuit.gates[g].get_output(0);
+        let line = match rewrite {
+            Rewrite::Line(line) => line,
+            Rewrite::Constant(value) => self.constant(value, pass),
+            Rewrite::Not(line) => self.inverted(line, pass)
+        };
+        if line == output {
+            return false;
+        }
+        self.alias[output] = line;
+        self.bypassed[g] = Some(pass);
+        true
+    }
+
+    fn fold_constants(&self, g: usize) -> Option<Rewrite> {
+        let kind = self.circuit.gates[g].kind();
+        let a = self.input(g, 0);
+        if kind == GateKind::Not {
+            return self.constant_value(a).map(|value| Rewrite::Constant(!value));
+        }
+        let b = self.input(g, 1);
+        if kind == GateKind::Mux {
+            let select = self.input(g, 2);
+            return self.constant_value(select).map(|high| Rewrite::Line(if high { b } else { a }));
+        }
+        let (value, other) = match (self.constant_value(a), self.constant_value(b)) {
+            (Some(value), _) => (value, b),
+            (None, Some(value)) => (value, a),
+            (None, None) => return None
+        };
+        Some(match (kind, value) {
+            (GateKind::And, false) | (GateKind::Nor, true) => Rewrite::Constant(false),
+            (GateKind::Or, true) | (GateKind::Nand, false) => Rewrite::Constant(true),
+            (GateKind::And, true) | (GateKind::Or, false) | (GateKind::Xor, false)
+                | (GateKind::Xnor, true) => Rewrite::Line(other),
+            _ => Rewrite::Not(other)
+        })
+    }
+
+    fn fold_tied(&self, g: usize) -> Option<Rewrite> {
+        let kind = self.circuit.gates[g].kind();
+        if kind == GateKind::Not || self.input(g, 0) != self.input(g, 1) {
+            return None;
+        }
+        let a = self.input(g, 0);
+        Some(match kind {
+            GateKind::And | GateKind::Or | GateKind::Mux => Rewrite::Line(a),
+            GateKind::Xor => Rewrite::Constant(false),
+            GateKind::Xnor => Rewrite::Constant(true),
+            _ => Rewrite::Not(a)
+        })
+    }
+
+    fn fold_double_inverter(&self, g: usize) -> Option<Rewrite> {
+        if self.circuit.gates[g].kind() != GateKind::Not {
+            return None;
+        }
+        let inner = self.driver(self.input(g, 0))?;
+        if self.circuit.gates[inner].kind() == GateKind::Not {
+            Some(Rewrite::Line(self.input(inner, 0)))
+        } else {
+            None
+        }
+    }
+
+    // Whether the gate's output can be replaced, which marked inputs can't be.
+    fn rewritable(&self, g: usize) -> bool {
+        self.bypassed[g].is_none()
+            && !self.circuit.inputs.contains(&self.circuit.gates[g].get_output(0))
+    }
+
+    // Runs the local rewrites over every gate once, returning whether any applied.
+    fn simplify(&mut self, options: &OptimizeOptions) -> bool {
+        let mut changed = false;
+        let mut g = 0;
+        while g < self.circuit.gates.len() {
+            if is_simple(self.circuit.gates[g].kind()) && self.rewritable(g) {
+                let rewrites = [
+                    (options.constant_propagation, Pass::ConstantPropagation),
+                    (options.tied_inputs, Pass::TiedInputs),
+                    (options.double_inverters, Pass::DoubleInverters)
+                ];
+                for &(enabled, pass) in &rewrites {
+                    let rewrite = match pass {
+                        _ if !enabled => None,
+                        Pass::ConstantPropagation => self.fold_constants(g),
+                        Pass::TiedInputs => self.fold_tied(g),
+                        _ => self.fold_double_inverter(g)
+                    };
+                    if let Some(rewrite) = rewrite {
+                        if self.rewrite(g, rewrite, pass) {
+                            changed = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            g += 1;
+        }
+        changed
+    }
+
+    // Merges gates of the same kind reading the same lines into the first of them.
+    fn hash(&mut self) -> bool {
+        let mut changed = false;
+        let mut seen = HashMap::new();
+        for g in 0..self.circuit.gates.len() {
+            let kind = self.circuit.gates[g].kind();
+            let mergeable = is_simple(kind) || match kind {
+                GateKind::Constant(line) => !line.is_disconnected(),
+                _ => false
+            };
+            if !mergeable || !self.rewritable(g) {
+                continue;
+            }
+            let mut inputs = (0..self.circuit.gates[g].num_inputs()).map(|i| self.input(g, i))
+                .collect::<Vec<_>>();
+            if kind != GateKind::Mux {
+                inputs.sort();
+            }
+            match seen.get(&(kind_key(kind), inputs.clone())).cloned() {
+                Some(first) => {
+                    changed |= self.rewrite(g, Rewrite::Line(first), Pass::StructuralHashing);
+                },
+                None => {
+                    seen.insert((kind_key(kind), inputs), self.circuit.gates[g].get_output(0));
+                }
+            }
+        }
+        changed
+    }
+
+    // Gates that reach a circuit output or a gate without outputs.
+    fn live(&self) -> Vec<bool> {
+        let mut live = vec![false; self.circuit.gates.len()];
+        let mut stack = self.circuit.outputs.iter().chain(&self.circuit.inputs)
+            .map(|&line| self.resolve(line)).collect::<Vec<_>>();
+        for (g, gate) in self.circuit.gates.iter().enumerate() {
+            if gate.num_outputs() == 0 {
+                live[g] = true;
+                stack.extend((0..gate.num_inputs()).map(|i| self.input(g, i)));
+            }
+        }
+        while let Some(line) = stack.pop() {
+            if let Some(g) = self.driver(line) {
+                if !live[g] {
+                    live[g] = true;
+                    stack.extend((0..self.circuit.gates[g].num_inputs()).map(|i| self.input(g, i)));
+                }
+            }
+        }
+        live
+    }
+
+    fn finish(self, options: &OptimizeOptions, report: &mut OptimizeReport) {
+        let keep = if options.dead_logic {
+            self.live()
+        } else {
+            self.bypassed.iter().map(|pass| pass.is_none()).collect()
+        };
+        for (g, &pass) in self.bypassed.iter().enumerate() {
+            let count = match pass {
+                _ if keep[g] => continue,
+                Some(Pass::ConstantPropagation) => &mut report.constant_propagation,
+                Some(Pass::StructuralHashing) => &mut report.structural_hashing,
+                Some(Pass::DoubleInverters) => &mut report.double_inverters,
+                Some(Pass::TiedInputs) => &mut report.tied_inputs,
+                None => &mut report.dead_logic
+            };
+            *count += 1;
+        }
+        for &pass in &self.added {
+            *match pass {
+                Pass::ConstantPropagation => &mut report.constant_propagation,
+                Pass::StructuralHashing => &mut report.structural_hashing,
+                Pass::DoubleInverters => &mut report.double_inverters,
+                Pass::TiedInputs => &mut report.tied_inputs
+            } -= 1;
+        }
+        for g in 0..self.circuit.gates.len() {
+            for i in 0..self.circuit.gates[g].num_inputs() {
+                let line = self.input(g, i);
+                self.circuit.gates[g].set_input(i, line);
+            }
+        }
+        for o in 0..self.circuit.outputs.len() {
+            self.circuit.outputs[o] = self.resolve(self.circuit.outputs[o]);
+        }
+        self.circuit.retain_gates(&keep);
+    }
+}
+
+/// Simplifies `circuit` in place without changing what its marked outputs compute from its
+/// marked inputs, and reports how many gates each pass removed.
+///
+/// Constant propagation, tied-input simplification and double-inverter elimination rewrite
+/// single gates and repeat, together with structural hashing, until none of them applies.
+/// Dead-logic removal then deletes everything that no longer reaches an output. Only the
+/// primitive gates that `Gate::kind` identifies are rewritten; other gates are kept unless
+/// they're dead.
+///
+/// The marked inputs and outputs keep their number and order, but lines are renumbered, and
+/// an output may end up on a different line, so look lines up again through the marked inputs
+/// and outputs afterwards. Rewrites follow two-valued logic, so a disconnected value may become
+/// low.
+pub fn optimize(circuit: &mut Circuit, options: &OptimizeOptions) -> OptimizeReport {
+    let mut report = OptimizeReport {
+        gates_before: circuit.gates.len(),
+        ..OptimizeReport::default()
+    };
+    {
+        let mut optimizer = Optimizer::new(circuit);
+        loop {
+            let simplified = optimizer.simplify(options);
+            let hashed = options.structural_hashing && optimizer.hash();
+            if !simplified && !hashed {
+                break;
+            }
+        }
+        optimizer.finish(options, &mut report);
+    }
+    report.gates_after = circuit.gates.len();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Sink;
+    use circuit::equivalence::{Engine, EquivalenceOptions, check_equivalence};
+    use circuit::gate::tests::random_logic;
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::simplegate::SimpleGate;
+    use super::*;
+
+    fn only(pass: usize) -> OptimizeOptions {
+        OptimizeOptions {
+            constant_propagation: pass == 0,
+            dead_logic: pass == 1,
+            structural_hashing: pass == 2,
+            double_inverters: pass == 3,
+            tied_inputs: pass == 4
+        }
+    }
+
+    fn assert_equivalent(a: &Circuit, b: &Circuit) {
+        let options = EquivalenceOptions { engine: Engine::Sat, ..Default::default() };
+        assert!(check_equivalence(a, b, &options).unwrap().is_equivalent());
+    }
+
+    fn gate(c: &mut Circuit, gate: SimpleGate, a: usize, b: usize) -> usize {
+        let g = c.add_gate(gate);
+        c.set_gate_input(g, 0, a);
+        c.set_gate_input(g, 1, b);
+        c.get_gate_output(g, 0)
+    }
+
+    fn constant(c: &mut Circuit, value: bool) -> usize {
+        let g = c.add_gate(if value { Source::new_high() } else { Source::new_low() });
+        c.get_gate_output(g, 0)
+    }
+
+    fn inputs(c: &mut Circuit, count: usize) -> Vec<usize> {
+        (0..count).map(|_| {
+            let line = c.add_line(Line::Low);
+            c.mark_line_as_circuit_input(line);
+            line
+        }).collect()
+    }
+
+    #[test]
+    fn every_pass_preserves_random_logic() {
+        for &width in &[0, 1, 8, 64, 65] {
+            for seed in 1..4 {
+                for rewritten in &[false, true] {
+                    let original = random_logic(seed, width, 60, 4, *rewritten);
+                    let passes = (0..5).map(only).chain(Some(OptimizeOptions::default()));
+                    for options in passes {
+                        let mut optimized = original.clone();
+                        let report = optimize(&mut optimized, &options);
+                        assert_equivalent(&original, &optimized);
+                        assert_eq!(report.gates_before, original.gates.len());
+                        assert_eq!(report.gates_after, optimized.gates.len());
+                        let passes = report.constant_propagation + report.dead_logic
+                            + report.structural_hashing + report.double_inverters
+                            + report.tied_inputs;
+                        assert_eq!(passes, report.removed());
+                        assert!(report.removed() >= 0 || !options.dead_logic);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn optimizing_twice_changes_nothing() {
+        let mut circuit = random_logic(9, 6, 80, 3, true);
+        optimize(&mut circuit, &Default::default());
+        let report = optimize(&mut circuit, &Default::default());
+        assert_eq!(report.removed(), 0);
+        assert_eq!(report, OptimizeReport {
+            gates_before: circuit.gates.len(),
+            gates_after: circuit.gates.len(),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn constants_fold_away() {
+        let mut c = Circuit::new();
+        let a = inputs(&mut c, 1)[0];
+        let (high, low) = (constant(&mut c, true), constant(&mut c, false));
+        let and = gate(&mut c, SimpleGate::and(), a, high);
+        let nor = gate(&mut c, SimpleGate::nor(), and, low);
+        let or = gate(&mut c, SimpleGate::or(), nor, high);
+        c.mark_line_as_circuit_output(nor);
+        c.mark_line_as_circuit_output(or);
+        let original = c.clone();
+        let report = optimize(&mut c, &Default::default());
+        assert_equivalent(&original, &c);
+        // An inverter of `a` and a high source are all that's left.
+        assert_eq!(c.gates.len(), 2);
+        assert_eq!(report.removed(), 3);
+        assert_eq!(report.constant_propagation, 2);
+        assert_eq!(report.dead_logic, 1);
+    }
+
+    #[test]
+    fn local_rewrites() {
+        let mut c = Circuit::new();
+        let ab = inputs(&mut c, 2);
+        let first = gate(&mut c, SimpleGate::xor(), ab[0], ab[1]);
+        let second = gate(&mut c, SimpleGate::xor(), ab[1], ab[0]);
+        let tied = gate(&mut c, SimpleGate::nand(), first, second);
+        let inverter = c.add_gate(Inverter::new());
+        c.set_gate_input(inverter, 0, tied);
+        let double = c.get_gate_output(inverter, 0);
+        c.mark_line_as_circuit_output(double);
+        let original = c.clone();
+        let report = optimize(&mut c, &Default::default());
+        assert_equivalent(&original, &c);
+        assert_eq!(c.gates.len(), 1);
+        assert_eq!(c.outputs, vec![first]);
+        assert_eq!((report.structural_hashing, report.double_inverters), (1, 1));
+        // The NAND of a line with itself became an inverter, so that pass saved nothing.
+        assert_eq!(report.tied_inputs, 0);
+        assert_eq!(report.to_string().lines().next(), Some("4 gates -> 1 gates"));
+    }
+
+    #[test]
+    fn dead_logic_keeps_sinks_and_read_state() {
+        let mut c = Circuit::new();
+        let ab = inputs(&mut c, 2);
+        let unused = gate(&mut c, SimpleGate::and(), ab[0], ab[1]);
+        let sunk = gate(&mut c, SimpleGate::or(), ab[0], ab[1]);
+        let sink = c.add_gate(Sink::new());
+        c.set_gate_input(sink, 0, sunk);
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        c.set_gate_input(ff, 0, ab[0]);
+        let q = c.get_gate_output(ff, 0);
+        c.mark_line_as_circuit_output(q);
+        let unread = c.add_gate(DFlipFlop::new(Edge::Rising));
+        c.set_gate_input(unread, 0, unused);
+        let lines = c.lines.len();
+        let report = optimize(&mut c, &Default::default());
+        assert_eq!(c.gates.len(), 3);
+        assert_eq!(report.dead_logic, 2);
+        // The AND's output and both of the unread flip-flop's.
+        assert_eq!(c.lines.len(), lines - 3);
+        assert!(c.gates.iter().any(|gate| gate.kind() == GateKind::Sink));
+        let mut kept = c.clone();
+        optimize(&mut kept, &only(0));
+        assert_eq!(kept.gates.len(), 3);
+    }
+}
@@ -0,0 +1,356 @@
+use std::sync::{Barrier, Mutex};
+use std::thread;
+
+use circuit::circuit::{Circuit, EvalMode};
+use circuit::clock::Clock;
+use circuit::gate::Gate;
+use circuit::line::Line;
+
+// Splits the gates into levels such that running the levels in order, with the gates inside each
+// in any order, gives the same result as running every gate in order. A gate goes one level above
+// the last earlier gate writing a line it reads or writes, and above every earlier gate reading a
+// line it writes. No two gates of a level then read and write, or both write, the same line.
+fn levelize(circuit: &Circuit) -> Vec<Vec<usize>> {
+    // One more than the level of the last gate to write and the highest gate to read each line.
+    let mut written = vec![0; circuit.lines.len()];
+    let mut read = vec![0; circuit.lines.len()];
+    let mut levels: Vec<Vec<usize>> = Vec::new();
+    for (g, gate) in circuit.gates.iter().enumerate() {
+        let inputs = (0..gate.num_inputs()).map(|i| gate.get_input(i)).collect::<Vec<_>>();
+        let outputs = (0..gate.num_outputs()).map(|o| gate.get_output(o)).collect::<Vec<_>>();
+        let level = inputs.iter().map(|&line| written[line])
+            .chain(outputs.iter().map(|&line| written[line].max(read[line])))
+            .max().unwrap_or(0);
+        for &line in &inputs {
+            read[line] = read[line].max(level + 1);
+        }
+        for &line in &outputs {
+            written[line] = level + 1;
+        }
+        if level == levels.len() {
+            levels.push(Vec::new());
+        }
+        levels[level].push(g);
+    }
+    levels
+}
+
+// What one call into the workers runs: `steps` times, optionally advance time and update the
+// clocks, then evaluate `passes` times.
+#[derive(Clone, Copy)]
+struct Schedule<'a> {
+    steps: u64,
+    tick: bool,
+    passes: usize,
+    time: u64,
+    clocks: &'a [(usize, Clock)]
+}
+
+// Output lines each worker wrote in the last level, double buffered so that a worker can start
+// the next level while others are still reading the last.
+type Slots = Vec<[Mutex<Vec<(usize, Line)>>; 2]>;
+
+// Runs one worker's share of every level on its own copy of the lines. After each level the
+// workers wait for each other and copy the lines written by the rest into their own.
+fn work(worker: usize, levels: &mut [Vec<&mut Box<dyn Gate>>], lines: &mut Vec<Line>,
+    schedule: Schedule, slots: &Slots, barrier: &Barrier) {
+    let mut parity = 0;
+    for step in 1..=schedule.steps {
+        if schedule.tick {
+            for &(line, clock) in schedule.clocks {
+                lines[line] = clock.level_at(schedule.time + step);
+            }
+        }
+        for _ in 0..schedule.passes {
+            for level in levels.iter_mut() {
+                {
+                    let mut written = slots[worker][parity].lock().unwrap();
+                    written.clear();
+                    for gate in level.iter_mut() {
+                        gate.eval(lines);
+                        for o in 0..gate.num_outputs() {
+                            let line = gate.get_output(o);
+                            written.push((line, lines[line]));
+                        }
+                    }
+                }
+                barrier.wait();
+                for (other, slot) in slots.iter().enumerate() {
+                    if other == worker {
+                        continue;
+                    }
+                    for &(line, state) in slot[parity].lock().unwrap().iter() {
+                        lines[line] = state;
+                    }
+                }
+                parity ^= 1;
+            }
+        }
+    }
+}
+
+/// A circuit evaluated on several threads at once.
+///
+/// The gates are split into levels of gates that share no lines they write, and each level is
+/// divided between the threads, which wait for each other before starting the next. Results are
+/// exactly those of `Circuit::eval` in `EvalMode::Immediate`, whatever the number of threads.
+/// Wide circuits with few levels, such as large memories built from gates, gain the most.
+///
+/// Each thread works on its own copy of the lines, and they exchange the lines their gates wrote
+/// at the end of every level. Threads only live for the duration of a call, so evaluating many
+/// passes or ticks at once with `eval_n_passes` or `advance` is much cheaper than calling `eval`
+/// or `tick` repeatedly.
+pub struct ParallelCircuit {
+    circuit: Circuit,
+    levels: Vec<Vec<usize>>,
+    threads: usize
+}
+
+impl ParallelCircuit {
+    pub(crate) fn new(circuit: Circuit, threads: usize) -> Self {
+        assert!(threads >= 1, "At least one thread is needed.");
+        assert!(circuit.mode == EvalMode::Immediate,
+            "Parallel evaluation needs EvalMode::Immediate.");
+        let levels = levelize(&circuit);
+        ParallelCircuit {
+            circuit,
+            levels,
+            threads
+        }
+    }
+
+    pub(crate) fn into_circuit(self) -> Circuit {
+        self.circuit
+    }
+
+    pub(crate) fn circuit(&self) -> &Circuit {
+        &self.circuit
+    }
+
+    pub(crate) fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Number of levels, each of which all threads wait for before going on to the next.
+    pub(crate) fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub(crate) fn set_line(&mut self, line: usize, state: Line) {
+        self.circuit.set_line(line, state);
+    }
+
+    pub(crate) fn get_line_state(&self, line: usize) -> Line {
+        self.circuit.get_line_state(line)
+    }
+
+    pub(crate) fn set_circuit_input(&mut self, input: usize, state: Line) {
+        self.circuit.set_circuit_input(input, state);
+    }
+
+    pub(crate) fn get_circuit_input(&self, input: usize) -> Line {
+        self.circuit.get_circuit_input(input)
+    }
+
+    pub(crate) fn get_circuit_output(&self, output: usize) -> Line {
+        self.circuit.get_circuit_output(output)
+    }
+
+    fn run(&mut self, steps: u64, tick: bool) {
+        let passes = if tick { self.circuit.passes_per_tick } else { 1 };
+        if self.threads == 1 {
+            for _ in 0..steps {
+                if tick {
+                    self.circuit.tick();
+                } else {
+                    self.circuit.eval();
+                }
+            }
+            return;
+        }
+        let threads = self.threads;
+        let circuit = &mut self.circuit;
+        let schedule = Schedule {
+            steps,
+            tick,
+            passes,
+            time: circuit.time,
+            clocks: &circuit.clocks
+        };
+        // Every thread's share of each level, in gate order.
+        let mut gates = circuit.gates.iter_mut().map(Some).collect::<Vec<_>>();
+        let mut shares = (0..threads).map(|_| Vec::new()).collect::<Vec<_>>();
+        for level in &self.levels {
+            let size = level.len().div_ceil(threads);
+            for (t, share) in shares.iter_mut().enumerate() {
+                share.push(level.iter().skip(t * size).take(size)
+                    .map(|&g| gates[g].take().unwrap()).collect::<Vec<_>>());
+            }
+        }
+        let slots = (0..threads).map(|_| [Mutex::new(Vec::new()), Mutex::new(Vec::new())])
+            .collect::<Slots>();
+        let barrier = Barrier::new(threads);
+        let lines = &mut circuit.lines;
+        thread::scope(|scope| {
+            let (slots, barrier) = (&slots, &barrier);
+            let mut shares = shares.into_iter().enumerate();
+            let (_, mut first) = shares.next().unwrap();
+            for (t, mut share) in shares {
+                let mut lines = lines.clone();
+                scope.spawn(move || work(t, &mut share, &mut lines, schedule, slots, barrier));
+            }
+            work(0, &mut first, lines, schedule, slots, barrier);
+        });
+        if tick {
+            circuit.time += steps;
+        }
+    }
+
+    pub(crate) fn eval(&mut self) {
+        self.run(1, false);
+    }
+
+    pub(crate) fn eval_n_passes(&mut self, passes: usize) {
+        self.run(passes as u64, false);
+    }
+
+    /// As `Circuit::tick`.
+    pub(crate) fn tick(&mut self) {
+        self.run(1, true);
+    }
+
+    pub(crate) fn advance(&mut self, ticks: u64) {
+        self.run(ticks, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Inverter;
+    use circuit::gate::tests::{Rng, random_logic};
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::sequential::{Counter, make_counter, make_lfsr};
+    use super::*;
+
+    const LINES: [Line; 3] = [Line::Low, Line::High, Line::Disconnected];
+
+    // Random logic with gate-level and behavioral counters and an LFSR on two clocks, and a
+    // flip-flop registering the first output.
+    fn with_state(seed: u64, inputs: usize) -> Circuit {
+        let mut c = random_logic(seed, inputs, 80, 3, seed % 2 == 1);
+        let fast = c.add_clock(Clock::new(2));
+        let slow = c.add_clock(Clock::new(6).with_high_time(1).with_phase(1));
+        let (a, b, d) = (c.outputs[0], c.outputs[1], c.outputs[2]);
+        let mut outputs = make_counter(6, a, b, d, fast, &mut c);
+        outputs.extend(make_lfsr(9, &[8, 4], b, slow, &mut c));
+        let counter = c.add_gate(Counter::new(5));
+        for (i, &line) in [a, d, b, slow].iter().enumerate() {
+            c.set_gate_input(counter, i, line);
+        }
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        c.set_gate_input(ff, 0, a);
+        c.set_gate_input(ff, 1, fast);
+        outputs.extend((0..6).map(|o| c.get_gate_output(counter, o)));
+        outputs.push(c.get_gate_output(ff, 0));
+        for line in outputs {
+            c.mark_line_as_circuit_output(line);
+        }
+        c.set_passes_per_tick(2);
+        c
+    }
+
+    #[test]
+    fn parallel_matches_serial() {
+        let mut rng = Rng(49);
+        for &inputs in &[0, 1, 64, 65] {
+            for &threads in &[1, 2, 3, 8] {
+                let mut serial = with_state(inputs as u64, inputs);
+                let mut parallel = ParallelCircuit::new(serial.clone(), threads);
+                assert_eq!(parallel.threads(), threads);
+                for step in 0..40 {
+                    for i in 0..inputs {
+                        let state = LINES[rng.below(3) as usize];
+                        serial.set_circuit_input(i, state);
+                        parallel.set_circuit_input(i, state);
+                        assert_eq!(parallel.get_circuit_input(i), state);
+                    }
+                    match step % 4 {
+                        0 => { serial.eval(); parallel.eval(); },
+                        1 => { serial.eval_n_passes(3); parallel.eval_n_passes(3); },
+                        2 => { serial.tick(); parallel.tick(); },
+                        _ => { serial.advance(5); parallel.advance(5); }
+                    }
+                    assert_eq!(parallel.circuit().lines, serial.lines);
+                    assert_eq!(parallel.circuit().time, serial.time);
+                    for o in 0..serial.outputs.len() {
+                        assert_eq!(parallel.get_circuit_output(o), serial.get_circuit_output(o));
+                    }
+                }
+                let parallel = parallel.into_circuit();
+                assert_eq!(parallel.lines, serial.lines);
+            }
+        }
+    }
+
+    // No two gates of a level write the same line, or write a line another reads, and every gate
+    // comes after the earlier gates it depends on.
+    #[test]
+    fn levels_are_independent() {
+        let circuit = with_state(3, 8);
+        let levels = levelize(&circuit);
+        let mut level_of = vec![usize::MAX; circuit.gates.len()];
+        for (l, level) in levels.iter().enumerate() {
+            assert!(level.windows(2).all(|pair| pair[0] < pair[1]));
+            for &g in level {
+                level_of[g] = l;
+            }
+        }
+        assert!(level_of.iter().all(|&l| l != usize::MAX));
+        let pins = |g: usize| {
+            let gate = &circuit.gates[g];
+            ((0..gate.num_inputs()).map(|i| gate.get_input(i)).collect::<Vec<_>>(),
+                (0..gate.num_outputs()).map(|o| gate.get_output(o)).collect::<Vec<_>>())
+        };
+        for a in 0..circuit.gates.len() {
+            for b in a + 1..circuit.gates.len() {
+                let ((a_in, a_out), (b_in, b_out)) = (pins(a), pins(b));
+                let shares = |x: &[usize], y: &[usize]| x.iter().any(|line| y.contains(line));
+                if shares(&a_out, &b_in) || shares(&a_out, &b_out) || shares(&a_in, &b_out) {
+                    assert!(level_of[a] < level_of[b], "gates {} and {} aren't ordered", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chains_and_rows() {
+        let mut chain = Circuit::new();
+        let mut line = chain.add_line(Line::Low);
+        let mut row = Circuit::new();
+        let input = row.add_line(Line::Low);
+        for _ in 0..10 {
+            let not = chain.add_gate(Inverter::new());
+            chain.set_gate_input(not, 0, line);
+            line = chain.get_gate_output(not, 0);
+            let not = row.add_gate(Inverter::new());
+            row.set_gate_input(not, 0, input);
+        }
+        assert_eq!(ParallelCircuit::new(chain, 2).num_levels(), 10);
+        assert_eq!(ParallelCircuit::new(row, 2).num_levels(), 1);
+        assert_eq!(ParallelCircuit::new(Circuit::new(), 2).num_levels(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one thread is needed.")]
+    fn no_threads() {
+        ParallelCircuit::new(Circuit::new(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Parallel evaluation needs EvalMode::Immediate.")]
+    fn synchronous_refused() {
+        let mut circuit = Circuit::new();
+        circuit.set_eval_mode(EvalMode::Synchronous);
+        ParallelCircuit::new(circuit, 2);
+    }
+}
@@ -0,0 +1,689 @@
+use std::mem;
+use std::ops::Not;
+
+use circuit::netlist::{Netlist, Node};
+
+/// A variable or its negation, for `Solver`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lit(usize);
+
+impl Lit {
+    pub fn positive(var: usize) -> Self {
+        Lit(var * 2)
+    }
+
+    pub fn negative(var: usize) -> Self {
+        Lit(var * 2 + 1)
+    }
+
+    pub fn var(self) -> usize {
+        self.0 / 2
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 & 1 == 1
+    }
+}
+
+impl Not for Lit {
+    type Output = Lit;
+
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+// Value of `lit` under `assigns`, if its variable is assigned.
+fn value(assigns: &[Option<bool>], lit: Lit) -> Option<bool> {
+    assigns[lit.var()].map(|value| value != lit.is_negative())
+}
+
+// Unassigned variables by activity, as a binary max-heap, so that branching doesn't have to
+// scan every variable. Variables are only dropped from it once they come out on top, so it can
+// hold assigned ones too.
+#[derive(Default)]
+struct Order {
+    heap: Vec<usize>,
+    // Where each variable is in `heap`.
+    positions: Vec<Option<usize>>
+}
+
+impl Order {
+    fn contains(&self, var: usize) -> bool {
+        self.positions[var].is_some()
+    }
+
+    fn insert(&mut self, var: usize, activity: &[f64]) {
+        if var >= self.positions.len() {
+            self.positions.resize(var + 1, None);
+        }
+        if !self.contains(var) {
+            self.positions[var] = Some(self.heap.len());
+            self.heap.push(var);
+            self.sift_up(self.heap.len() - 1, activity);
+        }
+    }
+
+    fn pop(&mut self, activity: &[f64]) -> Option<usize> {
+        let top = *self.heap.first()?;
+        let last = self.heap.pop().unwrap();
+        self.positions[top] = None;
+        if !self.heap.is_empty() {
+            self.heap[0] = last;
+            self.positions[last] = Some(0);
+            self.sift_down(0, activity);
+        }
+        Some(top)
+    }
+
+    // Restores the heap after the activity of `var` went up.
+    fn bumped(&mut self, var: usize, activity: &[f64]) {
+        if let Some(position) = self.positions[var] {
+            self.sift_up(position, activity);
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.positions[self.heap[a]] = Some(a);
+        self.positions[self.heap[b]] = Some(b);
+    }
+
+    fn sift_up(&mut self, mut position: usize, activity: &[f64]) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if activity[self.heap[parent]] >= activity[self.heap[position]] {
+                break;
+            }
+            self.swap(parent, position);
+            position = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut position: usize, activity: &[f64]) {
+        loop {
+            let mut largest = position;
+            for child in (2 * position + 1..).take(2).filter(|&child| child < self.heap.len()) {
+                if activity[self.heap[child]] > activity[self.heap[largest]] {
+                    largest = child;
+                }
+            }
+            if largest == position {
+                break;
+            }
+            self.swap(largest, position);
+            position = largest;
+        }
+    }
+}
+
+/// Small CDCL SAT solver: two watched literals, first-UIP clause learning, VSIDS branching with
+/// saved phases, geometric restarts, and a learnt clause database that drops its least active
+/// half whenever it outgrows its limit.
+///
+/// Clauses are added up front with `add_clause`, then `solve` looks for a satisfying assignment.
+/// More clauses can be added between calls.
+pub struct Solver {
+    clauses: Vec<Vec<Lit>>,
+    // Which clauses were learnt rather than added, and how often they took part in conflicts.
+    learnt: Vec<bool>,
+    clause_activity: Vec<f64>,
+    clause_increment: f64,
+    learnts: usize,
+    // Clauses watching each literal, so they're visited when it becomes false.
+    watches: Vec<Vec<usize>>,
+    assigns: Vec<Option<bool>>,
+    levels: Vec<usize>,
+    reasons: Vec<Option<usize>>,
+    phases: Vec<bool>,
+    activity: Vec<f64>,
+    increment: f64,
+    order: Order,
+    trail: Vec<Lit>,
+    // Trail length at the start of each decision level.
+    limits: Vec<usize>,
+    head: usize,
+    // False once the clauses are known to be unsatisfiable.
+    ok: bool
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver::new()
+    }
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Solver {
+            clauses: Vec::new(),
+            learnt: Vec::new(),
+            clause_activity: Vec::new(),
+            clause_increment: 1.0,
+            learnts: 0,
+            watches: Vec::new(),
+            assigns: Vec::new(),
+            levels: Vec::new(),
+            reasons: Vec::new(),
+            phases: Vec::new(),
+            activity: Vec::new(),
+            increment: 1.0,
+            order: Order::default(),
+            trail: Vec::new(),
+            limits: Vec::new(),
+            head: 0,
+            ok: true
+        }
+    }
+
+    pub fn new_var(&mut self) -> usize {
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        self.assigns.push(None);
+        self.levels.push(0);
+        self.reasons.push(None);
+        self.phases.push(false);
+        self.activity.push(0.0);
+        let var = self.assigns.len() - 1;
+        self.order.insert(var, &self.activity);
+        var
+    }
+
+    pub fn num_vars(&self) -> usize {
+        self.assigns.len()
+    }
+
+    fn level(&self) -> usize {
+        self.limits.len()
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<usize>) {
+        self.assigns[lit.var()] = Some(!lit.is_negative());
+        self.levels[lit.var()] = self.level();
+        self.reasons[lit.var()] = reason;
+        self.trail.push(lit);
+    }
+
+    fn attach(&mut self, clause: Vec<Lit>, learnt: bool) -> usize {
+        self.watches[clause[0].0].push(self.clauses.len());
+        self.watches[clause[1].0].push(self.clauses.len());
+        self.clauses.push(clause);
+        self.learnt.push(learnt);
+        self.clause_activity.push(0.0);
+        self.learnts += learnt as usize;
+        self.clauses.len() - 1
+    }
+
+    /// Requires at least one of `lits` to be true. Returns false if the clauses have become
+    /// unsatisfiable.
+    pub fn add_clause(&mut self, lits: &[Lit]) -> bool {
+        if !self.ok {
+            return false;
+        }
+        self.backtrack(0);
+        let mut clause = lits.to_vec();
+        clause.sort();
+        clause.dedup();
+        if clause.windows(2).any(|pair| pair[1] == !pair[0])
+            || clause.iter().any(|&lit| value(&self.assigns, lit) == Some(true)) {
+            return true;
+        }
+        clause.retain(|&lit| value(&self.assigns, lit).is_none());
+        match clause.len() {
+            0 => self.ok = false,
+            1 => {
+                self.assign(clause[0], None);
+                self.ok = self.propagate().is_none();
+            },
+            _ => {
+                self.attach(clause, false);
+            }
+        }
+        self.ok
+    }
+
+    // Propagates every assignment on the trail and returns a conflicting clause, if any.
+    fn propagate(&mut self) -> Option<usize> {
+        while self.head < self.trail.len() {
+            let false_lit = !self.trail[self.head];
+            self.head += 1;
+            let mut watching = mem::take(&mut self.watches[false_lit.0]);
+            let mut kept = 0;
+            let mut conflict = None;
+            for i in 0..watching.len() {
+                let c = watching[i];
+                if conflict.is_some() {
+                    watching[kept] = c;
+                    kept += 1;
+                    continue;
+                }
+                {
+                    let clause = &mut self.clauses[c];
+                    if clause[0] == false_lit {
+                        clause.swap(0, 1);
+                    }
+                }
+                let first = self.clauses[c][0];
+                if value(&self.assigns, first) == Some(true) {
+                    watching[kept] = c;
+                    kept += 1;
+                    continue;
+                }
+                let replacement = (2..self.clauses[c].len())
+                    .find(|&k| value(&self.assigns, self.clauses[c][k]) != Some(false));
+                if let Some(k) = replacement {
+                    self.clauses[c].swap(1, k);
+                    let watch = self.clauses[c][1];
+                    self.watches[watch.0].push(c);
+                    continue;
+                }
+                watching[kept] = c;
+                kept += 1;
+                if value(&self.assigns, first) == Some(false) {
+                    conflict = Some(c);
+                } else {
+                    self.assign(first, Some(c));
+                }
+            }
+            watching.truncate(kept);
+            self.watches[false_lit.0] = watching;
+            if conflict.is_some() {
+                return conflict;
+            }
+        }
+        None
+    }
+
+    fn bump(&mut self, var: usize) {
+        self.activity[var] += self.increment;
+        if self.activity[var] > 1e100 {
+            for activity in &mut self.activity {
+                *activity *= 1e-100;
+            }
+            self.increment *= 1e-100;
+        }
+        self.order.bumped(var, &self.activity);
+    }
+
+    fn bump_clause(&mut self, c: usize) {
+        self.clause_activity[c] += self.clause_increment;
+        if self.clause_activity[c] > 1e20 {
+            for activity in &mut self.clause_activity {
+                *activity *= 1e-20;
+            }
+            self.clause_increment *= 1e-20;
+        }
+    }
+
+    // Drops the less active half of the learnt clauses, keeping binary ones. Only done at level
+    // 0, where no learnt clause is the reason for an assignment that analysis would look at.
+    fn reduce(&mut self) {
+        debug_assert_eq!(self.level(), 0, "Clauses are only dropped at level 0.");
+        let mut candidates = (0..self.clauses.len())
+            .filter(|&c| self.learnt[c] && self.clauses[c].len() > 2)
+            .collect::<Vec<_>>();
+        candidates.sort_by(|&a, &b| {
+            self.clause_activity[a].partial_cmp(&self.clause_activity[b]).unwrap()
+        });
+        let mut keep = vec![true; self.clauses.len()];
+        for &c in &candidates[..candidates.len() / 2] {
+            keep[c] = false;
+        }
+        let kept = (0..self.clauses.len()).filter(|&c| keep[c]).collect::<Vec<_>>();
+        self.clauses = kept.iter().map(|&c| mem::take(&mut self.clauses[c])).collect();
+        self.learnt = kept.iter().map(|&c| self.learnt[c]).collect();
+        self.clause_activity = kept.iter().map(|&c| self.clause_activity[c]).collect();
+        self.learnts = self.learnt.iter().filter(|&&learnt| learnt).count();
+        for watching in &mut self.watches {
+            watching.clear();
+        }
+        for (c, clause) in self.clauses.iter().enumerate() {
+            self.watches[clause[0].0].push(c);
+            self.watches[clause[1].0].push(c);
+        }
+        for reason in &mut self.reasons {
+            *reason = None;
+        }
+    }
+
+    // Learns a first-UIP clause from `conflict`, asserting its first literal, and returns it
+    // with the level to go back to.
+    fn analyze(&mut self, mut conflict: usize) -> (Vec<Lit>, usize) {
+        let mut learnt = vec![Lit(0)];
+        let mut seen = vec![false; self.num_vars()];
+        let mut pending = 0;
+        let mut index = self.trail.len();
+        let mut implied = None;
+        loop {
+            if self.learnt[conflict] {
+                self.bump_clause(conflict);
+            }
+            let start = if implied.is_some() { 1 } else { 0 };
+            for k in start..self.clauses[conflict].len() {
+                let lit = self.clauses[conflict][k];
+                let var = lit.var();
+                if !seen[var] && self.levels[var] > 0 {
+                    seen[var] = true;
+                    self.bump(var);
+                    if self.levels[var] == self.level() {
+                        pending += 1;
+                    } else {
+                        learnt.push(lit);
+                    }
+                }
+            }
+            loop {
+                index -= 1;
+                if seen[self.trail[index].var()] {
+                    break;
+                }
+            }
+            let lit = self.trail[index];
+            seen[lit.var()] = false;
+            implied = Some(lit);
+            pending -= 1;
+            if pending == 0 {
+                learnt[0] = !lit;
+                break;
+            }
+            conflict = self.reasons[lit.var()].expect("Implied literals have reasons.");
+        }
+        let mut back = 0;
+        if learnt.len() > 1 {
+            let highest = (1..learnt.len()).max_by_key(|&k| self.levels[learnt[k].var()])
+                .unwrap();
+            learnt.swap(1, highest);
+            back = self.levels[learnt[1].var()];
+        }
+        (learnt, back)
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        if self.level() <= level {
+            return;
+        }
+        let limit = self.limits[level];
+        for lit in self.trail.drain(limit..) {
+            self.assigns[lit.var()] = None;
+            self.reasons[lit.var()] = None;
+            self.phases[lit.var()] = !lit.is_negative();
+            self.order.insert(lit.var(), &self.activity);
+        }
+        self.limits.truncate(level);
+        self.head = self.trail.len();
+    }
+
+    fn decide(&mut self) -> Option<Lit> {
+        while let Some(var) = self.order.pop(&self.activity) {
+            if self.assigns[var].is_none() {
+                return Some(if self.phases[var] { Lit::positive(var) } else { Lit::negative(var) });
+            }
+        }
+        None
+    }
+
+    /// Finds a value for every variable that satisfies all clauses, or `None` if there isn't
+    /// one.
+    pub fn solve(&mut self) -> Option<Vec<bool>> {
+        if !self.ok {
+            return None;
+        }
+        self.backtrack(0);
+        let mut conflicts = 0;
+        let mut restart = 100.0;
+        let mut max_learnts = (self.clauses.len() - self.learnts) as f64 / 3.0 + 100.0;
+        loop {
+            if let Some(conflict) = self.propagate() {
+                if self.level() == 0 {
+                    self.ok = false;
+                    return None;
+                }
+                let (learnt, back) = self.analyze(conflict);
+                self.backtrack(back);
+                if learnt.len() == 1 {
+                    self.assign(learnt[0], None);
+                } else {
+                    let asserting = learnt[0];
+                    let c = self.attach(learnt, true);
+                    self.assign(asserting, Some(c));
+                }
+                self.increment /= 0.95;
+                self.clause_increment /= 0.999;
+                conflicts += 1;
+            } else if conflicts as f64 >= restart {
+                self.backtrack(0);
+                conflicts = 0;
+                restart *= 1.5;
+                if self.learnts as f64 > max_learnts {
+                    self.reduce();
+                    max_learnts *= 1.1;
+                }
+            } else {
+                match self.decide() {
+                    Some(lit) => {
+                        self.limits.push(self.trail.len());
+                        self.assign(lit, None);
+                    },
+                    None => {
+                        let model = self.assigns.iter().map(|value| value.unwrap()).collect();
+                        self.backtrack(0);
+                        return Some(model);
+                    }
+                }
+            }
+        }
+    }
+
+    fn gate(&mut self, clauses: &[&[Lit]]) {
+        for clause in clauses {
+            self.add_clause(clause);
+        }
+    }
+
+    /// Adds `netlist` with its inputs bound to `inputs`, and returns a literal for each node.
+    pub fn add_netlist(&mut self, netlist: &Netlist, inputs: &[Lit]) -> Vec<Lit> {
+        assert_eq!(inputs.len(), netlist.num_inputs(), "Need one literal per input.");
+        let truth = Lit::positive(self.new_var());
+        self.add_clause(&[truth]);
+        let mut lits: Vec<Lit> = Vec::with_capacity(netlist.nodes.len());
+        for node in &netlist.nodes {
+            let lit = match *node {
+                Node::Input(i) => inputs[i],
+                Node::Constant(state) => if state { truth } else { !truth },
+                Node::Not(a) => !lits[a],
+                _ => {
+                    let out = Lit::positive(self.new_var());
+                    match *node {
+                        Node::And(a, b) => {
+                            let (a, b) = (lits[a], lits[b]);
+                            self.gate(&[&[!out, a], &[!out, b], &[out, !a, !b]]);
+                        },
+                        Node::Or(a, b) => {
+                            let (a, b) = (lits[a], lits[b]);
+                            self.gate(&[&[out, !a], &[out, !b], &[!out, a, b]]);
+                        },
+                        Node::Xor(a, b) => {
+                            let (a, b) = (lits[a], lits[b]);
+                            self.gate(&[&[!out, a, b], &[!out, !a, !b], &[out, !a, b],
+                                &[out, a, !b]]);
+                        },
+                        Node::Mux { select, low, high } => {
+                            let (s, l, h) = (lits[select], lits[low], lits[high]);
+                            self.gate(&[&[!s, !h, out], &[!s, h, !out], &[s, !l, out],
+                                &[s, l, !out]]);
+                        },
+                        _ => unreachable!()
+                    }
+                    out
+                }
+            };
+            lits.push(lit);
+        }
+        lits
+    }
+
+    /// A literal that's true exactly when `a` and `b` differ.
+    pub fn add_xor(&mut self, a: Lit, b: Lit) -> Lit {
+        let out = Lit::positive(self.new_var());
+        self.gate(&[&[!out, a, b], &[!out, !a, !b], &[out, !a, b], &[out, a, !b]]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use circuit::gate::tests::{Rng, random_logic};
+    use circuit::netlist::Netlist;
+    use super::*;
+
+    fn satisfies(model: &[bool], clauses: &[Vec<Lit>]) -> bool {
+        clauses.iter().all(|clause| clause.iter().any(|&lit| model[lit.var()] != lit.is_negative()))
+    }
+
+    fn random_clauses(rng: &mut Rng, vars: usize, clauses: usize, width: u64) -> Vec<Vec<Lit>> {
+        (0..clauses).map(|_| (0..1 + rng.below(width)).map(|_| {
+            let var = rng.below(vars as u64) as usize;
+            if rng.below(2) == 0 { Lit::positive(var) } else { Lit::negative(var) }
+        }).collect()).collect()
+    }
+
+    fn solver_for(vars: usize, clauses: &[Vec<Lit>]) -> Solver {
+        let mut solver = Solver::new();
+        for _ in 0..vars {
+            solver.new_var();
+        }
+        for clause in clauses {
+            solver.add_clause(clause);
+        }
+        solver
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        let mut rng = Rng(21);
+        for round in 0..300 {
+            let vars = 1 + round % 10;
+            let clauses = random_clauses(&mut rng, vars, 1 + round % 50, 3);
+            let satisfiable = (0..1u32 << vars).any(|assignment| {
+                let model = (0..vars).map(|v| assignment >> v & 1 == 1).collect::<Vec<_>>();
+                satisfies(&model, &clauses)
+            });
+            match solver_for(vars, &clauses).solve() {
+                Some(model) => assert!(satisfies(&model, &clauses)),
+                None => assert!(!satisfiable, "Missed a model of {:?}.", clauses)
+            }
+        }
+    }
+
+    // Clauses saying that `pigeons` pigeons each sit in one of `holes` holes, no two together.
+    fn pigeonhole(pigeons: usize, holes: usize) -> Vec<Vec<Lit>> {
+        let var = |pigeon: usize, hole: usize| pigeon * holes + hole;
+        let mut clauses = (0..pigeons)
+            .map(|p| (0..holes).map(|h| Lit::positive(var(p, h))).collect())
+            .collect::<Vec<_>>();
+        for h in 0..holes {
+            for p in 0..pigeons {
+                for q in p + 1..pigeons {
+                    clauses.push(vec![Lit::negative(var(p, h)), Lit::negative(var(q, h))]);
+                }
+            }
+        }
+        clauses
+    }
+
+    #[test]
+    fn pigeonholes() {
+        assert!(solver_for(30, &pigeonhole(6, 5)).solve().is_none());
+        let clauses = pigeonhole(6, 6);
+        assert!(satisfies(&solver_for(36, &clauses).solve().unwrap(), &clauses));
+    }
+
+    #[test]
+    fn learnt_clauses_are_reduced() {
+        // Random 3-SAT near the threshold, with a planted model so it's always satisfiable.
+        let mut rng = Rng(8);
+        let vars = 150;
+        let planted = (0..vars).map(|_| rng.below(2) == 1).collect::<Vec<_>>();
+        let clauses = (0..640).map(|_| loop {
+            let clause = (0..3).map(|_| random_clauses(&mut rng, vars, 1, 1)[0][0])
+                .collect::<Vec<_>>();
+            if satisfies(&planted, slice::from_ref(&clause)) {
+                break clause;
+            }
+        }).collect::<Vec<_>>();
+        let mut solver = solver_for(vars, &clauses);
+        let model = solver.solve().unwrap();
+        assert!(satisfies(&model, &clauses));
+        // Dropping learnt clauses by hand keeps every clause watched and the answers sound.
+        let long = |solver: &Solver| (0..solver.clauses.len())
+            .filter(|&c| solver.learnt[c] && solver.clauses[c].len() > 2).count();
+        let before = long(&solver);
+        assert!(before > 0, "Instance too easy to learn anything.");
+        solver.reduce();
+        assert_eq!(long(&solver), before - before / 2);
+        assert_eq!(solver.learnts, solver.learnt.iter().filter(|&&learnt| learnt).count());
+        for (c, clause) in solver.clauses.iter().enumerate() {
+            assert!(solver.watches[clause[0].0].contains(&c));
+            assert!(solver.watches[clause[1].0].contains(&c));
+        }
+        assert!(satisfies(&solver.solve().unwrap(), &clauses));
+    }
+
+    #[test]
+    fn clauses_can_be_added_between_solves() {
+        // Blocking every model found in turn counts them.
+        let mut rng = Rng(4);
+        for _ in 0..20 {
+            let clauses = random_clauses(&mut rng, 6, 8, 3);
+            let expected = (0..1u32 << 6).filter(|&assignment| {
+                let model = (0..6).map(|v| assignment >> v & 1 == 1).collect::<Vec<_>>();
+                satisfies(&model, &clauses)
+            }).count();
+            let mut solver = solver_for(6, &clauses);
+            let mut found = 0;
+            while let Some(model) = solver.solve() {
+                found += 1;
+                let block = model.iter().enumerate()
+                    .map(|(v, &value)| if value { Lit::negative(v) } else { Lit::positive(v) })
+                    .collect::<Vec<_>>();
+                solver.add_clause(&block);
+            }
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn empty_and_contradictory_clauses() {
+        let mut solver = Solver::new();
+        assert_eq!(solver.solve(), Some(vec![]));
+        let var = solver.new_var();
+        assert!(solver.add_clause(&[Lit::positive(var), Lit::negative(var)]));
+        assert!(solver.add_clause(&[Lit::positive(var)]));
+        assert!(!solver.add_clause(&[Lit::negative(var)]));
+        assert_eq!(solver.solve(), None);
+        assert!(!Solver::new().add_clause(&[]));
+    }
+
+    #[test]
+    fn netlists_are_encoded_faithfully() {
+        let mut rng = Rng(13);
+        for seed in 0..20 {
+            let netlist = Netlist::from_circuit(&random_logic(seed, 10, 60, 4, seed % 2 == 0))
+                .unwrap();
+            let mut solver = Solver::new();
+            let inputs = (0..10).map(|_| Lit::positive(solver.new_var())).collect::<Vec<_>>();
+            let nodes = solver.add_netlist(&netlist, &inputs);
+            let values = (0..10).map(|_| rng.below(2) == 1).collect::<Vec<_>>();
+            for (&lit, &value) in inputs.iter().zip(&values) {
+                solver.add_clause(&[if value { lit } else { !lit }]);
+            }
+            let model = solver.solve().unwrap();
+            let words = values.iter().map(|&value| value as u64).collect::<Vec<_>>();
+            for (&lit, &word) in nodes.iter().zip(&netlist.simulate_nodes(&words)) {
+                assert_eq!(model[lit.var()] != lit.is_negative(), word & 1 == 1);
+            }
+        }
+    }
+}
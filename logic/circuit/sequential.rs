@@ -0,0 +1,901 @@
+use circuit::gate::{Gate, Port, PortRole, StateRegister, lower_with};
+use circuit::line::{Line, lines_to_word, word_to_lines};
+use circuit::circuit::Circuit;
+use circuit::codegen::{Expr, GateCode, Statement, equals, step};
+use circuit::basics::{Inverter, Source};
+use circuit::simplegate::SimpleGate;
+use circuit::mux::Mux1_2;
+use circuit::memory::{DFlipFlop, Edge, make_select};
+
+// Adds one rising-edge `DFlipFlop` per bit, clocked by `clock`, and returns the gates along
+// with their q lines. The generators below add flip-flops before any next-state logic so that
+// every flip-flop samples values settled in the previous pass rather than a neighbour that has
+// already updated in the current one.
+fn make_flip_flops(width: usize, clock: usize, circuit: &mut Circuit) -> (Vec<usize>, Vec<usize>) {
+    let gates = (0..width).map(|_| {
+        let ff = circuit.add_gate(DFlipFlop::new(Edge::Rising));
+        circuit.set_gate_input(ff, 1, clock);
+        ff
+    }).collect::<Vec<_>>();
+    let q = gates.iter().map(|&ff| circuit.get_gate_output(ff, 0)).collect();
+    (gates, q)
+}
+
+fn to_lines(value: &[bool]) -> Vec<Line> {
+    value.iter().map(|&b| Line::from(b)).collect()
+}
+
+// Overwrites the stored bits of a register, which keeps its width.
+fn poke_bits(state: &mut [bool], value: &[Line]) {
+    assert_eq!(value.len(), state.len(), "Value width doesn't match the register.");
+    for (bit, line) in state.iter_mut().zip(value) {
+        *bit = line.is_high();
+    }
+}
+
+// Generated code shared by the gates below, whose state is the last clock followed by the stored
+// bits and any scratch bits. `on_edge` runs on a rising edge of `clock`.
+fn clocked_code(last_clock: Line, bits: &[bool], scratch: usize, clock: usize,
+    on_edge: Vec<Statement>, outputs: &[usize]) -> GateCode {
+    let mut state = vec![last_clock.is_high()];
+    state.extend_from_slice(bits);
+    state.extend((0..scratch).map(|_| false));
+    let mut body = vec![
+        Statement::If(!Expr::State(0) & Expr::Line(clock), on_edge, Vec::new()),
+        Statement::SetState(0, Expr::Line(clock))
+    ];
+    body.extend(outputs.iter().enumerate()
+        .map(|(b, &o)| Statement::SetLine(o, Expr::State(1 + b))));
+    GateCode {
+        state,
+        memories: Vec::new(),
+        body
+    }
+}
+
+/// `width`-bit register that loads its data inputs on a rising clock edge while load is high.
+///
+/// Spec:
+/// 0..width: data
+/// width: load
+/// width + 1: clock
+///
+/// Outputs are the stored bits. See `make_register` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct Register {
+    inputs: Vec<usize>,
+    last_clock: Line,
+    state: Vec<bool>,
+    outputs: Vec<usize>
+}
+
+impl Register {
+    pub(crate) fn new(width: usize) -> Self {
+        Register {
+            inputs: vec![0; width + 2],
+            last_clock: Line::Low,
+            state: vec![false; width],
+            outputs: vec![0; width]
+        }
+    }
+}
+
+impl Gate for Register {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let width = self.outputs.len();
+        vec![
+            Port::input("data", 0, width),
+            Port::input("load", width, 1).with_role(PortRole::Control),
+            Port::input("clock", width + 1, 1).with_role(PortRole::Clock),
+            Port::output("q", 0, width)
+        ]
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let width = self.state.len();
+        let clock = lines[self.inputs[width + 1]];
+        if Edge::Rising.triggered(self.last_clock, clock) && lines[self.inputs[width]].is_high() {
+            for b in 0..width {
+                self.state[b] = lines[self.inputs[b]].is_high();
+            }
+        }
+        self.last_clock = clock;
+        for (&o, new) in self.outputs.iter().zip(to_lines(&self.state)) {
+            lines[o] = new;
+        }
+    }
+
+    fn code(&self) -> Option<GateCode> {
+        let width = self.state.len();
+        let load = (0..width).map(|b| Statement::SetState(1 + b, Expr::Line(self.inputs[b])))
+            .collect();
+        let on_edge = vec![Statement::If(Expr::Line(self.inputs[width]), load, Vec::new())];
+        Some(clocked_code(self.last_clock, &self.state, 0, self.inputs[width + 1], on_edge,
+            &self.outputs))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", self.state.len(), 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        to_lines(&self.state)
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        poke_bits(&mut self.state, value);
+    }
+}
+
+/// Builds the gate-level equivalent of `Register` and returns its outputs.
+pub fn make_register(data: &[usize], load: usize, clock: usize, circuit: &mut Circuit)
+    -> Vec<usize> {
+    let (flip_flops, q) = make_flip_flops(data.len(), clock, circuit);
+    for b in 0..data.len() {
+        let next = make_select(load, data[b], q[b], circuit);
+        circuit.set_gate_input(flip_flops[b], 0, next);
+    }
+    q
+}
+
+/// `width`-bit up/down counter. On a rising clock edge it clears if clear is high, otherwise
+/// counts (wrapping) while enable is high, up if up is high and down if not.
+///
+/// Spec:
+/// 0: enable
+/// 1: up
+/// 2: clear
+/// 3: clock
+///
+/// Outputs `0..width` are the count and output `width` is carry-out, which is high while enabled
+/// and the next count wraps. See `make_counter` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct Counter {
+    inputs: [usize; 4],
+    last_clock: Line,
+    count: u64,
+    outputs: Vec<usize>
+}
+
+impl Counter {
+    pub(crate) fn new(width: usize) -> Self {
+        assert!((1..=64).contains(&width), "Counter width must be between 1 and 64 bits.");
+        Counter {
+            inputs: [0; 4],
+            last_clock: Line::Low,
+            count: 0,
+            outputs: vec![0; width + 1]
+        }
+    }
+
+    fn mask(&self) -> u64 {
+        !0 >> (64 - (self.outputs.len() - 1))
+    }
+}
+
+impl Gate for Counter {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        4
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let width = self.outputs.len() - 1;
+        vec![
+            Port::input("enable", 0, 1).with_role(PortRole::Enable),
+            Port::input("up", 1, 1).with_role(PortRole::Control),
+            Port::input("clear", 2, 1).with_role(PortRole::Control),
+            Port::input("clock", 3, 1).with_role(PortRole::Clock),
+            Port::output("count", 0, width),
+            Port::output("carry", width, 1)
+        ]
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let width = self.outputs.len() - 1;
+        let enable = lines[self.inputs[0]].is_high();
+        let up = lines[self.inputs[1]].is_high();
+        let clock = lines[self.inputs[3]];
+        if Edge::Rising.triggered(self.last_clock, clock) {
+            if lines[self.inputs[2]].is_high() {
+                self.count = 0;
+            } else if enable {
+                self.count = if up {
+                    self.count.wrapping_add(1)
+                } else {
+                    self.count.wrapping_sub(1)
+                } & self.mask();
+            }
+        }
+        self.last_clock = clock;
+        for b in 0..width {
+            lines[self.outputs[b]] = Line::from(self.count >> b & 1 == 1);
+        }
+        let wraps = if up { self.count == self.mask() } else { self.count == 0 };
+        lines[self.outputs[width]] = Line::from(enable && wraps);
+    }
+
+    // The count follows the last clock in the state, with a scratch bit for the carry after it.
+    fn code(&self) -> Option<GateCode> {
+        let width = self.outputs.len() - 1;
+        let line = |i: usize| Expr::Line(self.inputs[i]);
+        let count = (1..=width).collect::<Vec<_>>();
+        let clear = count.iter().map(|&b| Statement::SetState(b, Expr::Constant(false)))
+            .collect();
+        let on_edge = vec![Statement::If(line(2), clear, vec![
+            Statement::If(line(0), step(&count, line(1), width + 1), Vec::new())
+        ])];
+        let bits = (0..width).map(|b| self.count >> b & 1 == 1).collect::<Vec<_>>();
+        let mut code = clocked_code(self.last_clock, &bits, 1, self.inputs[3], on_edge,
+            &self.outputs[..width]);
+        let count = count.into_iter().map(Expr::State).collect::<Vec<_>>();
+        let wraps = line(1) & equals(&count, self.mask()) | !line(1) & equals(&count, 0);
+        code.body.push(Statement::SetLine(self.outputs[width], line(0) & wraps));
+        Some(code)
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("count", self.outputs.len() - 1, 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        word_to_lines(self.count, self.outputs.len() - 1)
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        assert_eq!(value.len(), self.outputs.len() - 1, "Value width doesn't match the counter.");
+        self.count = lines_to_word(value);
+    }
+}
+
+/// Builds the gate-level equivalent of `Counter` as a ripple incrementer/decrementer and returns
+/// the count lines followed by carry-out.
+pub fn make_counter(width: usize, enable: usize, up: usize, clear: usize, clock: usize,
+    circuit: &mut Circuit) -> Vec<usize> {
+    let (flip_flops, q) = make_flip_flops(width, clock, circuit);
+    let not_clear = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(not_clear, 0, clear);
+    let not_clear = circuit.get_gate_output(not_clear, 0);
+    // carry[b] is high when every lower bit is all ones (counting up) or all zeros (down).
+    let mut carry = enable;
+    for b in 0..width {
+        let toggle = circuit.add_gate(SimpleGate::xor());
+        circuit.set_gate_input(toggle, 0, q[b]);
+        circuit.set_gate_input(toggle, 1, carry);
+        let next = circuit.add_gate(SimpleGate::and());
+        circuit.connect_i_single(next, 0, toggle, 0);
+        circuit.set_gate_input(next, 1, not_clear);
+        circuit.connect_i_single(flip_flops[b], 0, next, 0);
+        let direction = circuit.add_gate(SimpleGate::xnor());
+        circuit.set_gate_input(direction, 0, q[b]);
+        circuit.set_gate_input(direction, 1, up);
+        let ripple = circuit.add_gate(SimpleGate::and());
+        circuit.set_gate_input(ripple, 0, carry);
+        circuit.connect_i_single(ripple, 1, direction, 0);
+        carry = circuit.get_gate_output(ripple, 0);
+    }
+    let mut outputs = q;
+    outputs.push(carry);
+    outputs
+}
+
+/// `width`-bit shift register with parallel load. On a rising clock edge it loads the data
+/// inputs if load is high, otherwise shifts towards the highest bit while shift is high, taking
+/// bit 0 from the serial input.
+///
+/// Spec:
+/// 0..width: data
+/// width: serial in
+/// width + 1: load
+/// width + 2: shift
+/// width + 3: clock
+///
+/// Outputs are the stored bits; output `width - 1` doubles as the serial output. See
+/// `make_shift_register` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct ShiftRegister {
+    inputs: Vec<usize>,
+    last_clock: Line,
+    state: Vec<bool>,
+    outputs: Vec<usize>
+}
+
+impl ShiftRegister {
+    pub(crate) fn new(width: usize) -> Self {
+        assert!(width >= 1, "A shift register needs at least one bit.");
+        ShiftRegister {
+            inputs: vec![0; width + 4],
+            last_clock: Line::Low,
+            state: vec![false; width],
+            outputs: vec![0; width]
+        }
+    }
+}
+
+impl Gate for ShiftRegister {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let width = self.outputs.len();
+        vec![
+            Port::input("data", 0, width),
+            Port::input("serial_in", width, 1),
+            Port::input("load", width + 1, 1).with_role(PortRole::Control),
+            Port::input("shift", width + 2, 1).with_role(PortRole::Control),
+            Port::input("clock", width + 3, 1).with_role(PortRole::Clock),
+            Port::output("q", 0, width)
+        ]
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let width = self.state.len();
+        let clock = lines[self.inputs[width + 3]];
+        if Edge::Rising.triggered(self.last_clock, clock) {
+            if lines[self.inputs[width + 1]].is_high() {
+                for b in 0..width {
+                    self.state[b] = lines[self.inputs[b]].is_high();
+                }
+            } else if lines[self.inputs[width + 2]].is_high() {
+                self.state.pop();
+                self.state.insert(0, lines[self.inputs[width]].is_high());
+            }
+        }
+        self.last_clock = clock;
+        for (&o, new) in self.outputs.iter().zip(to_lines(&self.state)) {
+            lines[o] = new;
+        }
+    }
+
+    fn code(&self) -> Option<GateCode> {
+        let width = self.state.len();
+        let load = (0..width).map(|b| Statement::SetState(1 + b, Expr::Line(self.inputs[b])))
+            .collect();
+        let mut shift = (1..width).rev().map(|b| Statement::SetState(1 + b, Expr::State(b)))
+            .collect::<Vec<_>>();
+        shift.push(Statement::SetState(1, Expr::Line(self.inputs[width])));
+        let on_edge = vec![Statement::If(Expr::Line(self.inputs[width + 1]), load, vec![
+            Statement::If(Expr::Line(self.inputs[width + 2]), shift, Vec::new())
+        ])];
+        Some(clocked_code(self.last_clock, &self.state, 0, self.inputs[width + 3], on_edge,
+            &self.outputs))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", self.state.len(), 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        to_lines(&self.state)
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        poke_bits(&mut self.state, value);
+    }
+}
+
+/// Builds the gate-level equivalent of `ShiftRegister` and returns its outputs.
+pub fn make_shift_register(data: &[usize], serial_in: usize, load: usize, shift: usize,
+    clock: usize, circuit: &mut Circuit) -> Vec<usize> {
+    let (flip_flops, q) = make_flip_flops(data.len(), clock, circuit);
+    for b in 0..data.len() {
+        let previous = if b == 0 { serial_in } else { q[b - 1] };
+        let shifted = make_select(shift, previous, q[b], circuit);
+        let next = make_select(load, data[b], shifted, circuit);
+        circuit.set_gate_input(flip_flops[b], 0, next);
+    }
+    q
+}
+
+fn shift_source(i: usize, distance: usize, width: usize, right: bool, rotate: bool)
+    -> Option<usize> {
+    match (right, rotate) {
+        (false, false) => i.checked_sub(distance),
+        (true, false) => if i + distance < width { Some(i + distance) } else { None },
+        (false, true) => Some((i + width - distance) % width),
+        (true, true) => Some((i + distance) % width)
+    }
+}
+
+/// Combinational barrel shifter over `width` bits, shifting or rotating by up to `width - 1`
+/// places in `log2(width)` stages.
+///
+/// Spec:
+/// 0..width: data
+/// width..width + log2(width): distance, least significant bit first
+/// width + log2(width): direction, high shifts towards bit 0
+///
+/// Bits shifted in are low. A disconnected distance bit disconnects every bit leaving its stage,
+/// and a disconnected direction disconnects every output, the same way the `Mux1_2`s in
+/// `make_barrel_shifter` do.
+#[derive(Clone)]
+pub struct BarrelShifter {
+    width: usize,
+    rotate: bool,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>
+}
+
+impl BarrelShifter {
+    pub(crate) fn new(width: usize, rotate: bool) -> Self {
+        assert!(width >= 2 && width.is_power_of_two(), "Width must be a power of two >= 2.");
+        let stages = width.trailing_zeros() as usize;
+        BarrelShifter {
+            width,
+            rotate,
+            inputs: vec![0; width + stages + 1],
+            outputs: vec![0; width]
+        }
+    }
+
+    fn shift(&self, lines: &[Line], right: bool) -> Vec<Line> {
+        let stages = self.inputs.len() - self.width - 1;
+        let mut current = (0..self.width).map(|b| lines[self.inputs[b]]).collect::<Vec<_>>();
+        for s in 0..stages {
+            let sel = lines[self.inputs[self.width + s]];
+            current = (0..self.width).map(|i| match sel {
+                Line::High => shift_source(i, 1 << s, self.width, right, self.rotate)
+                    .map_or(Line::Low, |src| current[src]),
+                Line::Low => current[i],
+                Line::Disconnected => Line::Disconnected
+            }).collect();
+        }
+        current
+    }
+}
+
+impl Gate for BarrelShifter {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        let stages = self.inputs.len() - self.width - 1;
+        vec![
+            Port::input("data", 0, self.width),
+            Port::input("distance", self.width, stages).with_role(PortRole::Select),
+            Port::input("direction", self.width + stages, 1).with_role(PortRole::Control),
+            Port::output("out", 0, self.width)
+        ]
+    }
+
+    fn lower(&self) -> Option<Circuit> {
+        let stages = self.inputs.len() - self.width - 1;
+        Some(lower_with(self.inputs.len(), |lines, circuit| {
+            make_barrel_shifter(&lines[..self.width], &lines[self.width..self.width + stages],
+                lines[self.width + stages], self.rotate, circuit)
+        }))
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let new = match lines[self.inputs[self.inputs.len() - 1]] {
+            Line::High => self.shift(lines, true),
+            Line::Low => self.shift(lines, false),
+            Line::Disconnected => vec![Line::Disconnected; self.width]
+        };
+        for (&o, new) in self.outputs.iter().zip(new) {
+            lines[o] = new;
+        }
+    }
+}
+
+/// Builds the gate-level equivalent of `BarrelShifter` from two `Mux1_2` networks, one per
+/// direction, and returns its outputs.
+pub fn make_barrel_shifter(data: &[usize], distance: &[usize], right: usize, rotate: bool,
+    circuit: &mut Circuit) -> Vec<usize> {
+    let width = data.len();
+    assert!(width >= 2 && width.is_power_of_two(), "Width must be a power of two >= 2.");
+    assert_eq!(1 << distance.len(), width, "Distance bits don't match the width.");
+    let low = circuit.add_gate(Source::new_low());
+    let low = circuit.get_gate_output(low, 0);
+    let mut networks = Vec::with_capacity(2);
+    for &direction in &[false, true] {
+        let mut current = data.to_vec();
+        for (s, &sel) in distance.iter().enumerate() {
+            current = (0..width).map(|i| {
+                let shifted = shift_source(i, 1 << s, width, direction, rotate)
+                    .map_or(low, |src| current[src]);
+                let mux = circuit.add_gate(Mux1_2::new());
+                circuit.set_gate_input(mux, 0, current[i]);
+                circuit.set_gate_input(mux, 1, shifted);
+                circuit.set_gate_input(mux, 2, sel);
+                circuit.get_gate_output(mux, 0)
+            }).collect();
+        }
+        networks.push(current);
+    }
+    (0..width).map(|i| {
+        let mux = circuit.add_gate(Mux1_2::new());
+        circuit.set_gate_input(mux, 0, networks[0][i]);
+        circuit.set_gate_input(mux, 1, networks[1][i]);
+        circuit.set_gate_input(mux, 2, right);
+        circuit.get_gate_output(mux, 0)
+    }).collect()
+}
+
+/// Fibonacci linear-feedback shift register. On a rising clock edge while enable is high, every
+/// bit moves one place towards the highest bit and bit 0 takes the XNOR of the tapped bits.
+/// Using XNOR makes the all-zero reset state part of the sequence, and all-ones the lock-up
+/// state instead.
+///
+/// Spec:
+/// 0: enable
+/// 1: clock
+///
+/// Outputs are the stored bits. See `make_lfsr` for the gate-level equivalent.
+#[derive(Clone)]
+pub struct Lfsr {
+    inputs: [usize; 2],
+    taps: Vec<usize>,
+    last_clock: Line,
+    state: Vec<bool>,
+    outputs: Vec<usize>
+}
+
+impl Lfsr {
+    pub(crate) fn new(width: usize, taps: &[usize]) -> Self {
+        check_taps(width, taps);
+        Lfsr {
+            inputs: [0; 2],
+            taps: taps.to_vec(),
+            last_clock: Line::Low,
+            state: vec![false; width],
+            outputs: vec![0; width]
+        }
+    }
+}
+
+impl Gate for Lfsr {
+    fn get_input(&self, i: usize) -> usize {
+        self.inputs[i]
+    }
+
+    fn set_input(&mut self, i: usize, new_i: usize) {
+        self.inputs[i] = new_i;
+    }
+
+    fn num_inputs(&self) -> usize {
+        2
+    }
+
+    fn get_output(&self, o: usize) -> usize {
+        self.outputs[o]
+    }
+
+    fn set_output(&mut self, o: usize, new_o: usize) {
+        self.outputs[o] = new_o;
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input("enable", 0, 1).with_role(PortRole::Enable),
+            Port::input("clock", 1, 1).with_role(PortRole::Clock),
+            Port::output("q", 0, self.outputs.len())
+        ]
+    }
+
+    fn eval(&mut self, lines: &mut Vec<Line>) {
+        let clock = lines[self.inputs[1]];
+        if Edge::Rising.triggered(self.last_clock, clock) && lines[self.inputs[0]].is_high() {
+            let parity = self.taps.iter().filter(|&&t| self.state[t]).count() % 2 == 1;
+            self.state.pop();
+            self.state.insert(0, !parity);
+        }
+        self.last_clock = clock;
+        for (&o, new) in self.outputs.iter().zip(to_lines(&self.state)) {
+            lines[o] = new;
+        }
+    }
+
+    // The feedback bit is worked out into a scratch bit after the stored ones before shifting.
+    fn code(&self) -> Option<GateCode> {
+        let width = self.state.len();
+        let parity = self.taps.iter().fold(Expr::Constant(false), |parity, &t| {
+            parity ^ Expr::State(1 + t)
+        });
+        let mut shift = vec![Statement::SetState(1 + width, !parity)];
+        shift.extend((1..width).rev().map(|b| Statement::SetState(1 + b, Expr::State(b))));
+        shift.push(Statement::SetState(1, Expr::State(1 + width)));
+        let on_edge = vec![Statement::If(Expr::Line(self.inputs[0]), shift, Vec::new())];
+        Some(clocked_code(self.last_clock, &self.state, 1, self.inputs[1], on_edge,
+            &self.outputs))
+    }
+
+    fn state_registers(&self) -> Vec<StateRegister> {
+        vec![StateRegister::new("state", self.state.len(), 1)]
+    }
+
+    fn peek_state(&self, _register: usize, _index: usize) -> Vec<Line> {
+        to_lines(&self.state)
+    }
+
+    fn poke_state(&mut self, _register: usize, _index: usize, value: &[Line]) {
+        poke_bits(&mut self.state, value);
+    }
+}
+
+fn check_taps(width: usize, taps: &[usize]) {
+    assert!(!taps.is_empty(), "An LFSR needs at least one tap.");
+    assert!(taps.iter().all(|&t| t < width), "Taps must be within the register.");
+}
+
+/// Builds the gate-level equivalent of `Lfsr` and returns its outputs.
+pub fn make_lfsr(width: usize, taps: &[usize], enable: usize, clock: usize,
+    circuit: &mut Circuit) -> Vec<usize> {
+    check_taps(width, taps);
+    let (flip_flops, q) = make_flip_flops(width, clock, circuit);
+    let mut parity = q[taps[0]];
+    for &t in &taps[1..] {
+        let xor = circuit.add_gate(SimpleGate::xor());
+        circuit.set_gate_input(xor, 0, parity);
+        circuit.set_gate_input(xor, 1, q[t]);
+        parity = circuit.get_gate_output(xor, 0);
+    }
+    let feedback = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(feedback, 0, parity);
+    let feedback = circuit.get_gate_output(feedback, 0);
+    for b in 0..width {
+        let previous = if b == 0 { feedback } else { q[b - 1] };
+        let next = make_select(enable, previous, q[b], circuit);
+        circuit.set_gate_input(flip_flops[b], 0, next);
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::circuit::Circuit;
+    use circuit::gate::Gate;
+    use circuit::gate::tests::{Rng, assert_lowering_matches};
+    use circuit::line::Line;
+    use super::*;
+
+    // Connects a behavioral gate and its gate-level equivalent to the same `inputs` lines, the
+    // last of which is the clock, then drives random values and clock edges and checks that
+    // their outputs agree after every change.
+    fn matches_gate_level<G, F>(gate: G, inputs: usize, build: F)
+        where G: Gate + 'static, F: FnOnce(&[usize], &mut Circuit) -> Vec<usize> {
+        let mut c = Circuit::new();
+        let lines = (0..inputs).map(|_| c.add_line(Line::Low)).collect::<Vec<_>>();
+        let g = c.add_gate(gate);
+        let pins = c.gates[g].num_inputs();
+        assert_eq!(pins, inputs, "Gate has a different number of inputs.");
+        for (i, &line) in lines.iter().enumerate() {
+            c.set_gate_input(g, i, line);
+        }
+        let behavioral = (0..c.gates[g].num_outputs()).map(|o| c.get_gate_output(g, o))
+            .collect::<Vec<_>>();
+        let reference = build(&lines, &mut c);
+        assert_eq!(behavioral.len(), reference.len());
+        let mut rng = Rng(0x1234_5678);
+        c.eval_n_passes(10);
+        for step in 0..600 {
+            if step % 2 == 0 {
+                // Control inputs are set less often than data, so that loads and clears
+                // don't drown out everything else.
+                for &line in &lines[..inputs - 1] {
+                    c.set_line(line, Line::from(rng.below(3) == 0));
+                }
+            } else {
+                let clock = c.get_line_state(lines[inputs - 1]);
+                c.set_line(lines[inputs - 1], !clock);
+            }
+            c.eval_n_passes(2 * inputs + 10);
+            for (&a, &b) in behavioral.iter().zip(&reference) {
+                assert_eq!(c.get_line_state(a), c.get_line_state(b), "Differs at step {}.", step);
+            }
+        }
+    }
+
+    #[test]
+    fn register_matches_gate_level() {
+        for &width in &[0, 1, 8, 64, 65] {
+            matches_gate_level(Register::new(width), width + 2, |l, c| {
+                make_register(&l[..width], l[width], l[width + 1], c)
+            });
+        }
+    }
+
+    #[test]
+    fn counter_matches_gate_level() {
+        for &width in &[1, 4, 64] {
+            matches_gate_level(Counter::new(width), 4, |l, c| {
+                make_counter(width, l[0], l[1], l[2], l[3], c)
+            });
+        }
+    }
+
+    #[test]
+    fn shift_register_matches_gate_level() {
+        for &width in &[1, 5, 64] {
+            matches_gate_level(ShiftRegister::new(width), width + 4, |l, c| {
+                make_shift_register(&l[..width], l[width], l[width + 1], l[width + 2],
+                    l[width + 3], c)
+            });
+        }
+    }
+
+    #[test]
+    fn lfsr_matches_gate_level() {
+        matches_gate_level(Lfsr::new(4, &[3, 2]), 2, |l, c| make_lfsr(4, &[3, 2], l[0], l[1], c));
+        matches_gate_level(Lfsr::new(16, &[15, 14, 12, 3]), 2, |l, c| {
+            make_lfsr(16, &[15, 14, 12, 3], l[0], l[1], c)
+        });
+    }
+
+    #[test]
+    fn barrel_shifter_lowering_matches() {
+        for &width in &[2, 4, 8] {
+            assert_lowering_matches(BarrelShifter::new(width, false));
+            assert_lowering_matches(BarrelShifter::new(width, true));
+        }
+    }
+
+    #[test]
+    fn counter_wraps_with_carry() {
+        let mut c = Circuit::new();
+        let enable = c.add_line(Line::High);
+        let up = c.add_line(Line::High);
+        let clock = c.add_line(Line::Low);
+        let counter = c.add_gate(Counter::new(2));
+        c.set_gate_input(counter, 0, enable);
+        c.set_gate_input(counter, 1, up);
+        c.set_gate_input(counter, 3, clock);
+        let carry = c.get_gate_output(counter, 2);
+        for expected in 1..=4 {
+            c.set_line(clock, Line::High);
+            c.eval();
+            c.set_line(clock, Line::Low);
+            c.eval();
+            assert_eq!(c.peek_state_word(counter, "count", 0), Some(expected % 4));
+            assert_eq!(c.get_line_state(carry), Line::from(expected == 3));
+        }
+    }
+
+    #[test]
+    fn lfsr_has_full_period() {
+        let mut c = Circuit::new();
+        let enable = c.add_line(Line::High);
+        let clock = c.add_line(Line::Low);
+        let lfsr = c.add_gate(Lfsr::new(4, &[3, 2]));
+        c.set_gate_input(lfsr, 0, enable);
+        c.set_gate_input(lfsr, 1, clock);
+        let mut seen = Vec::new();
+        for _ in 0..15 {
+            c.set_line(clock, Line::High);
+            c.eval();
+            c.set_line(clock, Line::Low);
+            c.eval();
+            seen.push(c.peek_state_word(lfsr, "state", 0).unwrap());
+        }
+        assert_eq!(seen[14], 0);
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 15);
+        assert!(!seen.contains(&15));
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and 64")]
+    fn counter_is_at_most_64_bits() {
+        Counter::new(65);
+    }
+
+    #[test]
+    fn poked_state_keeps_its_width() {
+        let mut register = Register::new(3);
+        register.poke_state(0, 0, &[Line::High, Line::Disconnected, Line::High]);
+        assert_eq!(register.peek_state(0, 0), vec![Line::High, Line::Low, Line::High]);
+        let mut counter = Counter::new(64);
+        counter.poke_state(0, 0, &[Line::High; 64]);
+        assert_eq!(counter.peek_state(0, 0), vec![Line::High; 64]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Value width doesn't match the register.")]
+    fn poked_registers_keep_their_width() {
+        ShiftRegister::new(3).poke_state(0, 0, &[Line::High; 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Value width doesn't match the counter.")]
+    fn poked_counters_keep_their_width() {
+        Counter::new(2).poke_state(0, 0, &[Line::High; 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Taps must be within the register.")]
+    fn gate_level_taps_are_checked() {
+        let mut c = Circuit::new();
+        let (enable, clock) = (c.add_line(Line::High), c.add_line(Line::Low));
+        make_lfsr(4, &[4], enable, clock, &mut c);
+    }
+}
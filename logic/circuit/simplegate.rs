@@ -1,10 +1,13 @@
 use circuit::line::{Line, and, or, xor, nand, nor, xnor};
-use circuit::gate::Gate;
+use circuit::gate::{Gate, GateKind, Port};
+use circuit::circuit::Circuit;
 
+#[derive(Clone)]
 pub struct SimpleGate {
     i0: usize,
     i1: usize,
     function: fn(Line, Line) -> Line,
+    kind: GateKind,
     output: usize
 }
 
@@ -14,6 +17,7 @@ impl SimpleGate {
             i0: 0,
             i1: 0,
             function: |i0, i1| and(i0, i1),
+            kind: GateKind::And,
             output: 0
         }
     }
@@ -23,6 +27,7 @@ impl SimpleGate {
             i0: 0,
             i1: 0,
             function: |i0, i1| or(i0, i1),
+            kind: GateKind::Or,
             output: 0
         }
     }
@@ -32,6 +37,7 @@ impl SimpleGate {
             i0: 0,
             i1: 0,
             function: |i0, i1| xor(i0, i1),
+            kind: GateKind::Xor,
             output: 0
         }
     }
@@ -41,6 +47,7 @@ impl SimpleGate {
             i0: 0,
             i1: 0,
             function: |i0, i1| nand(i0, i1),
+            kind: GateKind::Nand,
             output: 0
         }
     }
@@ -50,6 +57,7 @@ impl SimpleGate {
             i0: 0,
             i1: 0,
             function: |i0, i1| nor(i0, i1),
+            kind: GateKind::Nor,
             output: 0
         }
     }
@@ -59,6 +67,7 @@ impl SimpleGate {
             i0: 0,
             i1: 0,
             function: |i0, i1| xnor(i0, i1),
+            kind: GateKind::Xnor,
             output: 0
         }
     }
@@ -103,8 +112,94 @@ impl Gate for SimpleGate {
         1
     }
 
+    fn kind(&self) -> GateKind {
+        self.kind
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![Port::input("a", 0, 1), Port::input("b", 1, 1), Port::output("out", 0, 1)]
+    }
+
     fn eval(&mut self, lines: &mut Vec<Line>) {
         let tmp = (self.function)(lines[self.i0], lines[self.i1]);
         lines[self.output] = tmp;
     }
-}
\ No newline at end of file
+}
+
+fn make_tree(lines: &[usize], gate: fn() -> SimpleGate, circuit: &mut Circuit) -> usize {
+    assert!(!lines.is_empty(), "Cannot build a gate tree with no inputs.");
+    let mut layer = lines.to_vec();
+    while layer.len() > 1 {
+        let mut next = Vec::with_capacity(layer.len().div_ceil(2));
+        for pair in layer.chunks(2) {
+            if pair.len() == 2 {
+                let g = circuit.add_gate(gate());
+                circuit.set_gate_input(g, 0, pair[0]);
+                circuit.set_gate_input(g, 1, pair[1]);
+                next.push(circuit.get_gate_output(g, 0));
+            } else {
+                next.push(pair[0]);
+            }
+        }
+        layer = next;
+    }
+    layer[0]
+}
+
+/// Builds a balanced tree of 2-input AND gates over `lines` and returns the line carrying the
+/// result. A single line is returned as-is.
+pub fn make_and_tree(lines: &[usize], circuit: &mut Circuit) -> usize {
+    make_tree(lines, SimpleGate::and, circuit)
+}
+
+/// Builds a balanced tree of 2-input OR gates over `lines` and returns the line carrying the
+/// result. A single line is returned as-is.
+pub fn make_or_tree(lines: &[usize], circuit: &mut Circuit) -> usize {
+    make_tree(lines, SimpleGate::or, circuit)
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::circuit::Circuit;
+    use circuit::gate::tests::respond;
+    use circuit::line::Line;
+    use super::{make_and_tree, make_or_tree};
+
+    fn tree(lines: usize, make: fn(&[usize], &mut Circuit) -> usize) -> Circuit {
+        let mut circuit = Circuit::new();
+        let inputs = (0..lines).map(|_| circuit.add_line(Line::Low)).collect::<Vec<_>>();
+        for &line in &inputs {
+            circuit.mark_line_as_circuit_input(line);
+        }
+        let output = make(&inputs, &mut circuit);
+        circuit.mark_line_as_circuit_output(output);
+        circuit
+    }
+
+    #[test]
+    fn trees_reduce_every_input() {
+        for lines in 1..=7 {
+            let mut and = tree(lines, make_and_tree);
+            let mut or = tree(lines, make_or_tree);
+            for v in 0..1u32 << lines {
+                let inputs = (0..lines).map(|i| Line::from(v >> i & 1 == 1)).collect::<Vec<_>>();
+                let all = v == (1 << lines) - 1;
+                assert_eq!(respond(&mut and, &inputs), vec![Line::from(all)]);
+                assert_eq!(respond(&mut or, &inputs), vec![Line::from(v != 0)]);
+            }
+        }
+    }
+
+    #[test]
+    fn single_line_tree_adds_no_gates() {
+        let circuit = tree(1, make_and_tree);
+        assert!(circuit.gates.is_empty());
+        assert_eq!(circuit.outputs, circuit.inputs);
+    }
+
+    #[test]
+    #[should_panic(expected = "no inputs")]
+    fn empty_tree_panics() {
+        tree(0, make_or_tree);
+    }
+}
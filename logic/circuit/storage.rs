@@ -0,0 +1,239 @@
+const PAGE_WORDS: usize = 64;
+const PAGE_BITS: usize = PAGE_WORDS * 64;
+// Pages per table of the page table's second level.
+const TABLE_PAGES: usize = 4096;
+
+type Page = Option<Box<[u64]>>;
+
+/// Heap-allocated, bit-packed backing store for memory gates.
+///
+/// Bits live in fixed-size pages of `u64`s. A dense store allocates every page up front, while a
+/// sparse one only allocates a page the first time a high bit is written to it, so that unused
+/// regions of a large memory cost nothing and constructing one is cheap. Unallocated pages read
+/// as low. Pages are found through a two-level table whose tables of `TABLE_PAGES` pages are
+/// allocated along with their first page, so even the page table of a sparse store grows with
+/// use rather than size.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    bits: usize,
+    tables: Vec<Option<Box<[Page]>>>
+}
+
+impl Storage {
+    pub fn dense(bits: usize) -> Self {
+        let mut storage = Storage::sparse(bits);
+        for t in 0..storage.tables.len() {
+            let table = (0..storage.table_pages(t)).map(|_| Some(Storage::empty_page())).collect();
+            storage.tables[t] = Some(table);
+        }
+        storage
+    }
+
+    pub fn sparse(bits: usize) -> Self {
+        let pages = bits.div_ceil(PAGE_BITS);
+        Storage {
+            bits,
+            tables: vec![None; pages.div_ceil(TABLE_PAGES)]
+        }
+    }
+
+    fn empty_page() -> Box<[u64]> {
+        vec![0; PAGE_WORDS].into_boxed_slice()
+    }
+
+    // Pages in table `t`, which only covers the pages the store has.
+    fn table_pages(&self, t: usize) -> usize {
+        TABLE_PAGES.min(self.bits.div_ceil(PAGE_BITS) - t * TABLE_PAGES)
+    }
+
+    fn page(&self, bit: usize) -> Option<&[u64]> {
+        let page = bit / PAGE_BITS;
+        self.tables[page / TABLE_PAGES].as_ref()
+            .and_then(|table| table[page % TABLE_PAGES].as_deref())
+    }
+
+    fn page_slot(&mut self, bit: usize) -> &mut Page {
+        let page = bit / PAGE_BITS;
+        let t = page / TABLE_PAGES;
+        if self.tables[t].is_none() {
+            self.tables[t] = Some(vec![None; self.table_pages(t)].into_boxed_slice());
+        }
+        &mut self.tables[t].as_mut().unwrap()[page % TABLE_PAGES]
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Bytes of bit storage currently allocated on the heap.
+    pub fn allocated_bytes(&self) -> usize {
+        self.tables.iter().flatten().flat_map(|table| table.iter())
+            .filter(|page| page.is_some()).count() * PAGE_WORDS * 8
+    }
+
+    pub fn get(&self, bit: usize) -> bool {
+        assert!(bit < self.bits, "Bit {} is outside of storage of {} bits.", bit, self.bits);
+        match self.page(bit) {
+            Some(page) => {
+                let in_page = bit % PAGE_BITS;
+                page[in_page / 64] >> (in_page % 64) & 1 == 1
+            },
+            None => false
+        }
+    }
+
+    pub fn set(&mut self, bit: usize, value: bool) {
+        assert!(bit < self.bits, "Bit {} is outside of storage of {} bits.", bit, self.bits);
+        if !value && self.page(bit).is_none() {
+            return;
+        }
+        let slot = self.page_slot(bit);
+        let page = slot.get_or_insert_with(Storage::empty_page);
+        let in_page = bit % PAGE_BITS;
+        let mask = 1 << (in_page % 64);
+        if value {
+            page[in_page / 64] |= mask;
+        } else {
+            page[in_page / 64] &= !mask;
+        }
+    }
+
+    /// Reads the `width`-bit word at index `word`, with bit 0 in the least significant position.
+    pub fn get_word(&self, word: usize, width: usize) -> u64 {
+        assert!(width <= 64, "Words are limited to 64 bits.");
+        (0..width).map(|b| (self.get(word * width + b) as u64) << b).sum()
+    }
+
+    /// Writes `value` to the `width`-bit word at index `word`.
+    pub fn set_word(&mut self, word: usize, width: usize, value: u64) {
+        assert!(width <= 64, "Words are limited to 64 bits.");
+        for b in 0..width {
+            self.set(word * width + b, value >> b & 1 == 1);
+        }
+    }
+
+    /// Overwrites this store with the contents of `other`, which must be the same size. Pages
+    /// that are unallocated in `other` are released here as well.
+    pub fn copy_from(&mut self, other: &Storage) {
+        assert_eq!(self.bits, other.bits, "Storage sizes don't match.");
+        for (table, source) in self.tables.iter_mut().zip(&other.tables) {
+            match (table.as_mut(), source.as_ref()) {
+                (Some(table), Some(source)) => for (page, source) in table.iter_mut().zip(source) {
+                    match (page.as_mut(), source.as_ref()) {
+                        (Some(page), Some(source)) => page.copy_from_slice(source),
+                        (_, source) => *page = source.cloned()
+                    }
+                },
+                (_, source) => *table = source.cloned()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::gate::tests::Rng;
+    use super::{PAGE_BITS, TABLE_PAGES, Storage};
+
+    #[test]
+    fn sparse_and_dense_agree() {
+        let bits = 3 * PAGE_BITS + 17;
+        let mut dense = Storage::dense(bits);
+        let mut sparse = Storage::sparse(bits);
+        let mut rng = Rng(7);
+        for _ in 0..5000 {
+            let bit = rng.below(bits as u64) as usize;
+            let value = rng.next() & 1 == 1;
+            dense.set(bit, value);
+            sparse.set(bit, value);
+        }
+        assert!((0..bits).all(|bit| dense.get(bit) == sparse.get(bit)));
+    }
+
+    #[test]
+    fn sparse_allocates_on_first_high_bit() {
+        let mut storage = Storage::sparse(4 * PAGE_BITS);
+        assert_eq!(storage.allocated_bytes(), 0);
+        storage.set(PAGE_BITS + 3, false);
+        assert_eq!(storage.allocated_bytes(), 0);
+        storage.set(PAGE_BITS + 3, true);
+        assert_eq!(storage.allocated_bytes(), PAGE_BITS / 8);
+        assert!(storage.get(PAGE_BITS + 3));
+        assert!(!storage.get(3));
+    }
+
+    #[test]
+    fn words_round_trip() {
+        for &width in &[0, 1, 7, 63, 64] {
+            let mut storage = Storage::dense(10 * width);
+            let mask = if width == 64 { !0 } else { (1 << width) - 1 };
+            for word in 0..10 {
+                storage.set_word(word, width, 0xDEAD_BEEF_0123_4567u64.rotate_left(word as u32));
+            }
+            for word in 0..10 {
+                assert_eq!(storage.get_word(word, width),
+                    0xDEAD_BEEF_0123_4567u64.rotate_left(word as u32) & mask);
+            }
+        }
+    }
+
+    #[test]
+    fn copy_from_releases_pages() {
+        let mut source = Storage::sparse(2 * PAGE_BITS);
+        source.set(5, true);
+        let mut target = Storage::dense(2 * PAGE_BITS);
+        target.set(PAGE_BITS + 5, true);
+        target.copy_from(&source);
+        assert!(target.get(5));
+        assert!(!target.get(PAGE_BITS + 5));
+        assert_eq!(target.allocated_bytes(), source.allocated_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "limited to 64 bits")]
+    fn words_are_at_most_64_bits() {
+        Storage::dense(65).get_word(0, 65);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of storage")]
+    fn bits_are_bounds_checked() {
+        Storage::sparse(10).get(10);
+    }
+
+    #[test]
+    fn page_tables_grow_with_use() {
+        // The size of a 32-bit address, 64-bit word memory.
+        let bits = 1 << 38;
+        let mut storage = Storage::sparse(bits);
+        assert!(storage.tables.len() * 16 <= 256 << 10);
+        storage.set(bits - 1, true);
+        storage.set(3, true);
+        assert!(storage.get(bits - 1) && storage.get(3) && !storage.get(bits - 2));
+        assert_eq!(storage.tables.iter().filter(|table| table.is_some()).count(), 2);
+        assert_eq!(storage.allocated_bytes(), 2 * PAGE_BITS / 8);
+        let mut copy = Storage::sparse(bits);
+        copy.set(PAGE_BITS * TABLE_PAGES, true);
+        copy.copy_from(&storage);
+        assert!(copy.get(bits - 1) && !copy.get(PAGE_BITS * TABLE_PAGES));
+    }
+
+    #[test]
+    fn dense_tables_cover_every_page() {
+        let bits = (TABLE_PAGES + 2) * PAGE_BITS - 1;
+        let storage = Storage::dense(bits);
+        assert_eq!(storage.allocated_bytes(), (TABLE_PAGES + 2) * PAGE_BITS / 8);
+        assert!(!storage.get(bits - 1));
+    }
+
+    #[test]
+    fn empty_storage() {
+        let storage = Storage::dense(0);
+        assert!(storage.is_empty());
+        assert_eq!(storage.allocated_bytes(), 0);
+    }
+}
@@ -0,0 +1,716 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use circuit::basics::Source;
+use circuit::circuit::Circuit;
+use circuit::expression::{ParseError, parse_circuit};
+use circuit::line::Line;
+use circuit::lut::{Lut, MAX_LUT_INPUTS};
+use circuit::netlist::{Netlist, NetlistError, Node, batch_inputs};
+use circuit::simplegate::SimpleGate;
+
+/// Gate that `map_universal` builds circuits from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Universal {
+    Nand,
+    Nor
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Subject {
+    Input(usize),
+    Constant(bool),
+    Not(usize),
+    /// Two-input NAND or NOR, depending on the graph.
+    Base(usize, usize)
+}
+
+// Logic decomposed into inverters and one kind of two-input gate, with each distinct node kept
+// once and in topological order. Nodes are simplified as they're added: constants fold, double
+// inversions cancel and a gate of a line and its inverse becomes a constant.
+struct SubjectGraph {
+    universal: Universal,
+    nodes: Vec<Subject>,
+    memo: HashMap<Subject, usize>
+}
+
+impl SubjectGraph {
+    // Graph of `netlist` and the graph node of each netlist node.
+    fn new(netlist: &Netlist, universal: Universal) -> (Self, Vec<usize>) {
+        let mut graph = SubjectGraph {
+            universal,
+            nodes: Vec::new(),
+            memo: HashMap::new()
+        };
+        let mut nodes = Vec::with_capacity(netlist.nodes.len());
+        for node in &netlist.nodes {
+            let subject = match *node {
+                Node::Input(i) => graph.node(Subject::Input(i)),
+                Node::Constant(value) => graph.node(Subject::Constant(value)),
+                Node::Not(a) => graph.not(nodes[a]),
+                Node::And(a, b) => graph.and(nodes[a], nodes[b]),
+                Node::Or(a, b) => graph.or(nodes[a], nodes[b]),
+                Node::Xor(a, b) => graph.xor(nodes[a], nodes[b]),
+                Node::Mux { select, low, high } => graph.mux(nodes[select], nodes[low], nodes[high])
+            };
+            nodes.push(subject);
+        }
+        (graph, nodes)
+    }
+
+    fn node(&mut self, node: Subject) -> usize {
+        if let Some(&n) = self.memo.get(&node) {
+            return n;
+        }
+        self.nodes.push(node);
+        self.memo.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn not(&mut self, a: usize) -> usize {
+        match self.nodes[a] {
+            Subject::Not(b) => b,
+            Subject::Constant(value) => self.node(Subject::Constant(!value)),
+            _ => self.node(Subject::Not(a))
+        }
+    }
+
+    fn base(&mut self, a: usize, b: usize) -> usize {
+        // The input value that decides the output on its own: low for NAND, high for NOR.
+        let dominant = self.universal == Universal::Nor;
+        for &(x, y) in &[(a, b), (b, a)] {
+            if let Subject::Constant(value) = self.nodes[x] {
+                return if value == dominant {
+                    self.node(Subject::Constant(!dominant))
+                } else {
+                    self.not(y)
+                };
+            }
+        }
+        if a == b {
+            self.not(a)
+        } else if self.nodes[a] == Subject::Not(b) || self.nodes[b] == Subject::Not(a) {
+            self.node(Subject::Constant(!dominant))
+        } else {
+            self.node(Subject::Base(a.min(b), a.max(b)))
+        }
+    }
+
+    fn and(&mut self, a: usize, b: usize) -> usize {
+        match self.universal {
+            Universal::Nand => {
+                let nand = self.base(a, b);
+                self.not(nand)
+            },
+            Universal::Nor => {
+                let (a, b) = (self.not(a), self.not(b));
+                self.base(a, b)
+            }
+        }
+    }
+
+    fn or(&mut self, a: usize, b: usize) -> usize {
+        match self.universal {
+            Universal::Nand => {
+                let (a, b) = (self.not(a), self.not(b));
+                self.base(a, b)
+            },
+            Universal::Nor => {
+                let nor = self.base(a, b);
+                self.not(nor)
+            }
+        }
+    }
+
+    // Four NANDs, or four NORs computing XNOR and an inverter.
+    fn xor(&mut self, a: usize, b: usize) -> usize {
+        let both = self.base(a, b);
+        let (x, y) = (self.base(a, both), self.base(b, both));
+        let out = self.base(x, y);
+        match self.universal {
+            Universal::Nand => out,
+            Universal::Nor => self.not(out)
+        }
+    }
+
+    fn mux(&mut self, select: usize, low: usize, high: usize) -> usize {
+        let inverse = self.not(select);
+        let (x, y) = match self.universal {
+            Universal::Nand => (self.base(high, select), self.base(low, inverse)),
+            Universal::Nor => (self.base(high, inverse), self.base(low, select))
+        };
+        self.base(x, y)
+    }
+
+    // Whether each node is needed to compute `roots`.
+    fn used(&self, roots: &[usize]) -> Vec<bool> {
+        let mut used = vec![false; self.nodes.len()];
+        for &root in roots {
+            used[root] = true;
+        }
+        for n in (0..self.nodes.len()).rev() {
+            match self.nodes[n] {
+                Subject::Not(a) if used[n] => used[a] = true,
+                Subject::Base(a, b) if used[n] => {
+                    used[a] = true;
+                    used[b] = true;
+                },
+                _ => {}
+            }
+        }
+        used
+    }
+}
+
+// Subject graph of a combinational circuit and the graph node of each of its outputs.
+fn decompose(circuit: &Circuit, universal: Universal)
+    -> Result<(SubjectGraph, Vec<usize>), NetlistError> {
+    let netlist = Netlist::from_circuit(circuit)?;
+    let (graph, nodes) = SubjectGraph::new(&netlist, universal);
+    let roots = netlist.outputs.iter().map(|&(node, _)| nodes[node]).collect();
+    Ok((graph, roots))
+}
+
+// Circuit with `inputs` marked input lines, returned with it.
+fn circuit_with_inputs(inputs: usize) -> (Circuit, Vec<usize>) {
+    let mut circuit = Circuit::new();
+    let lines = (0..inputs).map(|_| {
+        let line = circuit.add_line(Line::Low);
+        circuit.mark_line_as_circuit_input(line);
+        line
+    }).collect();
+    (circuit, lines)
+}
+
+fn constant(circuit: &mut Circuit, value: bool) -> usize {
+    let gate = if value {
+        circuit.add_gate(Source::new_high())
+    } else {
+        circuit.add_gate(Source::new_low())
+    };
+    circuit.get_gate_output(gate, 0)
+}
+
+/// Rebuilds a combinational circuit with two-input NAND gates only, or NOR gates only, with an
+/// inverter being a gate with both inputs tied. Complex gates are lowered first, and `Source`s
+/// remain only where an output is constant.
+///
+/// The result has the same marked inputs and outputs in the same order, computing the same
+/// function as two-valued logic. Shared logic and double inversions are merged as it's built,
+/// so its gate count is a fair figure for comparing implementations.
+pub fn map_universal(circuit: &Circuit, universal: Universal) -> Result<Circuit, NetlistError> {
+    let (graph, roots) = decompose(circuit, universal)?;
+    let used = graph.used(&roots);
+    let (mut mapped, inputs) = circuit_with_inputs(circuit.inputs.len());
+    let mut lines = vec![0; graph.nodes.len()];
+    for n in (0..graph.nodes.len()).filter(|&n| used[n]) {
+        let (a, b) = match graph.nodes[n] {
+            Subject::Input(i) => {
+                lines[n] = inputs[i];
+                continue;
+            },
+            Subject::Constant(value) => {
+                lines[n] = constant(&mut mapped, value);
+                continue;
+            },
+            Subject::Not(a) => (lines[a], lines[a]),
+            Subject::Base(a, b) => (lines[a], lines[b])
+        };
+        let gate = match universal {
+            Universal::Nand => mapped.add_gate(SimpleGate::nand()),
+            Universal::Nor => mapped.add_gate(SimpleGate::nor())
+        };
+        mapped.set_gate_input(gate, 0, a);
+        mapped.set_gate_input(gate, 1, b);
+        lines[n] = mapped.get_gate_output(gate, 0);
+    }
+    for &root in &roots {
+        mapped.mark_line_as_circuit_output(lines[root]);
+    }
+    Ok(mapped)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapError {
+    /// The function of cell `cell` doesn't parse.
+    Parse { cell: String, error: ParseError },
+    /// Cell `cell` doesn't have a single output and between one and `MAX_LUT_INPUTS` inputs.
+    Pins { cell: String, inputs: usize, outputs: usize },
+    /// The library has no inverter or no two-input NAND, which every mapping may need.
+    Incomplete,
+    /// The circuit being mapped isn't purely combinational.
+    Netlist(NetlistError)
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapError::Parse { ref cell, ref error } => write!(f, "cell {}: {}", cell, error),
+            MapError::Pins { ref cell, inputs, outputs } => {
+                write!(f, "cell {} has {} inputs and {} outputs", cell, inputs, outputs)
+            },
+            MapError::Incomplete => write!(f, "library has no inverter or no two-input NAND"),
+            MapError::Netlist(ref error) => error.fmt(f)
+        }
+    }
+}
+
+impl Error for MapError {}
+
+impl From<NetlistError> for MapError {
+    fn from(error: NetlistError) -> Self {
+        MapError::Netlist(error)
+    }
+}
+
+/// A library cell: a single-output function with an area cost.
+#[derive(Clone, Debug)]
+pub struct Cell {
+    pub name: String,
+    pub area: f64,
+    /// Pin names, in the order the cell's gate takes them.
+    pub inputs: Vec<String>,
+    pub output: String,
+    table: u64,
+    // The function as a NAND graph, and the node of its output.
+    pattern: Vec<Subject>,
+    root: usize
+}
+
+impl Cell {
+    /// Cell computing `function`, an assignment in the syntax of `parse_circuit` such as
+    /// `y = !(a & b | c)`. Its pins are the names read, in order of first use.
+    pub fn new(name: &str, area: f64, function: &str) -> Result<Cell, MapError> {
+        let parsed = parse_circuit(function).map_err(|error| MapError::Parse {
+            cell: name.to_string(),
+            error
+        })?;
+        let (inputs, outputs) = (parsed.inputs.len(), parsed.outputs.len());
+        if outputs != 1 || inputs == 0 || inputs > MAX_LUT_INPUTS {
+            return Err(MapError::Pins { cell: name.to_string(), inputs, outputs });
+        }
+        let netlist = Netlist::from_circuit(&parsed.circuit)?;
+        let (graph, nodes) = SubjectGraph::new(&netlist, Universal::Nand);
+        let table = netlist.simulate(&batch_inputs(inputs, 0))[0];
+        Ok(Cell {
+            name: name.to_string(),
+            area,
+            inputs: parsed.inputs,
+            output: parsed.outputs[0].clone(),
+            table: Lut::new(inputs, table).table(),
+            pattern: graph.nodes,
+            root: nodes[netlist.outputs[0].0]
+        })
+    }
+
+    /// Truth table of the cell, bit `m` being the output when pin `i` carries bit `i` of `m`.
+    pub fn table(&self) -> u64 {
+        self.table
+    }
+}
+
+/// Cells available to `map_library`.
+#[derive(Clone, Debug)]
+pub struct CellLibrary {
+    cells: Vec<Cell>
+}
+
+impl CellLibrary {
+    /// Library of `cells`, which must include an inverter and a two-input NAND so that any
+    /// circuit can be mapped.
+    pub fn new(cells: Vec<Cell>) -> Result<CellLibrary, MapError> {
+        let has = |shape: fn(&[Subject], usize) -> bool| {
+            cells.iter().any(|cell| shape(&cell.pattern, cell.root))
+        };
+        let inverter = has(|pattern, root| match pattern[root] {
+            Subject::Not(a) => is_input(pattern, a),
+            _ => false
+        });
+        let nand = has(|pattern, root| match pattern[root] {
+            Subject::Base(a, b) => is_input(pattern, a) && is_input(pattern, b),
+            _ => false
+        });
+        if !inverter || !nand {
+            return Err(MapError::Incomplete);
+        }
+        Ok(CellLibrary { cells })
+    }
+
+    /// Common static CMOS cells, with their transistor counts as area.
+    pub fn cmos() -> CellLibrary {
+        let cells = [
+            ("INV", 2.0, "y = !a"),
+            ("NAND2", 4.0, "y = !(a & b)"),
+            ("NOR2", 4.0, "y = !(a | b)"),
+            ("AND2", 6.0, "y = a & b"),
+            ("OR2", 6.0, "y = a | b"),
+            ("NAND3", 6.0, "y = !(a & b & c)"),
+            ("NOR3", 6.0, "y = !(a | b | c)"),
+            ("AOI21", 6.0, "y = !(a & b | c)"),
+            ("OAI21", 6.0, "y = !((a | b) & c)"),
+            ("AOI22", 8.0, "y = !(a & b | c & d)"),
+            ("OAI22", 8.0, "y = !((a | b) & (c | d))"),
+            ("XOR2", 12.0, "y = a ^ b"),
+            ("XNOR2", 12.0, "y = !(a ^ b)"),
+            ("MUX2", 12.0, "y = !s & a | s & b")
+        ];
+        let cells = cells.iter()
+            .map(|&(name, area, function)| Cell::new(name, area, function).unwrap())
+            .collect();
+        CellLibrary::new(cells).unwrap()
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+}
+
+fn is_input(pattern: &[Subject], node: usize) -> bool {
+    matches!(pattern[node], Subject::Input(_))
+}
+
+/// A circuit mapped onto a `CellLibrary`.
+pub struct Mapping {
+    /// One `Lut` per cell instance, with the cell's pins as its inputs, plus `Source`s for
+    /// constant outputs.
+    pub circuit: Circuit,
+    /// Library cell of each gate of `circuit`, or `None` for `Source`s.
+    pub cells: Vec<Option<usize>>,
+    /// Total area of the cells used.
+    pub area: f64,
+    names: Vec<String>
+}
+
+impl Mapping {
+    /// Instances of library cell `cell`.
+    pub fn count(&self, cell: usize) -> usize {
+        self.cells.iter().filter(|&&c| c == Some(cell)).count()
+    }
+
+    /// Total number of cell instances.
+    pub fn num_cells(&self) -> usize {
+        self.cells.iter().filter(|c| c.is_some()).count()
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (c, name) in self.names.iter().enumerate() {
+            let count = self.count(c);
+            if count > 0 {
+                writeln!(f, "{:8} {}", name, count)?;
+            }
+        }
+        write!(f, "{} cells, area {}", self.num_cells(), self.area)
+    }
+}
+
+// A way to implement a subject node with a cell, binding each pattern node to a subject node.
+#[derive(Clone)]
+struct Match {
+    cell: usize,
+    binding: Vec<Option<usize>>,
+    cost: f64
+}
+
+struct Mapper<'a> {
+    graph: &'a SubjectGraph,
+    library: &'a CellLibrary,
+    fanout: Vec<usize>
+}
+
+impl<'a> Mapper<'a> {
+    // Every binding of pattern node `p` of `pattern` onto subject node `s`, extending `binding`.
+    // Operands of a two-input gate match in either order.
+    fn bind(&self, pattern: &[Subject], p: usize, s: usize, mut binding: Vec<Option<usize>>)
+        -> Vec<Vec<Option<usize>>> {
+        if let Some(bound) = binding[p] {
+            return if bound == s { vec![binding] } else { Vec::new() };
+        }
+        binding[p] = Some(s);
+        match (pattern[p], self.graph.nodes[s]) {
+            (Subject::Input(_), _) => vec![binding],
+            (Subject::Not(a), Subject::Not(x)) => self.bind(pattern, a, x, binding),
+            (Subject::Base(a, b), Subject::Base(x, y)) => {
+                let mut bindings = Vec::new();
+                for &(x, y) in &[(x, y), (y, x)] {
+                    for binding in self.bind(pattern, a, x, binding.clone()) {
+                        bindings.extend(self.bind(pattern, b, y, binding));
+                    }
+                }
+                bindings
+            },
+            _ => Vec::new()
+        }
+    }
+
+    // Whether no subject node inside the match, other than its root, is read from outside of
+    // it. Otherwise the node would have to be built a second time.
+    fn contained(&self, pattern: &[Subject], binding: &[Option<usize>], root: usize) -> bool {
+        let mut inside = binding.iter().enumerate()
+            .filter(|&(p, _)| !is_input(pattern, p))
+            .filter_map(|(_, &s)| s)
+            .collect::<Vec<_>>();
+        inside.sort();
+        inside.dedup();
+        inside.iter().filter(|&&s| s != root).all(|&s| {
+            let reads = inside.iter().map(|&n| match self.graph.nodes[n] {
+                Subject::Not(a) => (a == s) as usize,
+                Subject::Base(a, b) => (a == s) as usize + (b == s) as usize,
+                _ => 0
+            }).sum::<usize>();
+            reads == self.fanout[s]
+        })
+    }
+
+    // Subject nodes bound to the pins of a match.
+    fn leaves(&self, pattern: &[Subject], binding: &[Option<usize>]) -> Vec<usize> {
+        let mut leaves = binding.iter().enumerate()
+            .filter(|&(p, _)| is_input(pattern, p))
+            .filter_map(|(_, &s)| s)
+            .collect::<Vec<_>>();
+        leaves.sort();
+        leaves.dedup();
+        leaves
+    }
+
+    // Cheapest match of each gate node by area flow: the cost of each leaf is shared among
+    // everything that reads it.
+    fn cover(&self) -> Vec<Option<Match>> {
+        let mut best: Vec<Option<Match>> = vec![None; self.graph.nodes.len()];
+        for s in 0..self.graph.nodes.len() {
+            match self.graph.nodes[s] {
+                Subject::Not(_) | Subject::Base(_, _) => {},
+                _ => continue
+            }
+            for (c, cell) in self.library.cells.iter().enumerate() {
+                if is_input(&cell.pattern, cell.root) {
+                    continue;
+                }
+                let empty = vec![None; cell.pattern.len()];
+                for binding in self.bind(&cell.pattern, cell.root, s, empty) {
+                    if !self.contained(&cell.pattern, &binding, s) {
+                        continue;
+                    }
+                    let cost = cell.area + self.leaves(&cell.pattern, &binding).iter()
+                        .filter_map(|&leaf| {
+                            best[leaf].as_ref().map(|m| m.cost / self.fanout[leaf] as f64)
+                        })
+                        .sum::<f64>();
+                    if best[s].as_ref().is_none_or(|best| cost < best.cost) {
+                        best[s] = Some(Match { cell: c, binding, cost });
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Maps a combinational circuit onto the cells of `library`, aiming for the least total area.
+///
+/// The circuit is decomposed into two-input NANDs and inverters, as is each cell's function,
+/// and cells are matched against the decomposition structurally. Dynamic programming then picks
+/// the cheapest cell for each node, sharing the area of logic with several readers among them.
+/// This is exact for fanout-free logic and a heuristic elsewhere, and since matching is
+/// structural the result depends on how the circuit is built as well as on what it computes.
+/// The mapped circuit has the same marked inputs and outputs in the same order.
+pub fn map_library(circuit: &Circuit, library: &CellLibrary) -> Result<Mapping, MapError> {
+    let (graph, roots) = decompose(circuit, Universal::Nand)?;
+    let used = graph.used(&roots);
+    let mut fanout = vec![0; graph.nodes.len()];
+    for &root in &roots {
+        fanout[root] += 1;
+    }
+    for n in (0..graph.nodes.len()).filter(|&n| used[n]) {
+        match graph.nodes[n] {
+            Subject::Not(a) => fanout[a] += 1,
+            Subject::Base(a, b) => {
+                fanout[a] += 1;
+                fanout[b] += 1;
+            },
+            _ => {}
+        }
+    }
+    let mapper = Mapper { graph: &graph, library, fanout };
+    let best = mapper.cover();
+
+    // Walk back from the outputs to find the matches to build.
+    let mut built = vec![false; graph.nodes.len()];
+    for &root in &roots {
+        built[root] = true;
+    }
+    for s in (0..graph.nodes.len()).rev() {
+        if let (true, Some(m)) = (built[s], best[s].as_ref()) {
+            for leaf in mapper.leaves(&library.cells[m.cell].pattern, &m.binding) {
+                built[leaf] = true;
+            }
+        }
+    }
+
+    let (mut mapped, inputs) = circuit_with_inputs(circuit.inputs.len());
+    let mut cells = Vec::new();
+    let mut area = 0.0;
+    let mut lines = vec![0; graph.nodes.len()];
+    for s in (0..graph.nodes.len()).filter(|&s| built[s]) {
+        match graph.nodes[s] {
+            Subject::Input(i) => lines[s] = inputs[i],
+            Subject::Constant(value) => {
+                lines[s] = constant(&mut mapped, value);
+                cells.push(None);
+            },
+            _ => {
+                let m = best[s].as_ref().expect("Library can't cover node.");
+                let cell = &library.cells[m.cell];
+                let gate = mapped.add_gate(Lut::new(cell.inputs.len(), cell.table));
+                for (p, &node) in cell.pattern.iter().enumerate() {
+                    if let (Subject::Input(i), Some(leaf)) = (node, m.binding[p]) {
+                        mapped.set_gate_input(gate, i, lines[leaf]);
+                    }
+                }
+                lines[s] = mapped.get_gate_output(gate, 0);
+                cells.push(Some(m.cell));
+                area += cell.area;
+            }
+        }
+    }
+    for &root in &roots {
+        mapped.mark_line_as_circuit_output(lines[root]);
+    }
+    Ok(Mapping {
+        circuit: mapped,
+        cells,
+        area,
+        names: library.cells.iter().map(|cell| cell.name.clone()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::equivalence::{Engine, EquivalenceOptions, check_equivalence};
+    use circuit::gate::GateKind;
+    use circuit::gate::tests::random_logic;
+    use circuit::memory::{DFlipFlop, Edge};
+    use super::*;
+
+    fn assert_equivalent(a: &Circuit, b: &Circuit) {
+        let options = EquivalenceOptions { engine: Engine::Sat, ..Default::default() };
+        assert!(check_equivalence(a, b, &options).unwrap().is_equivalent());
+    }
+
+    fn parsed(source: &str) -> Circuit {
+        parse_circuit(source).unwrap().circuit
+    }
+
+    #[test]
+    fn universal_mapping_preserves_random_logic() {
+        for &width in &[0, 1, 8, 64, 65] {
+            for seed in 1..4 {
+                let circuit = random_logic(seed, width, 60, 4, seed % 2 == 0);
+                for &(universal, kind) in &[(Universal::Nand, GateKind::Nand),
+                    (Universal::Nor, GateKind::Nor)] {
+                    let mapped = map_universal(&circuit, universal).unwrap();
+                    assert_equivalent(&circuit, &mapped);
+                    assert!(mapped.gates.iter()
+                        .all(|gate| gate.kind() == kind || gate.num_inputs() == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn universal_gate_counts() {
+        let count = |source: &str, universal| map_universal(&parsed(source), universal).unwrap()
+            .gates.len();
+        assert_eq!(count("y = a ^ b", Universal::Nand), 4);
+        assert_eq!(count("y = a ^ b", Universal::Nor), 5);
+        assert_eq!(count("y = a & b", Universal::Nand), 2);
+        assert_eq!(count("y = a & b", Universal::Nor), 3);
+        assert_eq!(count("y = !!a", Universal::Nand), 0);
+        assert_eq!(count("y = (a | b) & (a | b)", Universal::Nor), 2);
+        // Logic folding to a constant leaves one `Source`.
+        let constant = map_universal(&parsed("y = a & !a | b & 0"), Universal::Nand).unwrap();
+        assert_eq!(constant.gates.len(), 1);
+        assert_eq!(constant.gates[0].kind(), GateKind::Constant(Line::Low));
+    }
+
+    #[test]
+    fn library_mapping_preserves_random_logic() {
+        let library = CellLibrary::cmos();
+        for &width in &[0, 1, 8, 64, 65] {
+            for seed in 1..4 {
+                let circuit = random_logic(seed, width, 60, 4, seed % 2 == 1);
+                let mapping = map_library(&circuit, &library).unwrap();
+                assert_equivalent(&circuit, &mapping.circuit);
+                assert_eq!(mapping.cells.len(), mapping.circuit.gates.len());
+                let area = mapping.cells.iter().flatten()
+                    .map(|&c| library.cells()[c].area).sum::<f64>();
+                assert_eq!(mapping.area, area);
+                let counted = (0..library.cells().len()).map(|c| mapping.count(c)).sum::<usize>();
+                assert_eq!(counted, mapping.num_cells());
+            }
+        }
+    }
+
+    #[test]
+    fn complex_cells_are_matched() {
+        let library = CellLibrary::cmos();
+        let cell = |name: &str| library.cells().iter().position(|cell| cell.name == name);
+        for &(source, name, area) in &[("y = !(a & b | c)", "AOI21", 6.0),
+            ("y = !((p | q) & (r | s))", "OAI22", 8.0), ("y = !s & a | s & b", "MUX2", 12.0),
+            ("y = !a", "INV", 2.0), ("y = a ^ b", "XOR2", 12.0)] {
+            let mapping = map_library(&parsed(source), &library).unwrap();
+            assert_eq!(mapping.num_cells(), 1, "{}", source);
+            assert_eq!(mapping.count(cell(name).unwrap()), 1, "{}", source);
+            assert_eq!(mapping.area, area);
+            assert_eq!(mapping.to_string(), format!("{:8} 1\n1 cells, area {}", name, area));
+        }
+        let mapping = map_library(&parsed("y = a & b; z = !(a & b)"), &library).unwrap();
+        assert_eq!(mapping.num_cells(), 2);
+        assert_eq!(mapping.area, 6.0);
+    }
+
+    #[test]
+    fn cells_and_libraries() {
+        let nand = Cell::new("NAND2", 4.0, "y = !(a & b)").unwrap();
+        assert_eq!((nand.inputs.clone(), nand.output.as_str()), (vec!["a".into(), "b".into()],
+            "y"));
+        assert_eq!(nand.table(), 0b0111);
+        let mux = Cell::new("MUX2", 12.0, "y = !s & a | s & b").unwrap();
+        assert_eq!(mux.inputs, vec!["s", "a", "b"]);
+        assert_eq!(mux.table(), 0b1110_0100);
+        match Cell::new("BAD", 1.0, "y = a $ b") {
+            Err(MapError::Parse { cell, .. }) => assert_eq!(cell, "BAD"),
+            _ => panic!("Expected a parse error.")
+        }
+        let pins = |function| match Cell::new("X", 1.0, function) {
+            Err(MapError::Pins { inputs, outputs, .. }) => (inputs, outputs),
+            _ => panic!("Expected a pin error.")
+        };
+        assert_eq!(pins("y = a; z = b"), (2, 2));
+        assert_eq!(pins("y = 1"), (0, 1));
+        assert_eq!(pins("y = a & b & c & d & e & f & g"), (7, 1));
+        Cell::new("AND6", 1.0, "y = a & b & c & d & e & f").unwrap();
+        let and = Cell::new("AND2", 6.0, "y = a & b").unwrap();
+        let inverter = Cell::new("INV", 2.0, "y = !a").unwrap();
+        assert_eq!(CellLibrary::new(vec![and.clone(), inverter.clone()]).err(),
+            Some(MapError::Incomplete));
+        assert_eq!(CellLibrary::new(vec![nand.clone()]).err(), Some(MapError::Incomplete));
+        let minimal = CellLibrary::new(vec![inverter, and, nand]).unwrap();
+        let circuit = random_logic(4, 6, 40, 3, false);
+        assert_equivalent(&circuit, &map_library(&circuit, &minimal).unwrap().circuit);
+    }
+
+    #[test]
+    fn state_is_refused() {
+        let mut circuit = Circuit::new();
+        let ff = circuit.add_gate(DFlipFlop::new(Edge::Rising));
+        let q = circuit.get_gate_output(ff, 0);
+        circuit.mark_line_as_circuit_output(q);
+        let error = NetlistError::Opaque { gate: ff };
+        assert_eq!(map_universal(&circuit, Universal::Nor).err(), Some(error.clone()));
+        assert_eq!(map_library(&circuit, &CellLibrary::cmos()).err(),
+            Some(MapError::Netlist(error)));
+    }
+}
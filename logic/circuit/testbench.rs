@@ -0,0 +1,404 @@
+use std::error::Error;
+use std::fmt;
+
+use circuit::bus::Bus;
+use circuit::circuit::Circuit;
+use circuit::line::Line;
+
+/// Expected value of an output signal, with a mask of the bits that matter. Bits outside of the
+/// mask are don't-cares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Expect {
+    value: u64,
+    care: u64
+}
+
+impl Expect {
+    pub fn value(value: u64) -> Self {
+        Expect {
+            value,
+            care: !0
+        }
+    }
+
+    /// Matches anything, including disconnected bits.
+    pub fn any() -> Self {
+        Expect {
+            value: 0,
+            care: 0
+        }
+    }
+
+    /// Parses a pattern written most significant bit first, such as `"10x1"`, where `x`, `X`,
+    /// `-` and `?` are don't-cares and `_` separates digits for readability.
+    pub fn pattern(pattern: &str) -> Self {
+        let mut expect = Expect::any();
+        for c in pattern.chars().filter(|&c| c != '_') {
+            expect.value <<= 1;
+            expect.care <<= 1;
+            match c {
+                '0' => expect.care |= 1,
+                '1' => {
+                    expect.value |= 1;
+                    expect.care |= 1;
+                },
+                'x' | 'X' | '-' | '?' => {},
+                _ => panic!("Invalid character {:?} in pattern {:?}.", c, pattern)
+            }
+        }
+        expect
+    }
+
+    /// Whether `actual`, least significant bit first, matches. Disconnected bits only match
+    /// don't-cares.
+    pub fn matches(&self, actual: &[Line]) -> bool {
+        actual.iter().enumerate().filter(|&(bit, _)| bit >= 64 || self.care >> bit & 1 == 1)
+            .all(|(bit, line)| {
+                let expected = bit < 64 && self.value >> bit & 1 == 1;
+                !line.is_disconnected() && line.is_high() == expected
+            })
+    }
+
+    fn format(&self, width: usize) -> String {
+        (0..width).rev().map(|bit| match (bit < 64 && self.care >> bit & 1 == 1,
+            bit < 64 && self.value >> bit & 1 == 1) {
+            (false, _) => 'x',
+            (true, true) => '1',
+            (true, false) => '0'
+        }).collect()
+    }
+}
+
+impl From<u64> for Expect {
+    fn from(value: u64) -> Self {
+        Expect::value(value)
+    }
+}
+
+fn format_lines(lines: &[Line]) -> String {
+    lines.iter().rev().map(|line| match *line {
+        Line::High => '1',
+        Line::Low => '0',
+        Line::Disconnected => 'z'
+    }).collect()
+}
+
+/// One output that didn't match in one vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the vector, which is also the clock cycle for clocked testbenches.
+    pub cycle: usize,
+    pub signal: String,
+    pub expected: Expect,
+    /// The signal's lines, least significant bit first.
+    pub actual: Vec<Line>
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cycle {}: {} expected {}, got {}", self.cycle, self.signal,
+            self.expected.format(self.actual.len()), format_lines(&self.actual))
+    }
+}
+
+/// Outcome of running a `Testbench`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub vectors: usize,
+    pub mismatches: Vec<Mismatch>,
+    /// Vectors after which the circuit was still changing when it ran out of passes, as
+    /// oscillators do. These fail even if every output happened to match.
+    pub unsettled: Vec<usize>
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty() && self.unsettled.is_empty()
+    }
+
+    /// `Ok` if every vector passed, so that a `#[test]` returning `Result` can use `?` on it.
+    pub fn into_result(self) -> Result<(), Report> {
+        if self.passed() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Panics with the full report if any vector failed, for use in `#[test]` functions.
+    pub fn assert_passed(&self) {
+        if !self.passed() {
+            panic!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} vectors, {} mismatches", self.vectors, self.mismatches.len())?;
+        for mismatch in &self.mismatches {
+            write!(f, "\n  {}", mismatch)?;
+        }
+        for cycle in &self.unsettled {
+            write!(f, "\n  cycle {}: circuit didn't settle", cycle)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Report {}
+
+struct Vector {
+    inputs: Vec<u64>,
+    outputs: Vec<Expect>
+}
+
+/// Stimulus and expected responses for a circuit.
+///
+/// Signals are named buses of at most 64 bits declared with `input` and `output`, and each
+/// vector gives one value per input and one `Expect` per output, in declaration order. A
+/// combinational testbench drives each vector's inputs, lets the circuit settle and checks the
+/// outputs. A clocked one, made with `clocked`, also pulses the clock high and low after driving
+/// the inputs, so each vector is one clock cycle and the outputs are checked after the cycle
+/// completes.
+///
+/// `Report::assert_passed` turns a run into a `#[test]`, so circuit regressions run under
+/// `cargo test` alongside everything else.
+pub struct Testbench {
+    inputs: Vec<(String, Bus)>,
+    outputs: Vec<(String, Bus)>,
+    clock: Option<usize>,
+    max_passes: usize,
+    vectors: Vec<Vector>
+}
+
+impl Default for Testbench {
+    fn default() -> Self {
+        Testbench::new()
+    }
+}
+
+impl Testbench {
+    pub fn new() -> Self {
+        Testbench {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            clock: None,
+            max_passes: 1000,
+            vectors: Vec::new()
+        }
+    }
+
+    /// Pulses `clock` once per vector.
+    pub fn clocked(mut self, clock: usize) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Limits how many passes the circuit gets to settle after each change. Defaults to 1000.
+    pub fn max_passes(mut self, passes: usize) -> Self {
+        self.max_passes = passes;
+        self
+    }
+
+    pub fn input<B: Into<Bus>>(mut self, name: &str, bus: B) -> Self {
+        let bus = self.signal(name, bus.into());
+        self.inputs.push((name.to_string(), bus));
+        self
+    }
+
+    pub fn output<B: Into<Bus>>(mut self, name: &str, bus: B) -> Self {
+        let bus = self.signal(name, bus.into());
+        self.outputs.push((name.to_string(), bus));
+        self
+    }
+
+    // Checks a signal being declared, whose values have to fit in a `u64`.
+    fn signal(&self, name: &str, bus: Bus) -> Bus {
+        assert!(self.vectors.is_empty(), "Signals must be declared before any vectors.");
+        assert!(bus.width() <= 64, "Signal {} is {} bits wide, but at most 64 are supported.",
+            name, bus.width());
+        bus
+    }
+
+    pub fn vector(mut self, inputs: &[u64], outputs: &[Expect]) -> Self {
+        assert_eq!(inputs.len(), self.inputs.len(), "Vector needs one value per input.");
+        assert_eq!(outputs.len(), self.outputs.len(), "Vector needs one value per output.");
+        self.vectors.push(Vector {
+            inputs: inputs.to_vec(),
+            outputs: outputs.to_vec()
+        });
+        self
+    }
+
+    /// Adds a vector for every `(inputs, outputs)` pair in `sequence`.
+    pub fn sequence<'a, I>(self, sequence: I) -> Self
+        where I: IntoIterator<Item = (&'a [u64], &'a [Expect])> {
+        sequence.into_iter().fold(self, |bench, (inputs, outputs)| bench.vector(inputs, outputs))
+    }
+
+    /// Evaluates until the circuit stops changing and returns whether it did.
+    fn settle(&self, circuit: &mut Circuit) -> bool {
+        circuit.eval_until_stable(self.max_passes).is_some()
+    }
+
+    /// Runs every vector against `circuit` in order and collects the mismatches.
+    pub fn run(&self, circuit: &mut Circuit) -> Report {
+        let mut mismatches = Vec::new();
+        let mut unsettled = Vec::new();
+        for (cycle, vector) in self.vectors.iter().enumerate() {
+            for ((_, bus), &value) in self.inputs.iter().zip(&vector.inputs) {
+                circuit.drive_bus(bus, value);
+            }
+            let mut settled = self.settle(circuit);
+            if let Some(clock) = self.clock {
+                circuit.set_line(clock, Line::High);
+                settled &= self.settle(circuit);
+                circuit.set_line(clock, Line::Low);
+                settled &= self.settle(circuit);
+            }
+            if !settled {
+                unsettled.push(cycle);
+            }
+            for ((name, bus), &expected) in self.outputs.iter().zip(&vector.outputs) {
+                let actual = bus.iter().map(|&line| circuit.get_line_state(line))
+                    .collect::<Vec<_>>();
+                if !expected.matches(&actual) {
+                    mismatches.push(Mismatch {
+                        cycle,
+                        signal: name.clone(),
+                        expected,
+                        actual
+                    });
+                }
+            }
+        }
+        Report {
+            vectors: self.vectors.len(),
+            mismatches,
+            unsettled
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Inverter;
+    use circuit::memory::{DFlipFlop, Edge};
+    use circuit::simplegate::SimpleGate;
+    use super::*;
+
+    // An AND gate with inputs a and b and output y.
+    fn and_gate() -> (Circuit, usize, usize, usize) {
+        let mut c = Circuit::new();
+        let (a, b) = (c.add_line(Line::Low), c.add_line(Line::Low));
+        let gate = c.add_gate(SimpleGate::and());
+        c.set_gate_input(gate, 0, a);
+        c.set_gate_input(gate, 1, b);
+        let y = c.get_gate_output(gate, 0);
+        (c, a, b, y)
+    }
+
+    #[test]
+    fn patterns() {
+        let expect = Expect::pattern("1x_0-");
+        assert_eq!(expect.format(4), "1x0x");
+        assert!(expect.matches(&[Line::Disconnected, Line::Low, Line::High, Line::High]));
+        assert!(!expect.matches(&[Line::Low, Line::Disconnected, Line::High, Line::High]));
+        assert!(!expect.matches(&[Line::Low, Line::High, Line::High, Line::High]));
+        assert!(Expect::any().matches(&[Line::Disconnected; 4]));
+        assert!(Expect::value(1).matches(&[Line::High, Line::Low]));
+        assert!(Expect::value(!0).matches(&[Line::High; 64]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid character")]
+    fn bad_pattern() {
+        Expect::pattern("10z");
+    }
+
+    #[test]
+    fn combinational_vectors() {
+        let (mut c, a, b, y) = and_gate();
+        let inputs = [[0, 0], [0, 1], [1, 0], [1, 1]];
+        let outputs = [[Expect::value(0)], [Expect::value(0)], [Expect::value(0)],
+            [Expect::value(1)]];
+        let bench = Testbench::new().input("a", vec![a]).input("b", vec![b]).output("y", vec![y])
+            .sequence(inputs.iter().map(|v| &v[..]).zip(outputs.iter().map(|v| &v[..])));
+        bench.run(&mut c).assert_passed();
+        let bench = Testbench::new().input("a", vec![a]).input("b", vec![b]).output("y", vec![y])
+            .vector(&[1, 1], &[Expect::value(0)]);
+        let report = bench.run(&mut c);
+        assert_eq!(report.mismatches, vec![Mismatch {
+            cycle: 0,
+            signal: "y".to_string(),
+            expected: Expect::value(0),
+            actual: vec![Line::High]
+        }]);
+        assert_eq!(report.to_string(), "1 vectors, 1 mismatches\n  cycle 0: y expected 0, got 1");
+        assert!(report.into_result().is_err());
+    }
+
+    #[test]
+    fn clocked_vectors() {
+        let mut c = Circuit::new();
+        let (d, clock) = (c.add_line(Line::Low), c.add_line(Line::Low));
+        let ff = c.add_gate(DFlipFlop::new(Edge::Rising));
+        c.set_gate_input(ff, 0, d);
+        c.set_gate_input(ff, 1, clock);
+        let q = c.get_gate_output(ff, 0);
+        Testbench::new().clocked(clock).input("d", vec![d]).output("q", vec![q])
+            .vector(&[1], &[Expect::value(1)])
+            .vector(&[0], &[Expect::value(0)])
+            .vector(&[1], &[Expect::pattern("1")])
+            .run(&mut c).assert_passed();
+    }
+
+    #[test]
+    fn oscillators_fail() {
+        let mut c = Circuit::new();
+        let inverter = c.add_gate(Inverter::new());
+        let y = c.get_gate_output(inverter, 0);
+        c.set_gate_input(inverter, 0, y);
+        let report = Testbench::new().max_passes(5).output("y", vec![y])
+            .vector(&[], &[Expect::any()])
+            .run(&mut c);
+        assert!(report.mismatches.is_empty());
+        assert_eq!(report.unsettled, vec![0]);
+        assert!(!report.passed());
+        assert!(report.to_string().ends_with("cycle 0: circuit didn't settle"));
+    }
+
+    #[test]
+    #[should_panic(expected = "one value per input")]
+    fn vectors_need_every_input() {
+        let (_, a, _, y) = and_gate();
+        Testbench::new().input("a", vec![a]).output("y", vec![y]).vector(&[], &[Expect::any()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "declared before any vectors")]
+    fn signals_come_first() {
+        let (_, a, _, y) = and_gate();
+        Testbench::new().output("y", vec![y]).vector(&[], &[Expect::any()]).input("a", vec![a]);
+    }
+
+    #[test]
+    fn signals_up_to_64_bits() {
+        let mut c = Circuit::new();
+        let bus = c.add_bus(64, Line::Low);
+        let bench = Testbench::new().input("a", bus.clone()).output("y", bus)
+            .vector(&[!0], &[Expect::value(!0)]).vector(&[1 << 63], &[Expect::pattern("1")]);
+        let report = bench.run(&mut c);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].actual[63], Line::High);
+    }
+
+    #[test]
+    #[should_panic(expected = "Signal y is 65 bits wide, but at most 64 are supported.")]
+    fn wide_signals_are_refused() {
+        let mut c = Circuit::new();
+        let bus = c.add_bus(65, Line::Low);
+        Testbench::new().output("y", bus);
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+
+use circuit::circuit::Circuit;
+use circuit::line::{Line, word_to_lines};
+
+/// What `truth_table` does when a circuit has more inputs than `TruthTableOptions::limit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverLimit {
+    Refuse,
+    /// Evaluates this many distinct input combinations picked at random from `seed`.
+    Sample { rows: usize, seed: u64 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TruthTableOptions {
+    /// Most inputs to enumerate exhaustively. Defaults to 16.
+    pub limit: usize,
+    pub over_limit: OverLimit,
+    /// Most passes a combination gets to settle. Defaults to 1000.
+    pub max_passes: usize
+}
+
+impl Default for TruthTableOptions {
+    fn default() -> Self {
+        TruthTableOptions {
+            limit: 16,
+            over_limit: OverLimit::Refuse,
+            max_passes: 1000
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TruthTableError {
+    TooManyInputs { inputs: usize, limit: usize },
+    /// The outputs for this combination differed depending on what was applied before it, so
+    /// the circuit holds state.
+    StateDependent { row: u64 },
+    /// The circuit was still changing after the maximum number of passes, as oscillators do.
+    Unsettled { row: u64 }
+}
+
+impl fmt::Display for TruthTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TruthTableError::TooManyInputs { inputs, limit } => {
+                write!(f, "{} inputs is more than the limit of {}", inputs, limit)
+            },
+            TruthTableError::StateDependent { row } => {
+                write!(f, "outputs for inputs {:#b} depend on internal state", row)
+            },
+            TruthTableError::Unsettled { row } => {
+                write!(f, "circuit doesn't settle for inputs {:#b}", row)
+            }
+        }
+    }
+}
+
+impl Error for TruthTableError {}
+
+/// Outputs of a combinational circuit for each combination of its inputs. Input `i` of the
+/// circuit is bit `i` of a row's input value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TruthTable {
+    inputs: usize,
+    outputs: usize,
+    exhaustive: bool,
+    rows: Vec<(u64, Vec<Line>)>
+}
+
+// xorshift64*, enough to pick sample rows without pulling in a dependency.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn line_char(line: Line) -> char {
+    match line {
+        Line::High => '1',
+        Line::Low => '0',
+        Line::Disconnected => 'Z'
+    }
+}
+
+fn apply(circuit: &mut Circuit, row: u64, max_passes: usize) -> Result<Vec<Line>, TruthTableError> {
+    let inputs = circuit.inputs.len();
+    for (i, state) in word_to_lines(row, inputs).into_iter().enumerate() {
+        circuit.set_circuit_input(i, state);
+    }
+    if circuit.eval_until_stable(max_passes).is_none() {
+        return Err(TruthTableError::Unsettled { row });
+    }
+    Ok((0..circuit.outputs.len()).map(|o| circuit.get_circuit_output(o)).collect())
+}
+
+fn same(a: &[Line], b: &[Line]) -> bool {
+    a.iter().zip(b).all(|(a, b)| line_char(*a) == line_char(*b))
+}
+
+/// Builds the truth table of `circuit` from its marked inputs and outputs.
+///
+/// Every combination is applied twice, first in ascending order and then in descending order,
+/// and any row whose outputs differ between the two sweeps means the circuit isn't purely
+/// combinational. The circuit is left holding the last combination applied. Circuits with more
+/// than 64 inputs are refused whatever `TruthTableOptions::over_limit` says.
+pub fn truth_table(circuit: &mut Circuit, options: &TruthTableOptions)
+    -> Result<TruthTable, TruthTableError> {
+    let inputs = circuit.inputs.len();
+    // Rows are numbered by a `u64`, so not even a sample can cover more inputs than that.
+    if inputs > 64 {
+        return Err(TruthTableError::TooManyInputs { inputs, limit: options.limit.min(64) });
+    }
+    let (rows, exhaustive) = if inputs <= options.limit && inputs < 64 {
+        ((0..1u64 << inputs).collect::<Vec<_>>(), true)
+    } else {
+        match options.over_limit {
+            OverLimit::Refuse => {
+                return Err(TruthTableError::TooManyInputs { inputs, limit: options.limit });
+            },
+            OverLimit::Sample { rows, seed } => {
+                let mask = if inputs == 64 { !0 } else { (1u64 << inputs) - 1 };
+                let rows = if inputs == 64 { rows } else { rows.min(1 << inputs) };
+                let mut state = seed | 1;
+                let mut sample = BTreeSet::new();
+                while sample.len() < rows {
+                    sample.insert(next_random(&mut state) & mask);
+                }
+                (sample.into_iter().collect(), false)
+            }
+        }
+    };
+    let table = rows.iter()
+        .map(|&row| apply(circuit, row, options.max_passes).map(|outputs| (row, outputs)))
+        .collect::<Result<Vec<_>, _>>()?;
+    for &(row, ref outputs) in table.iter().rev() {
+        if !same(outputs, &apply(circuit, row, options.max_passes)?) {
+            return Err(TruthTableError::StateDependent { row });
+        }
+    }
+    Ok(TruthTable {
+        inputs,
+        outputs: circuit.outputs.len(),
+        exhaustive,
+        rows: table
+    })
+}
+
+impl TruthTable {
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Whether every input combination is present, rather than a sample of them.
+    pub fn is_exhaustive(&self) -> bool {
+        self.exhaustive
+    }
+
+    /// Each row's input value and outputs, in ascending order of input value.
+    pub fn rows(&self) -> &[(u64, Vec<Line>)] {
+        &self.rows
+    }
+
+    pub fn get(&self, inputs: u64) -> Option<&[Line]> {
+        self.rows.binary_search_by_key(&inputs, |&(row, _)| row).ok()
+            .map(|i| &self.rows[i].1[..])
+    }
+
+    fn header(&self) -> Vec<String> {
+        (0..self.inputs).map(|i| format!("in{}", i))
+            .chain((0..self.outputs).map(|o| format!("out{}", o)))
+            .collect()
+    }
+
+    fn cells(&self, row: u64, outputs: &[Line]) -> Vec<char> {
+        (0..self.inputs).map(|i| if i < 64 && row >> i & 1 == 1 { '1' } else { '0' })
+            .chain(outputs.iter().map(|&line| line_char(line)))
+            .collect()
+    }
+
+    /// One header line of column names, then one line per row of `0`, `1` or `Z` for
+    /// disconnected outputs.
+    pub fn to_csv(&self) -> String {
+        let mut out = self.header().join(",");
+        out.push('\n');
+        for &(row, ref outputs) in &self.rows {
+            let cells = self.cells(row, outputs).iter().map(|c| c.to_string())
+                .collect::<Vec<_>>();
+            out.push_str(&cells.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let header = self.header();
+        let mut out = format!("| {} |\n", header.join(" | "));
+        out.push_str(&format!("|{}\n", header.iter().map(|name| {
+            format!("{}|", "-".repeat(name.len() + 2))
+        }).collect::<String>()));
+        for &(row, ref outputs) in &self.rows {
+            out.push('|');
+            for (name, cell) in header.iter().zip(self.cells(row, outputs)) {
+                out.push_str(&format!(" {:^w$} |", cell, w = name.len()));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// One line per row with the input bits, a space and the output bits, each in column order.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for &(row, ref outputs) in &self.rows {
+            let cells = self.cells(row, outputs);
+            out.extend(&cells[..self.inputs]);
+            out.push(' ');
+            out.extend(&cells[self.inputs..]);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use circuit::basics::Inverter;
+    use circuit::gate::tests::single_gate;
+    use circuit::simplegate::SimpleGate;
+    use super::*;
+
+    // A circuit whose single output is the parity of `inputs` marked inputs.
+    fn parity(inputs: usize) -> Circuit {
+        let mut c = Circuit::new();
+        let lines = (0..inputs).map(|_| {
+            let line = c.add_line(Line::Low);
+            c.mark_line_as_circuit_input(line);
+            line
+        }).collect::<Vec<_>>();
+        let mut out = c.add_line(Line::Low);
+        for &line in &lines {
+            let xor = c.add_gate(SimpleGate::xor());
+            c.set_gate_input(xor, 0, out);
+            c.set_gate_input(xor, 1, line);
+            out = c.get_gate_output(xor, 0);
+        }
+        c.mark_line_as_circuit_output(out);
+        c
+    }
+
+    fn sampled(rows: usize) -> TruthTableOptions {
+        TruthTableOptions {
+            over_limit: OverLimit::Sample { rows, seed: 3 },
+            ..TruthTableOptions::default()
+        }
+    }
+
+    #[test]
+    fn exhaustive_tables() {
+        let table = truth_table(&mut single_gate(SimpleGate::and()), &Default::default())
+            .unwrap();
+        assert!(table.is_exhaustive());
+        assert_eq!((table.inputs(), table.outputs()), (2, 1));
+        assert_eq!(table.get(3), Some(&[Line::High][..]));
+        assert_eq!(table.to_csv(), "in0,in1,out0\n0,0,0\n1,0,0\n0,1,0\n1,1,1\n");
+        assert_eq!(table.to_text(), "00 0\n10 0\n01 0\n11 1\n");
+        let table = truth_table(&mut parity(0), &Default::default()).unwrap();
+        assert_eq!(table.rows().len(), 1);
+    }
+
+    #[test]
+    fn limits() {
+        let options = TruthTableOptions { limit: 4, ..TruthTableOptions::default() };
+        assert_eq!(truth_table(&mut parity(5), &options),
+            Err(TruthTableError::TooManyInputs { inputs: 5, limit: 4 }));
+        let table = truth_table(&mut parity(20), &sampled(50)).unwrap();
+        assert!(!table.is_exhaustive());
+        assert_eq!(table.rows().len(), 50);
+        for &(row, ref outputs) in table.rows() {
+            assert_eq!(outputs[0].is_high(), row.count_ones() % 2 == 1);
+        }
+        // Asking for more rows than there are gives every row once.
+        let table = truth_table(&mut parity(3), &TruthTableOptions { limit: 2, ..sampled(100) })
+            .unwrap();
+        assert_eq!(table.rows().len(), 8);
+    }
+
+    #[test]
+    fn sixty_four_inputs_can_be_sampled() {
+        let table = truth_table(&mut parity(64), &sampled(20)).unwrap();
+        assert_eq!(table.rows().len(), 20);
+        for &(row, ref outputs) in table.rows() {
+            assert_eq!(outputs[0].is_high(), row.count_ones() % 2 == 1);
+        }
+    }
+
+    #[test]
+    fn sixty_five_inputs_are_refused() {
+        assert_eq!(truth_table(&mut parity(65), &sampled(20)),
+            Err(TruthTableError::TooManyInputs { inputs: 65, limit: 16 }));
+        assert_eq!(truth_table(&mut parity(65), &Default::default()),
+            Err(TruthTableError::TooManyInputs { inputs: 65, limit: 16 }));
+    }
+
+    #[test]
+    fn state_and_oscillation_are_errors() {
+        // Cross-coupled NOR gates hold q when both reset (input 0) and set are low.
+        let mut c = Circuit::new();
+        let (reset, set) = (c.add_line(Line::Low), c.add_line(Line::Low));
+        let (upper, lower) = (c.add_gate(SimpleGate::nor()), c.add_gate(SimpleGate::nor()));
+        let (q, q_bar) = (c.get_gate_output(upper, 0), c.get_gate_output(lower, 0));
+        for &(gate, a, b) in &[(upper, reset, q_bar), (lower, set, q)] {
+            c.set_gate_input(gate, 0, a);
+            c.set_gate_input(gate, 1, b);
+        }
+        c.mark_line_as_circuit_input(reset);
+        c.mark_line_as_circuit_input(set);
+        c.mark_line_as_circuit_output(q);
+        assert_eq!(truth_table(&mut c, &Default::default()),
+            Err(TruthTableError::StateDependent { row: 0 }));
+        let mut c = Circuit::new();
+        let inverter = c.add_gate(Inverter::new());
+        let out = c.get_gate_output(inverter, 0);
+        c.set_gate_input(inverter, 0, out);
+        c.mark_line_as_circuit_output(out);
+        let options = TruthTableOptions { max_passes: 10, ..TruthTableOptions::default() };
+        assert_eq!(truth_table(&mut c, &options), Err(TruthTableError::Unsettled { row: 0 }));
+    }
+}
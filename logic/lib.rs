@@ -2,11 +2,43 @@
 
 pub mod circuit;
 
-pub use circuit::circuit::Circuit;
+pub use circuit::circuit::{Circuit, EvalMode};
+pub use circuit::compiled::CompiledCircuit;
+pub use circuit::parallel::ParallelCircuit;
+pub use circuit::batch::Batch;
+pub use circuit::codegen::{Language, Expr, Statement, GateCode, CodegenError, MAX_MEMORY_WORDS,
+    generate_code};
 pub use circuit::basics::{Inverter, Source, Sink};
-pub use circuit::simplegate::SimpleGate;
-pub use circuit::gate::Gate;
-pub use circuit::line::{and, or, xor, nand, nor, xnor, not, Line};
+pub use circuit::simplegate::{SimpleGate, make_and_tree, make_or_tree};
+pub use circuit::lut::{Lut, MAX_LUT_INPUTS};
+pub use circuit::gate::{Gate, GateClone, GateKind, StateRegister, Port, PortDirection, PortRole,
+    PortError, check_ports, pin_name};
+pub use circuit::line::{and, or, xor, nand, nor, xnor, not, Line, lines_to_word, word_to_lines};
 pub use circuit::memory::{MasterSlaveFlipFlop, NORLatch, MSFFRAM8, MSFFRAM16, NORLatchRAM8,
-    NORLatchRAM16};
-pub use circuit::mux::{Mux1_2, Dmux1_2};
\ No newline at end of file
+    NORLatchRAM16, Ram, RamKind, RamPort, Rom, DualPortRam, RegisterFile, WordMemory, Edge,
+    MAX_ADDRESS_WIDTH, DFlipFlop, JKFlipFlop, TFlipFlop, make_d_flip_flop, make_jk_flip_flop,
+    make_t_flip_flop};
+pub use circuit::bus::{Bus, BusError};
+pub use circuit::clock::Clock;
+pub use circuit::testbench::{Testbench, Expect, Mismatch, Report};
+pub use circuit::truth_table::{TruthTable, TruthTableOptions, TruthTableError, OverLimit,
+    truth_table};
+pub use circuit::netlist::{Netlist, Node, NetInput, NetOutput, NetlistError};
+pub use circuit::sat::{Solver, Lit};
+pub use circuit::bdd::{Bdd, Cube, CircuitBdd, VariableOrder, circuit_to_bdd, format_cubes};
+pub use circuit::equivalence::{Equivalence, EquivalenceOptions, EquivalenceError, Engine,
+    Counterexample, check_equivalence};
+pub use circuit::expression::{ParsedCircuit, ParseError, ParseErrorKind, parse_circuit};
+pub use circuit::minimize::{BooleanFunction, Cover, Form, Implicant, Term, MinimizeError};
+pub use circuit::optimize::{optimize, OptimizeOptions, OptimizeReport};
+pub use circuit::techmap::{Universal, Cell, CellLibrary, Mapping, MapError, map_universal,
+    map_library};
+pub use circuit::lutmap::{LutGoal, LutOptions, LutReport, map_luts};
+pub use circuit::storage::Storage;
+pub use circuit::image::{MemoryImage, ImageFormat, ImageError, hexdump};
+pub use circuit::sequential::{Register, Counter, ShiftRegister, BarrelShifter, Lfsr, make_register,
+    make_counter, make_shift_register, make_barrel_shifter, make_lfsr};
+pub use circuit::fifo::{Fifo, Stack};
+pub use circuit::mux::{Mux1_2, Dmux1_2, Mux, Dmux, make_mux, make_dmux};
+pub use circuit::encoder::{Decoder, PriorityEncoder, OneHotEncoder, make_decoder,
+    make_priority_encoder, make_one_hot_encoder};
\ No newline at end of file
@@ -10,7 +10,9 @@ criterion_group!{
     config = Criterion::default().sample_size(1000).measurement_time(Duration::from_secs(60));
     targets = bench_mux16_8w, bench_mux16_8w_const, bench_ram_8, bench_ram_8_const,
         bench_mux16_8w_gates, bench_mux16_8w_gates_const, bench_mux16_8w_conditionless,
-        bench_mux16_8w_conditionless_const, bench_ram8_of_gates, bench_ram8_of_gates_const
+        bench_mux16_8w_conditionless_const, bench_ram8_of_gates, bench_ram8_of_gates_const,
+        bench_mux16_8w_gates_compiled, bench_ram8_of_gates_compiled, bench_ram8_of_gates_parallel,
+        bench_ram8_of_gates_batch
 }
 
 criterion_main!{logic_benches}
@@ -20,8 +22,13 @@ pub mod circuit;
 use circuit::line::Line;
 use circuit::gate::Gate;
 use circuit::circuit::Circuit;
+use circuit::compiled::CompiledCircuit;
+use circuit::parallel::ParallelCircuit;
+use circuit::batch::Batch;
 use circuit::memory::MSFFRAM8;
+use circuit::bus::{Bus, decode};
 
+#[derive(Clone)]
 struct MUX16_8W {
     inputs: [usize; 128 + 3],
     outputs: [usize; 16]
@@ -62,8 +69,7 @@ impl Gate for MUX16_8W {
     }
 
     fn eval(&mut self, lines: &mut Vec<Line>) {
-        let which = (0..3).map(|i| (lines[self.inputs[128 + i]].is_high() as usize) << i)
-            .sum::<usize>();
+        let which = decode(lines, &self.inputs[128..]);
         for i in 0..16 {
             let new = lines[self.inputs[which * 16 + i]];
             lines[self.outputs[i]] = new;
@@ -180,7 +186,7 @@ macro_rules! push {
 
 use circuit::mux::Mux1_2;
 
-fn bench_mux16_8w_gates(c: &mut Criterion) {
+fn make_mux16_8w_gates() -> (Circuit, [usize; 128], [usize; 3]) {
     let mut circuit = Circuit::new();
     let mut first_layer_muxes = Vec::new();
     let mut second_layer_muxes = Vec::new();
@@ -214,10 +220,6 @@ fn bench_mux16_8w_gates(c: &mut Criterion) {
     }
     controls[2] = circuit.add_line(Line::Low);
     for mux in &first_layer_muxes {
-        let tmp0 = circuit.get_gate_input(*mux, 0);
-        let tmp1 = circuit.get_gate_input(*mux, 1);
-        circuit.mark_line_as_circuit_input(tmp0);
-        circuit.mark_line_as_circuit_input(tmp1);
         circuit.set_gate_input(*mux, 2, controls[2]);
     }
     let mut inputs = [0; 128];
@@ -231,9 +233,68 @@ fn bench_mux16_8w_gates(c: &mut Criterion) {
     for input in inputs.iter() {
         circuit.mark_line_as_circuit_input(*input);
     }
-    for control in &controls {
+    // The first layer picks by the least significant select bit.
+    for control in controls.iter().rev() {
         circuit.mark_line_as_circuit_input(*control);
     }
+    for mux in &third_layer_muxes {
+        let output = circuit.get_gate_output(*mux, 0);
+        circuit.mark_line_as_circuit_output(output);
+    }
+    (circuit, inputs, controls)
+}
+
+// A xorshift generator for the stimuli of the checks below.
+fn next_stimulus(seed: &mut u64) -> u64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    *seed
+}
+
+// Makes sure the gate network computes what `MUX16_8W` does before timing either of them, by
+// driving both with the same data under every select value.
+fn check_mux16_8w_gates() {
+    let mut behavioral = Circuit::new();
+    let mux = behavioral.add_gate(MUX16_8W::new());
+    for i in 0..128 + 3 {
+        let line = behavioral.add_line(Line::Low);
+        behavioral.mark_line_as_circuit_input(line);
+        behavioral.set_gate_input(mux, i, line);
+    }
+    for o in 0..16 {
+        let output = behavioral.get_gate_output(mux, o);
+        behavioral.mark_line_as_circuit_output(output);
+    }
+    let (mut gates, _, _) = make_mux16_8w_gates();
+    let mut seed = 0x2545_F491_4F6C_DD1D;
+    for pattern in 0..64 {
+        for i in 0..128 {
+            let value = Line::from(next_stimulus(&mut seed) & 1 == 1);
+            behavioral.set_circuit_input(i, value);
+            gates.set_circuit_input(i, value);
+        }
+        for select in 0..8 {
+            for i in 0..3 {
+                let value = Line::from(select >> i & 1 == 1);
+                behavioral.set_circuit_input(128 + i, value);
+                gates.set_circuit_input(128 + i, value);
+            }
+            behavioral.eval();
+            gates.eval();
+            for o in 0..16 {
+                assert_eq!(behavioral.get_circuit_output(o).is_high(),
+                    gates.get_circuit_output(o).is_high(),
+                    "MUX16_8W and its gates differ on output {} of pattern {} with select {}.",
+                    o, pattern, select);
+            }
+        }
+    }
+}
+
+fn bench_mux16_8w_gates(c: &mut Criterion) {
+    check_mux16_8w_gates();
+    let (mut circuit, inputs, controls) = make_mux16_8w_gates();
     let mut counter = 0;
     c.bench_function("MUX gate of `Gate`s", move |b| b.iter(|| {
         let tmp = circuit.get_circuit_input(inputs[counter % 128]);
@@ -246,6 +307,21 @@ fn bench_mux16_8w_gates(c: &mut Criterion) {
     }));
 }
 
+fn bench_mux16_8w_gates_compiled(c: &mut Criterion) {
+    let (circuit, inputs, controls) = make_mux16_8w_gates();
+    let mut circuit = CompiledCircuit::new(circuit);
+    let mut counter = 0;
+    c.bench_function("MUX gate of `Gate`s (compiled)", move |b| b.iter(|| {
+        let tmp = circuit.get_circuit_input(inputs[counter % 128]);
+        circuit.set_line(inputs[counter % 128], !tmp);
+        for i in 0..3 {
+            circuit.set_line(controls[i], (counter >> i & 1 == 1).into());
+        };
+        circuit.eval();
+        counter += 1;
+    }));
+}
+
 fn bench_mux16_8w_gates_const(c: &mut Criterion) {
     let mut circuit = Circuit::new();
     let mut first_layer_muxes = Vec::new();
@@ -295,6 +371,7 @@ fn bench_mux16_8w_gates_const(c: &mut Criterion) {
     }));
 }
 
+#[derive(Clone)]
 struct ConditionlessMux8_16w {
     inputs: [usize; 128 + 3],
     outputs: [usize; 16]
@@ -397,53 +474,124 @@ fn bench_mux16_8w_conditionless_const(c: &mut Criterion) {
     }));
 }
 
-fn bench_ram8_of_gates(c: &mut Criterion) {
+// The memory module of `bench_ram8_of_gates`, with its address and write value buses, its
+// write, read and clock lines, and the bus it reads onto.
+fn make_ram8_of_gates() -> (Circuit, Bus, Bus, [usize; 3], Bus) {
     let mut circuit = Circuit::new();
-    let mut addr = [0; 8];
-    for i in 0..8 {
-        addr[i] = circuit.add_line(Line::Low);
-    }
-    let mut write_val = [0; 8];
-    for i in 0..8 {
-        write_val[i] = circuit.add_line(Line::Low);
-    }
+    let addr = circuit.add_bus(8, Line::Low);
+    let write_val = circuit.add_bus(8, Line::Low);
     let write = circuit.add_line(Line::Low);
     let read = circuit.add_line(Line::Low);
     let clock = circuit.add_line(Line::Low);
-    let _read_lines = make_8bx256_storage(addr, write_val, write, read, clock, &mut circuit);
-    let mut counter = 0;
-    c.bench_function("Memory module of gates", move |b| b.iter(|| {
-        let tmp = circuit.get_line_state(addr[counter % 8]);
-        circuit.set_line(addr[counter % 8], !tmp);
-        let tmp = circuit.get_line_state(write_val[7 - (counter % 8)]);
-        circuit.set_line(write_val[7 - (counter % 8)], !tmp);
-        circuit.set_line(write, Line::High);
-        circuit.set_line(clock, Line::High);
-        circuit.eval();
-        circuit.set_line(write, Line::Low);
-        circuit.set_line(clock, Line::Low);
-        circuit.set_line(read, Line::High);
-        circuit.eval();
-        circuit.set_line(read, Line::Low);
-        circuit.eval();
-        counter += 1;
-    }));
+    let read_lines = make_8bx256_storage(&addr, &write_val, write, read, clock, &mut circuit);
+    (circuit, addr, write_val, [write, read, clock], read_lines)
 }
 
-fn bench_ram8_of_gates_const(c: &mut Criterion) {
-    let mut circuit = Circuit::new();
-    let mut addr = [0; 8];
-    for i in 0..8 {
-        addr[i] = circuit.add_line(Line::Low);
+// Makes sure the gate network stores and reads back what `MSFFRAM8` does, by clocking both
+// through the same random writes and reading each address back after it.
+fn check_ram8_of_gates() {
+    let mut behavioral = Circuit::new();
+    let mem = behavioral.add_gate(MSFFRAM8::new());
+    let pins = behavioral.add_bus(19, Line::Low);
+    for i in 0..19 {
+        behavioral.set_gate_input(mem, i, pins[i]);
     }
-    let mut write_val = [0; 8];
-    for i in 0..8 {
-        write_val[i] = circuit.add_line(Line::Low);
+    let outputs = behavioral.get_gate_output_bus(mem, "read_value");
+    let (mut gates, addr, write_val, [write, read, clock], read_lines) = make_ram8_of_gates();
+    let mut seed = 0x9E37_79B9_7F4A_7C15;
+    for step in 0..512 {
+        let stimulus = next_stimulus(&mut seed);
+        let (address, value) = (stimulus & 0xFF, stimulus >> 8 & 0xFF);
+        behavioral.drive_bus(&pins.slice(0..8), address);
+        behavioral.drive_bus(&pins.slice(8..16), value);
+        gates.drive_bus(&addr, address);
+        gates.drive_bus(&write_val, value);
+        if stimulus >> 16 & 1 == 1 {
+            for &(pin, line, state) in &[(16, write, Line::High), (18, clock, Line::High),
+                (16, write, Line::Low), (18, clock, Line::Low)] {
+                behavioral.set_line(pins[pin], state);
+                gates.set_line(line, state);
+                if pin == 18 {
+                    behavioral.eval();
+                    gates.eval_until_stable(64).expect("The RAM of gates didn't settle.");
+                }
+            }
+        }
+        behavioral.set_line(pins[17], Line::High);
+        gates.set_line(read, Line::High);
+        behavioral.eval();
+        gates.eval_until_stable(64).expect("The RAM of gates didn't settle.");
+        assert_eq!(behavioral.read_bus(&outputs), gates.read_bus(&read_lines),
+            "MSFFRAM8 and its gates read back differently at address {} on step {}.",
+            address, step);
+        behavioral.set_line(pins[17], Line::Low);
+        gates.set_line(read, Line::Low);
     }
-    let write = circuit.add_line(Line::Low);
-    let read = circuit.add_line(Line::Low);
-    let clock = circuit.add_line(Line::Low);
-    let _read_lines = make_8bx256_storage(addr, write_val, write, read, clock, &mut circuit);
+}
+
+// Times writing to the RAM of gates and reading it back, flipping an address bit and a value
+// bit each time, on the backend that `$backend` makes out of the circuit. Backends only share
+// the names of `set_line`, `get_line_state` and `eval`, so this is a macro.
+macro_rules! bench_ram8_of_gates_on {
+    ($c:ident, $name:expr, $backend:expr) => {{
+        let (circuit, addr, write_val, [write, read, clock], _) = make_ram8_of_gates();
+        let mut circuit = $backend(circuit);
+        let mut counter = 0;
+        $c.bench_function($name, move |b| b.iter(|| {
+            let tmp = circuit.get_line_state(addr[counter % 8]);
+            circuit.set_line(addr[counter % 8], !tmp);
+            let tmp = circuit.get_line_state(write_val[7 - (counter % 8)]);
+            circuit.set_line(write_val[7 - (counter % 8)], !tmp);
+            circuit.set_line(write, Line::High);
+            circuit.set_line(clock, Line::High);
+            circuit.eval();
+            circuit.set_line(write, Line::Low);
+            circuit.set_line(clock, Line::Low);
+            circuit.set_line(read, Line::High);
+            circuit.eval();
+            circuit.set_line(read, Line::Low);
+            circuit.eval();
+            counter += 1;
+        }));
+    }}
+}
+
+fn bench_ram8_of_gates(c: &mut Criterion) {
+    check_ram8_of_gates();
+    bench_ram8_of_gates_on!(c, "Memory module of gates", |circuit: Circuit| circuit);
+}
+
+fn bench_ram8_of_gates_compiled(c: &mut Criterion) {
+    bench_ram8_of_gates_on!(c, "Memory module of gates (compiled)", CompiledCircuit::new);
+}
+
+fn bench_ram8_of_gates_parallel(c: &mut Criterion) {
+    bench_ram8_of_gates_on!(c, "Memory module of gates (4 threads)",
+        |circuit| ParallelCircuit::new(circuit, 4));
+}
+
+fn bench_ram8_of_gates_batch(c: &mut Criterion) {
+    let (circuit, addr, write_val, [write, read, clock], read_lines) = make_ram8_of_gates();
+    let batch = Batch::new(circuit, 4);
+    let stimuli = (0..16).collect::<Vec<u64>>();
+    c.bench_function("Memory module of gates (16 instances, 4 threads)", move |b| b.iter(|| {
+        black_box(batch.run(&stimuli, |&value, circuit| {
+            circuit.drive_bus(&addr, value);
+            circuit.drive_bus(&write_val, value * 3);
+            circuit.set_line(write, Line::High);
+            circuit.set_line(clock, Line::High);
+            circuit.eval();
+            circuit.set_line(write, Line::Low);
+            circuit.set_line(clock, Line::Low);
+            circuit.set_line(read, Line::High);
+            circuit.eval();
+            read_lines.iter().map(|&line| circuit.get_line_state(line)).collect::<Vec<_>>()
+        }));
+    }));
+}
+
+fn bench_ram8_of_gates_const(c: &mut Criterion) {
+    let (mut circuit, ..) = make_ram8_of_gates();
     c.bench_function("Memory module of gates (const)", move |b| b.iter(|| {
         black_box(circuit.eval());
     }));
@@ -453,76 +601,70 @@ use circuit::simplegate::SimpleGate;
 use circuit::basics::Inverter;
 
 // inputs: 0, 1
-// output: 7
-// gate count: 9
+// output: 6
+// gate count: 7
+// A master latch that is set by `i0` and reset by `i1`, which are only pulsed while the clock is
+// high, and a slave latch that takes the master's value while the clock is low.
 pub fn make_ms_flip_flop(i0: usize, i1: usize, clock: usize, circuit: &mut Circuit) -> usize {
-    let m_and1 = circuit.add_gate(SimpleGate::and());
-    circuit.set_gate_input(m_and1, 0, i0);
-    circuit.set_gate_input(m_and1, 1, clock);
-    let m_and2 = circuit.add_gate(SimpleGate::and());
-    circuit.set_gate_input(m_and1, 0, i1);
-    circuit.set_gate_input(m_and1, 1, clock);
-    let m_xor1 = circuit.add_gate(SimpleGate::xor());
-    let m_xor2 = circuit.add_gate(SimpleGate::xor());
-    circuit.connect_i_single(m_xor1, 0, m_and1, 0);
-    circuit.connect_i_single(m_xor1, 1, m_xor2, 0);
-    circuit.connect_i_single(m_xor2, 0, m_xor1, 0);
-    circuit.connect_i_single(m_xor2, 1, m_and2, 0);
-    let not = circuit.add_gate(Inverter::new());
-    circuit.set_gate_input(not, 0, clock);
-    let s_and1 = circuit.add_gate(SimpleGate::and());
-    circuit.set_gate_input(s_and1, 0, i0);
-    circuit.set_gate_input(s_and1, 1, clock);
-    let s_and2 = circuit.add_gate(SimpleGate::and());
-    circuit.set_gate_input(s_and1, 0, i1);
-    circuit.set_gate_input(s_and1, 1, clock);
-    let s_xor1 = circuit.add_gate(SimpleGate::xor());
-    let s_xor2 = circuit.add_gate(SimpleGate::xor());
-    circuit.connect_i_single(s_xor1, 0, s_and1, 0);
-    circuit.connect_i_single(s_xor1, 1, s_xor2, 0);
-    circuit.connect_i_single(s_xor2, 0, s_xor1, 0);
-    circuit.connect_i_single(s_xor2, 1, s_and2, 0);
-    circuit.get_gate_output(s_xor1, 0)
-}
-
-pub fn make_1bx256_storage(input: usize, address: [usize; 8], write: usize, read: usize,
-    clock: usize, circuit: &mut Circuit) -> usize {
+    let not_reset = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(not_reset, 0, i1);
+    let m_hold = circuit.add_gate(SimpleGate::and());
+    let m_or = circuit.add_gate(SimpleGate::or());
+    circuit.connect_i_single(m_hold, 0, not_reset, 0);
+    circuit.connect_i_single(m_hold, 1, m_or, 0);
+    circuit.set_gate_input(m_or, 0, i0);
+    circuit.connect_i_single(m_or, 1, m_hold, 0);
+    let not_clock = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(not_clock, 0, clock);
+    let s_pass = circuit.add_gate(SimpleGate::and());
+    let s_hold = circuit.add_gate(SimpleGate::and());
+    let s_or = circuit.add_gate(SimpleGate::or());
+    circuit.connect_i_single(s_pass, 0, not_clock, 0);
+    circuit.connect_i_single(s_pass, 1, m_or, 0);
+    circuit.set_gate_input(s_hold, 0, clock);
+    circuit.connect_i_single(s_hold, 1, s_or, 0);
+    circuit.connect_i_single(s_or, 0, s_pass, 0);
+    circuit.connect_i_single(s_or, 1, s_hold, 0);
+    circuit.get_gate_output(s_or, 0)
+}
+
+pub fn make_1bx256_storage(input: usize, address: &Bus, write: usize, read: usize, clock: usize,
+    circuit: &mut Circuit) -> usize {
+    // Only the addressed cell sees `write`. The other outputs of the `Dmux1_2`s keep their
+    // last value, which `write` going low at the same address clears again.
     let important_dmux_gates = make_1bx256_dmux(address, circuit);
-    circuit.set_gate_input(important_dmux_gates[0], 0, input);
+    circuit.set_gate_input(important_dmux_gates[128], 0, write);
     let important_mux_gates = make_1bx256_mux(address, circuit);
+    let not_input = circuit.add_gate(Inverter::new());
+    circuit.set_gate_input(not_input, 0, input);
     for i in 0..256 {
-        let inv = circuit.add_gate(Inverter::new());
-        circuit.connect_i_single(inv, 0, important_dmux_gates[i / 2], i % 2);
-        let gate_high = circuit.add_gate(SimpleGate::and());
-        circuit.set_gate_input(gate_high, 0, clock);
-        circuit.connect_i_single(gate_high, 1, important_dmux_gates[i / 2], i % 2);
-        let gate_low = circuit.add_gate(SimpleGate::and());
-        circuit.set_gate_input(gate_low, 0, clock);
-        circuit.connect_i_single(gate_low, 1, inv, 0);
-        let write_high = circuit.add_gate(SimpleGate::and());
-        circuit.set_gate_input(write_high, 0, write);
-        circuit.connect_i_single(write_high, 1, gate_high, 0);
-        let write_low = circuit.add_gate(SimpleGate::and());
-        circuit.set_gate_input(write_low, 0, write);
-        circuit.connect_i_single(write_low, 1, gate_low, 0);
-        let bit_i0 = circuit.get_gate_output(write_high, 0);
-        let bit_i1 = circuit.get_gate_output(write_low, 0);
+        let enable = circuit.add_gate(SimpleGate::and());
+        circuit.set_gate_input(enable, 0, clock);
+        circuit.connect_i_single(enable, 1, important_dmux_gates[i / 2], i % 2);
+        let set = circuit.add_gate(SimpleGate::and());
+        circuit.connect_i_single(set, 0, enable, 0);
+        circuit.set_gate_input(set, 1, input);
+        let reset = circuit.add_gate(SimpleGate::and());
+        circuit.connect_i_single(reset, 0, enable, 0);
+        circuit.connect_i_single(reset, 1, not_input, 0);
+        let bit_i0 = circuit.get_gate_output(set, 0);
+        let bit_i1 = circuit.get_gate_output(reset, 0);
         let bit_output = make_ms_flip_flop(bit_i0, bit_i1, clock, circuit);
         let read_gate = circuit.add_gate(SimpleGate::and());
         circuit.set_gate_input(read_gate, 0, bit_output);
         circuit.set_gate_input(read_gate, 1, read);
-        circuit.connect_i_single(important_mux_gates[i / 2], i % 2, read_gate, 0);
+        // The mux tree picks by the most significant address bit first.
+        let slot = (i as u8).reverse_bits() as usize;
+        circuit.connect_i_single(important_mux_gates[slot / 2], slot % 2, read_gate, 0);
     }
-    important_mux_gates[128]
+    circuit.get_gate_output(important_mux_gates[128], 0)
 }
 
-pub fn make_8bx256_storage(address: [usize; 8], write_value: [usize; 8], write: usize, read: usize,
-    clock: usize, circuit: &mut Circuit) -> [usize; 8] {
-    let mut output_lines = [0; 8];
-    for i in 0..8 {
-        output_lines[i] = make_1bx256_storage(write_value[i], address, write, read, clock, circuit);
-    }
-    output_lines
+pub fn make_8bx256_storage(address: &Bus, write_value: &Bus, write: usize, read: usize,
+    clock: usize, circuit: &mut Circuit) -> Bus {
+    write_value.iter()
+        .map(|&value| make_1bx256_storage(value, address, write, read, clock, circuit))
+        .collect::<Vec<_>>().into()
 }
 
 macro_rules! dmuxes {
@@ -541,7 +683,7 @@ macro_rules! dmuxes {
 
 use circuit::mux::Dmux1_2;
 
-pub fn make_1bx256_dmux(controls: [usize; 8], circuit: &mut Circuit) -> [usize; 129] {
+pub fn make_1bx256_dmux(controls: &Bus, circuit: &mut Circuit) -> [usize; 129] {
     let mut gates = Vec::new();
     let dmux_70 = circuit.add_gate(Dmux1_2::new());
     circuit.set_gate_input(dmux_70, 1, controls[7]);
@@ -715,8 +857,8 @@ pub fn make_1bx256_dmux(controls: [usize; 8], circuit: &mut Circuit) -> [usize;
         dmux_0124, dmux_0125, dmux_0126, dmux_0127
     );
     let mut entry_and_outputs = [0; 129];
-    for i in (0..128).rev() {
-        entry_and_outputs[i] = gates[gates.len() - 1 - i];
+    for i in 0..128 {
+        entry_and_outputs[i] = gates[gates.len() - 128 + i];
     }
     entry_and_outputs[128] = gates[0];
     entry_and_outputs
@@ -734,7 +876,7 @@ macro_rules! muxes {
     }
 }
 
-pub fn make_1bx256_mux(controls: [usize; 8], circuit: &mut Circuit) -> [usize; 129] {
+pub fn make_1bx256_mux(controls: &Bus, circuit: &mut Circuit) -> [usize; 129] {
     let mut gates = Vec::new();
     for _ in 0..128 {
         let mux = circuit.add_gate(Mux1_2::new());
@@ -899,6 +1041,6 @@ pub fn make_1bx256_mux(controls: [usize; 8], circuit: &mut Circuit) -> [usize; 1
     for i in 0..128 {
         inputs_and_output[i] = gates[i];
     }
-    inputs_and_output[128] = gates[gates.len() - 1];
+    inputs_and_output[128] = gates[128];
     inputs_and_output
 }